@@ -0,0 +1,254 @@
+//! Access logging, for ingestion of the requests that the gateway handled by log pipelines.
+//!
+//! One JSON line is written per request, independent of the tracing logs, with the time the
+//! request was received, the address of the client, the method and path of the request, the app
+//! that was called, the status and size of the response and the time it took to respond.
+
+use crate::request_id::current_request_id;
+use axum::body::HttpBody;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use holochain_types::prelude::Timestamp;
+use serde::Serialize;
+use std::cell::OnceCell;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+tokio::task_local! {
+    static APP_ID: OnceCell<String>;
+}
+
+/// Record the app that the current request is for, to be included in its access log entry.
+///
+/// Does nothing if access logging is disabled, or if the app was already recorded.
+pub(crate) fn record_app_id(installed_app_id: &str) {
+    let _ = APP_ID.try_with(|app_id| app_id.set(installed_app_id.to_string()));
+}
+
+/// Destination of the access log.
+///
+/// Clones share the same destination, so that lines written by concurrent requests don't
+/// interleave.
+#[derive(Clone)]
+pub struct AccessLog {
+    writer: Arc<Mutex<dyn Write + Send>>,
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLog").finish_non_exhaustive()
+    }
+}
+
+impl AccessLog {
+    /// Write the access log to standard output.
+    pub fn stdout() -> Self {
+        Self::from_writer(std::io::stdout())
+    }
+
+    /// Append the access log to the file at the given path, creating it if it doesn't exist.
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::from_writer(file))
+    }
+
+    /// Write the access log to the given writer.
+    pub fn from_writer(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    /// Write an entry as a line of JSON.
+    ///
+    /// Also used for the [audit log](crate::AuditLog), which is written the same way.
+    pub(crate) fn write(&self, entry: &impl Serialize) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!(?e, "Failed to serialize log entry");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().expect("Access log lock poisoned");
+        if let Err(e) = writer.write_all(&line).and_then(|()| writer.flush()) {
+            tracing::warn!(?e, "Failed to write log entry");
+        }
+    }
+}
+
+/// The address of the client that sent the request, if it is known.
+///
+/// Clients that connect over IPv4 to a dual-stack listener have IPv4-mapped IPv6 addresses, which
+/// are given as the IPv4 addresses that they map, so that a client is logged the same way whatever
+/// the listener is bound to.
+pub(crate) fn client_ip(request: &Request) -> Option<IpAddr> {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip().to_canonical())
+}
+
+/// An access log line.
+#[derive(Debug, Serialize)]
+struct AccessLogEntry<'a> {
+    /// When the request was received, as an RFC 3339 timestamp.
+    timestamp: String,
+    client_ip: Option<IpAddr>,
+    method: &'a str,
+    /// The path of the request, without the query, so that payloads don't end up in logs.
+    path: &'a str,
+    /// The installed app id of the app that was called, if the request got that far.
+    app_id: Option<String>,
+    status: u16,
+    duration_ms: f64,
+    /// The size of the response body, if it is known up front.
+    bytes: Option<u64>,
+    request_id: Option<String>,
+}
+
+/// Middleware that writes an access log line for every request.
+pub(crate) async fn log_access(
+    State(access_log): State<AccessLog>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let timestamp = Timestamp::now();
+    let started = Instant::now();
+    let client_ip = client_ip(&request);
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    let (app_id, response) = APP_ID
+        .scope(OnceCell::new(), async move {
+            let response = next.run(request).await;
+            (APP_ID.with(|app_id| app_id.get().cloned()), response)
+        })
+        .await;
+
+    access_log.write(&AccessLogEntry {
+        timestamp: timestamp.to_string(),
+        client_ip,
+        method: method.as_str(),
+        path: &path,
+        app_id,
+        status: response.status().as_u16(),
+        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        bytes: response.body().size_hint().exact(),
+        request_id: current_request_id(),
+    });
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::router::TestRouter;
+    use crate::{AllowedFns, Configuration};
+    use axum::body::Body;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use tower::ServiceExt;
+
+    /// A writer that can be read from after it has been handed to the access log.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<serde_json::Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_are_logged_as_json_lines() {
+        let buffer = Buffer::default();
+        let mut config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "coordinator",
+            HashMap::from([("coordinator".to_string(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap();
+        config.access_log = Some(AccessLog::from_writer(buffer.clone()));
+        let router = TestRouter::new_with_config(config);
+
+        let mut request = axum::http::Request::builder()
+            .uri("/uhC0kAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQF-z86-/coordinator/zome_name/fn_name")
+            .header("x-request-id", "access-log-test")
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::new(
+            Ipv4Addr::LOCALHOST.into(),
+            1234,
+        )));
+        let response = (*router).clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let (status_code, _) = router.request("/health").await;
+        assert_eq!(status_code, 200);
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["client_ip"], "127.0.0.1");
+        assert_eq!(lines[0]["method"], "GET");
+        assert_eq!(
+            lines[0]["path"],
+            "/uhC0kAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQF-z86-/coordinator/zome_name/fn_name"
+        );
+        assert_eq!(lines[0]["app_id"], "coordinator");
+        assert_eq!(lines[0]["status"], 200);
+        assert_eq!(lines[0]["bytes"], 4);
+        assert_eq!(lines[0]["request_id"], "access-log-test");
+        assert!(lines[0]["timestamp"].is_string());
+        assert!(lines[0]["duration_ms"].is_f64());
+
+        assert_eq!(lines[1]["path"], "/health");
+        assert_eq!(lines[1]["app_id"], serde_json::Value::Null);
+        assert_eq!(lines[1]["client_ip"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn ipv4_mapped_client_addresses_are_given_as_ipv4() {
+        let client_ip_of = |ip: IpAddr| {
+            let mut request = Request::new(Body::empty());
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new(ip, 1234)));
+            client_ip(&request)
+        };
+
+        assert_eq!(
+            client_ip_of(Ipv4Addr::LOCALHOST.to_ipv6_mapped().into()),
+            Some(Ipv4Addr::LOCALHOST.into())
+        );
+        assert_eq!(
+            client_ip_of(Ipv6Addr::LOCALHOST.into()),
+            Some(Ipv6Addr::LOCALHOST.into())
+        );
+        assert_eq!(client_ip(&Request::new(Body::empty())), None);
+    }
+}
@@ -0,0 +1,139 @@
+//! Alerting on upstream outages, so that operators are paged without building log based alerting.
+//!
+//! When a conductor can no longer be reached, and when it can be reached again, a JSON
+//! notification is posted to the configured webhook.
+
+use holochain_types::prelude::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use url::Url;
+
+/// Time that posting a notification to the webhook may take.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Webhook that outage notifications are posted to.
+#[derive(Debug, Clone)]
+pub struct AlertWebhook {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl AlertWebhook {
+    /// Create a webhook that posts notifications to the given URL.
+    pub fn new(url: Url) -> reqwest::Result<Self> {
+        let client = reqwest::Client::builder().timeout(NOTIFY_TIMEOUT).build()?;
+
+        Ok(Self { client, url })
+    }
+
+    /// Post a notification in the background.
+    ///
+    /// Does nothing outside of a Tokio runtime. Notifications that fail to be posted are logged
+    /// and dropped.
+    pub(crate) fn notify(&self, alert: OutageAlert) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let this = self.clone();
+
+        runtime.spawn(async move {
+            let result = this
+                .client
+                .post(this.url.clone())
+                .json(&alert)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                tracing::warn!(?e, ?alert, "Failed to post alert to the webhook");
+            }
+        });
+    }
+}
+
+/// What happened to the connection to a conductor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OutageEvent {
+    /// The conductor could no longer be reached.
+    UpstreamUnavailable,
+    /// The conductor could be reached again.
+    UpstreamRecovered,
+}
+
+/// The notification that is posted to the webhook.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct OutageAlert {
+    pub(crate) event: OutageEvent,
+    /// Name of the conductor.
+    pub(crate) conductor: String,
+    /// When the gateway noticed, as an RFC 3339 timestamp.
+    pub(crate) timestamp: String,
+    /// How long the conductor could not be reached, when it has recovered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) outage_ms: Option<u64>,
+}
+
+impl OutageAlert {
+    pub(crate) fn unavailable(conductor: &str) -> Self {
+        Self {
+            event: OutageEvent::UpstreamUnavailable,
+            conductor: conductor.to_string(),
+            timestamp: Timestamp::now().to_string(),
+            outage_ms: None,
+        }
+    }
+
+    pub(crate) fn recovered(conductor: &str, outage: Option<Duration>) -> Self {
+        Self {
+            event: OutageEvent::UpstreamRecovered,
+            conductor: conductor.to_string(),
+            timestamp: Timestamp::now().to_string(),
+            outage_ms: outage.map(|outage| outage.as_millis() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConductorHealth;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use futures::StreamExt;
+    use futures::channel::mpsc;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn outage_transitions_are_posted() {
+        let (sender, mut alerts) = mpsc::unbounded();
+        let webhook = Router::new().route(
+            "/hook",
+            post(move |Json(alert): Json<OutageAlert>| {
+                let sender = sender.clone();
+                async move { sender.unbounded_send(alert).unwrap() }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(async move { axum::serve(listener, webhook).await.unwrap() });
+
+        let health = ConductorHealth::default().with_alert_webhook(AlertWebhook::new(url).unwrap());
+        // Only transitions are posted
+        health.record("a", true);
+        health.record("a", false);
+        let alert = alerts.next().await.unwrap();
+        assert_eq!(alert.event, OutageEvent::UpstreamUnavailable);
+        assert_eq!(alert.conductor, "a");
+        assert_eq!(alert.outage_ms, None);
+
+        health.record("a", false);
+        health.record("a", true);
+        let alert = alerts.next().await.unwrap();
+        assert_eq!(alert.event, OutageEvent::UpstreamRecovered);
+        assert_eq!(alert.conductor, "a");
+        assert!(alert.outage_ms.is_some());
+    }
+}
@@ -1,13 +1,20 @@
-use holochain_client::AppInfo;
+use holochain_client::{AppInfo, CellId};
 use holochain_conductor_api::{AppStatusFilter, CellInfo};
+use holochain_types::app::InstalledAppId;
 use holochain_types::dna::DnaHash;
+use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
-use crate::{AdminCall, config::AllowedAppIds};
+use crate::request_id::current_request_id;
+use crate::{AdminCall, ConductorHandle, Conductors, config::AllowedAppIds};
 
-#[derive(Debug, PartialEq, Error)]
+/// Variants are ordered from the least to the most specific reason for not finding an app, so
+/// that the most useful error can be reported when searching across conductors.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Error)]
 pub enum AppSelectionError {
     #[error("App is not installed on the conductor")]
     NotInstalled,
@@ -19,7 +26,117 @@ pub enum AppSelectionError {
     MultipleMatching,
 }
 
-pub type AppInfoCache = Arc<tokio::sync::RwLock<Vec<AppInfo>>>;
+pub type AppInfoCache = Arc<InstalledApps>;
+
+/// The apps installed on a conductor, as last listed by the gateway.
+///
+/// Dereferences to the lock of the list. Lookups that miss the list refresh it one at a time,
+/// under a separate lock, so that lookups that find their app in the list are not held up while
+/// the apps are listed.
+#[derive(Debug, Default)]
+pub struct InstalledApps {
+    apps: tokio::sync::RwLock<Vec<AppInfo>>,
+    refresh: tokio::sync::Mutex<()>,
+    /// Number of refreshes by lookups that missed the list.
+    refreshes: AtomicU64,
+}
+
+impl From<Vec<AppInfo>> for InstalledApps {
+    fn from(apps: Vec<AppInfo>) -> Self {
+        Self {
+            apps: tokio::sync::RwLock::new(apps),
+            ..Default::default()
+        }
+    }
+}
+
+impl Deref for InstalledApps {
+    type Target = tokio::sync::RwLock<Vec<AppInfo>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.apps
+    }
+}
+
+/// Number of lookups that a [`NotInstalledCache`] remembers at most.
+const MAX_NOT_INSTALLED_ENTRIES: usize = 10_000;
+
+/// Lookups that recently found no installed app, keyed by DNA hash and coordinator identifier.
+///
+/// Entries expire after the configured TTL, so that repeated requests for an unknown app don't
+/// each list the apps on the conductor. A TTL of zero disables the cache.
+#[derive(Debug, Clone, Default)]
+pub struct NotInstalledCache {
+    ttl: Duration,
+    entries: Arc<Mutex<NotInstalledEntries>>,
+}
+
+#[derive(Debug, Default)]
+struct NotInstalledEntries {
+    lookups: HashMap<(DnaHash, String), Instant>,
+    /// When the expired lookups were last pruned.
+    pruned_at: Option<Instant>,
+}
+
+impl NotInstalledCache {
+    /// Create an empty cache whose entries expire after the given TTL.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Default::default(),
+        }
+    }
+
+    /// Whether the lookup found no installed app within the TTL.
+    ///
+    /// Only the entry of the lookup is checked, and removed if it has expired.
+    pub fn contains(&self, dna_hash: &DnaHash, coordinator_identifier: &str) -> bool {
+        let mut entries = self.entries.lock().expect("Invalid lock");
+        let key = (dna_hash.clone(), coordinator_identifier.to_string());
+        match entries.lookups.get(&key) {
+            Some(recorded_at) if recorded_at.elapsed() < self.ttl => true,
+            Some(_) => {
+                entries.lookups.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Record that the lookup found no installed app.
+    ///
+    /// The entries that have expired are pruned at most once per TTL, so that a flood of lookups
+    /// for unknown apps doesn't scan every entry on each insert. Once there are
+    /// [`MAX_NOT_INSTALLED_ENTRIES`] entries, further lookups are not recorded until expired
+    /// entries have been pruned.
+    pub fn insert(&self, dna_hash: DnaHash, coordinator_identifier: String) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("Invalid lock");
+        if entries
+            .pruned_at
+            .is_none_or(|pruned_at| now.duration_since(pruned_at) >= self.ttl)
+        {
+            entries
+                .lookups
+                .retain(|_, recorded_at| now.duration_since(*recorded_at) < self.ttl);
+            entries.pruned_at = Some(now);
+        }
+        if entries.lookups.len() < MAX_NOT_INSTALLED_ENTRIES {
+            entries
+                .lookups
+                .insert((dna_hash, coordinator_identifier), now);
+        }
+    }
+
+    /// Forget all recorded lookups, for example because the installed apps have changed.
+    pub fn clear(&self) {
+        self.entries.lock().expect("Invalid lock").lookups.clear();
+    }
+}
 
 /// Return the [`AppInfo`] of the matching valid app if unique.
 ///
@@ -33,52 +150,83 @@ pub type AppInfoCache = Arc<tokio::sync::RwLock<Vec<AppInfo>>>;
 /// # Side effects
 /// If a matching app is not found in the provided list of installed apps then a request to the
 /// admin websocket will be made and the list will be updated with the results of that request.
+/// That request is skipped if the lookup is in the `not_installed` cache, and the lookup is added
+/// to it if the app is still not found.
+///
+/// Only one refresh of the list of installed apps runs at a time. Concurrent lookups that miss
+/// the cache wait for that refresh and use its result rather than listing the apps themselves,
+/// while lookups that find their app in the list don't wait for it.
 pub async fn try_get_valid_app(
-    dna_hash: DnaHash,
-    coordinator_identifier: String,
+    dna_hash: &DnaHash,
+    coordinator_identifier: &str,
     installed_apps: AppInfoCache,
+    not_installed: &NotInstalledCache,
     allowed_apps: &AllowedAppIds,
     admin_call: impl Deref<Target = impl AdminCall + ?Sized>,
 ) -> Result<AppInfo, AppSelectionError> {
     let app_info = {
         let installed_apps = installed_apps.read().await;
-        choose_unique_app(&dna_hash, &coordinator_identifier, &installed_apps)
+        choose_unique_app(dna_hash, coordinator_identifier, &installed_apps)
             .ok()
             .cloned()
     };
 
     let app_info = match app_info {
         Some(app_info) => app_info,
+        None if not_installed.contains(dna_hash, coordinator_identifier) => {
+            tracing::debug!(
+                ?dna_hash,
+                ?coordinator_identifier,
+                "App was recently not found, not listing apps again"
+            );
+            return Err(AppSelectionError::NotInstalled);
+        }
         None => {
-            let new_installed_apps = admin_call
-                .list_apps(Some(AppStatusFilter::Enabled))
-                .await
-                .unwrap_or_else(|e| {
-                    tracing::error!("Failed to get a list of apps from Holochain: {}", e);
-                    vec![]
-                });
-
-            if !new_installed_apps.is_empty() {
-                // If we got a response from Holochain, then we have a chance of finding the app.
-                // Update the app info cache and search again.
-
-                let mut installed_apps = installed_apps.write().await;
-                *installed_apps = new_installed_apps.clone();
-                choose_unique_app(
-                    &dna_hash,
-                    &coordinator_identifier,
-                    &installed_apps.downgrade(),
-                )?
-                .clone()
-            } else {
-                // We either couldn't get a response from Holochain or the response was empty.
-                // In either case, we can't find the app.
-
+            // Only one lookup refreshes the list at a time. Lookups that waited for another
+            // refresh use its result rather than listing the apps again.
+            let refreshes = installed_apps.refreshes.load(Ordering::Acquire);
+            let _refresh = installed_apps.refresh.lock().await;
+            let new_installed_apps = if installed_apps.refreshes.load(Ordering::Acquire)
+                != refreshes
+            {
+                installed_apps.read().await.clone()
+            } else if not_installed.contains(dna_hash, coordinator_identifier) {
                 return Err(AppSelectionError::NotInstalled);
+            } else {
+                let new_installed_apps =
+                    match admin_call.list_apps(Some(AppStatusFilter::Enabled)).await {
+                        Ok(new_installed_apps) => new_installed_apps,
+                        Err(e) => {
+                            // Don't remember the lookup, the app may well be found once Holochain
+                            // can be reached again.
+                            tracing::error!("Failed to get a list of apps from Holochain: {}", e);
+                            return Err(AppSelectionError::NotInstalled);
+                        }
+                    };
+                // The write lock is only taken to swap in the new list
+                *installed_apps.write().await = new_installed_apps.clone();
+                installed_apps.refreshes.fetch_add(1, Ordering::AcqRel);
+                new_installed_apps
+            };
+
+            match choose_unique_app(dna_hash, coordinator_identifier, &new_installed_apps) {
+                Ok(app_info) => app_info.clone(),
+                Err(AppSelectionError::NotInstalled) => {
+                    not_installed.insert(dna_hash.clone(), coordinator_identifier.to_string());
+                    return Err(AppSelectionError::NotInstalled);
+                }
+                Err(e) => return Err(e),
             }
         }
     };
 
+    check_app_allowed(app_info, allowed_apps)
+}
+
+fn check_app_allowed(
+    app_info: AppInfo,
+    allowed_apps: &AllowedAppIds,
+) -> Result<AppInfo, AppSelectionError> {
     if !allowed_apps.contains(&app_info.installed_app_id) {
         tracing::info!(
             "Found an app but access is not permitted: {}",
@@ -90,6 +238,124 @@ pub async fn try_get_valid_app(
     Ok(app_info)
 }
 
+/// Return the conductors hosting the matching valid app, along with the [`AppInfo`] of the app on
+/// each conductor.
+///
+/// Each conductor that is permitted to serve the app is searched with [`try_get_valid_app`]. When
+/// no conductor is permitted to serve the app, all conductors are searched so that an installed
+/// but not allowed app is still reported as such.
+///
+/// If the app is found on more than one conductor, then an error is returned unless `failover` is
+/// enabled. With `failover`, all matches are returned in order of preference: healthy conductors
+/// first, then in the order that the conductors were configured.
+///
+/// The returned list is never empty.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        app_id = %coordinator_identifier,
+        %dna_hash,
+        request_id = current_request_id().as_deref(),
+    )
+)]
+pub async fn try_get_valid_app_from_conductors<'a>(
+    dna_hash: &DnaHash,
+    coordinator_identifier: &str,
+    conductors: &'a Conductors,
+    failover: bool,
+) -> Result<Vec<(&'a ConductorHandle, AppInfo)>, AppSelectionError> {
+    let allowed_conductors = conductors
+        .iter()
+        .filter(|conductor| conductor.allowed_app_ids.contains(coordinator_identifier))
+        .collect::<Vec<_>>();
+    let candidates = if allowed_conductors.is_empty() {
+        conductors.iter().collect()
+    } else {
+        allowed_conductors
+    };
+
+    let mut found = Vec::new();
+    let mut error = AppSelectionError::NotInstalled;
+    for conductor in candidates {
+        match try_get_valid_app(
+            dna_hash,
+            coordinator_identifier,
+            conductor.app_info_cache.clone(),
+            &conductor.not_installed_cache,
+            &conductor.allowed_app_ids,
+            conductor.admin_call.clone(),
+        )
+        .await
+        {
+            Ok(app_info) => {
+                tracing::debug!(conductor = %conductor.name, app_id = %app_info.installed_app_id, "Found app on conductor");
+                found.push((conductor, app_info));
+            }
+            Err(e) => error = error.max(e),
+        }
+    }
+
+    if found.is_empty() {
+        return Err(error);
+    }
+
+    if found.len() > 1 && !failover {
+        tracing::warn!(
+            ?dna_hash,
+            ?coordinator_identifier,
+            "App found on multiple conductors, could not determine which to call"
+        );
+        return Err(AppSelectionError::MultipleMatching);
+    }
+
+    // The sort is stable, so the configured order is kept among healthy and unhealthy conductors.
+    found.sort_by_key(|(conductor, _)| !conductor.health.is_healthy());
+
+    Ok(found)
+}
+
+/// Return the ids of the apps that differ between two listings of the apps on a conductor.
+///
+/// An app has changed if it was installed or uninstalled between the listings, or if anything in
+/// its [`AppInfo`] differs, such as its status or its cells.
+pub fn changed_app_ids(previous: &[AppInfo], current: &[AppInfo]) -> HashSet<InstalledAppId> {
+    let previous = previous
+        .iter()
+        .map(|app| (&app.installed_app_id, app))
+        .collect::<HashMap<_, _>>();
+    let current = current
+        .iter()
+        .map(|app| (&app.installed_app_id, app))
+        .collect::<HashMap<_, _>>();
+
+    previous
+        .keys()
+        .chain(current.keys())
+        .filter(|app_id| previous.get(*app_id) != current.get(*app_id))
+        .map(|app_id| (*app_id).clone())
+        .collect()
+}
+
+/// Return the id of the cell in the app that has the given DNA hash, if any.
+///
+/// Enabled clone cells are considered as well as provisioned cells, so a clone that was created
+/// after the app info was cached can be found once the app info has been refreshed.
+pub fn find_cell_id(app_info: &AppInfo, dna_hash: &DnaHash) -> Option<CellId> {
+    app_info
+        .cell_info
+        .values()
+        .flatten()
+        .find_map(|cell_info| match cell_info {
+            CellInfo::Provisioned(provisioned) if provisioned.cell_id.dna_hash() == dna_hash => {
+                Some(provisioned.cell_id.clone())
+            }
+            CellInfo::Cloned(cloned) if cloned.enabled && cloned.cell_id.dna_hash() == dna_hash => {
+                Some(cloned.cell_id.clone())
+            }
+            _ => None,
+        })
+}
+
 fn choose_unique_app<'a>(
     dna_hash: &DnaHash,
     coordinator_identifier: &str,
@@ -97,15 +363,7 @@ fn choose_unique_app<'a>(
 ) -> Result<&'a AppInfo, AppSelectionError> {
     let mut found_apps = installed_apps.iter().filter(|a| {
         // TODO: Use real `coordinator_identifier` when field available.
-        a.installed_app_id == coordinator_identifier
-            && a.cell_info.values().any(|cell_infos| {
-                cell_infos.iter().any(|cell_info| match cell_info {
-                    CellInfo::Provisioned(provisioned) => {
-                        provisioned.cell_id.dna_hash() == dna_hash
-                    }
-                    _ => false,
-                })
-            })
+        a.installed_app_id == coordinator_identifier && find_cell_id(a, dna_hash).is_some()
     });
 
     let app_info = found_apps.next().ok_or(AppSelectionError::NotInstalled)?;
@@ -126,10 +384,10 @@ fn choose_unique_app<'a>(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::MockAdminCall;
     use crate::test::data;
+    use crate::{MockAdminCall, MockAppCall};
+    use holochain_types::app::{AppStatus, DisabledAppReason};
     use std::str::FromStr;
-    use tokio::sync::RwLock;
 
     #[tokio::test]
     async fn returns_error_if_app_not_installed() {
@@ -143,9 +401,10 @@ mod tests {
             .once();
 
         let result = try_get_valid_app(
-            dna_hash,
-            "app_1".to_string(),
+            &dna_hash,
+            "app_1",
             installed_apps,
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
@@ -158,14 +417,15 @@ mod tests {
     async fn returns_error_if_app_installed_but_not_allowed() {
         let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
         let installed_apps = vec![data::new_test_app_info("some_app_id", dna_hash.clone())];
-        let installed_apps = Arc::new(RwLock::new(installed_apps));
+        let installed_apps = Arc::new(InstalledApps::from(installed_apps));
         let allowed_apps = AllowedAppIds::from_str("other_app_id").unwrap();
         let admin_websocket = MockAdminCall::new();
 
         let result = try_get_valid_app(
-            dna_hash,
-            "some_app_id".to_string(),
+            &dna_hash,
+            "some_app_id",
             installed_apps,
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
@@ -179,14 +439,15 @@ mod tests {
         let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
         let app_info = data::new_test_app_info("some_app_id", dna_hash.clone());
         let installed_apps = vec![app_info.clone()];
-        let installed_apps = Arc::new(RwLock::new(installed_apps));
+        let installed_apps = Arc::new(InstalledApps::from(installed_apps));
         let allowed_apps = AllowedAppIds::from_str("some_app_id").unwrap();
         let admin_websocket = MockAdminCall::new();
 
         let result = try_get_valid_app(
-            dna_hash,
-            "some_app_id".to_string(),
+            &dna_hash,
+            "some_app_id",
             installed_apps,
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
@@ -199,7 +460,7 @@ mod tests {
     async fn checks_app_list_from_websocket_if_not_in_installed_apps() {
         let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
         let installed_apps = Vec::new();
-        let installed_apps = Arc::new(RwLock::new(installed_apps));
+        let installed_apps = Arc::new(InstalledApps::from(installed_apps));
         let allowed_apps = AllowedAppIds::from_str("some_app_id").unwrap();
         let mut admin_websocket = MockAdminCall::new();
         let app_info = data::new_test_app_info("some_app_id", dna_hash.clone());
@@ -213,9 +474,10 @@ mod tests {
             .once();
 
         let result = try_get_valid_app(
-            dna_hash,
-            "some_app_id".to_string(),
+            &dna_hash,
+            "some_app_id",
             installed_apps,
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
@@ -224,6 +486,47 @@ mod tests {
         assert_eq!(result, Ok(app_info));
     }
 
+    #[tokio::test]
+    async fn refreshes_app_list_to_find_new_clone_cell() {
+        let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
+        let clone_dna_hash = DnaHash::from_raw_32([2; 32].to_vec());
+        let app_info = data::new_test_app_info("some_app_id", dna_hash.clone());
+        let installed_apps = Arc::new(InstalledApps::from(vec![app_info.clone()]));
+        let allowed_apps = AllowedAppIds::from_str("some_app_id").unwrap();
+
+        // The clone cell was created after the app list was cached
+        let mut app_info_with_clone = app_info;
+        data::add_test_clone_cell(&mut app_info_with_clone, clone_dna_hash.clone());
+        let mut admin_websocket = MockAdminCall::new();
+        admin_websocket
+            .expect_list_apps()
+            .returning({
+                let app_info = app_info_with_clone.clone();
+                move |_| {
+                    let app_info = app_info.clone();
+                    Box::pin(async { Ok(vec![app_info]) })
+                }
+            })
+            .once();
+
+        let result = try_get_valid_app(
+            &clone_dna_hash,
+            "some_app_id",
+            installed_apps,
+            &NotInstalledCache::default(),
+            &allowed_apps,
+            &admin_websocket,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result, app_info_with_clone);
+        assert_eq!(
+            find_cell_id(&result, &clone_dna_hash).unwrap().dna_hash(),
+            &clone_dna_hash
+        );
+    }
+
     #[tokio::test]
     async fn returns_error_if_multiple_apps_match() {
         let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
@@ -231,7 +534,7 @@ mod tests {
             data::new_test_app_info("app_1", dna_hash.clone()),
             data::new_test_app_info("app_1", dna_hash.clone()),
         ];
-        let installed_apps_cache = Arc::new(RwLock::new(installed_apps.clone()));
+        let installed_apps_cache = Arc::new(InstalledApps::from(installed_apps.clone()));
         let allowed_apps = AllowedAppIds::from_str("app_1,app_2").unwrap();
         let mut admin_websocket = MockAdminCall::new();
         let installed_apps_cloned = installed_apps.clone();
@@ -244,9 +547,10 @@ mod tests {
             .once();
 
         let result = try_get_valid_app(
-            dna_hash,
-            "app_1".to_string(),
+            &dna_hash,
+            "app_1",
             installed_apps_cache,
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
@@ -259,7 +563,7 @@ mod tests {
     async fn returns_error_if_coordinator_identifier_does_not_match_app_id() {
         let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
         let installed_apps = vec![data::new_test_app_info("app_2", dna_hash.clone())];
-        let installed_apps_cache = Arc::new(RwLock::new(installed_apps.clone()));
+        let installed_apps_cache = Arc::new(InstalledApps::from(installed_apps.clone()));
         let allowed_apps = AllowedAppIds::from_str("app_2").unwrap();
         let mut admin_websocket = MockAdminCall::new();
         let installed_apps_cloned = installed_apps.clone();
@@ -272,9 +576,10 @@ mod tests {
             .once();
 
         let result = try_get_valid_app(
-            dna_hash,
-            "app_1".to_string(),
+            &dna_hash,
+            "app_1",
             installed_apps_cache,
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
@@ -286,7 +591,7 @@ mod tests {
     #[tokio::test]
     async fn returns_error_if_matching_coordinator_identifier_not_in_allowed_list() {
         let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
-        let installed_apps_cache = Arc::new(RwLock::new(vec![
+        let installed_apps_cache = Arc::new(InstalledApps::from(vec![
             data::new_test_app_info("app_1", dna_hash.clone()),
             data::new_test_app_info("app_2", dna_hash.clone()),
         ]));
@@ -294,9 +599,10 @@ mod tests {
         let admin_websocket = MockAdminCall::new();
 
         let result = try_get_valid_app(
-            dna_hash,
-            "app_1".to_string(),
+            &dna_hash,
+            "app_1",
             installed_apps_cache,
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
@@ -322,9 +628,10 @@ mod tests {
             .once();
 
         try_get_valid_app(
-            dna_hash,
-            "app_1".to_string(),
+            &dna_hash,
+            "app_1",
             installed_apps.clone(),
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
@@ -334,6 +641,136 @@ mod tests {
         assert_eq!(&*installed_apps.read().await, &new_installed_apps);
     }
 
+    #[test]
+    fn detects_changed_apps() {
+        let unchanged = data::new_test_app_info("unchanged", DnaHash::from_raw_32(vec![1; 32]));
+        let uninstalled = data::new_test_app_info("uninstalled", DnaHash::from_raw_32(vec![2; 32]));
+        let installed = data::new_test_app_info("installed", DnaHash::from_raw_32(vec![3; 32]));
+        let enabled = data::new_test_app_info("disabled", DnaHash::from_raw_32(vec![4; 32]));
+        let mut disabled = enabled.clone();
+        disabled.status = AppStatus::Disabled(DisabledAppReason::User);
+        let mut cloned = data::new_test_app_info("cloned", DnaHash::from_raw_32(vec![5; 32]));
+        let without_clone = cloned.clone();
+        data::add_test_clone_cell(&mut cloned, DnaHash::from_raw_32(vec![6; 32]));
+
+        let previous = vec![unchanged.clone(), uninstalled, enabled, without_clone];
+        let current = vec![unchanged, installed, disabled, cloned];
+
+        let mut changed = changed_app_ids(&previous, &current)
+            .into_iter()
+            .collect::<Vec<_>>();
+        changed.sort();
+        assert_eq!(
+            changed,
+            vec!["cloned", "disabled", "installed", "uninstalled"]
+        );
+        assert!(changed_app_ids(&current, &current).is_empty());
+    }
+
+    fn conductor_with_apps(
+        name: &str,
+        allowed_apps: &str,
+        installed_apps: Vec<AppInfo>,
+    ) -> ConductorHandle {
+        let mut admin_call = MockAdminCall::new();
+        admin_call.expect_list_apps().returning(move |_| {
+            let installed_apps = installed_apps.clone();
+            Box::pin(async move { Ok(installed_apps) })
+        });
+
+        ConductorHandle::new(
+            name,
+            AllowedAppIds::from_str(allowed_apps).unwrap(),
+            Arc::new(admin_call),
+            Arc::new(MockAppCall::new()),
+        )
+    }
+
+    #[tokio::test]
+    async fn selects_conductor_that_hosts_the_app() {
+        let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
+        let mut conductors = Conductors::default();
+        conductors.insert(conductor_with_apps(
+            "a",
+            "app_1",
+            vec![data::new_test_app_info("app_1", dna_hash.clone())],
+        ));
+        conductors.insert(conductor_with_apps(
+            "b",
+            "app_2",
+            vec![data::new_test_app_info("app_2", dna_hash.clone())],
+        ));
+
+        let found = try_get_valid_app_from_conductors(&dna_hash, "app_2", &conductors, false)
+            .await
+            .unwrap();
+
+        assert_eq!(found.len(), 1);
+        let (conductor, app_info) = &found[0];
+        assert_eq!(app_info.installed_app_id, "app_2");
+        assert!(conductor.allowed_app_ids.contains("app_2"));
+    }
+
+    #[tokio::test]
+    async fn reports_not_allowed_when_app_is_installed_on_a_conductor_that_does_not_allow_it() {
+        let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
+        let mut conductors = Conductors::default();
+        conductors.insert(conductor_with_apps("a", "", vec![]));
+        conductors.insert(conductor_with_apps(
+            "b",
+            "app_2",
+            vec![data::new_test_app_info("app_1", dna_hash.clone())],
+        ));
+
+        let result =
+            try_get_valid_app_from_conductors(&dna_hash, "app_1", &conductors, false).await;
+
+        assert_eq!(result.unwrap_err(), AppSelectionError::NotAllowed);
+    }
+
+    #[tokio::test]
+    async fn returns_error_if_app_is_on_multiple_conductors() {
+        let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
+        let mut conductors = Conductors::default();
+        for name in ["a", "b"] {
+            conductors.insert(conductor_with_apps(
+                name,
+                "app_1",
+                vec![data::new_test_app_info("app_1", dna_hash.clone())],
+            ));
+        }
+
+        let result =
+            try_get_valid_app_from_conductors(&dna_hash, "app_1", &conductors, false).await;
+
+        assert_eq!(result.unwrap_err(), AppSelectionError::MultipleMatching);
+    }
+
+    #[tokio::test]
+    async fn returns_healthy_conductors_first_with_failover() {
+        let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
+        let mut conductors = Conductors::default();
+        for name in ["a", "b", "c"] {
+            conductors.insert(conductor_with_apps(
+                name,
+                "app_1",
+                vec![data::new_test_app_info("app_1", dna_hash.clone())],
+            ));
+        }
+        conductors.get("a").unwrap().record_health(false);
+
+        let found = try_get_valid_app_from_conductors(&dna_hash, "app_1", &conductors, true)
+            .await
+            .unwrap();
+
+        let order = found
+            .iter()
+            .map(|(conductor, _)| conductor.health.is_healthy())
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![true, true, false]);
+        assert!(std::ptr::eq(found[0].0, conductors.get("b").unwrap()));
+    }
+
     #[tokio::test]
     async fn installed_apps_results_are_cached_and_reused() {
         let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
@@ -355,9 +792,10 @@ mod tests {
             .once();
 
         try_get_valid_app(
-            dna_hash.clone(),
-            "app_1".to_string(),
+            &dna_hash,
+            "app_1",
             installed_apps_cache.clone(),
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
@@ -370,13 +808,190 @@ mod tests {
 
         // This time the cache is used and so no new request is made.
         try_get_valid_app(
-            dna_hash,
-            "app_1".to_string(),
+            &dna_hash,
+            "app_1",
             installed_apps_cache,
+            &NotInstalledCache::default(),
             &allowed_apps,
             &admin_websocket,
         )
         .await
         .unwrap();
     }
+
+    #[tokio::test]
+    async fn not_installed_results_are_cached_until_they_expire() {
+        let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
+        let allowed_apps = AllowedAppIds::from_str("app_1").unwrap();
+        let installed_apps_cache: AppInfoCache = Default::default();
+        let not_installed = NotInstalledCache::new(Duration::from_millis(100));
+        let mut admin_websocket = MockAdminCall::new();
+        admin_websocket
+            .expect_list_apps()
+            .returning(|_| {
+                Box::pin(async {
+                    Ok(vec![data::new_test_app_info(
+                        "other_app",
+                        DnaHash::from_raw_32([2; 32].to_vec()),
+                    )])
+                })
+            })
+            .times(2);
+
+        for _ in 0..3 {
+            let result = try_get_valid_app(
+                &dna_hash,
+                "app_1",
+                installed_apps_cache.clone(),
+                &not_installed,
+                &allowed_apps,
+                &admin_websocket,
+            )
+            .await;
+            assert_eq!(result, Err(AppSelectionError::NotInstalled));
+        }
+
+        // Once the entry has expired, the apps are listed again
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let result = try_get_valid_app(
+            &dna_hash,
+            "app_1",
+            installed_apps_cache,
+            &not_installed,
+            &allowed_apps,
+            &admin_websocket,
+        )
+        .await;
+        assert_eq!(result, Err(AppSelectionError::NotInstalled));
+    }
+
+    #[test]
+    fn expired_not_installed_entries_are_pruned_on_insert() {
+        let not_installed = NotInstalledCache::new(Duration::from_millis(10));
+        not_installed.insert(DnaHash::from_raw_32([1; 32].to_vec()), "app_1".to_string());
+        std::thread::sleep(Duration::from_millis(20));
+
+        not_installed.insert(DnaHash::from_raw_32([2; 32].to_vec()), "app_2".to_string());
+
+        assert_eq!(not_installed.entries.lock().unwrap().lookups.len(), 1);
+        assert!(!not_installed.contains(&DnaHash::from_raw_32([1; 32].to_vec()), "app_1"));
+        assert!(not_installed.contains(&DnaHash::from_raw_32([2; 32].to_vec()), "app_2"));
+    }
+
+    #[test]
+    fn not_installed_entries_are_bounded() {
+        let not_installed = NotInstalledCache::new(Duration::from_secs(60));
+        for app in 0..=MAX_NOT_INSTALLED_ENTRIES {
+            not_installed.insert(DnaHash::from_raw_32([1; 32].to_vec()), format!("app_{app}"));
+        }
+
+        assert_eq!(
+            not_installed.entries.lock().unwrap().lookups.len(),
+            MAX_NOT_INSTALLED_ENTRIES
+        );
+        assert!(not_installed.contains(&DnaHash::from_raw_32([1; 32].to_vec()), "app_0"));
+        assert!(!not_installed.contains(
+            &DnaHash::from_raw_32([1; 32].to_vec()),
+            &format!("app_{MAX_NOT_INSTALLED_ENTRIES}")
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_cache_misses_list_apps_once() {
+        let dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
+        let allowed_apps = AllowedAppIds::from_str("app_1").unwrap();
+        let installed_apps_cache: AppInfoCache = Default::default();
+        let not_installed = NotInstalledCache::default();
+        let mut admin_websocket = MockAdminCall::new();
+        let new_installed_apps = vec![data::new_test_app_info("app_1", dna_hash.clone())];
+        admin_websocket
+            .expect_list_apps()
+            .returning(move |_| {
+                let new_installed_apps = new_installed_apps.clone();
+                Box::pin(async move {
+                    // Give the other lookups time to miss the cache.
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok(new_installed_apps)
+                })
+            })
+            .once();
+
+        let results = futures::future::join_all((0..10).map(|_| {
+            try_get_valid_app(
+                &dna_hash,
+                "app_1",
+                installed_apps_cache.clone(),
+                &not_installed,
+                &allowed_apps,
+                &admin_websocket,
+            )
+        }))
+        .await;
+
+        for result in results {
+            assert_eq!(result.unwrap().installed_app_id, "app_1");
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_hits_are_not_blocked_by_a_refresh() {
+        let cached_dna_hash = DnaHash::from_raw_32([1; 32].to_vec());
+        let allowed_apps = AllowedAppIds::from_str("app_1").unwrap();
+        let installed_apps_cache = Arc::new(InstalledApps::from(vec![data::new_test_app_info(
+            "app_1",
+            cached_dna_hash.clone(),
+        )]));
+        let not_installed = NotInstalledCache::default();
+        let mut admin_websocket = MockAdminCall::new();
+        admin_websocket
+            .expect_list_apps()
+            .returning(|_| {
+                Box::pin(async {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Ok(Vec::new())
+                })
+            })
+            .once();
+
+        // Lookups of two unknown apps share a single refresh
+        let unknown_dna_hashes = [2, 3].map(|byte| DnaHash::from_raw_32([byte; 32].to_vec()));
+        let refreshes = futures::future::join_all(unknown_dna_hashes.iter().map(|dna_hash| {
+            try_get_valid_app(
+                dna_hash,
+                "app_1",
+                installed_apps_cache.clone(),
+                &not_installed,
+                &allowed_apps,
+                &admin_websocket,
+            )
+        }));
+        let cache_hit = async {
+            // Let the refresh start first
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            tokio::time::timeout(
+                Duration::from_millis(100),
+                try_get_valid_app(
+                    &cached_dna_hash,
+                    "app_1",
+                    installed_apps_cache.clone(),
+                    &not_installed,
+                    &allowed_apps,
+                    &admin_websocket,
+                ),
+            )
+            .await
+        };
+        let (refreshes, cache_hit) = tokio::join!(refreshes, cache_hit);
+
+        assert_eq!(
+            cache_hit
+                .expect("The cache hit waited for the refresh")
+                .unwrap()
+                .installed_app_id,
+            "app_1"
+        );
+        for result in refreshes {
+            assert_eq!(result, Err(AppSelectionError::NotInstalled));
+        }
+    }
 }
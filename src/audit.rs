@@ -0,0 +1,173 @@
+//! Audit trail of the management actions that change the state of the gateway at runtime.
+//!
+//! Shared gateways are managed by more than one operator, so every request to a management
+//! endpoint that changes state is written to the audit log as a line of JSON, with who made it,
+//! what it did, when, and whether it succeeded. Requests that are rejected for lacking the
+//! management token are written as well.
+
+use crate::AccessLog;
+use crate::access_log::client_ip;
+use crate::request_id::current_request_id;
+use axum::extract::{OriginalUri, Request, State};
+use axum::http::{Method, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use holochain_types::prelude::Timestamp;
+use serde::Serialize;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Destination of the audit log.
+///
+/// Clones share the same destination.
+#[derive(Debug, Clone)]
+pub struct AuditLog(AccessLog);
+
+impl AuditLog {
+    /// Write the audit log to standard output.
+    pub fn stdout() -> Self {
+        Self(AccessLog::stdout())
+    }
+
+    /// Append the audit log to the file at the given path, creating it if it doesn't exist.
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        AccessLog::file(path).map(Self)
+    }
+
+    /// Write the audit log to the given writer.
+    pub fn from_writer(writer: impl Write + Send + 'static) -> Self {
+        Self(AccessLog::from_writer(writer))
+    }
+}
+
+/// An audit log line.
+#[derive(Debug, Serialize)]
+struct AuditLogEntry<'a> {
+    /// When the action was requested, as an RFC 3339 timestamp.
+    timestamp: String,
+    /// The address of the client that requested the action.
+    client_ip: Option<IpAddr>,
+    /// The user agent of the client, to tell operators' tools apart.
+    user_agent: Option<&'a str>,
+    /// The action, as the method and the path of the request, for example
+    /// `POST /management/app-ports/clear`.
+    action: String,
+    status: u16,
+    request_id: Option<String>,
+}
+
+/// Middleware that writes an audit log line for every management request that changes state.
+///
+/// Requests that only read state, with method `GET`, are not audited.
+pub(crate) async fn audit_management(
+    State(audit_log): State<AuditLog>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() == Method::GET {
+        return next.run(request).await;
+    }
+
+    let timestamp = Timestamp::now();
+    let client_ip = client_ip(&request);
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    // The management routes are nested, so the path of the request has lost their prefix
+    let uri = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(request.uri(), |original_uri| &original_uri.0);
+    let action = format!("{} {}", request.method(), uri.path());
+
+    let response = next.run(request).await;
+
+    audit_log.0.write(&AuditLogEntry {
+        timestamp: timestamp.to_string(),
+        client_ip,
+        user_agent: user_agent.as_deref(),
+        action,
+        status: response.status().as_u16(),
+        request_id: current_request_id(),
+    });
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ManagementToken;
+    use crate::test::router::TestRouter;
+    use crate::{Conductors, Configuration, MockAdminCall, MockAppCall};
+    use axum::body::Body;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn management_actions_are_audited() {
+        let path =
+            std::env::temp_dir().join(format!("hc-http-gw-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "",
+            HashMap::new(),
+            "",
+            "",
+        )
+        .unwrap();
+        config.management_token = Some(ManagementToken::new("secret"));
+        config.audit_log = Some(AuditLog::file(&path).unwrap());
+        let mut app_call = MockAppCall::new();
+        app_call.expect_clear_cached_app_ports().return_const(());
+        app_call.expect_app_connection_stats().returning(Vec::new);
+        let conductors =
+            Conductors::single(&config, Arc::new(MockAdminCall::new()), Arc::new(app_call));
+        let router = TestRouter::new_with_config_and_conductors(config, conductors);
+
+        for token in ["secret", "wrong"] {
+            let response = (*router)
+                .clone()
+                .oneshot(
+                    axum::http::Request::builder()
+                        .method("POST")
+                        .uri("/management/app-ports/clear")
+                        .header("authorization", format!("Bearer {token}"))
+                        .header("user-agent", "ops-script")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert!(response.status().is_success() || response.status() == 401);
+        }
+        // Reading state is not audited
+        let (status_code, _) = router
+            .request_with_headers(
+                "/management/app-connections",
+                &[("authorization", "Bearer secret")],
+            )
+            .await;
+        assert_eq!(status_code, 200);
+
+        let lines = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["action"], "POST /management/app-ports/clear");
+        assert_eq!(lines[0]["status"], 204);
+        assert_eq!(lines[0]["user_agent"], "ops-script");
+        assert!(lines[0]["timestamp"].is_string());
+        assert_eq!(lines[1]["status"], 401);
+    }
+}
@@ -1,87 +1,885 @@
 use anyhow::Context;
 use clap::Parser;
+#[cfg(feature = "alert-webhook")]
+use holochain_http_gateway::AlertWebhook;
+#[cfg(feature = "scripting")]
+use holochain_http_gateway::ScriptTransform;
 use holochain_http_gateway::{
-    AdminConn, AllowedAppIds, AllowedFns, AppConnPool, Configuration, HcHttpGatewayService,
+    ACCESS_LOG_KEY, ADDRESS_KEY, ADMIN_POOL_SIZE_KEY, ADMIN_WS_URL_KEY, ALERT_WEBHOOK_URL_KEY,
+    ALLOWED_APP_IDS_KEY, ALLOWED_FNS_KEY_PREFIX, APP_CALL_QUEUE_TIMEOUT_MS_KEY,
+    APP_IDLE_TIMEOUT_MS_KEY, APP_INTERFACES_CACHE_TTL_MS_KEY, APP_KEEPALIVE_INTERVAL_MS_KEY,
+    APP_MAX_AGE_MS_KEY, APP_MAX_IN_FLIGHT_KEY, APP_MAX_REQUESTS_KEY, APP_WATCH_INTERVAL_MS_KEY,
+    AUDIT_LOG_KEY, AccessLog, AllowedAppIds, AuditLog, BINARY_ENCODING_KEY, CACHE_MAX_STALE_MS_KEY,
+    CAMEL_CASE_KEYS_KEY, CONDUCTOR_FAILOVER_KEY, ConductorConfig, Conductors, ConfigLayer,
+    Configuration, DEDICATED_APP_INTERFACES_KEY, DEFAULT_STATSD_PREFIX, DEGRADED_ERROR_RATE_KEY,
+    DEGRADED_FAILED_CONNECTS_KEY, DRAIN_TIMEOUT_MS_KEY, EAGER_SIGNING_AUTHORIZATION_KEY,
+    ENUM_FIELDS_KEY, ENUM_TAG_KEY, ERROR_FORMAT_KEY, HEALTH_CHECK_INTERVAL_MS_KEY,
+    HcHttpGatewayService, LAIR_CONNECTION_URL_KEY, LAIR_PASSPHRASE_FILE_KEY,
+    LARGE_INTEGERS_AS_STRINGS_KEY, LATENCY_SHED_FRACTION_KEY, LATENCY_SHED_THRESHOLD_MS_KEY,
+    LairSigningConfig, LayeredConfig, MANAGEMENT_TOKEN_KEY, MAX_APP_CONCURRENT_CALLS_KEY,
+    MAX_APP_CONNECTIONS_KEY, MAX_IN_FLIGHT_REQUESTS_KEY, MAX_QUEUED_REQUESTS_KEY,
+    METRICS_MAX_FUNCTIONS_KEY, ManagementToken, NOT_INSTALLED_CACHE_TTL_MS_KEY,
+    PAYLOAD_LIMIT_BYTES_KEY, PAYLOAD_MAX_DEPTH_KEY, PAYLOAD_MAX_OBJECT_KEYS_KEY,
+    PAYLOAD_MAX_STRING_BYTES_KEY, PORT_KEY, QUEUE_TIMEOUT_MS_KEY, RECORD_FILE_KEY, REPLAY_FILE_KEY,
+    REQUEST_ID_HEADER_KEY, RESPONSE_CACHE_MAX_ENTRIES_KEY, RESPONSE_CACHE_REDIS_URL_KEY,
+    RESPONSE_SCHEMAS_FILE_KEY, RESPONSE_VALIDATION_KEY, REUSE_PORT_KEY, Recorder, Recording,
+    ResponseSchemas, SELF_CHECK_INTERVAL_MS_KEY, SIGNING_MODE_KEY, SLOW_REQUEST_THRESHOLD_MS_KEY,
+    STATSD_ADDRESS_KEY, STATSD_FLAVOR_KEY, STATSD_INTERVAL_MS_KEY, STATSD_PREFIX_KEY, SigningMode,
+    StatsdExporter, TIMESTAMP_FIELDS_KEY, UNHEALTHY_ERROR_RATE_KEY, USAGE_EXPORT_FILE_KEY,
+    USAGE_EXPORT_FORMAT_KEY, USAGE_EXPORT_INTERVAL_MS_KEY, USAGE_SUMMARY_INTERVAL_MS_KEY,
+    ZOME_CALL_TIMEOUT_MS_KEY, bind_listener, conductor_key, inherited_listener, key_to_env_var,
     resolve_address_from_url,
 };
-use std::net::IpAddr;
-use std::sync::Arc;
-use std::{collections::HashMap, env, str::FromStr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fs};
 use tracing_subscriber::{
     EnvFilter, Registry,
     fmt::{self, format::FmtSpan, time::UtcTime},
     layer::SubscriberExt,
 };
 
+#[cfg(windows)]
+mod service;
+
 const DEFAULT_LOG_LEVEL: &str = "info";
 
-/// Command line arguments and environment variables for configuring the Gateway Service
+/// Environment variable that marks the background process started for `--daemon`.
+const DAEMONIZED_ENV: &str = "HC_GW_DAEMONIZED";
+
+/// Command line arguments for configuring the Gateway Service
+///
+/// Every setting can also be provided by an environment variable or the configuration file.
+/// Flags take precedence over environment variables, which take precedence over the file.
 #[derive(clap::Parser, Debug)]
 pub struct HcHttpGatewayArgs {
+    /// Path to a TOML configuration file
+    #[arg(short, long, env = "HC_GW_CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Name of the profile to select from the configuration file
+    #[arg(long, env = "HC_GW_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Run the gateway in the background, detached from the terminal
+    ///
+    /// Standard output and standard error are kept, so that the logs can be redirected to a file.
+    #[arg(long, env = "HC_GW_DAEMON")]
+    pub daemon: bool,
+
+    /// File to write the id of the gateway process to, which is removed when the gateway exits
+    #[arg(long, env = "HC_GW_PID_FILE")]
+    pub pid_file: Option<PathBuf>,
+
     /// The address to use
-    #[arg(short, long, env = "HC_GW_ADDRESS", default_value = "127.0.0.1")]
-    pub address: IpAddr,
+    #[arg(short, long)]
+    pub address: Option<IpAddr>,
 
     /// The port to bind to
-    #[arg(short, long, env = "HC_GW_PORT", default_value = "8090")]
-    pub port: u16,
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// The websocket URL for Holochain's admin interface
+    #[arg(long)]
+    pub admin_ws_url: Option<String>,
+
+    /// The maximum size for payloads, in bytes
+    #[arg(long)]
+    pub payload_limit_bytes: Option<u32>,
+
+    /// Comma separated list of installed app ids that the gateway is allowed to access
+    #[arg(long)]
+    pub allowed_app_ids: Option<String>,
+
+    /// Allowed functions for an app, e.g. `my_app=main/list_mews,main/count_likes` or `my_app=*`
+    #[arg(long, value_name = "APP_ID=FNS")]
+    pub allowed_fns: Vec<String>,
+
+    /// The admin websocket URL of an additional conductor, e.g. `b=ws://localhost:8889`
+    #[arg(long, value_name = "NAME=URL")]
+    pub conductor: Vec<String>,
+
+    /// Comma separated list of allowed app ids on an additional conductor, e.g. `b=app1,app2`
+    #[arg(long, value_name = "NAME=APP_IDS")]
+    pub conductor_allowed_app_ids: Vec<String>,
+
+    /// The maximum number of app websocket connections that the gateway will maintain
+    #[arg(long)]
+    pub max_app_connections: Option<u32>,
+
+    /// Timeout in milliseconds for zome calls
+    #[arg(long)]
+    pub zome_call_timeout_ms: Option<u64>,
+
+    /// Set any setting by its key, e.g. `conductor_failover=true`. Takes precedence over the
+    /// dedicated flags.
+    #[arg(short, long, value_name = "KEY=VALUE")]
+    pub set: Vec<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands of the gateway binary
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Print the effective configuration and where each value came from, then exit
+    PrintConfig,
+    /// Register the gateway as a Windows service, which is run with the arguments that are given
+    /// before this subcommand
+    #[cfg(windows)]
+    InstallService {
+        /// Name of the service
+        #[arg(long, default_value = service::DEFAULT_SERVICE_NAME)]
+        name: String,
+    },
+    /// Remove the Windows service of the gateway
+    #[cfg(windows)]
+    UninstallService {
+        /// Name of the service
+        #[arg(long, default_value = service::DEFAULT_SERVICE_NAME)]
+        name: String,
+    },
+    /// Run the gateway as a Windows service, which is how the service manager starts it
+    #[cfg(windows)]
+    #[command(hide = true)]
+    RunService {
+        /// Name of the service
+        #[arg(long, default_value = service::DEFAULT_SERVICE_NAME)]
+        name: String,
+    },
+}
+
+impl HcHttpGatewayArgs {
+    fn to_config_layer(&self) -> anyhow::Result<ConfigLayer> {
+        let mut layer = ConfigLayer::default();
+
+        if let Some(address) = self.address {
+            layer.set(ADDRESS_KEY, address.to_string());
+        }
+        if let Some(port) = self.port {
+            layer.set(PORT_KEY, port.to_string());
+        }
+        if let Some(admin_ws_url) = &self.admin_ws_url {
+            layer.set(ADMIN_WS_URL_KEY, admin_ws_url);
+        }
+        if let Some(payload_limit_bytes) = self.payload_limit_bytes {
+            layer.set(PAYLOAD_LIMIT_BYTES_KEY, payload_limit_bytes.to_string());
+        }
+        if let Some(allowed_app_ids) = &self.allowed_app_ids {
+            layer.set(ALLOWED_APP_IDS_KEY, allowed_app_ids);
+        }
+        for allowed_fns in &self.allowed_fns {
+            let (app_id, fns) = allowed_fns.split_once('=').context(format!(
+                "Expected APP_ID=FNS for --allowed-fns, got {allowed_fns}"
+            ))?;
+            layer.set(format!("{ALLOWED_FNS_KEY_PREFIX}{app_id}"), fns);
+        }
+        for conductor in &self.conductor {
+            let (name, admin_ws_url) = conductor.split_once('=').context(format!(
+                "Expected NAME=URL for --conductor, got {conductor}"
+            ))?;
+            layer.set(conductor_key(name, ADMIN_WS_URL_KEY), admin_ws_url);
+        }
+        for allowed_app_ids in &self.conductor_allowed_app_ids {
+            let (name, app_ids) = allowed_app_ids.split_once('=').context(format!(
+                "Expected NAME=APP_IDS for --conductor-allowed-app-ids, got {allowed_app_ids}"
+            ))?;
+            layer.set(conductor_key(name, ALLOWED_APP_IDS_KEY), app_ids);
+        }
+        if let Some(max_app_connections) = self.max_app_connections {
+            layer.set(MAX_APP_CONNECTIONS_KEY, max_app_connections.to_string());
+        }
+        if let Some(zome_call_timeout_ms) = self.zome_call_timeout_ms {
+            layer.set(ZOME_CALL_TIMEOUT_MS_KEY, zome_call_timeout_ms.to_string());
+        }
+
+        for setting in &self.set {
+            let (key, value) = setting
+                .split_once('=')
+                .context(format!("Expected KEY=VALUE for --set, got {setting}"))?;
+            layer.set(key, value);
+        }
+
+        Ok(layer)
+    }
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
+    let args = HcHttpGatewayArgs::parse();
+
+    match try_main(args).await {
+        Ok(Exit::Normal) => ExitCode::SUCCESS,
+        Ok(Exit::Interrupted) => ExitCode::from(EXIT_INTERRUPTED),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+async fn try_main(args: HcHttpGatewayArgs) -> anyhow::Result<Exit> {
+    #[cfg(windows)]
+    match args.command.clone() {
+        Some(Command::InstallService { name }) => {
+            return service::install(&name).map(|()| Exit::Normal);
+        }
+        Some(Command::UninstallService { name }) => {
+            return service::uninstall(&name).map(|()| Exit::Normal);
+        }
+        Some(Command::RunService { name }) => {
+            return service::run(name, args).map(|()| Exit::Normal);
+        }
+        _ => {}
+    }
+
+    let (graceful, immediate) = stop_signals().context("Failed to listen for stop signals")?;
+    run(args, graceful, immediate).await
+}
+
+/// How the gateway exited, when it didn't fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    /// The gateway was stopped gracefully, or had nothing to run.
+    Normal,
+    /// The gateway was stopped immediately, without waiting for requests in flight.
+    Interrupted,
+}
+
+/// Exit status when the gateway fails while running.
+const EXIT_RUNTIME_ERROR: u8 = 1;
+
+/// Exit status when the configuration is invalid, which is assumed of any failure that isn't
+/// marked otherwise.
+const EXIT_CONFIG_ERROR: u8 = 2;
+
+/// Exit status when the gateway can't listen on its address.
+const EXIT_BIND_ERROR: u8 = 3;
+
+/// Exit status when the admin websocket of a conductor can't be resolved.
+const EXIT_UPSTREAM_ERROR: u8 = 4;
+
+/// Exit status when the gateway is stopped immediately by `SIGINT`, as shells report it.
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Marks the step that a failure happened in, which decides the exit status.
+#[derive(Debug, thiserror::Error)]
+enum Failure {
+    #[error("Failed to listen for connections")]
+    Bind,
+    #[error("Failed to reach the conductor")]
+    Upstream,
+    #[error("The gateway failed while running")]
+    Run,
+}
+
+/// The exit status for a failure.
+fn exit_code(error: &anyhow::Error) -> u8 {
+    match error.downcast_ref::<Failure>() {
+        Some(Failure::Bind) => EXIT_BIND_ERROR,
+        Some(Failure::Upstream) => EXIT_UPSTREAM_ERROR,
+        Some(Failure::Run) => EXIT_RUNTIME_ERROR,
+        None => EXIT_CONFIG_ERROR,
+    }
+}
+
+/// Run the gateway until it is stopped.
+///
+/// Once `graceful` resolves, the gateway stops accepting connections and waits for the requests in
+/// flight. Once `immediate` resolves, it stops without waiting for them.
+async fn run(
+    args: HcHttpGatewayArgs,
+    graceful: impl Future<Output = ()> + Send + 'static,
+    immediate: impl Future<Output = ()>,
+) -> anyhow::Result<Exit> {
+    let layered_config = load_layered_config(&args)?;
+
+    if let Some(Command::PrintConfig) = args.command {
+        if let Some(profile) = &args.profile {
+            println!("# profile: {profile}");
+        }
+        for (key, value, source) in layered_config.iter() {
+            println!("{key} = {value:?} ({source})");
+        }
+        return Ok(Exit::Normal);
+    }
+
     initialize_tracing_subscriber()?;
 
-    let configuration = load_config_from_env().await?;
+    let configuration = load_config(&layered_config).await?;
 
-    let args = HcHttpGatewayArgs::parse();
+    // The configuration is checked first, so that mistakes are reported on the terminal
+    if args.daemon && env::var_os(DAEMONIZED_ENV).is_none() {
+        return daemonize().map(|()| Exit::Normal);
+    }
+
+    let address = layered_config
+        .get(ADDRESS_KEY)
+        .unwrap_or_default()
+        .parse::<IpAddr>()
+        .context("Invalid address")?;
+    let port = layered_config
+        .get(PORT_KEY)
+        .unwrap_or_default()
+        .parse::<u16>()
+        .context("Invalid port")?;
+
+    let reuse_port = layered_config
+        .get_parsed::<bool>(REUSE_PORT_KEY)?
+        .unwrap_or_default();
 
-    let admin_call = Arc::new(AdminConn::new(configuration.admin_socket_addr));
-    let app_call = Arc::new(AppConnPool::new(configuration.clone(), admin_call.clone()));
+    // A listener passed by the previous gateway process or a process manager takes precedence
+    let listener = match inherited_listener()
+        .context("Failed to inherit the listener")
+        .context(Failure::Bind)?
+    {
+        Some(listener) => listener,
+        None => bind_listener((address, port).into(), reuse_port)
+            .context(format!("Failed to bind to {address}:{port}"))
+            .context(Failure::Bind)?,
+    };
 
-    let service =
-        HcHttpGatewayService::new(args.address, args.port, configuration, admin_call, app_call)
-            .await?;
+    // Written once the gateway is listening, and removed when it exits
+    let _pid_file = args.pid_file.map(PidFile::create).transpose()?;
 
-    service.run().await?;
+    let conductors = match layered_config.get(REPLAY_FILE_KEY) {
+        Some(replay_file) => {
+            let recording = Recording::file(replay_file)
+                .context(format!("Failed to read the recording {replay_file}"))?;
+            tracing::info!(
+                replay_file,
+                "Replaying recorded zome calls instead of connecting to the conductors"
+            );
+            Conductors::replayed(&configuration, &recording)
+        }
+        None => Conductors::from_configuration(&configuration),
+    };
+    let conductors = match layered_config.get(RECORD_FILE_KEY) {
+        Some(record_file) => conductors.recorded(
+            &Recorder::file(record_file)
+                .context(format!("Failed to open the recording {record_file}"))?,
+        ),
+        None => conductors,
+    };
+
+    let service = HcHttpGatewayService::with_listener(listener, configuration, conductors);
+    let transform_scripts = layered_config.transform_scripts();
+    #[cfg(feature = "scripting")]
+    let service = transform_scripts.into_iter().try_fold(
+        service,
+        |service, (app_id, script_file)| -> anyhow::Result<_> {
+            let script = fs::read_to_string(&script_file).context(format!(
+                "Failed to read the transform script {}",
+                script_file.display()
+            ))?;
+            Ok(service.with_transform(ScriptTransform::new(app_id, &script)?))
+        },
+    )?;
+    #[cfg(not(feature = "scripting"))]
+    if !transform_scripts.is_empty() {
+        anyhow::bail!(
+            "Transform scripts require the gateway to be built with the scripting feature"
+        );
+    }
+
+    // Start draining when asked to, ahead of the gateway being replaced in a rolling deploy
+    #[cfg(unix)]
+    {
+        let drain = service.drain_handle();
+        let mut drain_signal =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())
+                .context("Failed to listen for the drain signal")?;
+        tokio::spawn(async move {
+            if drain_signal.recv().await.is_some() {
+                drain.drain();
+            }
+        });
+    }
+
+    // Shut down gracefully when terminated or stopped by the service manager
+    let shutdown = service.shutdown_handle();
+    tokio::spawn(async move {
+        graceful.await;
+        shutdown.shutdown();
+    });
+
+    // Stop immediately when interrupted, dropping the connections with requests in flight
+    tokio::select! {
+        result = service.run() => {
+            result.context(Failure::Run)?;
+            Ok(Exit::Normal)
+        }
+        () = immediate => {
+            tracing::warn!("Interrupted, stopping without waiting for requests in flight");
+            Ok(Exit::Interrupted)
+        }
+    }
+}
+
+/// Start the gateway again as a background process with the same arguments, and exit.
+///
+/// The background process is put in a process group of its own, so that signals sent to the
+/// foreground processes of the terminal don't reach it. It is stopped like any other gateway, by
+/// sending `SIGTERM` to it, which shuts it down gracefully.
+#[cfg(unix)]
+fn daemonize() -> anyhow::Result<()> {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let executable = env::current_exe().context("Failed to find the gateway executable")?;
+    let child = Command::new(executable)
+        .args(env::args_os().skip(1))
+        .env(DAEMONIZED_ENV, "1")
+        .stdin(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .context("Failed to start the gateway in the background")?;
+    tracing::info!(pid = child.id(), "Started the gateway in the background");
 
     Ok(())
 }
 
-async fn load_config_from_env() -> anyhow::Result<Configuration> {
-    let admin_ws_url = env::var("HC_GW_ADMIN_WS_URL").context("HC_GW_ADMIN_WS_URL is not set")?;
-    let admin_socket_addr = resolve_address_from_url(&admin_ws_url)
-        .await
-        .context("Failed to extract socket address from the admin websocket URL")?;
-    tracing::info!("Resolved admin socket address: {}", admin_socket_addr);
+/// Running in the background is only supported on Unix.
+#[cfg(not(unix))]
+fn daemonize() -> anyhow::Result<()> {
+    anyhow::bail!("Running the gateway in the background is only supported on Unix")
+}
 
-    let payload_limit_bytes = env::var("HC_GW_PAYLOAD_LIMIT_BYTES").unwrap_or_default();
+/// A file holding the id of the gateway process, which is removed when it is dropped.
+struct PidFile(PathBuf);
 
-    let allowed_app_ids = env::var("HC_GW_ALLOWED_APP_IDS").unwrap_or_default();
+impl PidFile {
+    fn create(path: PathBuf) -> anyhow::Result<Self> {
+        fs::write(&path, format!("{}\n", std::process::id()))
+            .context(format!("Failed to write the PID file {}", path.display()))?;
 
-    let mut allowed_fns = HashMap::new();
+        Ok(Self(path))
+    }
+}
 
-    let app_ids = AllowedAppIds::from_str(&allowed_app_ids)?;
-    for app_id in app_ids.iter() {
-        let fns = env::var(format!("HC_GW_ALLOWED_FNS_{app_id}"))
-            .context(format!("Missing HC_GW_ALLOWED_FNS_{app_id} env var"))?;
-        let fns = AllowedFns::from_str(&fns)?;
-        allowed_fns.insert(app_id.to_owned(), fns);
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            tracing::warn!(?e, path = ?self.0, "Failed to remove the PID file");
+        }
     }
+}
 
-    let max_app_connections = env::var("HC_GW_MAX_APP_CONNECTIONS").unwrap_or_default();
+/// Listen for the signals that stop the gateway, as a pair of futures that resolve when the gateway
+/// should stop gracefully and immediately respectively.
+///
+/// On Unix, `SIGTERM` stops the gateway gracefully and `SIGINT` stops it immediately. Elsewhere,
+/// Ctrl-C stops it gracefully.
+fn stop_signals() -> std::io::Result<(
+    impl Future<Output = ()> + Send + 'static,
+    impl Future<Output = ()>,
+)> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
 
-    let zome_call_timeout = env::var("HC_GW_ZOME_CALL_TIMEOUT_MS").unwrap_or_default();
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        Ok((
+            async move {
+                terminate.recv().await;
+            },
+            async move {
+                interrupt.recv().await;
+            },
+        ))
+    }
+    #[cfg(not(unix))]
+    Ok((
+        async {
+            let _ = tokio::signal::ctrl_c().await;
+        },
+        std::future::pending(),
+    ))
+}
 
-    let config = Configuration::try_new(
+fn load_layered_config(args: &HcHttpGatewayArgs) -> anyhow::Result<LayeredConfig> {
+    let file = match (&args.config, &args.profile) {
+        (Some(path), profile) => {
+            let contents = fs::read_to_string(path).context(format!(
+                "Failed to read configuration file {}",
+                path.display()
+            ))?;
+            ConfigLayer::from_toml_str(&contents, profile.as_deref())?
+        }
+        (None, Some(profile)) => {
+            anyhow::bail!("Profile {profile} was selected but no configuration file was given");
+        }
+        (None, None) => ConfigLayer::default(),
+    };
+
+    let env = ConfigLayer::from_env_vars(env::vars());
+
+    let flags = args.to_config_layer()?;
+
+    Ok(LayeredConfig::new(file, env, flags))
+}
+
+async fn load_config(layered_config: &LayeredConfig) -> anyhow::Result<Configuration> {
+    let admin_ws_url = layered_config.get(ADMIN_WS_URL_KEY);
+    let admin_socket_addr = match admin_ws_url {
+        Some(admin_ws_url) => {
+            let admin_socket_addr = resolve_address_from_url(admin_ws_url)
+                .await
+                .context("Failed to extract socket address from the admin websocket URL")
+                .context(Failure::Upstream)?;
+            tracing::info!("Resolved admin socket address: {}", admin_socket_addr);
+            admin_socket_addr
+        }
+        // The conductor isn't connected to when zome calls are replayed from a recording
+        None if layered_config.get(REPLAY_FILE_KEY).is_some() => {
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0))
+        }
+        None => anyhow::bail!(
+            "The admin websocket URL is not set, use HC_GW_ADMIN_WS_URL or --admin-ws-url"
+        ),
+    };
+
+    let mut config = Configuration::try_new(
         admin_socket_addr,
-        &payload_limit_bytes,
-        &allowed_app_ids,
-        allowed_fns,
-        &max_app_connections,
-        &zome_call_timeout,
+        layered_config
+            .get(PAYLOAD_LIMIT_BYTES_KEY)
+            .unwrap_or_default(),
+        layered_config.get(ALLOWED_APP_IDS_KEY).unwrap_or_default(),
+        layered_config.allowed_fns()?,
+        layered_config
+            .get(MAX_APP_CONNECTIONS_KEY)
+            .unwrap_or_default(),
+        layered_config
+            .get(ZOME_CALL_TIMEOUT_MS_KEY)
+            .unwrap_or_default(),
     )?;
+    config.admin_ws_url = admin_ws_url.map(str::to_string);
+
+    if let Some(conductor_failover) = layered_config.get_parsed(CONDUCTOR_FAILOVER_KEY)? {
+        config.conductor_failover = conductor_failover;
+    }
+    if let Some(health_check_interval_ms) =
+        layered_config.get_parsed(HEALTH_CHECK_INTERVAL_MS_KEY)?
+    {
+        config.health_check_interval = Duration::from_millis(health_check_interval_ms);
+    }
+
+    if let Some(admin_pool_size) = layered_config.get_parsed(ADMIN_POOL_SIZE_KEY)? {
+        config.admin_pool_size = admin_pool_size;
+    }
+    if let Some(app_keepalive_interval_ms) =
+        layered_config.get_parsed(APP_KEEPALIVE_INTERVAL_MS_KEY)?
+    {
+        config.app_keepalive_interval = Duration::from_millis(app_keepalive_interval_ms);
+    }
+    if let Some(app_idle_timeout_ms) = layered_config.get_parsed(APP_IDLE_TIMEOUT_MS_KEY)? {
+        config.app_idle_timeout = Some(Duration::from_millis(app_idle_timeout_ms));
+    }
+    if let Some(app_max_age_ms) = layered_config.get_parsed(APP_MAX_AGE_MS_KEY)? {
+        config.app_max_age = Some(Duration::from_millis(app_max_age_ms));
+    }
+    if let Some(app_max_requests) = layered_config.get_parsed(APP_MAX_REQUESTS_KEY)? {
+        config.app_max_requests = Some(app_max_requests);
+    }
+    if let Some(app_max_in_flight) = layered_config.get_parsed(APP_MAX_IN_FLIGHT_KEY)? {
+        config.app_max_in_flight = Some(app_max_in_flight);
+    }
+    if let Some(eager_signing_authorization) =
+        layered_config.get_parsed(EAGER_SIGNING_AUTHORIZATION_KEY)?
+    {
+        config.eager_signing_authorization = eager_signing_authorization;
+    }
+    if let Some(dedicated_app_interfaces) =
+        layered_config.get_parsed(DEDICATED_APP_INTERFACES_KEY)?
+    {
+        config.dedicated_app_interfaces = dedicated_app_interfaces;
+    }
+    if let Some(app_watch_interval_ms) = layered_config.get_parsed(APP_WATCH_INTERVAL_MS_KEY)? {
+        config.app_watch_interval = Duration::from_millis(app_watch_interval_ms);
+    }
+    if let Some(not_installed_cache_ttl_ms) =
+        layered_config.get_parsed(NOT_INSTALLED_CACHE_TTL_MS_KEY)?
+    {
+        config.not_installed_cache_ttl = Duration::from_millis(not_installed_cache_ttl_ms);
+    }
+    if let Some(app_interfaces_cache_ttl_ms) =
+        layered_config.get_parsed(APP_INTERFACES_CACHE_TTL_MS_KEY)?
+    {
+        config.app_interfaces_cache_ttl = Duration::from_millis(app_interfaces_cache_ttl_ms);
+    }
+    match layered_config.get(SIGNING_MODE_KEY).unwrap_or_default() {
+        "credentials" => config.signing_mode = SigningMode::Credentials,
+        "lair" => {
+            if !cfg!(feature = "lair-signing") {
+                anyhow::bail!(
+                    "Signing with a Lair keystore requires the gateway to be built with the lair-signing feature"
+                );
+            }
+
+            let connection_url = layered_config
+                .get(LAIR_CONNECTION_URL_KEY)
+                .context("The Lair connection URL is not set, use HC_GW_LAIR_CONNECTION_URL")?;
+            let passphrase_file = layered_config
+                .get(LAIR_PASSPHRASE_FILE_KEY)
+                .context("The Lair passphrase file is not set, use HC_GW_LAIR_PASSPHRASE_FILE")?;
+            config.signing_mode = SigningMode::Lair(LairSigningConfig {
+                connection_url: connection_url
+                    .parse()
+                    .context("Invalid Lair connection URL")?,
+                passphrase_file: PathBuf::from(passphrase_file),
+            });
+        }
+        signing_mode => anyhow::bail!(
+            "Invalid signing mode {signing_mode:?}, expected \"credentials\" or \"lair\""
+        ),
+    }
+
+    if let Some(max_app_concurrent_calls) =
+        layered_config.get_parsed(MAX_APP_CONCURRENT_CALLS_KEY)?
+    {
+        config.max_app_concurrent_calls = Some(max_app_concurrent_calls);
+    }
+    if let Some(app_call_queue_timeout_ms) =
+        layered_config.get_parsed(APP_CALL_QUEUE_TIMEOUT_MS_KEY)?
+    {
+        config.app_call_queue_timeout = Duration::from_millis(app_call_queue_timeout_ms);
+    }
+    if let Some(max_in_flight_requests) = layered_config.get_parsed(MAX_IN_FLIGHT_REQUESTS_KEY)? {
+        config.max_in_flight_requests = Some(max_in_flight_requests);
+    }
+    if let Some(max_queued_requests) = layered_config.get_parsed(MAX_QUEUED_REQUESTS_KEY)? {
+        config.max_queued_requests = max_queued_requests;
+    }
+    if let Some(queue_timeout_ms) = layered_config.get_parsed(QUEUE_TIMEOUT_MS_KEY)? {
+        config.queue_timeout = Duration::from_millis(queue_timeout_ms);
+    }
+    if let Some(latency_shed_threshold_ms) =
+        layered_config.get_parsed(LATENCY_SHED_THRESHOLD_MS_KEY)?
+    {
+        config.latency_shed_threshold = Some(Duration::from_millis(latency_shed_threshold_ms));
+    }
+    if let Some(latency_shed_fraction) = layered_config.get_parsed(LATENCY_SHED_FRACTION_KEY)? {
+        if !(0.0..=1.0).contains(&latency_shed_fraction) {
+            anyhow::bail!(
+                "{} must be between 0 and 1",
+                key_to_env_var(LATENCY_SHED_FRACTION_KEY)
+            );
+        }
+        config.latency_shed_fraction = latency_shed_fraction;
+    }
+    config.app_priorities = layered_config.app_priorities()?;
+    if let Some(drain_timeout_ms) = layered_config.get_parsed(DRAIN_TIMEOUT_MS_KEY)? {
+        config.drain_timeout = Duration::from_millis(drain_timeout_ms);
+    }
+    if let Some(management_token) = layered_config.get(MANAGEMENT_TOKEN_KEY) {
+        if management_token.trim().is_empty() {
+            anyhow::bail!("The management token must not be empty, unset it to disable management");
+        }
+        config.management_token = Some(ManagementToken::new(management_token));
+    }
+    if let Some(metrics_max_functions) = layered_config.get_parsed(METRICS_MAX_FUNCTIONS_KEY)? {
+        config.metrics_max_functions = metrics_max_functions;
+    }
+    if let Some(request_id_header) = layered_config.get_parsed(REQUEST_ID_HEADER_KEY)? {
+        config.request_id_header = request_id_header;
+    }
+    if let Some(error_format) = layered_config.get_parsed(ERROR_FORMAT_KEY)? {
+        config.error_format = error_format;
+    }
+    if let Some(access_log) = layered_config.get(ACCESS_LOG_KEY) {
+        config.access_log = Some(if access_log == "stdout" {
+            AccessLog::stdout()
+        } else {
+            AccessLog::file(access_log)
+                .context(format!("Failed to open the access log file {access_log}"))?
+        });
+    }
+    if let Some(audit_log) = layered_config.get(AUDIT_LOG_KEY) {
+        config.audit_log = Some(if audit_log == "stdout" {
+            AuditLog::stdout()
+        } else {
+            AuditLog::file(audit_log)
+                .context(format!("Failed to open the audit log file {audit_log}"))?
+        });
+    }
+    if let Some(slow_request_threshold_ms) =
+        layered_config.get_parsed(SLOW_REQUEST_THRESHOLD_MS_KEY)?
+    {
+        config.slow_request_threshold = Some(Duration::from_millis(slow_request_threshold_ms));
+    }
+    if let Some(statsd_address) = layered_config.get(STATSD_ADDRESS_KEY) {
+        let prefix = layered_config
+            .get(STATSD_PREFIX_KEY)
+            .unwrap_or(DEFAULT_STATSD_PREFIX);
+        let flavor = layered_config
+            .get_parsed(STATSD_FLAVOR_KEY)?
+            .unwrap_or_default();
+        config.statsd = Some(
+            StatsdExporter::new(statsd_address, prefix, flavor)
+                .context(format!("Failed to set up StatsD at {statsd_address}"))?,
+        );
+    }
+    if let Some(statsd_interval_ms) = layered_config.get_parsed(STATSD_INTERVAL_MS_KEY)? {
+        config.statsd_interval = Duration::from_millis(statsd_interval_ms);
+    }
+    if let Some(usage_summary_interval_ms) =
+        layered_config.get_parsed(USAGE_SUMMARY_INTERVAL_MS_KEY)?
+    {
+        config.usage_summary_interval = Some(Duration::from_millis(usage_summary_interval_ms));
+    }
+    if let Some(usage_export_file) = layered_config.get(USAGE_EXPORT_FILE_KEY) {
+        config.usage_export_file = Some(PathBuf::from(usage_export_file));
+    }
+    if let Some(usage_export_format) = layered_config.get_parsed(USAGE_EXPORT_FORMAT_KEY)? {
+        config.usage_export_format = usage_export_format;
+    }
+    if let Some(usage_export_interval_ms) =
+        layered_config.get_parsed(USAGE_EXPORT_INTERVAL_MS_KEY)?
+    {
+        config.usage_export_interval = Duration::from_millis(usage_export_interval_ms);
+    }
+    if let Some(alert_webhook_url) = layered_config.get(ALERT_WEBHOOK_URL_KEY) {
+        #[cfg(feature = "alert-webhook")]
+        {
+            let url = alert_webhook_url
+                .parse()
+                .context(format!("Invalid alert webhook URL {alert_webhook_url}"))?;
+            config.alert_webhook =
+                Some(AlertWebhook::new(url).context("Failed to set up the alert webhook")?);
+        }
+        #[cfg(not(feature = "alert-webhook"))]
+        anyhow::bail!(
+            "Posting alerts to {alert_webhook_url} requires the gateway to be built with the \
+             alert-webhook feature"
+        );
+    }
+    if let Some(degraded_error_rate) = layered_config.get_parsed(DEGRADED_ERROR_RATE_KEY)? {
+        config.degraded_error_rate = degraded_error_rate;
+    }
+    if let Some(unhealthy_error_rate) = layered_config.get_parsed(UNHEALTHY_ERROR_RATE_KEY)? {
+        config.unhealthy_error_rate = unhealthy_error_rate;
+    }
+    if let Some(degraded_failed_connects) =
+        layered_config.get_parsed(DEGRADED_FAILED_CONNECTS_KEY)?
+    {
+        config.degraded_failed_connects = degraded_failed_connects;
+    }
+    if let Some(self_check_interval_ms) = layered_config.get_parsed(SELF_CHECK_INTERVAL_MS_KEY)? {
+        config.self_check_interval = Duration::from_millis(self_check_interval_ms);
+    }
+    if let Some(binary_encoding) = layered_config.get_parsed(BINARY_ENCODING_KEY)? {
+        config.binary_encoding = binary_encoding;
+    }
+    if let Some(large_integers_as_strings) =
+        layered_config.get_parsed(LARGE_INTEGERS_AS_STRINGS_KEY)?
+    {
+        config.large_integers_as_strings = large_integers_as_strings;
+    }
+    config.timestamp_fns = layered_config.timestamp_fns()?;
+    config.empty_payload_fns = layered_config.empty_payload_fns()?;
+    config.cache_ttl_fns = layered_config.cache_ttl_fns()?;
+    if let Some(response_cache_max_entries) =
+        layered_config.get_parsed(RESPONSE_CACHE_MAX_ENTRIES_KEY)?
+    {
+        config.response_cache_max_entries = response_cache_max_entries;
+    }
+    if let Some(cache_max_stale_ms) = layered_config.get_parsed(CACHE_MAX_STALE_MS_KEY)? {
+        config.cache_max_stale = Duration::from_millis(cache_max_stale_ms);
+    }
+    if let Some(redis_url) = layered_config.get(RESPONSE_CACHE_REDIS_URL_KEY) {
+        if !cfg!(feature = "redis") {
+            anyhow::bail!(
+                "Caching responses in Redis requires the gateway to be built with the redis feature"
+            );
+        }
+        config.response_cache_redis_url = Some(
+            redis_url
+                .parse()
+                .context(format!("Invalid response cache Redis URL {redis_url}"))?,
+        );
+    }
+    if let Some(max_depth) = layered_config.get_parsed(PAYLOAD_MAX_DEPTH_KEY)? {
+        config.payload_limits.max_depth = max_depth;
+    }
+    if let Some(max_object_keys) = layered_config.get_parsed(PAYLOAD_MAX_OBJECT_KEYS_KEY)? {
+        config.payload_limits.max_object_keys = max_object_keys;
+    }
+    if let Some(max_string_bytes) = layered_config.get_parsed(PAYLOAD_MAX_STRING_BYTES_KEY)? {
+        config.payload_limits.max_string_bytes = max_string_bytes;
+    }
+    if let Some(timestamp_fields) = layered_config.get(TIMESTAMP_FIELDS_KEY) {
+        config.timestamp_fields = timestamp_fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    config.enum_fns = layered_config.enum_fns()?;
+    if let Some(enum_fields) = layered_config.get(ENUM_FIELDS_KEY) {
+        config.enum_fields = enum_fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    if let Some(enum_tag) = layered_config.get(ENUM_TAG_KEY) {
+        config.enum_tag = enum_tag.to_string();
+    }
+    if let Some(camel_case_keys) = layered_config.get_parsed(CAMEL_CASE_KEYS_KEY)? {
+        config.camel_case_keys = camel_case_keys;
+    }
+    if let Some(response_validation) = layered_config.get_parsed(RESPONSE_VALIDATION_KEY)? {
+        config.response_validation = response_validation;
+    }
+    if let Some(response_schemas_file) = layered_config.get(RESPONSE_SCHEMAS_FILE_KEY) {
+        let contents = std::fs::read_to_string(response_schemas_file).context(format!(
+            "Failed to read the response schemas file {response_schemas_file}"
+        ))?;
+        config.response_schemas = ResponseSchemas::from_json_str(&contents)?;
+    }
+
+    let mut additional_conductors = Vec::new();
+    for name in layered_config.conductor_names() {
+        let admin_ws_url = layered_config
+            .get(&conductor_key(&name, ADMIN_WS_URL_KEY))
+            .context(format!(
+                "The admin websocket URL of conductor {name} is not set, use {}",
+                key_to_env_var(&conductor_key(&name, ADMIN_WS_URL_KEY))
+            ))?;
+        let admin_socket_addr = resolve_address_from_url(admin_ws_url)
+            .await
+            .context(format!(
+                "Failed to extract socket address from the admin websocket URL of conductor {name}"
+            ))
+            .context(Failure::Upstream)?;
+        tracing::info!(
+            "Resolved admin socket address of conductor {}: {}",
+            name,
+            admin_socket_addr
+        );
+
+        let allowed_app_ids = AllowedAppIds::from_str(
+            layered_config
+                .get(&conductor_key(&name, ALLOWED_APP_IDS_KEY))
+                .unwrap_or_default(),
+        )?;
+
+        additional_conductors.push(ConductorConfig {
+            name,
+            admin_socket_addr,
+            admin_ws_url: Some(admin_ws_url.to_string()),
+            allowed_app_ids,
+        });
+    }
 
-    Ok(config)
+    Ok(config.with_additional_conductors(additional_conductors)?)
 }
 
 /// Initialize a global tracing subscriber
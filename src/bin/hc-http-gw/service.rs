@@ -0,0 +1,193 @@
+//! Running the gateway as a Windows service.
+//!
+//! The gateway is registered with the service control manager by `install-service`, which starts
+//! it with the `run-service` subcommand. Stopping the service, or shutting down the host, shuts
+//! the gateway down gracefully.
+
+use super::HcHttpGatewayArgs;
+use anyhow::Context;
+use std::ffi::OsString;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+/// Name of the service, unless another is given.
+pub const DEFAULT_SERVICE_NAME: &str = "hc-http-gw";
+
+/// What the service needs to run the gateway, handed from `main` to the thread that the service
+/// control manager runs the service on.
+struct ServiceContext {
+    name: String,
+    args: HcHttpGatewayArgs,
+    runtime: Handle,
+}
+
+static SERVICE_CONTEXT: OnceLock<Mutex<Option<ServiceContext>>> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Register the gateway as a service that starts with the host.
+///
+/// The service runs this executable with the arguments that were given before `install-service`.
+/// The service starts in the system directory, so paths in the arguments should be absolute.
+pub fn install(name: &str) -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("Failed to connect to the service control manager")?;
+
+    let mut launch_arguments = std::env::args_os()
+        .skip(1)
+        .take_while(|arg| arg != "install-service")
+        .collect::<Vec<_>>();
+    launch_arguments.extend([
+        OsString::from("run-service"),
+        OsString::from("--name"),
+        OsString::from(name),
+    ]);
+
+    let service_info = ServiceInfo {
+        name: OsString::from(name),
+        display_name: OsString::from("Holochain HTTP Gateway"),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()
+            .context("Failed to find the gateway executable")?,
+        launch_arguments,
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&service_info, ServiceAccess::CHANGE_CONFIG)
+        .context(format!("Failed to register the service {name}"))?;
+    service
+        .set_description("Bridges HTTP requests to zome calls on Holochain conductors")
+        .context("Failed to describe the service")?;
+
+    println!("Registered the service {name}");
+    Ok(())
+}
+
+/// Remove the service of the gateway. A running service is removed once it has stopped.
+pub fn uninstall(name: &str) -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("Failed to connect to the service control manager")?;
+    let service = manager
+        .open_service(name, ServiceAccess::DELETE)
+        .context(format!("Failed to open the service {name}"))?;
+    service
+        .delete()
+        .context(format!("Failed to remove the service {name}"))?;
+
+    println!("Removed the service {name}");
+    Ok(())
+}
+
+/// Run the gateway as the service with the given name, until the service is stopped.
+///
+/// Hands control of this thread to the service control manager, which runs the service on a
+/// thread of its own. The gateway runs on the runtime of the caller.
+pub fn run(name: String, args: HcHttpGatewayArgs) -> anyhow::Result<()> {
+    SERVICE_CONTEXT
+        .get_or_init(Default::default)
+        .lock()
+        .expect("Service context lock poisoned")
+        .replace(ServiceContext {
+            name: name.clone(),
+            args,
+            runtime: Handle::current(),
+        });
+
+    service_dispatcher::start(&name, ffi_service_main)
+        .context("Failed to start the service dispatcher")?;
+
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let Some(context) = SERVICE_CONTEXT.get().and_then(|context| {
+        context
+            .lock()
+            .expect("Service context lock poisoned")
+            .take()
+    }) else {
+        return;
+    };
+
+    if let Err(e) = run_service(context) {
+        tracing::error!(?e, "Service failed");
+    }
+}
+
+fn run_service(context: ServiceContext) -> anyhow::Result<()> {
+    let stop = Arc::new(Notify::new());
+    let status_handle = service_control_handler::register(&context.name, {
+        let stop = stop.clone();
+        move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                stop.notify_one();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    })
+    .context("Failed to register the service control handler")?;
+
+    status_handle
+        .set_service_status(service_status(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            ServiceExitCode::Win32(0),
+        ))
+        .context("Failed to report that the service is running")?;
+
+    let result = context.runtime.block_on(super::run(
+        context.args,
+        async move {
+            stop.notified().await;
+        },
+        std::future::pending(),
+    ));
+
+    let exit_code = match &result {
+        Ok(_) => ServiceExitCode::Win32(0),
+        Err(e) => ServiceExitCode::ServiceSpecific(u32::from(super::exit_code(e))),
+    };
+    status_handle
+        .set_service_status(service_status(
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            exit_code,
+        ))
+        .context("Failed to report that the service has stopped")?;
+
+    result.map(|_| ())
+}
+
+fn service_status(
+    current_state: ServiceState,
+    controls_accepted: ServiceControlAccept,
+    exit_code: ServiceExitCode,
+) -> ServiceStatus {
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
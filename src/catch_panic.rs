@@ -0,0 +1,107 @@
+//! Recovery from panics in request handlers.
+//!
+//! Without it, a panic while handling a request takes down the task serving the connection, and
+//! the client only sees the connection being dropped. Instead, the panic is logged and counted,
+//! and the client gets an error response like for any other internal error.
+
+use crate::HcHttpGatewayError;
+use crate::metrics::Metrics;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use futures::FutureExt;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+/// Middleware that turns a panic while handling a request into a response with status 500.
+pub(crate) async fn catch_panic(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    match AssertUnwindSafe(next.run(request)).catch_unwind().await {
+        Ok(response) => response,
+        Err(panic) => {
+            tracing::error!(
+                %method,
+                path,
+                panic = panic_message(panic.as_ref()),
+                "Request handler panicked"
+            );
+            metrics.record_panic();
+            HcHttpGatewayError::Panicked.into_response()
+        }
+    }
+}
+
+/// The message that a panic was raised with, if it was raised with a message.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::router::TestRouter;
+    use crate::{AllowedFns, Configuration, MockAdminCall, MockAppCall};
+    use holochain_types::prelude::DnaHash;
+    use reqwest::StatusCode;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    #[tokio::test]
+    async fn panics_are_answered_with_an_error() {
+        let config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "coordinator",
+            HashMap::from([("coordinator".to_string(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap();
+        let mut admin_call = MockAdminCall::new();
+        admin_call.expect_list_apps().returning(|_| {
+            Box::pin(async {
+                let app_info = crate::test::data::new_test_app_info(
+                    "coordinator",
+                    DnaHash::from_raw_32(vec![1; 32]),
+                );
+                Ok(vec![app_info])
+            })
+        });
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_handle_zome_call()
+            .returning(|_| Box::pin(async { panic!("Invalid lock") }));
+        let router = TestRouter::new_with_config_and_interfaces(
+            config,
+            Arc::new(admin_call),
+            Arc::new(app_call),
+        );
+
+        let (status_code, body) = router
+            .request("/uhC0kAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQF-z86-/coordinator/zome_name/fn_name")
+            .await;
+        assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            body,
+            r#"{"error":"The gateway failed to handle the request","request_id":"test-request-id"}"#
+        );
+    }
+
+    #[test]
+    fn panic_messages_are_extracted() {
+        assert_eq!(panic_message(&"static"), "static");
+        assert_eq!(panic_message(&"owned".to_string()), "owned");
+        assert_eq!(panic_message(&1), "Box<dyn Any>");
+    }
+}
@@ -3,13 +3,24 @@
 //! This module provides the configuration structure and related types for
 //! controlling the behavior of the HTTP Gateway.
 
+#[cfg(feature = "alert-webhook")]
+use crate::AlertWebhook;
+use crate::{
+    AccessLog, AuditLog, ErrorFormat, ResponseSchemas, ResponseValidation, StatsdExporter,
+    UsageExportFormat,
+};
+use axum::http::HeaderName;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::{
     collections::{HashMap, HashSet},
     ops::Deref,
     str::FromStr,
 };
 
+mod layered;
+pub use layered::*;
+
 /// Default payload size limit (10 kilobytes)
 pub const DEFAULT_PAYLOAD_LIMIT_BYTES: u32 = 10 * 1024;
 
@@ -19,6 +30,93 @@ pub const DEFAULT_MAX_APP_CONNECTIONS: u32 = 50;
 /// Default timeout for zome calls
 pub const DEFAULT_ZOME_CALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
+/// Default number of admin websocket connections that the gateway maintains per conductor.
+pub const DEFAULT_ADMIN_POOL_SIZE: u32 = 1;
+
+/// Default interval between health checks of the conductors
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Default interval between keepalive checks of the open app websocket connections
+pub const DEFAULT_APP_KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Default interval between checks for apps that changed on the conductors
+pub const DEFAULT_APP_WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Default time for which a lookup that found no installed app is remembered
+pub const DEFAULT_NOT_INSTALLED_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Default maximum number of zome call responses in the response cache
+pub const DEFAULT_RESPONSE_CACHE_MAX_ENTRIES: usize = 10_000;
+
+/// Default time after expiry for which a cached response is served while it is refreshed
+pub const DEFAULT_CACHE_MAX_STALE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Default time for which a listing of the app interfaces of a conductor is reused
+pub const DEFAULT_APP_INTERFACES_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Default time that a zome call waits when an app is at its concurrent call limit
+pub const DEFAULT_APP_CALL_QUEUE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Default maximum number of zome call requests that wait for a request in flight to finish
+pub const DEFAULT_MAX_QUEUED_REQUESTS: u32 = 100;
+
+/// Default time that a zome call request waits for a request in flight to finish
+pub const DEFAULT_QUEUE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Default share of new zome call requests that are shed while conductors respond slowly
+pub const DEFAULT_LATENCY_SHED_FRACTION: f64 = 0.5;
+
+/// Default time that requests in flight may take to finish once the gateway starts draining
+pub const DEFAULT_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Default maximum nesting depth of arrays and objects in a payload
+pub const DEFAULT_PAYLOAD_MAX_DEPTH: u32 = 32;
+
+/// Default maximum number of keys of an object in a payload
+pub const DEFAULT_PAYLOAD_MAX_OBJECT_KEYS: u32 = 1024;
+
+/// Default maximum length of a string in a payload, in bytes
+pub const DEFAULT_PAYLOAD_MAX_STRING_BYTES: u32 = 64 * 1024;
+
+/// Default maximum number of functions that zome call metrics are recorded for separately
+pub const DEFAULT_METRICS_MAX_FUNCTIONS: u32 = 1000;
+
+/// Default name of the fields that hold timestamps
+pub const DEFAULT_TIMESTAMP_FIELD: &str = "timestamp";
+
+/// Default name of the field that holds the variant of an internally tagged enum.
+pub const DEFAULT_ENUM_TAG: &str = "type";
+
+/// Default prefix of the names of the metrics sent to StatsD
+pub const DEFAULT_STATSD_PREFIX: &str = "hc_gw";
+
+/// Default interval at which gauges are sent to StatsD (10 seconds)
+pub const DEFAULT_STATSD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Default interval at which the usage of every app is exported (1 hour)
+pub const DEFAULT_USAGE_EXPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Default share of recent zome call requests that fail with a server error, above which the
+/// gateway reports itself as degraded
+pub const DEFAULT_DEGRADED_ERROR_RATE: f64 = 0.05;
+
+/// Default share of recent zome call requests that fail with a server error, above which the
+/// gateway reports itself as unhealthy
+pub const DEFAULT_UNHEALTHY_ERROR_RATE: f64 = 0.5;
+
+/// Default number of recent failed connects to conductors, above which the gateway reports
+/// itself as degraded
+pub const DEFAULT_DEGRADED_FAILED_CONNECTS: u64 = 10;
+
+/// Default interval between self-checks of the gateway (1 minute)
+pub const DEFAULT_SELF_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Default name of the header that holds the id of a request.
+pub const DEFAULT_REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Name of the conductor that is configured by the top level admin websocket settings.
+pub const DEFAULT_CONDUCTOR_NAME: &str = "default";
+
 /// Errors when parsing config arguments.
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigParseError {
@@ -38,16 +136,438 @@ pub type ConfigParseResult<T> = Result<T, ConfigParseError>;
 pub struct Configuration {
     /// WebSocket URL for admin connections and management interfaces
     pub admin_socket_addr: SocketAddr,
+    /// URL that `admin_socket_addr` was resolved from, if any. The URL is resolved again when
+    /// reconnecting, so that the gateway follows changes to the conductor's DNS record.
+    pub admin_ws_url: Option<String>,
     /// Maximum size in bytes that request payloads can be
     pub payload_limit_bytes: u32,
     /// Controls which applications are permitted to connect to the gateway
     pub allowed_app_ids: AllowedAppIds,
     /// Maps application IDs to their allowed function configurations
     pub allowed_fns: HashMap<AppId, AllowedFns>,
-    /// Maximum number of app connections that the gateway will maintain concurrently.
+    /// Maximum number of app connections that the gateway will maintain concurrently, including
+    /// the overflow connections of apps.
     pub max_app_connections: u32,
     /// Timeout for zome calls
     pub zome_call_timeout: std::time::Duration,
+    /// Conductors that the gateway routes requests to, in addition to the conductor at
+    /// `admin_socket_addr`
+    pub additional_conductors: Vec<ConductorConfig>,
+    /// Whether zome calls fail over to another conductor hosting the same app when the preferred
+    /// conductor cannot be reached
+    pub conductor_failover: bool,
+    /// Interval between health checks of the conductors
+    pub health_check_interval: std::time::Duration,
+    /// Number of admin websocket connections that the gateway maintains per conductor
+    pub admin_pool_size: u32,
+    /// Interval between keepalive checks of the open app websocket connections
+    pub app_keepalive_interval: std::time::Duration,
+    /// Time after which an unused app websocket connection is closed, if any
+    pub app_idle_timeout: Option<std::time::Duration>,
+    /// Age after which an app websocket connection is replaced by a new one on its next use, if any
+    pub app_max_age: Option<std::time::Duration>,
+    /// Number of requests after which an app websocket connection is replaced by a new one, if any
+    pub app_max_requests: Option<u64>,
+    /// Number of zome calls in flight over an app websocket connection beyond which further calls
+    /// are made over a second connection for the app, if any
+    pub app_max_in_flight: Option<u32>,
+    /// Whether signing credentials are authorized for every cell of an app when connecting to it,
+    /// rather than for each cell the first time it is called
+    pub eager_signing_authorization: bool,
+    /// Whether each app is connected to through an app interface of its own, rather than through
+    /// one app interface shared by all apps
+    pub dedicated_app_interfaces: bool,
+    /// Interval between checks for apps that changed on the conductors
+    pub app_watch_interval: std::time::Duration,
+    /// Time for which a lookup that found no installed app is remembered, zero to disable
+    pub not_installed_cache_ttl: std::time::Duration,
+    /// Time for which a listing of the app interfaces of a conductor is reused, zero to disable
+    pub app_interfaces_cache_ttl: std::time::Duration,
+    /// How zome calls made by the gateway are signed
+    pub signing_mode: SigningMode,
+    /// Maximum number of zome calls that are made to an app at the same time, if any
+    pub max_app_concurrent_calls: Option<u32>,
+    /// Time that a zome call waits for one of the concurrent calls to an app to finish before failing
+    pub app_call_queue_timeout: std::time::Duration,
+    /// Maximum number of zome call requests that the gateway handles at the same time, if any
+    pub max_in_flight_requests: Option<u32>,
+    /// Maximum number of zome call requests that wait for one of the requests in flight to finish
+    pub max_queued_requests: u32,
+    /// Time that a zome call request waits for one of the requests in flight to finish before it
+    /// is shed
+    pub queue_timeout: std::time::Duration,
+    /// The 95th percentile of recent conductor latencies above which new zome call requests are
+    /// shed, if any
+    pub latency_shed_threshold: Option<std::time::Duration>,
+    /// Share of new zome call requests that are shed while the conductor latency is above
+    /// [`latency_shed_threshold`](Self::latency_shed_threshold)
+    pub latency_shed_fraction: f64,
+    /// Maps application IDs to the priority of their requests when the gateway is under load,
+    /// apps that are not listed have priority 0
+    pub app_priorities: HashMap<AppId, u32>,
+    /// Time that requests in flight may take to finish once the gateway starts draining or
+    /// shutting down
+    pub drain_timeout: std::time::Duration,
+    /// Token that management requests must present, the management endpoints are disabled if
+    /// there is none
+    pub management_token: Option<ManagementToken>,
+    /// How binary values are rendered in zome call responses and recognized in request payloads
+    pub binary_encoding: BinaryEncoding,
+    /// Whether integers in zome call responses that exceed the precision of a JSON number in
+    /// JavaScript are rendered as strings
+    pub large_integers_as_strings: bool,
+    /// Maps application IDs to the functions whose timestamps are rendered as RFC 3339 strings
+    pub timestamp_fns: HashMap<AppId, AllowedFns>,
+    /// Names of the fields that hold timestamps in the payloads and responses of the functions in
+    /// `timestamp_fns`
+    pub timestamp_fields: HashSet<String>,
+    /// Maps application IDs to the functions whose enums are internally tagged in JSON
+    pub enum_fns: HashMap<AppId, AllowedFns>,
+    /// Names of the fields that hold enums in the payloads and responses of the functions in
+    /// `enum_fns`
+    pub enum_fields: HashSet<String>,
+    /// Name of the field that holds the variant of an internally tagged enum
+    pub enum_tag: String,
+    /// Whether object keys are converted from camelCase to snake_case in request payloads, and
+    /// from snake_case to camelCase in zome call responses
+    pub camel_case_keys: bool,
+    /// Maps application IDs to the payloads of their functions when a request has no payload
+    pub empty_payload_fns: HashMap<AppId, EmptyPayloadFns>,
+    /// Maps application IDs to the times for which the responses of their functions are cached
+    pub cache_ttl_fns: HashMap<AppId, CacheTtlFns>,
+    /// Maximum number of zome call responses in the response cache
+    pub response_cache_max_entries: usize,
+    /// Time after expiry for which a cached response is served while it is refreshed in the
+    /// background, zero to disable
+    pub cache_max_stale: std::time::Duration,
+    /// URL of the Redis server that responses are cached in, instead of in memory, which requires
+    /// the `redis` feature
+    pub response_cache_redis_url: Option<url::Url>,
+    /// Limits on the structure of JSON payloads
+    pub payload_limits: PayloadLimits,
+    /// What happens when a zome call response doesn't match the schema registered for the function
+    pub response_validation: ResponseValidation,
+    /// The schemas that zome call responses are validated against
+    pub response_schemas: ResponseSchemas,
+    /// Maximum number of functions that zome call metrics are recorded for separately, calls to
+    /// further functions are recorded together
+    pub metrics_max_functions: u32,
+    /// Name of the header that holds the id of a request, in requests and responses
+    pub request_id_header: HeaderName,
+    /// The format of error responses
+    pub error_format: ErrorFormat,
+    /// Where a JSON line is written for every request, if anywhere
+    pub access_log: Option<AccessLog>,
+    /// Where a JSON line is written for every management request that changes state, if anywhere
+    pub audit_log: Option<AuditLog>,
+    /// Duration above which a completed request is logged as slow, if any
+    pub slow_request_threshold: Option<std::time::Duration>,
+    /// Where metrics are pushed to, besides being served at the metrics endpoint, if anywhere
+    pub statsd: Option<StatsdExporter>,
+    /// Interval at which the connection counters and runtime metrics are sent to StatsD
+    pub statsd_interval: std::time::Duration,
+    /// Interval at which a summary of the usage of every app is logged, if at all
+    pub usage_summary_interval: Option<std::time::Duration>,
+    /// File that the usage of every app is appended to for billing, if any
+    pub usage_export_file: Option<PathBuf>,
+    /// Format that the usage of every app is exported in
+    pub usage_export_format: UsageExportFormat,
+    /// Interval at which the usage of every app is exported
+    pub usage_export_interval: std::time::Duration,
+    /// Webhook that a notification is posted to when a conductor becomes unreachable or reachable
+    /// again, if any
+    #[cfg(feature = "alert-webhook")]
+    pub alert_webhook: Option<AlertWebhook>,
+    /// Share of recent zome call requests failing with a server error at which the deep health
+    /// check reports the gateway as degraded
+    pub degraded_error_rate: f64,
+    /// Share of recent zome call requests failing with a server error at which the deep health
+    /// check reports the gateway as unhealthy
+    pub unhealthy_error_rate: f64,
+    /// Number of recent failed connects at which the deep health check reports the gateway as
+    /// degraded
+    pub degraded_failed_connects: u64,
+    /// Interval between self-checks of the gateway, each logged as a heartbeat
+    pub self_check_interval: std::time::Duration,
+}
+
+/// Bearer token that grants access to the management endpoints.
+///
+/// The token is not included in the debug output, so that it doesn't end up in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ManagementToken(String);
+
+impl ManagementToken {
+    /// Create a management token from its value.
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+
+    /// Whether the given token matches, compared in constant time for tokens of the same length.
+    ///
+    /// An empty token never matches, so that an empty management token can't grant access.
+    pub fn matches(&self, token: &str) -> bool {
+        !self.0.is_empty()
+            && self.0.len() == token.len()
+            && self
+                .0
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+}
+
+impl std::fmt::Debug for ManagementToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ManagementToken(..)")
+    }
+}
+
+/// How binary values, other than holo hashes, are rendered in JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    /// An array of byte values, for example `[1,2,3]`.
+    #[default]
+    Array,
+    /// An object with the base64 encoded bytes in a `$base64` field, for example
+    /// `{"$base64":"AQID"}`. Such objects in request payloads are decoded into binary values.
+    Base64Object,
+    /// A base64 encoded string, for example `"AQID"`. Strings in request payloads can't be told
+    /// apart from binary values, so only `$base64` objects are decoded into binary values.
+    Base64,
+}
+
+impl FromStr for BinaryEncoding {
+    type Err = ConfigParseError;
+
+    /// Expected format
+    /// - One of `array`, `base64-object` or `base64`
+    fn from_str(s: &str) -> ConfigParseResult<Self> {
+        match s.trim() {
+            "array" => Ok(BinaryEncoding::Array),
+            "base64-object" => Ok(BinaryEncoding::Base64Object),
+            "base64" => Ok(BinaryEncoding::Base64),
+            s => Err(ConfigParseError::Other(format!(
+                "Unknown binary encoding {s:?}, expected \"array\", \"base64-object\" or \"base64\""
+            ))),
+        }
+    }
+}
+
+/// Limits on the structure of JSON payloads, which are checked while a payload is parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadLimits {
+    /// Maximum nesting depth of arrays and objects
+    pub max_depth: u32,
+    /// Maximum number of keys of an object
+    pub max_object_keys: u32,
+    /// Maximum length of a string, including object keys, in bytes
+    pub max_string_bytes: u32,
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_PAYLOAD_MAX_DEPTH,
+            max_object_keys: DEFAULT_PAYLOAD_MAX_OBJECT_KEYS,
+            max_string_bytes: DEFAULT_PAYLOAD_MAX_STRING_BYTES,
+        }
+    }
+}
+
+/// How the payload of a zome call is encoded when the request doesn't have one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptyPayload {
+    /// The unit value, as `()` is encoded.
+    #[default]
+    Unit,
+    /// msgpack nil, as `None` is encoded, for functions that take an `Option`.
+    Nil,
+    /// An empty map, for functions that take a struct whose fields are all optional.
+    EmptyMap,
+}
+
+impl FromStr for EmptyPayload {
+    type Err = ConfigParseError;
+
+    /// Expected format
+    /// - One of `unit`, `nil` or `empty-map`
+    fn from_str(s: &str) -> ConfigParseResult<Self> {
+        match s.trim() {
+            "unit" => Ok(EmptyPayload::Unit),
+            "nil" => Ok(EmptyPayload::Nil),
+            "empty-map" => Ok(EmptyPayload::EmptyMap),
+            s => Err(ConfigParseError::Other(format!(
+                "Unknown empty payload {s:?}, expected \"unit\", \"nil\" or \"empty-map\""
+            ))),
+        }
+    }
+}
+
+/// The empty payloads of the functions of an app.
+#[derive(Debug, Clone, Default)]
+pub struct EmptyPayloadFns {
+    /// The empty payload of the functions that aren't listed, if one is set for all functions
+    pub all: Option<EmptyPayload>,
+    /// The empty payloads of individual functions
+    pub fns: HashMap<ZomeFn, EmptyPayload>,
+}
+
+impl EmptyPayloadFns {
+    /// The empty payload of a function, if one is set.
+    pub fn get(&self, zome_name: &str, fn_name: &str) -> Option<EmptyPayload> {
+        let zome_fn = ZomeFn {
+            zome_name: zome_name.to_string(),
+            fn_name: fn_name.to_string(),
+        };
+        self.fns.get(&zome_fn).copied().or(self.all)
+    }
+}
+
+impl FromStr for EmptyPayloadFns {
+    type Err = ConfigParseError;
+
+    /// Expected format
+    /// - A comma separated list of zome_name/fn_name=empty-payload pairs, where the zome and
+    ///   function may be replaced by an asterix ("*") to set the empty payload of all other
+    ///   functions
+    fn from_str(s: &str) -> ConfigParseResult<Self> {
+        let mut empty_payload_fns = EmptyPayloadFns::default();
+        for entry in s.split(',') {
+            let Some((zome_fn_path, empty_payload)) = entry.trim().split_once('=') else {
+                return Err(ConfigParseError::Other(format!(
+                    "Failed to parse the function and empty payload from value: {entry}"
+                )));
+            };
+            let empty_payload = EmptyPayload::from_str(empty_payload)?;
+
+            if zome_fn_path.trim() == "*" {
+                empty_payload_fns.all = Some(empty_payload);
+                continue;
+            }
+            let Some((zome_name, fn_name)) = zome_fn_path.trim().split_once('/') else {
+                return Err(ConfigParseError::Other(format!(
+                    "Failed to parse the zome name and function name from value: {zome_fn_path}",
+                )));
+            };
+            if zome_name.is_empty() || fn_name.is_empty() {
+                return Err(ConfigParseError::Other(format!(
+                    "Zome name or function name is empty for value: {zome_fn_path}"
+                )));
+            }
+
+            empty_payload_fns.fns.insert(
+                ZomeFn {
+                    zome_name: zome_name.to_string(),
+                    fn_name: fn_name.to_string(),
+                },
+                empty_payload,
+            );
+        }
+
+        Ok(empty_payload_fns)
+    }
+}
+
+/// The times for which the responses of the functions of an app are cached.
+#[derive(Debug, Clone, Default)]
+pub struct CacheTtlFns {
+    /// The TTL of the functions that aren't listed, if one is set for all functions
+    pub all: Option<std::time::Duration>,
+    /// The TTLs of individual functions
+    pub fns: HashMap<ZomeFn, std::time::Duration>,
+}
+
+impl CacheTtlFns {
+    /// The TTL of a function, if its responses are cached.
+    pub fn get(&self, zome_name: &str, fn_name: &str) -> Option<std::time::Duration> {
+        let zome_fn = ZomeFn {
+            zome_name: zome_name.to_string(),
+            fn_name: fn_name.to_string(),
+        };
+        self.fns
+            .get(&zome_fn)
+            .copied()
+            .or(self.all)
+            .filter(|ttl| !ttl.is_zero())
+    }
+}
+
+impl FromStr for CacheTtlFns {
+    type Err = ConfigParseError;
+
+    /// Expected format
+    /// - A comma separated list of zome_name/fn_name=ttl_ms pairs, where the zome and function
+    ///   may be replaced by an asterix ("*") to set the TTL of all other functions
+    fn from_str(s: &str) -> ConfigParseResult<Self> {
+        let mut cache_ttl_fns = CacheTtlFns::default();
+        for entry in s.split(',') {
+            let Some((zome_fn_path, ttl_ms)) = entry.trim().split_once('=') else {
+                return Err(ConfigParseError::Other(format!(
+                    "Failed to parse the function and TTL from value: {entry}"
+                )));
+            };
+            let ttl = std::time::Duration::from_millis(ttl_ms.trim().parse()?);
+
+            if zome_fn_path.trim() == "*" {
+                cache_ttl_fns.all = Some(ttl);
+                continue;
+            }
+            let Some((zome_name, fn_name)) = zome_fn_path.trim().split_once('/') else {
+                return Err(ConfigParseError::Other(format!(
+                    "Failed to parse the zome name and function name from value: {zome_fn_path}",
+                )));
+            };
+            if zome_name.is_empty() || fn_name.is_empty() {
+                return Err(ConfigParseError::Other(format!(
+                    "Zome name or function name is empty for value: {zome_fn_path}"
+                )));
+            }
+
+            cache_ttl_fns.fns.insert(
+                ZomeFn {
+                    zome_name: zome_name.to_string(),
+                    fn_name: fn_name.to_string(),
+                },
+                ttl,
+            );
+        }
+
+        Ok(cache_ttl_fns)
+    }
+}
+
+/// How the gateway signs the zome calls that it makes.
+#[derive(Debug, Clone, Default)]
+pub enum SigningMode {
+    /// Sign with signing credentials that the gateway generates and authorizes for each cell.
+    #[default]
+    Credentials,
+    /// Sign with the agent keys held by a Lair keystore, which requires the `lair-signing` feature.
+    Lair(LairSigningConfig),
+}
+
+/// Connection settings for the Lair keystore used to sign zome calls.
+#[derive(Debug, Clone)]
+pub struct LairSigningConfig {
+    /// Connection URL of the Lair keystore
+    pub connection_url: url::Url,
+    /// Path to a file holding the passphrase of the Lair keystore
+    pub passphrase_file: PathBuf,
+}
+
+/// Configuration of a Holochain conductor that the gateway routes requests to.
+#[derive(Debug, Clone)]
+pub struct ConductorConfig {
+    /// Name used to identify the conductor
+    pub name: ConductorName,
+    /// Socket address of the conductor's admin interface
+    pub admin_socket_addr: SocketAddr,
+    /// URL that `admin_socket_addr` was resolved from, if any
+    pub admin_ws_url: Option<String>,
+    /// Apps installed on this conductor that the gateway is permitted to access
+    pub allowed_app_ids: AllowedAppIds,
 }
 
 impl Configuration {
@@ -98,12 +618,134 @@ impl Configuration {
 
         Ok(Configuration {
             admin_socket_addr,
+            admin_ws_url: None,
             payload_limit_bytes,
             allowed_app_ids,
             allowed_fns,
             max_app_connections,
             zome_call_timeout,
+            additional_conductors: Vec::new(),
+            conductor_failover: false,
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            admin_pool_size: DEFAULT_ADMIN_POOL_SIZE,
+            app_keepalive_interval: DEFAULT_APP_KEEPALIVE_INTERVAL,
+            app_idle_timeout: None,
+            app_max_age: None,
+            app_max_requests: None,
+            app_max_in_flight: None,
+            eager_signing_authorization: false,
+            dedicated_app_interfaces: false,
+            app_watch_interval: DEFAULT_APP_WATCH_INTERVAL,
+            not_installed_cache_ttl: DEFAULT_NOT_INSTALLED_CACHE_TTL,
+            app_interfaces_cache_ttl: DEFAULT_APP_INTERFACES_CACHE_TTL,
+            signing_mode: SigningMode::Credentials,
+            max_app_concurrent_calls: None,
+            app_call_queue_timeout: DEFAULT_APP_CALL_QUEUE_TIMEOUT,
+            max_in_flight_requests: None,
+            max_queued_requests: DEFAULT_MAX_QUEUED_REQUESTS,
+            queue_timeout: DEFAULT_QUEUE_TIMEOUT,
+            latency_shed_threshold: None,
+            latency_shed_fraction: DEFAULT_LATENCY_SHED_FRACTION,
+            app_priorities: HashMap::new(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            management_token: None,
+            binary_encoding: BinaryEncoding::Array,
+            large_integers_as_strings: false,
+            timestamp_fns: HashMap::new(),
+            timestamp_fields: HashSet::from([DEFAULT_TIMESTAMP_FIELD.to_string()]),
+            enum_fns: HashMap::new(),
+            enum_fields: HashSet::new(),
+            enum_tag: DEFAULT_ENUM_TAG.to_string(),
+            camel_case_keys: false,
+            empty_payload_fns: HashMap::new(),
+            cache_ttl_fns: HashMap::new(),
+            response_cache_max_entries: DEFAULT_RESPONSE_CACHE_MAX_ENTRIES,
+            cache_max_stale: DEFAULT_CACHE_MAX_STALE,
+            response_cache_redis_url: None,
+            payload_limits: PayloadLimits::default(),
+            response_validation: ResponseValidation::Off,
+            response_schemas: ResponseSchemas::default(),
+            metrics_max_functions: DEFAULT_METRICS_MAX_FUNCTIONS,
+            request_id_header: HeaderName::from_static(DEFAULT_REQUEST_ID_HEADER),
+            error_format: ErrorFormat::Json,
+            access_log: None,
+            audit_log: None,
+            slow_request_threshold: None,
+            statsd: None,
+            statsd_interval: DEFAULT_STATSD_INTERVAL,
+            usage_summary_interval: None,
+            usage_export_file: None,
+            usage_export_format: UsageExportFormat::default(),
+            usage_export_interval: DEFAULT_USAGE_EXPORT_INTERVAL,
+            #[cfg(feature = "alert-webhook")]
+            alert_webhook: None,
+            degraded_error_rate: DEFAULT_DEGRADED_ERROR_RATE,
+            unhealthy_error_rate: DEFAULT_UNHEALTHY_ERROR_RATE,
+            degraded_failed_connects: DEFAULT_DEGRADED_FAILED_CONNECTS,
+            self_check_interval: DEFAULT_SELF_CHECK_INTERVAL,
+        })
+    }
+
+    /// Add conductors that the gateway routes requests to, in addition to the conductor at
+    /// `admin_socket_addr`.
+    ///
+    /// Conductor names must be unique and must not be [`DEFAULT_CONDUCTOR_NAME`], and every app
+    /// id that a conductor allows must have a corresponding entry in the allowed_fns map.
+    pub fn with_additional_conductors(
+        mut self,
+        conductors: Vec<ConductorConfig>,
+    ) -> ConfigParseResult<Self> {
+        let mut names = HashSet::new();
+
+        for conductor in &conductors {
+            if conductor.name == DEFAULT_CONDUCTOR_NAME || !names.insert(&conductor.name) {
+                return Err(ConfigParseError::Other(format!(
+                    "Conductor name {} is used more than once",
+                    conductor.name
+                )));
+            }
+
+            for app_id in conductor.allowed_app_ids.iter() {
+                if !self.allowed_fns.contains_key(app_id) {
+                    return Err(ConfigParseError::Other(format!(
+                        "{app_id} is not present in allowed_fns"
+                    )));
+                }
+            }
+        }
+
+        self.additional_conductors = conductors;
+
+        Ok(self)
+    }
+
+    /// All conductors that the gateway routes requests to.
+    ///
+    /// The first conductor is the one configured by `admin_socket_addr` and `allowed_app_ids`,
+    /// named [`DEFAULT_CONDUCTOR_NAME`].
+    pub fn conductors(&self) -> Vec<ConductorConfig> {
+        std::iter::once(ConductorConfig {
+            name: DEFAULT_CONDUCTOR_NAME.to_string(),
+            admin_socket_addr: self.admin_socket_addr,
+            admin_ws_url: self.admin_ws_url.clone(),
+            allowed_app_ids: self.allowed_app_ids.clone(),
         })
+        .chain(self.additional_conductors.iter().cloned())
+        .collect()
+    }
+
+    /// The configuration to use for connections to the given conductor.
+    ///
+    /// The conductor's admin socket address and allowed apps replace the top level settings and
+    /// there are no additional conductors. All other settings are shared.
+    pub fn for_conductor(&self, conductor: &ConductorConfig) -> Configuration {
+        Configuration {
+            admin_socket_addr: conductor.admin_socket_addr,
+            admin_ws_url: conductor.admin_ws_url.clone(),
+            allowed_app_ids: conductor.allowed_app_ids.clone(),
+            additional_conductors: Vec::new(),
+            ..self.clone()
+        }
     }
 }
 
@@ -143,9 +785,11 @@ impl FromStr for AllowedAppIds {
 }
 
 impl Configuration {
-    /// Check if the app_id is in the allowed list
+    /// Check if the app_id is in the allowed list of any conductor
     pub fn is_app_allowed(&self, app_id: &str) -> bool {
-        self.allowed_app_ids.contains(&app_id.to_string())
+        self.conductors()
+            .iter()
+            .any(|conductor| conductor.allowed_app_ids.contains(app_id))
     }
 
     /// Get the allowed functions for a given app_id
@@ -155,25 +799,51 @@ impl Configuration {
 
     /// Check if a function of an app is allowed
     pub fn is_function_allowed(&self, app_id: &str, zome_name: &str, fn_name: &str) -> bool {
-        match self.get_allowed_functions(app_id) {
-            None => false,
-            Some(allowed_fns) => match allowed_fns {
-                AllowedFns::All => true,
-                AllowedFns::Restricted(zome_fns) => {
-                    let zome_fn = ZomeFn {
-                        zome_name: zome_name.to_string(),
-                        fn_name: fn_name.to_string(),
-                    };
-                    zome_fns.contains(&zome_fn)
-                }
-            },
-        }
+        self.get_allowed_functions(app_id)
+            .is_some_and(|allowed_fns| allowed_fns.contains(zome_name, fn_name))
+    }
+
+    /// The payload of a function of an app when a request has no payload
+    pub fn empty_payload(&self, app_id: &str, zome_name: &str, fn_name: &str) -> EmptyPayload {
+        self.empty_payload_fns
+            .get(app_id)
+            .and_then(|empty_payload_fns| empty_payload_fns.get(zome_name, fn_name))
+            .unwrap_or_default()
+    }
+
+    /// The time for which the responses of a function of an app are cached, if they are
+    pub fn cache_ttl(
+        &self,
+        app_id: &str,
+        zome_name: &str,
+        fn_name: &str,
+    ) -> Option<std::time::Duration> {
+        self.cache_ttl_fns
+            .get(app_id)
+            .and_then(|cache_ttl_fns| cache_ttl_fns.get(zome_name, fn_name))
+    }
+
+    /// Check if the timestamps of a function of an app are rendered as RFC 3339 strings
+    pub fn formats_timestamps(&self, app_id: &str, zome_name: &str, fn_name: &str) -> bool {
+        self.timestamp_fns
+            .get(app_id)
+            .is_some_and(|timestamp_fns| timestamp_fns.contains(zome_name, fn_name))
+    }
+
+    /// Check if the enums of a function of an app are internally tagged in JSON
+    pub fn tags_enums_internally(&self, app_id: &str, zome_name: &str, fn_name: &str) -> bool {
+        self.enum_fns
+            .get(app_id)
+            .is_some_and(|enum_fns| enum_fns.contains(zome_name, fn_name))
     }
 }
 
 /// Type alias for application identifiers.
 pub type AppId = String;
 
+/// Type alias for the names that identify conductors.
+pub type ConductorName = String;
+
 /// Controls which functions can be called.
 #[derive(Debug, Clone)]
 pub enum AllowedFns {
@@ -184,6 +854,20 @@ pub enum AllowedFns {
     All,
 }
 
+impl AllowedFns {
+    /// Check if the function is one of the functions
+    pub fn contains(&self, zome_name: &str, fn_name: &str) -> bool {
+        match self {
+            AllowedFns::All => true,
+            // Comparing in place avoids allocating a key for every request, and the sets of
+            // allowed functions are small
+            AllowedFns::Restricted(zome_fns) => zome_fns
+                .iter()
+                .any(|zome_fn| zome_fn.zome_name == zome_name && zome_fn.fn_name == fn_name),
+        }
+    }
+}
+
 /// Represents a function within a Holochain zome that can be called through the gateway
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct ZomeFn {
@@ -238,6 +922,13 @@ mod tests {
     use std::net::Ipv4Addr;
     use std::str::FromStr;
 
+    #[test]
+    fn empty_management_token_never_matches() {
+        assert!(ManagementToken::new("secret").matches("secret"));
+        assert!(!ManagementToken::new("secret").matches("secreT"));
+        assert!(!ManagementToken::new("").matches(""));
+    }
+
     // Helper function to create a ZomeFn
     fn create_zome_fn(zome_name: &str, fn_name: &str) -> ZomeFn {
         ZomeFn {
@@ -257,11 +948,71 @@ mod tests {
 
         Configuration {
             admin_socket_addr: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            admin_ws_url: None,
             payload_limit_bytes: 1024 * 1024,
             allowed_app_ids: AllowedAppIds(HashSet::from(["app1".to_string(), "app2".to_string()])),
             allowed_fns,
             max_app_connections: DEFAULT_MAX_APP_CONNECTIONS,
             zome_call_timeout: DEFAULT_ZOME_CALL_TIMEOUT,
+            additional_conductors: Vec::new(),
+            conductor_failover: false,
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            admin_pool_size: DEFAULT_ADMIN_POOL_SIZE,
+            app_keepalive_interval: DEFAULT_APP_KEEPALIVE_INTERVAL,
+            app_idle_timeout: None,
+            app_max_age: None,
+            app_max_requests: None,
+            app_max_in_flight: None,
+            eager_signing_authorization: false,
+            dedicated_app_interfaces: false,
+            app_watch_interval: DEFAULT_APP_WATCH_INTERVAL,
+            not_installed_cache_ttl: DEFAULT_NOT_INSTALLED_CACHE_TTL,
+            app_interfaces_cache_ttl: DEFAULT_APP_INTERFACES_CACHE_TTL,
+            signing_mode: SigningMode::Credentials,
+            max_app_concurrent_calls: None,
+            app_call_queue_timeout: DEFAULT_APP_CALL_QUEUE_TIMEOUT,
+            max_in_flight_requests: None,
+            max_queued_requests: DEFAULT_MAX_QUEUED_REQUESTS,
+            queue_timeout: DEFAULT_QUEUE_TIMEOUT,
+            latency_shed_threshold: None,
+            latency_shed_fraction: DEFAULT_LATENCY_SHED_FRACTION,
+            app_priorities: HashMap::new(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            management_token: None,
+            binary_encoding: BinaryEncoding::Array,
+            large_integers_as_strings: false,
+            timestamp_fns: HashMap::new(),
+            timestamp_fields: HashSet::from([DEFAULT_TIMESTAMP_FIELD.to_string()]),
+            enum_fns: HashMap::new(),
+            enum_fields: HashSet::new(),
+            enum_tag: DEFAULT_ENUM_TAG.to_string(),
+            camel_case_keys: false,
+            empty_payload_fns: HashMap::new(),
+            cache_ttl_fns: HashMap::new(),
+            response_cache_max_entries: DEFAULT_RESPONSE_CACHE_MAX_ENTRIES,
+            cache_max_stale: DEFAULT_CACHE_MAX_STALE,
+            response_cache_redis_url: None,
+            payload_limits: PayloadLimits::default(),
+            response_validation: ResponseValidation::Off,
+            response_schemas: ResponseSchemas::default(),
+            metrics_max_functions: DEFAULT_METRICS_MAX_FUNCTIONS,
+            request_id_header: HeaderName::from_static(DEFAULT_REQUEST_ID_HEADER),
+            error_format: ErrorFormat::Json,
+            access_log: None,
+            audit_log: None,
+            slow_request_threshold: None,
+            statsd: None,
+            statsd_interval: DEFAULT_STATSD_INTERVAL,
+            usage_summary_interval: None,
+            usage_export_file: None,
+            usage_export_format: UsageExportFormat::default(),
+            usage_export_interval: DEFAULT_USAGE_EXPORT_INTERVAL,
+            #[cfg(feature = "alert-webhook")]
+            alert_webhook: None,
+            degraded_error_rate: DEFAULT_DEGRADED_ERROR_RATE,
+            unhealthy_error_rate: DEFAULT_UNHEALTHY_ERROR_RATE,
+            degraded_failed_connects: DEFAULT_DEGRADED_FAILED_CONNECTS,
+            self_check_interval: DEFAULT_SELF_CHECK_INTERVAL,
         }
     }
 
@@ -295,6 +1046,92 @@ mod tests {
         }
     }
 
+    mod binary_encoding_tests {
+        use super::*;
+
+        #[test]
+        fn from_str_parses_known_encodings() {
+            assert_eq!(
+                BinaryEncoding::from_str("array").unwrap(),
+                BinaryEncoding::Array
+            );
+            assert_eq!(
+                BinaryEncoding::from_str("base64-object").unwrap(),
+                BinaryEncoding::Base64Object
+            );
+            assert_eq!(
+                BinaryEncoding::from_str(" base64 ").unwrap(),
+                BinaryEncoding::Base64
+            );
+            assert!(BinaryEncoding::from_str("hex").is_err());
+        }
+    }
+
+    mod empty_payload_tests {
+        use super::*;
+
+        #[test]
+        fn from_str_parses_function_empty_payloads() {
+            let empty_payload_fns =
+                EmptyPayloadFns::from_str("posts/get_post=nil, posts/list=empty-map,*=unit")
+                    .unwrap();
+            assert_eq!(
+                empty_payload_fns.get("posts", "get_post"),
+                Some(EmptyPayload::Nil)
+            );
+            assert_eq!(
+                empty_payload_fns.get("posts", "list"),
+                Some(EmptyPayload::EmptyMap)
+            );
+            assert_eq!(
+                empty_payload_fns.get("posts", "other"),
+                Some(EmptyPayload::Unit)
+            );
+
+            let empty_payload_fns = EmptyPayloadFns::from_str("posts/get_post=nil").unwrap();
+            assert_eq!(empty_payload_fns.get("posts", "other"), None);
+        }
+
+        #[test]
+        fn from_str_rejects_invalid_entries() {
+            assert!(EmptyPayloadFns::from_str("posts/get_post").is_err());
+            assert!(EmptyPayloadFns::from_str("posts/get_post=none").is_err());
+            assert!(EmptyPayloadFns::from_str("get_post=nil").is_err());
+            assert!(EmptyPayloadFns::from_str("/get_post=nil").is_err());
+        }
+    }
+
+    mod cache_ttl_tests {
+        use super::*;
+        use std::time::Duration;
+
+        #[test]
+        fn from_str_parses_function_ttls() {
+            let cache_ttl_fns =
+                CacheTtlFns::from_str("posts/get_post=5000, posts/list=0, *=1000").unwrap();
+            assert_eq!(
+                cache_ttl_fns.get("posts", "get_post"),
+                Some(Duration::from_secs(5))
+            );
+            // A TTL of zero disables caching
+            assert_eq!(cache_ttl_fns.get("posts", "list"), None);
+            assert_eq!(
+                cache_ttl_fns.get("posts", "other"),
+                Some(Duration::from_secs(1))
+            );
+
+            let cache_ttl_fns = CacheTtlFns::from_str("posts/get_post=5000").unwrap();
+            assert_eq!(cache_ttl_fns.get("posts", "other"), None);
+        }
+
+        #[test]
+        fn from_str_rejects_invalid_entries() {
+            assert!(CacheTtlFns::from_str("posts/get_post").is_err());
+            assert!(CacheTtlFns::from_str("posts/get_post=soon").is_err());
+            assert!(CacheTtlFns::from_str("get_post=1000").is_err());
+        }
+    }
+
     mod allowed_fns_tests {
         use super::*;
 
@@ -495,5 +1332,66 @@ mod tests {
             );
             assert!(result.is_err());
         }
+
+        #[test]
+        fn additional_conductors_are_validated() {
+            let config = create_test_config();
+            let conductor = |name: &str, app_ids: &str| ConductorConfig {
+                name: name.to_string(),
+                admin_socket_addr: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 9999),
+                admin_ws_url: None,
+                allowed_app_ids: AllowedAppIds::from_str(app_ids).unwrap(),
+            };
+
+            // Valid conductor
+            let result = config
+                .clone()
+                .with_additional_conductors(vec![conductor("other", "app2")]);
+            assert!(result.is_ok());
+
+            // Duplicate name
+            let result = config.clone().with_additional_conductors(vec![
+                conductor("other", "app2"),
+                conductor("other", "app1"),
+            ]);
+            assert!(result.is_err());
+
+            // Same name as the default conductor
+            let result = config
+                .clone()
+                .with_additional_conductors(vec![conductor(DEFAULT_CONDUCTOR_NAME, "app2")]);
+            assert!(result.is_err());
+
+            // Missing allowed functions for app3
+            let result = config.with_additional_conductors(vec![conductor("other", "app3")]);
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn additional_conductors_are_used_for_app_checks() {
+            let mut config = create_test_config();
+            config
+                .allowed_fns
+                .insert("app3".to_string(), AllowedFns::All);
+            let other = ConductorConfig {
+                name: "other".to_string(),
+                admin_socket_addr: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 9999),
+                admin_ws_url: None,
+                allowed_app_ids: AllowedAppIds::from_str("app3").unwrap(),
+            };
+            let config = config
+                .with_additional_conductors(vec![other.clone()])
+                .unwrap();
+
+            assert!(config.is_app_allowed("app1"));
+            assert!(config.is_app_allowed("app3"));
+            assert_eq!(config.conductors().len(), 2);
+
+            let other_config = config.for_conductor(&other);
+            assert_eq!(other_config.admin_socket_addr.port(), 9999);
+            assert!(!other_config.is_app_allowed("app1"));
+            assert!(other_config.is_app_allowed("app3"));
+            assert!(other_config.additional_conductors.is_empty());
+        }
     }
 }
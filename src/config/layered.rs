@@ -0,0 +1,1123 @@
+//! Layered configuration sources.
+//!
+//! Settings can be provided by a configuration file, by environment variables and by command line
+//! flags. Each source is loaded into a [`ConfigLayer`] and the layers are merged into a
+//! [`LayeredConfig`], where flags override environment variables, which override the
+//! configuration file, which overrides the built-in defaults.
+//!
+//! Every effective value remembers the [`ConfigSource`] it came from, so that operators can find
+//! out why the gateway is running with a given setting.
+
+use super::{
+    ConfigParseError, ConfigParseResult, DEFAULT_ADMIN_POOL_SIZE, DEFAULT_APP_CALL_QUEUE_TIMEOUT,
+    DEFAULT_APP_INTERFACES_CACHE_TTL, DEFAULT_APP_KEEPALIVE_INTERVAL, DEFAULT_APP_WATCH_INTERVAL,
+    DEFAULT_CACHE_MAX_STALE, DEFAULT_DEGRADED_ERROR_RATE, DEFAULT_DEGRADED_FAILED_CONNECTS,
+    DEFAULT_DRAIN_TIMEOUT, DEFAULT_ENUM_TAG, DEFAULT_HEALTH_CHECK_INTERVAL,
+    DEFAULT_LATENCY_SHED_FRACTION, DEFAULT_MAX_APP_CONNECTIONS, DEFAULT_MAX_QUEUED_REQUESTS,
+    DEFAULT_METRICS_MAX_FUNCTIONS, DEFAULT_NOT_INSTALLED_CACHE_TTL, DEFAULT_PAYLOAD_LIMIT_BYTES,
+    DEFAULT_PAYLOAD_MAX_DEPTH, DEFAULT_PAYLOAD_MAX_OBJECT_KEYS, DEFAULT_PAYLOAD_MAX_STRING_BYTES,
+    DEFAULT_QUEUE_TIMEOUT, DEFAULT_REQUEST_ID_HEADER, DEFAULT_RESPONSE_CACHE_MAX_ENTRIES,
+    DEFAULT_SELF_CHECK_INTERVAL, DEFAULT_STATSD_INTERVAL, DEFAULT_STATSD_PREFIX,
+    DEFAULT_TIMESTAMP_FIELD, DEFAULT_UNHEALTHY_ERROR_RATE, DEFAULT_USAGE_EXPORT_INTERVAL,
+    DEFAULT_ZOME_CALL_TIMEOUT,
+};
+use crate::config::{AllowedFns, AppId, CacheTtlFns, ConductorName, EmptyPayloadFns};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Prefix of every environment variable read by the gateway.
+pub const ENV_PREFIX: &str = "HC_GW_";
+
+/// Setting key for the websocket URL of the Holochain admin interface.
+pub const ADMIN_WS_URL_KEY: &str = "admin_ws_url";
+/// Setting key for the address that the gateway binds to.
+pub const ADDRESS_KEY: &str = "address";
+/// Setting key for the port that the gateway binds to.
+pub const PORT_KEY: &str = "port";
+/// Setting key for binding the port with `SO_REUSEPORT`, so that it can be shared with another
+/// gateway process.
+pub const REUSE_PORT_KEY: &str = "reuse_port";
+/// Setting key for the payload limit in bytes.
+pub const PAYLOAD_LIMIT_BYTES_KEY: &str = "payload_limit_bytes";
+/// Setting key for the maximum nesting depth of arrays and objects in a payload.
+pub const PAYLOAD_MAX_DEPTH_KEY: &str = "payload_max_depth";
+/// Setting key for the maximum number of keys of an object in a payload.
+pub const PAYLOAD_MAX_OBJECT_KEYS_KEY: &str = "payload_max_object_keys";
+/// Setting key for the maximum length of a string in a payload, in bytes.
+pub const PAYLOAD_MAX_STRING_BYTES_KEY: &str = "payload_max_string_bytes";
+/// Setting key for the comma separated list of allowed app ids.
+pub const ALLOWED_APP_IDS_KEY: &str = "allowed_app_ids";
+/// Setting key for the maximum number of app connections.
+pub const MAX_APP_CONNECTIONS_KEY: &str = "max_app_connections";
+/// Setting key for the zome call timeout in milliseconds.
+pub const ZOME_CALL_TIMEOUT_MS_KEY: &str = "zome_call_timeout_ms";
+/// Setting key for enabling failover of zome calls between conductors.
+pub const CONDUCTOR_FAILOVER_KEY: &str = "conductor_failover";
+/// Setting key for the interval between conductor health checks in milliseconds.
+pub const HEALTH_CHECK_INTERVAL_MS_KEY: &str = "health_check_interval_ms";
+/// Setting key for the number of admin websocket connections per conductor.
+pub const ADMIN_POOL_SIZE_KEY: &str = "admin_pool_size";
+/// Setting key for the interval between app websocket keepalive checks in milliseconds.
+pub const APP_KEEPALIVE_INTERVAL_MS_KEY: &str = "app_keepalive_interval_ms";
+/// Setting key for the time in milliseconds after which an unused app websocket is closed.
+pub const APP_IDLE_TIMEOUT_MS_KEY: &str = "app_idle_timeout_ms";
+/// Setting key for the age in milliseconds after which an app websocket is replaced.
+pub const APP_MAX_AGE_MS_KEY: &str = "app_max_age_ms";
+/// Setting key for the number of requests after which an app websocket is replaced.
+pub const APP_MAX_REQUESTS_KEY: &str = "app_max_requests";
+/// Setting key for the number of zome calls in flight over an app websocket before another opens.
+pub const APP_MAX_IN_FLIGHT_KEY: &str = "app_max_in_flight";
+/// Setting key for authorizing signing credentials for all cells of an app when connecting.
+pub const EAGER_SIGNING_AUTHORIZATION_KEY: &str = "eager_signing_authorization";
+/// Setting key for connecting to each app through an app interface of its own.
+pub const DEDICATED_APP_INTERFACES_KEY: &str = "dedicated_app_interfaces";
+/// Setting key for the interval between checks for changed apps in milliseconds.
+pub const APP_WATCH_INTERVAL_MS_KEY: &str = "app_watch_interval_ms";
+/// Setting key for the time in milliseconds for which a lookup that found no app is remembered.
+pub const NOT_INSTALLED_CACHE_TTL_MS_KEY: &str = "not_installed_cache_ttl_ms";
+/// Setting key for the time in milliseconds for which a listing of the app interfaces is reused.
+pub const APP_INTERFACES_CACHE_TTL_MS_KEY: &str = "app_interfaces_cache_ttl_ms";
+/// Setting key for how zome calls are signed, either `credentials` or `lair`.
+pub const SIGNING_MODE_KEY: &str = "signing_mode";
+/// Setting key for the connection URL of the Lair keystore used to sign zome calls.
+pub const LAIR_CONNECTION_URL_KEY: &str = "lair_connection_url";
+/// Setting key for the path to a file holding the passphrase of the Lair keystore.
+pub const LAIR_PASSPHRASE_FILE_KEY: &str = "lair_passphrase_file";
+/// Setting key for the maximum number of zome calls made to an app at the same time.
+pub const MAX_APP_CONCURRENT_CALLS_KEY: &str = "max_app_concurrent_calls";
+/// Setting key for the time in milliseconds that a zome call waits when an app is at its limit.
+pub const APP_CALL_QUEUE_TIMEOUT_MS_KEY: &str = "app_call_queue_timeout_ms";
+/// Setting key for the maximum number of zome call requests handled at the same time.
+pub const MAX_IN_FLIGHT_REQUESTS_KEY: &str = "max_in_flight_requests";
+/// Setting key for the maximum number of zome call requests waiting to be handled.
+pub const MAX_QUEUED_REQUESTS_KEY: &str = "max_queued_requests";
+/// Setting key for the time in milliseconds that zome call requests wait to be handled.
+pub const QUEUE_TIMEOUT_MS_KEY: &str = "queue_timeout_ms";
+/// Setting key for the conductor latency in milliseconds above which zome call requests are shed.
+pub const LATENCY_SHED_THRESHOLD_MS_KEY: &str = "latency_shed_threshold_ms";
+/// Setting key for the share of zome call requests shed while conductors respond slowly.
+pub const LATENCY_SHED_FRACTION_KEY: &str = "latency_shed_fraction";
+/// Setting key for the time in milliseconds that requests in flight may take to finish when draining.
+pub const DRAIN_TIMEOUT_MS_KEY: &str = "drain_timeout_ms";
+/// Setting key for the bearer token that enables the management endpoints.
+pub const MANAGEMENT_TOKEN_KEY: &str = "management_token";
+/// Setting key for how binary values are rendered in responses and recognized in payloads.
+pub const BINARY_ENCODING_KEY: &str = "binary_encoding";
+/// Setting key for whether integers that exceed the precision of a JSON number are rendered as strings.
+pub const LARGE_INTEGERS_AS_STRINGS_KEY: &str = "large_integers_as_strings";
+/// Setting key for whether object keys are converted between camelCase and snake_case.
+pub const CAMEL_CASE_KEYS_KEY: &str = "camel_case_keys";
+/// Setting key for the names of the fields that hold timestamps.
+pub const TIMESTAMP_FIELDS_KEY: &str = "timestamp_fields";
+/// Setting key for the names of the fields that hold enums.
+pub const ENUM_FIELDS_KEY: &str = "enum_fields";
+/// Setting key for the name of the field that holds the variant of an internally tagged enum.
+pub const ENUM_TAG_KEY: &str = "enum_tag";
+/// Setting key for what happens when a zome call response doesn't match its schema.
+pub const RESPONSE_VALIDATION_KEY: &str = "response_validation";
+/// Setting key for the path to a JSON file holding the schemas of zome call responses.
+pub const RESPONSE_SCHEMAS_FILE_KEY: &str = "response_schemas_file";
+/// Setting key for the maximum number of functions that zome call metrics are recorded for separately.
+pub const METRICS_MAX_FUNCTIONS_KEY: &str = "metrics_max_functions";
+/// Setting key for the name of the header that holds the id of a request.
+pub const REQUEST_ID_HEADER_KEY: &str = "request_id_header";
+/// Setting key for the format of error responses.
+pub const ERROR_FORMAT_KEY: &str = "error_format";
+/// Setting key for where the access log is written, `stdout` or the path to a file.
+pub const ACCESS_LOG_KEY: &str = "access_log";
+/// Setting key for where the audit log is written, `stdout` or the path to a file.
+pub const AUDIT_LOG_KEY: &str = "audit_log";
+/// Setting key for the duration in milliseconds above which a request is logged as slow.
+pub const SLOW_REQUEST_THRESHOLD_MS_KEY: &str = "slow_request_threshold_ms";
+/// Setting key for the address of the StatsD server that metrics are pushed to.
+pub const STATSD_ADDRESS_KEY: &str = "statsd_address";
+/// Setting key for the prefix of the names of the metrics sent to StatsD.
+pub const STATSD_PREFIX_KEY: &str = "statsd_prefix";
+/// Setting key for the dialect of StatsD, `statsd` or `dogstatsd`.
+pub const STATSD_FLAVOR_KEY: &str = "statsd_flavor";
+/// Setting key for the interval in milliseconds at which gauges are sent to StatsD.
+pub const STATSD_INTERVAL_MS_KEY: &str = "statsd_interval_ms";
+/// Setting key for the interval in milliseconds at which usage summaries are logged.
+pub const USAGE_SUMMARY_INTERVAL_MS_KEY: &str = "usage_summary_interval_ms";
+/// Setting key for the path of the file that the usage of every app is appended to.
+pub const USAGE_EXPORT_FILE_KEY: &str = "usage_export_file";
+/// Setting key for the format of the exported usage, `csv` or `json`.
+pub const USAGE_EXPORT_FORMAT_KEY: &str = "usage_export_format";
+/// Setting key for the interval in milliseconds at which the usage is exported.
+pub const USAGE_EXPORT_INTERVAL_MS_KEY: &str = "usage_export_interval_ms";
+/// Setting key for the URL of the webhook that upstream outages are posted to.
+pub const ALERT_WEBHOOK_URL_KEY: &str = "alert_webhook_url";
+/// Setting key for the share of recent requests failing at which the gateway is degraded.
+pub const DEGRADED_ERROR_RATE_KEY: &str = "degraded_error_rate";
+/// Setting key for the share of recent requests failing at which the gateway is unhealthy.
+pub const UNHEALTHY_ERROR_RATE_KEY: &str = "unhealthy_error_rate";
+/// Setting key for the number of recent failed connects at which the gateway is degraded.
+pub const DEGRADED_FAILED_CONNECTS_KEY: &str = "degraded_failed_connects";
+/// Setting key for the maximum number of zome call responses in the response cache.
+pub const RESPONSE_CACHE_MAX_ENTRIES_KEY: &str = "response_cache_max_entries";
+/// Setting key for the time after expiry for which a cached response is served while it is
+/// refreshed, in milliseconds.
+pub const CACHE_MAX_STALE_MS_KEY: &str = "cache_max_stale_ms";
+/// Setting key for the URL of the Redis server that responses are cached in.
+pub const RESPONSE_CACHE_REDIS_URL_KEY: &str = "response_cache_redis_url";
+/// Setting key for the interval between self-checks of the gateway in milliseconds.
+pub const SELF_CHECK_INTERVAL_MS_KEY: &str = "self_check_interval_ms";
+/// Setting key for the path of the file that zome calls are recorded to.
+pub const RECORD_FILE_KEY: &str = "record_file";
+/// Setting key for the path of the recording that zome calls are replayed from.
+pub const REPLAY_FILE_KEY: &str = "replay_file";
+/// Prefix of the setting keys holding the allowed functions for an app, e.g. `allowed_fns.my_app`.
+pub const ALLOWED_FNS_KEY_PREFIX: &str = "allowed_fns.";
+/// Prefix of the setting keys holding the priority of an app, e.g. `app_priority.my_app`.
+pub const APP_PRIORITY_KEY_PREFIX: &str = "app_priority.";
+/// Prefix of the setting keys holding the functions of an app whose timestamps are formatted,
+/// e.g. `timestamp_fns.my_app`.
+pub const TIMESTAMP_FNS_KEY_PREFIX: &str = "timestamp_fns.";
+/// Prefix of the setting keys holding the payloads of the functions of an app when a request has
+/// no payload, e.g. `empty_payload.my_app`.
+pub const EMPTY_PAYLOAD_KEY_PREFIX: &str = "empty_payload.";
+/// Prefix of the setting keys holding the functions of an app whose enums are internally tagged,
+/// e.g. `enum_fns.my_app`.
+pub const ENUM_FNS_KEY_PREFIX: &str = "enum_fns.";
+/// Prefix of the setting keys holding the path to the transform script of an app, e.g.
+/// `transform_script.my_app`.
+pub const TRANSFORM_SCRIPT_KEY_PREFIX: &str = "transform_script.";
+/// Prefix of the setting keys holding the times for which the responses of the functions of an
+/// app are cached, e.g. `cache_ttl.my_app`.
+pub const CACHE_TTL_KEY_PREFIX: &str = "cache_ttl.";
+
+/// Default address that the gateway binds to.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1";
+
+/// Default port that the gateway binds to.
+pub const DEFAULT_PORT: u16 = 8090;
+
+/// Prefix of the setting keys describing additional conductors, e.g. `conductors.b.admin_ws_url`.
+///
+/// Each additional conductor takes an [`ADMIN_WS_URL_KEY`] and an [`ALLOWED_APP_IDS_KEY`] setting.
+pub const CONDUCTORS_KEY_PREFIX: &str = "conductors.";
+
+/// Name of the table holding the named profiles in the configuration file.
+pub const PROFILES_KEY: &str = "profiles";
+/// Name of the profile setting that selects the profile to inherit from.
+pub const INHERITS_KEY: &str = "inherits";
+
+const CONDUCTOR_KEYS: &[&str] = &[ADMIN_WS_URL_KEY, ALLOWED_APP_IDS_KEY];
+
+const FIXED_KEYS: &[&str] = &[
+    ADMIN_WS_URL_KEY,
+    ADDRESS_KEY,
+    PORT_KEY,
+    REUSE_PORT_KEY,
+    PAYLOAD_LIMIT_BYTES_KEY,
+    PAYLOAD_MAX_DEPTH_KEY,
+    PAYLOAD_MAX_OBJECT_KEYS_KEY,
+    PAYLOAD_MAX_STRING_BYTES_KEY,
+    ALLOWED_APP_IDS_KEY,
+    MAX_APP_CONNECTIONS_KEY,
+    ZOME_CALL_TIMEOUT_MS_KEY,
+    CONDUCTOR_FAILOVER_KEY,
+    HEALTH_CHECK_INTERVAL_MS_KEY,
+    ADMIN_POOL_SIZE_KEY,
+    APP_KEEPALIVE_INTERVAL_MS_KEY,
+    APP_IDLE_TIMEOUT_MS_KEY,
+    APP_MAX_AGE_MS_KEY,
+    APP_MAX_REQUESTS_KEY,
+    APP_MAX_IN_FLIGHT_KEY,
+    EAGER_SIGNING_AUTHORIZATION_KEY,
+    DEDICATED_APP_INTERFACES_KEY,
+    APP_WATCH_INTERVAL_MS_KEY,
+    NOT_INSTALLED_CACHE_TTL_MS_KEY,
+    APP_INTERFACES_CACHE_TTL_MS_KEY,
+    SIGNING_MODE_KEY,
+    LAIR_CONNECTION_URL_KEY,
+    LAIR_PASSPHRASE_FILE_KEY,
+    MAX_APP_CONCURRENT_CALLS_KEY,
+    APP_CALL_QUEUE_TIMEOUT_MS_KEY,
+    MAX_IN_FLIGHT_REQUESTS_KEY,
+    MAX_QUEUED_REQUESTS_KEY,
+    QUEUE_TIMEOUT_MS_KEY,
+    LATENCY_SHED_THRESHOLD_MS_KEY,
+    LATENCY_SHED_FRACTION_KEY,
+    DRAIN_TIMEOUT_MS_KEY,
+    MANAGEMENT_TOKEN_KEY,
+    BINARY_ENCODING_KEY,
+    LARGE_INTEGERS_AS_STRINGS_KEY,
+    TIMESTAMP_FIELDS_KEY,
+    ENUM_FIELDS_KEY,
+    ENUM_TAG_KEY,
+    CAMEL_CASE_KEYS_KEY,
+    RESPONSE_VALIDATION_KEY,
+    RESPONSE_SCHEMAS_FILE_KEY,
+    METRICS_MAX_FUNCTIONS_KEY,
+    REQUEST_ID_HEADER_KEY,
+    ERROR_FORMAT_KEY,
+    ACCESS_LOG_KEY,
+    AUDIT_LOG_KEY,
+    SLOW_REQUEST_THRESHOLD_MS_KEY,
+    STATSD_ADDRESS_KEY,
+    STATSD_PREFIX_KEY,
+    STATSD_FLAVOR_KEY,
+    STATSD_INTERVAL_MS_KEY,
+    USAGE_SUMMARY_INTERVAL_MS_KEY,
+    USAGE_EXPORT_FILE_KEY,
+    USAGE_EXPORT_FORMAT_KEY,
+    USAGE_EXPORT_INTERVAL_MS_KEY,
+    ALERT_WEBHOOK_URL_KEY,
+    DEGRADED_ERROR_RATE_KEY,
+    UNHEALTHY_ERROR_RATE_KEY,
+    DEGRADED_FAILED_CONNECTS_KEY,
+    SELF_CHECK_INTERVAL_MS_KEY,
+    RESPONSE_CACHE_MAX_ENTRIES_KEY,
+    CACHE_MAX_STALE_MS_KEY,
+    RESPONSE_CACHE_REDIS_URL_KEY,
+    RECORD_FILE_KEY,
+    REPLAY_FILE_KEY,
+];
+
+/// Prefixes of the per-app setting keys, with the prefixes of the environment variables that they
+/// are read from. The app id follows either prefix as is.
+const PER_APP_KEY_PREFIXES: &[(&str, &str)] = &[
+    (ALLOWED_FNS_KEY_PREFIX, "ALLOWED_FNS_"),
+    (APP_PRIORITY_KEY_PREFIX, "APP_PRIORITY_"),
+    (TIMESTAMP_FNS_KEY_PREFIX, "TIMESTAMP_FNS_"),
+    (EMPTY_PAYLOAD_KEY_PREFIX, "EMPTY_PAYLOAD_"),
+    (ENUM_FNS_KEY_PREFIX, "ENUM_FNS_"),
+    (TRANSFORM_SCRIPT_KEY_PREFIX, "TRANSFORM_SCRIPT_"),
+    (CACHE_TTL_KEY_PREFIX, "CACHE_TTL_"),
+];
+
+/// Where an effective configuration value came from.
+///
+/// Sources are ordered by precedence, a value from a later source overrides a value from an
+/// earlier one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigSource {
+    /// Built-in default value.
+    Default,
+    /// Configuration file.
+    File,
+    /// Environment variable.
+    Env,
+    /// Command line flag.
+    Flag,
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let source = match self {
+            ConfigSource::Default => "default",
+            ConfigSource::File => "file",
+            ConfigSource::Env => "env",
+            ConfigSource::Flag => "flag",
+        };
+        f.write_str(source)
+    }
+}
+
+/// A set of settings provided by a single configuration source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigLayer(BTreeMap<String, String>);
+
+impl ConfigLayer {
+    /// Set the value of a setting.
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.0.insert(key.into(), value.into());
+    }
+
+    /// Get the value of a setting, if this layer provides it.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// The built-in defaults.
+    pub fn defaults() -> Self {
+        let mut layer = Self::default();
+        layer.set(ADDRESS_KEY, DEFAULT_ADDRESS);
+        layer.set(PORT_KEY, DEFAULT_PORT.to_string());
+        layer.set(REUSE_PORT_KEY, "false");
+        layer.set(
+            PAYLOAD_LIMIT_BYTES_KEY,
+            DEFAULT_PAYLOAD_LIMIT_BYTES.to_string(),
+        );
+        layer.set(PAYLOAD_MAX_DEPTH_KEY, DEFAULT_PAYLOAD_MAX_DEPTH.to_string());
+        layer.set(
+            PAYLOAD_MAX_OBJECT_KEYS_KEY,
+            DEFAULT_PAYLOAD_MAX_OBJECT_KEYS.to_string(),
+        );
+        layer.set(
+            PAYLOAD_MAX_STRING_BYTES_KEY,
+            DEFAULT_PAYLOAD_MAX_STRING_BYTES.to_string(),
+        );
+        layer.set(
+            MAX_APP_CONNECTIONS_KEY,
+            DEFAULT_MAX_APP_CONNECTIONS.to_string(),
+        );
+        layer.set(
+            ZOME_CALL_TIMEOUT_MS_KEY,
+            DEFAULT_ZOME_CALL_TIMEOUT.as_millis().to_string(),
+        );
+        layer.set(CONDUCTOR_FAILOVER_KEY, "false");
+        layer.set(EAGER_SIGNING_AUTHORIZATION_KEY, "false");
+        layer.set(DEDICATED_APP_INTERFACES_KEY, "false");
+        layer.set(
+            HEALTH_CHECK_INTERVAL_MS_KEY,
+            DEFAULT_HEALTH_CHECK_INTERVAL.as_millis().to_string(),
+        );
+        layer.set(ADMIN_POOL_SIZE_KEY, DEFAULT_ADMIN_POOL_SIZE.to_string());
+        layer.set(
+            APP_KEEPALIVE_INTERVAL_MS_KEY,
+            DEFAULT_APP_KEEPALIVE_INTERVAL.as_millis().to_string(),
+        );
+        layer.set(
+            APP_WATCH_INTERVAL_MS_KEY,
+            DEFAULT_APP_WATCH_INTERVAL.as_millis().to_string(),
+        );
+        layer.set(
+            NOT_INSTALLED_CACHE_TTL_MS_KEY,
+            DEFAULT_NOT_INSTALLED_CACHE_TTL.as_millis().to_string(),
+        );
+        layer.set(
+            APP_INTERFACES_CACHE_TTL_MS_KEY,
+            DEFAULT_APP_INTERFACES_CACHE_TTL.as_millis().to_string(),
+        );
+        layer.set(SIGNING_MODE_KEY, "credentials");
+        layer.set(
+            APP_CALL_QUEUE_TIMEOUT_MS_KEY,
+            DEFAULT_APP_CALL_QUEUE_TIMEOUT.as_millis().to_string(),
+        );
+        layer.set(
+            MAX_QUEUED_REQUESTS_KEY,
+            DEFAULT_MAX_QUEUED_REQUESTS.to_string(),
+        );
+        layer.set(
+            QUEUE_TIMEOUT_MS_KEY,
+            DEFAULT_QUEUE_TIMEOUT.as_millis().to_string(),
+        );
+        layer.set(
+            LATENCY_SHED_FRACTION_KEY,
+            DEFAULT_LATENCY_SHED_FRACTION.to_string(),
+        );
+        layer.set(
+            DRAIN_TIMEOUT_MS_KEY,
+            DEFAULT_DRAIN_TIMEOUT.as_millis().to_string(),
+        );
+        layer.set(BINARY_ENCODING_KEY, "array");
+        layer.set(LARGE_INTEGERS_AS_STRINGS_KEY, "false");
+        layer.set(TIMESTAMP_FIELDS_KEY, DEFAULT_TIMESTAMP_FIELD);
+        layer.set(ENUM_TAG_KEY, DEFAULT_ENUM_TAG);
+        layer.set(CAMEL_CASE_KEYS_KEY, "false");
+        layer.set(RESPONSE_VALIDATION_KEY, "off");
+        layer.set(
+            METRICS_MAX_FUNCTIONS_KEY,
+            DEFAULT_METRICS_MAX_FUNCTIONS.to_string(),
+        );
+        layer.set(REQUEST_ID_HEADER_KEY, DEFAULT_REQUEST_ID_HEADER);
+        layer.set(ERROR_FORMAT_KEY, "json");
+        layer.set(STATSD_PREFIX_KEY, DEFAULT_STATSD_PREFIX);
+        layer.set(STATSD_FLAVOR_KEY, "statsd");
+        layer.set(
+            STATSD_INTERVAL_MS_KEY,
+            DEFAULT_STATSD_INTERVAL.as_millis().to_string(),
+        );
+        layer.set(USAGE_EXPORT_FORMAT_KEY, "csv");
+        layer.set(
+            DEGRADED_ERROR_RATE_KEY,
+            DEFAULT_DEGRADED_ERROR_RATE.to_string(),
+        );
+        layer.set(
+            UNHEALTHY_ERROR_RATE_KEY,
+            DEFAULT_UNHEALTHY_ERROR_RATE.to_string(),
+        );
+        layer.set(
+            DEGRADED_FAILED_CONNECTS_KEY,
+            DEFAULT_DEGRADED_FAILED_CONNECTS.to_string(),
+        );
+        layer.set(
+            USAGE_EXPORT_INTERVAL_MS_KEY,
+            DEFAULT_USAGE_EXPORT_INTERVAL.as_millis().to_string(),
+        );
+        layer.set(
+            RESPONSE_CACHE_MAX_ENTRIES_KEY,
+            DEFAULT_RESPONSE_CACHE_MAX_ENTRIES.to_string(),
+        );
+        layer.set(
+            CACHE_MAX_STALE_MS_KEY,
+            DEFAULT_CACHE_MAX_STALE.as_millis().to_string(),
+        );
+        layer.set(
+            SELF_CHECK_INTERVAL_MS_KEY,
+            DEFAULT_SELF_CHECK_INTERVAL.as_millis().to_string(),
+        );
+        layer
+    }
+
+    /// Load the settings from environment variables.
+    ///
+    /// Only variables starting with [`ENV_PREFIX`] that map to a known setting are considered,
+    /// everything else is ignored. See [`key_to_env_var`] for the naming scheme.
+    pub fn from_env_vars(vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut layer = Self::default();
+        for (name, value) in vars {
+            if let Some(key) = env_var_to_key(&name) {
+                layer.set(key, value);
+            }
+        }
+        layer
+    }
+
+    /// Load the settings from the contents of a TOML configuration file.
+    ///
+    /// Nested tables are flattened into dotted keys, so the allowed functions of an app can be
+    /// given in an `[allowed_fns]` table. Lists of strings are joined with commas.
+    ///
+    /// The top level settings form the base configuration. A file may also define named profiles
+    /// in `[profiles.<name>]` tables. When a `profile` is selected, its settings override the base
+    /// configuration. A profile can set `inherits = "<name>"` to start from the settings of another
+    /// profile rather than directly from the base configuration.
+    pub fn from_toml_str(contents: &str, profile: Option<&str>) -> ConfigParseResult<Self> {
+        let mut table = toml::from_str::<toml::Table>(contents)
+            .map_err(|e| ConfigParseError::Other(format!("Invalid configuration file: {e}")))?;
+
+        let profiles = match table.remove(PROFILES_KEY) {
+            Some(toml::Value::Table(profiles)) => profiles,
+            Some(_) => {
+                return Err(ConfigParseError::Other(format!(
+                    "Expected a table of profiles for {PROFILES_KEY}"
+                )));
+            }
+            None => toml::Table::new(),
+        };
+
+        let mut layer = Self::default();
+        flatten_toml_table("", table, &mut layer)?;
+
+        if let Some(profile) = profile {
+            // Apply the most distant ancestor first, so that each profile overrides the one it
+            // inherits from.
+            for profile_table in profile_chain(&profiles, profile)?.into_iter().rev() {
+                flatten_toml_table("", profile_table, &mut layer)?;
+            }
+        }
+
+        if let Some(key) = layer.0.keys().find(|key| !is_known_key(key)) {
+            return Err(ConfigParseError::Other(format!(
+                "Unknown setting in configuration file: {key}"
+            )));
+        }
+
+        Ok(layer)
+    }
+}
+
+/// The effective configuration, merged from all configuration sources.
+#[derive(Debug, Clone, Default)]
+pub struct LayeredConfig {
+    values: BTreeMap<String, (String, ConfigSource)>,
+}
+
+impl LayeredConfig {
+    /// Merge the given layers on top of the built-in defaults.
+    ///
+    /// Flags override environment variables, which override the configuration file.
+    pub fn new(file: ConfigLayer, env: ConfigLayer, flags: ConfigLayer) -> Self {
+        let mut values = BTreeMap::new();
+        for (source, layer) in [
+            (ConfigSource::Default, ConfigLayer::defaults()),
+            (ConfigSource::File, file),
+            (ConfigSource::Env, env),
+            (ConfigSource::Flag, flags),
+        ] {
+            for (key, value) in layer.0 {
+                values.insert(key, (value, source));
+            }
+        }
+
+        Self { values }
+    }
+
+    /// Get the effective value of a setting.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|(value, _)| value.as_str())
+    }
+
+    /// Parse the effective value of a setting.
+    pub fn get_parsed<T>(&self, key: &str) -> ConfigParseResult<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.get(key)
+            .map(|value| {
+                value.parse::<T>().map_err(|e| {
+                    ConfigParseError::Other(format!("Invalid value {value:?} for {key}: {e}"))
+                })
+            })
+            .transpose()
+    }
+
+    /// Get the source that the effective value of a setting came from.
+    pub fn source(&self, key: &str) -> Option<ConfigSource> {
+        self.values.get(key).map(|(_, source)| *source)
+    }
+
+    /// Iterate over all effective settings, ordered by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, ConfigSource)> {
+        self.values
+            .iter()
+            .map(|(key, (value, source))| (key.as_str(), value.as_str(), *source))
+    }
+
+    /// Names of the additional conductors that have at least one setting.
+    pub fn conductor_names(&self) -> BTreeSet<ConductorName> {
+        self.values
+            .keys()
+            .filter_map(|key| split_conductor_key(key))
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    /// Parse the allowed functions of every app that has an `allowed_fns` setting.
+    pub fn allowed_fns(&self) -> ConfigParseResult<HashMap<AppId, AllowedFns>> {
+        self.parse_per_app(ALLOWED_FNS_KEY_PREFIX, AllowedFns::from_str)
+    }
+
+    /// Parse the value of every app that has a setting with the given per-app key prefix.
+    fn parse_per_app<T, E>(
+        &self,
+        prefix: &str,
+        parse_value: impl Fn(&str) -> Result<T, E>,
+    ) -> ConfigParseResult<HashMap<AppId, T>>
+    where
+        ConfigParseError: From<E>,
+    {
+        self.values
+            .iter()
+            .filter_map(|(key, (value, _))| key.strip_prefix(prefix).map(|app_id| (app_id, value)))
+            .map(|(app_id, value)| Ok((app_id.to_string(), parse_value(value)?)))
+            .collect()
+    }
+
+    /// Parse the priority of every app that has an `app_priority` setting.
+    pub fn app_priorities(&self) -> ConfigParseResult<HashMap<AppId, u32>> {
+        self.parse_per_app(APP_PRIORITY_KEY_PREFIX, str::parse)
+    }
+
+    /// Parse the functions of every app that has a `timestamp_fns` setting.
+    pub fn timestamp_fns(&self) -> ConfigParseResult<HashMap<AppId, AllowedFns>> {
+        self.parse_per_app(TIMESTAMP_FNS_KEY_PREFIX, AllowedFns::from_str)
+    }
+
+    /// Parse the functions of every app that has an `enum_fns` setting.
+    pub fn enum_fns(&self) -> ConfigParseResult<HashMap<AppId, AllowedFns>> {
+        self.parse_per_app(ENUM_FNS_KEY_PREFIX, AllowedFns::from_str)
+    }
+
+    /// The paths to the transform scripts of every app that has a `transform_script` setting.
+    pub fn transform_scripts(&self) -> HashMap<AppId, PathBuf> {
+        self.values
+            .iter()
+            .filter_map(|(key, (value, _))| {
+                key.strip_prefix(TRANSFORM_SCRIPT_KEY_PREFIX)
+                    .map(|app_id| (app_id.to_string(), PathBuf::from(value)))
+            })
+            .collect()
+    }
+
+    /// Parse the cache TTLs of every app that has a `cache_ttl` setting.
+    pub fn cache_ttl_fns(&self) -> ConfigParseResult<HashMap<AppId, CacheTtlFns>> {
+        self.parse_per_app(CACHE_TTL_KEY_PREFIX, CacheTtlFns::from_str)
+    }
+
+    /// Parse the empty payloads of every app that has an `empty_payload` setting.
+    pub fn empty_payload_fns(&self) -> ConfigParseResult<HashMap<AppId, EmptyPayloadFns>> {
+        self.parse_per_app(EMPTY_PAYLOAD_KEY_PREFIX, EmptyPayloadFns::from_str)
+    }
+}
+
+/// The key of a setting for the additional conductor with the given name.
+pub fn conductor_key(name: &str, setting: &str) -> String {
+    format!("{CONDUCTORS_KEY_PREFIX}{name}.{setting}")
+}
+
+/// The name of the environment variable that provides the setting with the given key.
+///
+/// Fixed settings are upper cased, e.g. `max_app_connections` is read from
+/// `HC_GW_MAX_APP_CONNECTIONS`. Per-app settings keep the app id as is, so `allowed_fns.my_app` is
+/// read from `HC_GW_ALLOWED_FNS_my_app`. Settings of additional conductors are suffixed with the
+/// conductor name, so `conductors.b.admin_ws_url` is read from `HC_GW_ADMIN_WS_URL_b`.
+pub fn key_to_env_var(key: &str) -> String {
+    for (key_prefix, env_var_prefix) in PER_APP_KEY_PREFIXES {
+        if let Some(app_id) = key.strip_prefix(key_prefix) {
+            return format!("{ENV_PREFIX}{env_var_prefix}{app_id}");
+        }
+    }
+
+    if let Some((name, setting)) = split_conductor_key(key) {
+        return format!("{ENV_PREFIX}{}_{name}", setting.to_uppercase());
+    }
+
+    format!("{ENV_PREFIX}{}", key.to_uppercase())
+}
+
+fn env_var_to_key(name: &str) -> Option<String> {
+    let name = name.strip_prefix(ENV_PREFIX)?;
+
+    for (key_prefix, env_var_prefix) in PER_APP_KEY_PREFIXES {
+        if let Some(app_id) = name.strip_prefix(env_var_prefix) {
+            return Some(format!("{key_prefix}{app_id}"));
+        }
+    }
+
+    for setting in CONDUCTOR_KEYS {
+        if let Some(conductor_name) = name
+            .strip_prefix(&setting.to_uppercase())
+            .and_then(|rest| rest.strip_prefix('_'))
+        {
+            return Some(conductor_key(conductor_name, setting));
+        }
+    }
+
+    FIXED_KEYS
+        .iter()
+        .find(|key| key.to_uppercase() == name)
+        .map(|key| key.to_string())
+}
+
+fn is_known_key(key: &str) -> bool {
+    FIXED_KEYS.contains(&key)
+        || PER_APP_KEY_PREFIXES.iter().any(|(key_prefix, _)| {
+            key.strip_prefix(key_prefix)
+                .is_some_and(|app_id| !app_id.is_empty())
+        })
+        || split_conductor_key(key).is_some()
+}
+
+/// Split a key of an additional conductor setting into the conductor name and the setting.
+fn split_conductor_key(key: &str) -> Option<(&str, &str)> {
+    key.strip_prefix(CONDUCTORS_KEY_PREFIX)?
+        .rsplit_once('.')
+        .filter(|(name, setting)| !name.is_empty() && CONDUCTOR_KEYS.contains(setting))
+}
+
+/// Collect the tables of the given profile and of all profiles it inherits from, starting with the
+/// selected profile.
+fn profile_chain(profiles: &toml::Table, profile: &str) -> ConfigParseResult<Vec<toml::Table>> {
+    let mut visited = Vec::new();
+    let mut chain = Vec::new();
+    let mut next = Some(profile.to_string());
+
+    while let Some(name) = next {
+        if visited.contains(&name) {
+            return Err(ConfigParseError::Other(format!(
+                "Profile {name} inherits from itself"
+            )));
+        }
+
+        let Some(toml::Value::Table(table)) = profiles.get(&name) else {
+            return Err(ConfigParseError::Other(format!(
+                "Profile {name} is not defined in the configuration file"
+            )));
+        };
+
+        let mut table = table.clone();
+        next = match table.remove(INHERITS_KEY) {
+            Some(toml::Value::String(parent)) => Some(parent),
+            Some(_) => {
+                return Err(ConfigParseError::Other(format!(
+                    "Expected a profile name for {INHERITS_KEY} in profile {name}"
+                )));
+            }
+            None => None,
+        };
+
+        visited.push(name);
+        chain.push(table);
+    }
+
+    Ok(chain)
+}
+
+fn flatten_toml_table(
+    prefix: &str,
+    table: toml::Table,
+    layer: &mut ConfigLayer,
+) -> ConfigParseResult<()> {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{prefix}.{key}")
+        };
+
+        match value {
+            toml::Value::Table(table) => flatten_toml_table(&key, table, layer)?,
+            toml::Value::String(value) => layer.set(key, value),
+            toml::Value::Array(values) => {
+                let values = values
+                    .into_iter()
+                    .map(|value| match value {
+                        toml::Value::String(value) => Ok(value),
+                        other => Err(ConfigParseError::Other(format!(
+                            "Expected a list of strings for {key}, found {other}"
+                        ))),
+                    })
+                    .collect::<ConfigParseResult<Vec<_>>>()?;
+                layer.set(key, values.join(","));
+            }
+            other => layer.set(key, other.to_string()),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EmptyPayload;
+
+    fn layer(values: &[(&str, &str)]) -> ConfigLayer {
+        let mut layer = ConfigLayer::default();
+        for (key, value) in values {
+            layer.set(*key, *value);
+        }
+        layer
+    }
+
+    #[test]
+    fn flags_override_env_override_file_override_defaults() {
+        let file = layer(&[
+            (PORT_KEY, "1000"),
+            (ADDRESS_KEY, "0.0.0.0"),
+            ("allowed_app_ids", "a"),
+        ]);
+        let env = layer(&[(PORT_KEY, "2000"), (ADDRESS_KEY, "::1")]);
+        let flags = layer(&[(PORT_KEY, "3000")]);
+
+        let config = LayeredConfig::new(file, env, flags);
+
+        assert_eq!(config.get(PORT_KEY), Some("3000"));
+        assert_eq!(config.source(PORT_KEY), Some(ConfigSource::Flag));
+        assert_eq!(config.get(ADDRESS_KEY), Some("::1"));
+        assert_eq!(config.source(ADDRESS_KEY), Some(ConfigSource::Env));
+        assert_eq!(config.get(ALLOWED_APP_IDS_KEY), Some("a"));
+        assert_eq!(config.source(ALLOWED_APP_IDS_KEY), Some(ConfigSource::File));
+        assert_eq!(
+            config.source(MAX_APP_CONNECTIONS_KEY),
+            Some(ConfigSource::Default)
+        );
+        assert_eq!(config.get(ADMIN_WS_URL_KEY), None);
+    }
+
+    #[test]
+    fn env_vars_map_to_setting_keys() {
+        let env = ConfigLayer::from_env_vars([
+            (
+                "HC_GW_ADMIN_WS_URL".to_string(),
+                "ws://localhost:1".to_string(),
+            ),
+            ("HC_GW_ALLOWED_FNS_my_App".to_string(), "*".to_string()),
+            ("HC_GW_UNKNOWN".to_string(), "ignored".to_string()),
+            ("PATH".to_string(), "/bin".to_string()),
+        ]);
+
+        assert_eq!(
+            env,
+            layer(&[
+                (ADMIN_WS_URL_KEY, "ws://localhost:1"),
+                ("allowed_fns.my_App", "*")
+            ])
+        );
+        assert_eq!(
+            key_to_env_var("allowed_fns.my_App"),
+            "HC_GW_ALLOWED_FNS_my_App"
+        );
+        assert_eq!(key_to_env_var(PORT_KEY), "HC_GW_PORT");
+    }
+
+    #[test]
+    fn toml_file_is_flattened() {
+        let file = ConfigLayer::from_toml_str(
+            r#"
+            admin_ws_url = "ws://localhost:8888"
+            port = 9000
+            allowed_app_ids = ["app1", "app2"]
+
+            [allowed_fns]
+            app1 = "*"
+            app2 = ["zome1/fn1", "zome2/fn2"]
+            "#,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(file.get(ADMIN_WS_URL_KEY), Some("ws://localhost:8888"));
+        assert_eq!(file.get(PORT_KEY), Some("9000"));
+        assert_eq!(file.get(ALLOWED_APP_IDS_KEY), Some("app1,app2"));
+        assert_eq!(file.get("allowed_fns.app1"), Some("*"));
+        assert_eq!(file.get("allowed_fns.app2"), Some("zome1/fn1,zome2/fn2"));
+    }
+
+    #[test]
+    fn toml_file_with_unknown_setting_is_rejected() {
+        let result = ConfigLayer::from_toml_str("max_connections = 10", None);
+        assert!(result.is_err());
+
+        let result = ConfigLayer::from_toml_str("allowed_app_ids = [1, 2]", None);
+        assert!(result.is_err());
+    }
+
+    const PROFILES_TOML: &str = r#"
+        admin_ws_url = "ws://localhost:8888"
+        zome_call_timeout_ms = 1000
+        allowed_app_ids = "app1"
+
+        [profiles.dev]
+        zome_call_timeout_ms = 30000
+
+        [profiles.staging]
+        allowed_app_ids = "app1,app2"
+        max_app_connections = 10
+
+        [profiles.prod]
+        inherits = "staging"
+        max_app_connections = 100
+
+        [profiles.loop]
+        inherits = "loop"
+        "#;
+
+    #[test]
+    fn toml_file_without_profile_uses_base_settings() {
+        let file = ConfigLayer::from_toml_str(PROFILES_TOML, None).unwrap();
+
+        assert_eq!(file.get(ZOME_CALL_TIMEOUT_MS_KEY), Some("1000"));
+        assert_eq!(file.get(ALLOWED_APP_IDS_KEY), Some("app1"));
+        assert_eq!(file.get(MAX_APP_CONNECTIONS_KEY), None);
+    }
+
+    #[test]
+    fn toml_profile_overrides_base_settings() {
+        let file = ConfigLayer::from_toml_str(PROFILES_TOML, Some("dev")).unwrap();
+
+        assert_eq!(file.get(ADMIN_WS_URL_KEY), Some("ws://localhost:8888"));
+        assert_eq!(file.get(ZOME_CALL_TIMEOUT_MS_KEY), Some("30000"));
+        assert_eq!(file.get(ALLOWED_APP_IDS_KEY), Some("app1"));
+    }
+
+    #[test]
+    fn toml_profile_inherits_from_other_profile() {
+        let file = ConfigLayer::from_toml_str(PROFILES_TOML, Some("prod")).unwrap();
+
+        assert_eq!(file.get(ZOME_CALL_TIMEOUT_MS_KEY), Some("1000"));
+        assert_eq!(file.get(ALLOWED_APP_IDS_KEY), Some("app1,app2"));
+        assert_eq!(file.get(MAX_APP_CONNECTIONS_KEY), Some("100"));
+        assert_eq!(file.get(INHERITS_KEY), None);
+    }
+
+    #[test]
+    fn toml_profile_errors() {
+        // Unknown profile
+        let result = ConfigLayer::from_toml_str(PROFILES_TOML, Some("qa"));
+        assert!(result.is_err());
+
+        // Inheritance cycle
+        let result = ConfigLayer::from_toml_str(PROFILES_TOML, Some("loop"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn conductor_settings_are_read_from_all_sources() {
+        let file = ConfigLayer::from_toml_str(
+            r#"
+            [conductors.b]
+            admin_ws_url = "ws://localhost:2"
+            allowed_app_ids = ["app2"]
+            "#,
+            None,
+        )
+        .unwrap();
+        let env = ConfigLayer::from_env_vars([
+            (
+                "HC_GW_ADMIN_WS_URL_c".to_string(),
+                "ws://localhost:3".to_string(),
+            ),
+            ("HC_GW_ALLOWED_APP_IDS_c".to_string(), "app3".to_string()),
+            ("HC_GW_ALLOWED_APP_IDS".to_string(), "app1".to_string()),
+        ]);
+
+        let config = LayeredConfig::new(file, env, ConfigLayer::default());
+
+        assert_eq!(
+            config.conductor_names().into_iter().collect::<Vec<_>>(),
+            vec!["b".to_string(), "c".to_string()]
+        );
+        assert_eq!(
+            config.get(&conductor_key("b", ADMIN_WS_URL_KEY)),
+            Some("ws://localhost:2")
+        );
+        assert_eq!(
+            config.get(&conductor_key("c", ALLOWED_APP_IDS_KEY)),
+            Some("app3")
+        );
+        assert_eq!(config.get(ALLOWED_APP_IDS_KEY), Some("app1"));
+        assert_eq!(
+            key_to_env_var(&conductor_key("c", ADMIN_WS_URL_KEY)),
+            "HC_GW_ADMIN_WS_URL_c"
+        );
+    }
+
+    #[test]
+    fn settings_are_parsed_with_their_key_in_errors() {
+        let env = layer(&[(CONDUCTOR_FAILOVER_KEY, "true"), (PORT_KEY, "http")]);
+        let config = LayeredConfig::new(ConfigLayer::default(), env, ConfigLayer::default());
+
+        assert_eq!(
+            config.get_parsed::<bool>(CONDUCTOR_FAILOVER_KEY).unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            config
+                .get_parsed::<u64>(HEALTH_CHECK_INTERVAL_MS_KEY)
+                .unwrap(),
+            Some(DEFAULT_HEALTH_CHECK_INTERVAL.as_millis() as u64)
+        );
+        assert_eq!(config.get_parsed::<u16>(ADMIN_WS_URL_KEY).unwrap(), None);
+
+        let err = config.get_parsed::<u16>(PORT_KEY).unwrap_err();
+        assert!(err.to_string().contains(PORT_KEY));
+    }
+
+    #[test]
+    fn allowed_fns_are_parsed_from_all_sources() {
+        let file = layer(&[("allowed_fns.app1", "zome1/fn1")]);
+        let env = layer(&[("allowed_fns.app2", "*")]);
+
+        let config = LayeredConfig::new(file, env, ConfigLayer::default());
+        let allowed_fns = config.allowed_fns().unwrap();
+
+        assert_eq!(allowed_fns.len(), 2);
+        assert!(matches!(allowed_fns["app1"], AllowedFns::Restricted(_)));
+        assert!(matches!(allowed_fns["app2"], AllowedFns::All));
+    }
+
+    #[test]
+    fn timestamp_fns_are_parsed_from_env_vars() {
+        let env = ConfigLayer::from_env_vars([(
+            "HC_GW_TIMESTAMP_FNS_my_App".to_string(),
+            "posts/get_post".to_string(),
+        )]);
+        assert_eq!(env.get("timestamp_fns.my_App"), Some("posts/get_post"));
+        assert_eq!(
+            key_to_env_var("timestamp_fns.my_App"),
+            "HC_GW_TIMESTAMP_FNS_my_App"
+        );
+
+        let config = LayeredConfig::new(ConfigLayer::default(), env, ConfigLayer::default());
+        let timestamp_fns = config.timestamp_fns().unwrap();
+        assert!(timestamp_fns["my_App"].contains("posts", "get_post"));
+        assert!(!timestamp_fns["my_App"].contains("posts", "get_posts"));
+    }
+
+    #[test]
+    fn enum_fns_are_parsed_from_env_vars() {
+        let env = ConfigLayer::from_env_vars([(
+            "HC_GW_ENUM_FNS_my_App".to_string(),
+            "posts/get_post".to_string(),
+        )]);
+        assert_eq!(env.get("enum_fns.my_App"), Some("posts/get_post"));
+        assert_eq!(key_to_env_var("enum_fns.my_App"), "HC_GW_ENUM_FNS_my_App");
+
+        let config = LayeredConfig::new(ConfigLayer::default(), env, ConfigLayer::default());
+        let enum_fns = config.enum_fns().unwrap();
+        assert!(enum_fns["my_App"].contains("posts", "get_post"));
+        assert!(!enum_fns["my_App"].contains("posts", "get_posts"));
+    }
+
+    #[test]
+    fn transform_scripts_are_read_from_env_vars() {
+        let env = ConfigLayer::from_env_vars([(
+            "HC_GW_TRANSFORM_SCRIPT_my_App".to_string(),
+            "/etc/hc-http-gw/my_app.rhai".to_string(),
+        )]);
+        assert_eq!(
+            env.get("transform_script.my_App"),
+            Some("/etc/hc-http-gw/my_app.rhai")
+        );
+        assert_eq!(
+            key_to_env_var("transform_script.my_App"),
+            "HC_GW_TRANSFORM_SCRIPT_my_App"
+        );
+
+        let config = LayeredConfig::new(ConfigLayer::default(), env, ConfigLayer::default());
+        assert_eq!(
+            config.transform_scripts(),
+            HashMap::from([(
+                "my_App".to_string(),
+                PathBuf::from("/etc/hc-http-gw/my_app.rhai")
+            )])
+        );
+    }
+
+    #[test]
+    fn cache_ttl_fns_are_parsed_from_env_vars() {
+        let env = ConfigLayer::from_env_vars([(
+            "HC_GW_CACHE_TTL_my_App".to_string(),
+            "posts/list_posts=5000".to_string(),
+        )]);
+        assert_eq!(env.get("cache_ttl.my_App"), Some("posts/list_posts=5000"));
+        assert_eq!(key_to_env_var("cache_ttl.my_App"), "HC_GW_CACHE_TTL_my_App");
+
+        let config = LayeredConfig::new(ConfigLayer::default(), env, ConfigLayer::default());
+        let cache_ttl_fns = config.cache_ttl_fns().unwrap();
+        assert_eq!(
+            cache_ttl_fns["my_App"].get("posts", "list_posts"),
+            Some(std::time::Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn empty_payload_fns_are_parsed_from_env_vars() {
+        let env = ConfigLayer::from_env_vars([(
+            "HC_GW_EMPTY_PAYLOAD_my_App".to_string(),
+            "posts/get_post=nil".to_string(),
+        )]);
+        assert_eq!(env.get("empty_payload.my_App"), Some("posts/get_post=nil"));
+        assert_eq!(
+            key_to_env_var("empty_payload.my_App"),
+            "HC_GW_EMPTY_PAYLOAD_my_App"
+        );
+
+        let config = LayeredConfig::new(ConfigLayer::default(), env, ConfigLayer::default());
+        let empty_payload_fns = config.empty_payload_fns().unwrap();
+        assert_eq!(
+            empty_payload_fns["my_App"].get("posts", "get_post"),
+            Some(EmptyPayload::Nil)
+        );
+    }
+
+    #[test]
+    fn app_priorities_are_parsed_from_env_vars() {
+        let env = ConfigLayer::from_env_vars([(
+            "HC_GW_APP_PRIORITY_paid_App".to_string(),
+            "10".to_string(),
+        )]);
+        assert_eq!(env.get("app_priority.paid_App"), Some("10"));
+        assert_eq!(
+            key_to_env_var("app_priority.paid_App"),
+            "HC_GW_APP_PRIORITY_paid_App"
+        );
+
+        let config = LayeredConfig::new(ConfigLayer::default(), env, ConfigLayer::default());
+        assert_eq!(
+            config.app_priorities().unwrap(),
+            HashMap::from([("paid_App".to_string(), 10)])
+        );
+
+        let env = layer(&[("app_priority.paid_App", "high")]);
+        let config = LayeredConfig::new(ConfigLayer::default(), env, ConfigLayer::default());
+        assert!(config.app_priorities().is_err());
+    }
+}
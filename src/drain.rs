@@ -0,0 +1,150 @@
+//! Draining of the gateway ahead of a rolling deploy.
+//!
+//! Once draining starts, new requests are rejected with status 503 and `Connection: close`, so
+//! that load balancers and clients move on to another instance, while the requests that are
+//! already in flight are allowed to finish.
+
+use crate::HcHttpGatewayError;
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Handle for draining the gateway.
+///
+/// Clones share the same state, so a handle can be taken from the service before it is run and
+/// used to start draining from elsewhere, for example a signal handler.
+#[derive(Debug, Clone)]
+pub struct DrainHandle {
+    draining: Arc<watch::Sender<bool>>,
+    in_flight: Arc<watch::Sender<usize>>,
+}
+
+impl Default for DrainHandle {
+    fn default() -> Self {
+        Self {
+            draining: Arc::new(watch::Sender::new(false)),
+            in_flight: Arc::new(watch::Sender::new(0)),
+        }
+    }
+}
+
+impl DrainHandle {
+    /// Start draining. Does nothing if the gateway is already draining.
+    pub fn drain(&self) {
+        if !self.draining.send_replace(true) {
+            tracing::info!(
+                in_flight = *self.in_flight.borrow(),
+                "Draining, new requests are rejected"
+            );
+        }
+    }
+
+    /// Whether the gateway is draining.
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// Wait until draining has started and the requests in flight have finished, or until
+    /// `timeout` has passed since draining started.
+    pub(crate) async fn drained(&self, timeout: Duration) {
+        let mut draining = self.draining.subscribe();
+        // The sender is held by this handle, so it can't be closed while waiting
+        let _ = draining.wait_for(|draining| *draining).await;
+
+        let mut in_flight = self.in_flight.subscribe();
+        let finished =
+            tokio::time::timeout(timeout, in_flight.wait_for(|in_flight| *in_flight == 0))
+                .await
+                .is_ok();
+        if finished {
+            tracing::info!("Drained, all requests in flight have finished");
+        } else {
+            tracing::warn!(
+                in_flight = *self.in_flight.borrow(),
+                "Drain timeout elapsed with requests still in flight"
+            );
+        }
+    }
+}
+
+/// Counts a request as in flight until it is dropped, whether it completed or was cancelled.
+struct InFlight(Arc<watch::Sender<usize>>);
+
+impl InFlight {
+    fn new(drain: &DrainHandle) -> Self {
+        drain.in_flight.send_modify(|in_flight| *in_flight += 1);
+        Self(drain.in_flight.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.send_modify(|in_flight| *in_flight -= 1);
+    }
+}
+
+/// Middleware that rejects requests while the gateway is draining, and otherwise keeps track of
+/// the requests in flight.
+pub(crate) async fn reject_when_draining(
+    State(drain): State<DrainHandle>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Counted before checking, so that a drain that starts in between still waits for the request
+    let _in_flight = InFlight::new(&drain);
+    if drain.is_draining() {
+        return HcHttpGatewayError::Draining.into_response();
+    }
+
+    let mut response = next.run(request).await;
+    if drain.is_draining() {
+        // Draining started while the request was in flight, so the connection won't be reused
+        response
+            .headers_mut()
+            .insert(header::CONNECTION, HeaderValue::from_static("close"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drained_waits_for_requests_in_flight() {
+        let drain = DrainHandle::default();
+        let in_flight = InFlight::new(&drain);
+
+        drain.drain();
+        let drained = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.drained(Duration::from_secs(10)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!drained.is_finished());
+
+        drop(in_flight);
+        tokio::time::timeout(Duration::from_secs(1), drained)
+            .await
+            .expect("Drain did not finish")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn drained_gives_up_after_timeout() {
+        let drain = DrainHandle::default();
+        let _in_flight = InFlight::new(&drain);
+
+        drain.drain();
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            drain.drained(Duration::from_millis(10)),
+        )
+        .await
+        .expect("Drain did not time out");
+    }
+}
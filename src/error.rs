@@ -1,12 +1,25 @@
 //! hc-http-gw error types
 
 use crate::app_selection::AppSelectionError;
+use crate::config::{ConfigParseError, ConfigParseResult};
+use crate::request_id::current_request_id;
 use axum::Json;
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use holochain_client::ConductorApiError;
 use holochain_conductor_api::ExternalApiWireError;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Number of seconds after which clients are asked to retry a request that was shed or that the
+/// gateway was too busy to handle.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Media type of the problem documents of [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807).
+pub const PROBLEM_JSON: &str = "application/problem+json";
 
 /// Core HTTP Gateway error type
 #[derive(thiserror::Error, Debug)]
@@ -31,25 +44,162 @@ pub enum HcHttpGatewayError {
     HolochainError(#[from] holochain_client::ConductorApiError),
     /// Error returned when a connection cannot be made to the upstream Holochain service
     #[error("The upstream Holochain service could not be reached")]
-    UpstreamUnavailable,
+    UpstreamUnavailable(UpstreamConnection),
+    /// Error returned when the connection to the upstream Holochain service was lost after a zome
+    /// call was sent over it, so that the call may have been made
+    #[error("The connection to the upstream Holochain service was lost during the call")]
+    UpstreamLost(UpstreamConnection),
+    /// Error returned when an app already has as many zome calls in progress as it is permitted,
+    /// and none of them finished in time for another call to be made
+    #[error("Too many concurrent requests for app {0}")]
+    AppBusy(String),
+    /// Error returned when the gateway is handling as many requests as it is permitted and no more
+    /// requests can wait to be handled
+    #[error("The gateway is overloaded, retry later")]
+    Overloaded,
+    /// Error returned when a zome call doesn't complete within the time given to it
+    #[error("The zome call timed out")]
+    ZomeCallTimeout,
+    /// Error returned when the gateway is draining ahead of shutting down and no longer accepts
+    /// requests
+    #[error("The gateway is shutting down")]
+    Draining,
+    /// Error returned when a zome call response doesn't match the schema registered for the
+    /// function, in strict validation mode
+    #[error("The zome call response doesn't match its schema: {0}")]
+    ResponseSchemaMismatch(String),
+    /// Error returned when a zome call response can't be decoded from msgpack
+    #[error("The zome call response could not be decoded: {0}")]
+    ResponseDecodeError(String),
+    /// Error returned when a transform fails to rewrite a payload or response
+    #[error("A transform failed: {0}")]
+    TransformError(String),
     /// Handle errors specific to app selection
     #[error("Error selecting a valid app: {0}")]
     AppSelectionError(#[from] AppSelectionError),
+    /// Error returned when the gateway panicked while handling a request
+    #[error("The gateway failed to handle the request")]
+    Panicked,
+}
+
+/// The connection to the upstream Holochain service that could not be made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamConnection {
+    /// The admin websocket of a conductor
+    Admin,
+    /// An app websocket of a conductor
+    App,
+    /// The Lair keystore that signs zome calls
+    Keystore,
+    /// The Redis server that zome call responses are cached in
+    ResponseCache,
 }
 
 /// Gateway result type.
 pub type HcHttpGatewayResult<T> = Result<T, HcHttpGatewayError>;
 
 /// Error format returned to the caller.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ErrorResponse {
     /// The error message
     pub error: String,
+    /// The connection to Holochain that failed, if the upstream service could not be reached or
+    /// was lost during the call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<UpstreamConnection>,
+    /// The id of the request, to be quoted when reporting the error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 impl From<String> for ErrorResponse {
     fn from(value: String) -> Self {
-        Self { error: value }
+        Self {
+            error: value,
+            upstream: None,
+            request_id: None,
+        }
+    }
+}
+
+/// Error format of the [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807) problem documents
+/// returned to callers that ask for them, see [`ErrorFormat`].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProblemDetails {
+    /// URI identifying the type of problem, `about:blank` as problems are described by their
+    /// status
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// The reason phrase of the status
+    pub title: String,
+    /// The status of the response
+    pub status: u16,
+    /// The error message
+    pub detail: String,
+    /// The id of the request, to be quoted when reporting the error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// The connection to Holochain that failed, if the upstream service could not be reached or
+    /// was lost during the call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<UpstreamConnection>,
+}
+
+impl ProblemDetails {
+    fn new(status: StatusCode, error: ErrorResponse) -> Self {
+        Self {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: error.error,
+            instance: error.request_id,
+            upstream: error.upstream,
+        }
+    }
+}
+
+/// The format of error responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// An [`ErrorResponse`], unless the request accepts `application/problem+json`, in which case
+    /// a [`ProblemDetails`] document.
+    #[default]
+    Json,
+    /// A [`ProblemDetails`] document, for every request.
+    Problem,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = ConfigParseError;
+
+    /// Expected format
+    /// - One of `json` or `problem`
+    fn from_str(s: &str) -> ConfigParseResult<Self> {
+        match s.trim() {
+            "json" => Ok(ErrorFormat::Json),
+            "problem" => Ok(ErrorFormat::Problem),
+            s => Err(ConfigParseError::Other(format!(
+                "Unknown error format {s:?}, expected \"json\" or \"problem\""
+            ))),
+        }
+    }
+}
+
+impl ErrorFormat {
+    /// Whether the errors of a request with the given headers are returned as problem documents.
+    fn is_problem(self, headers: &HeaderMap) -> bool {
+        self == ErrorFormat::Problem
+            || headers
+                .get_all(header::ACCEPT)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(|media_range| {
+                    media_range.split(';').next().is_some_and(|media_type| {
+                        media_type.trim().eq_ignore_ascii_case(PROBLEM_JSON)
+                    })
+                })
     }
 }
 
@@ -61,10 +211,25 @@ impl HcHttpGatewayError {
             HcHttpGatewayError::UnauthorizedFunction { .. } => {
                 (StatusCode::FORBIDDEN, self.to_string())
             }
-            HcHttpGatewayError::UpstreamUnavailable => (
+            HcHttpGatewayError::UpstreamUnavailable(_) => (
                 StatusCode::BAD_GATEWAY,
                 "Could not connect to Holochain".to_string(),
             ),
+            HcHttpGatewayError::UpstreamLost(_) => (
+                StatusCode::BAD_GATEWAY,
+                "Lost the connection to Holochain during the call".to_string(),
+            ),
+            HcHttpGatewayError::ResponseSchemaMismatch(_)
+            | HcHttpGatewayError::ResponseDecodeError(_) => {
+                (StatusCode::BAD_GATEWAY, self.to_string())
+            }
+            HcHttpGatewayError::AppBusy(_)
+            | HcHttpGatewayError::Overloaded
+            | HcHttpGatewayError::Draining => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            HcHttpGatewayError::ZomeCallTimeout => (StatusCode::GATEWAY_TIMEOUT, self.to_string()),
+            HcHttpGatewayError::TransformError(_) | HcHttpGatewayError::Panicked => {
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
+            }
             HcHttpGatewayError::AppSelectionError(AppSelectionError::NotInstalled) => {
                 (StatusCode::NOT_FOUND, self.to_string())
             }
@@ -74,6 +239,18 @@ impl HcHttpGatewayError {
             HcHttpGatewayError::AppSelectionError(AppSelectionError::MultipleMatching) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
             }
+            // The app and cell were found in the cached app info when the app was selected, so
+            // they changed on the conductor between the selection and the call
+            HcHttpGatewayError::HolochainError(ConductorApiError::AppNotFound) => (
+                StatusCode::NOT_FOUND,
+                "The app was not found on the conductor, it may have been uninstalled or disabled"
+                    .to_string(),
+            ),
+            HcHttpGatewayError::HolochainError(ConductorApiError::CellNotFound) => (
+                StatusCode::CONFLICT,
+                "The cell was not found in the app, the app may have been disabled or changed"
+                    .to_string(),
+            ),
             HcHttpGatewayError::HolochainError(ConductorApiError::ExternalApiWireError(
                 ExternalApiWireError::RibosomeError(e),
             )) => (StatusCode::INTERNAL_SERVER_ERROR, e),
@@ -87,7 +264,166 @@ impl HcHttpGatewayError {
 
 impl IntoResponse for HcHttpGatewayError {
     fn into_response(self) -> axum::response::Response {
+        let busy = matches!(
+            self,
+            HcHttpGatewayError::AppBusy(_) | HcHttpGatewayError::Overloaded
+        );
+        let draining = matches!(self, HcHttpGatewayError::Draining);
+        let upstream = match self {
+            HcHttpGatewayError::UpstreamUnavailable(connection)
+            | HcHttpGatewayError::UpstreamLost(connection) => Some(connection),
+            _ => None,
+        };
+        // The call may have been made when the connection was lost, so it is not retried
+        let retryable = matches!(self, HcHttpGatewayError::UpstreamUnavailable(_));
         let (status_code, body) = self.into_status_code_and_body();
-        (status_code, Json(ErrorResponse::from(body))).into_response()
+        let body = ErrorResponse {
+            upstream,
+            request_id: current_request_id(),
+            ..ErrorResponse::from(body)
+        };
+        let mut response = (status_code, Json(body.clone())).into_response();
+        // Marks the response for `format_errors`
+        response.extensions_mut().insert(body);
+        if let Some(upstream) = upstream
+            && retryable
+        {
+            // Marks the response for `retry_upstream_unavailable`
+            response.extensions_mut().insert(upstream);
+        }
+        if busy {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS));
+        }
+        if draining {
+            // Make the client open a new connection, which may reach another instance
+            response
+                .headers_mut()
+                .insert(header::CONNECTION, HeaderValue::from_static("close"));
+        }
+        response
+    }
+}
+
+/// Middleware that asks clients to retry requests that failed because Holochain could not be
+/// reached after `retry_after`.
+///
+/// Other errors with status 502 are not expected to go away by retrying, so they are left alone.
+///
+/// A conductor that can't be reached is checked again by the health monitor, which reconnects to
+/// it, so the health check interval is used as the time to wait.
+pub(crate) async fn retry_upstream_unavailable(
+    State(retry_after): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    if response.extensions().get::<UpstreamConnection>().is_some() {
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(secs.max(1)));
+    }
+    response
+}
+
+/// Middleware that returns errors as problem documents when the [`ErrorFormat`] or the `Accept`
+/// header of the request asks for them.
+pub(crate) async fn format_errors(
+    State(error_format): State<ErrorFormat>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_problem = error_format.is_problem(request.headers());
+    let mut response = next.run(request).await;
+    if is_problem && let Some(error) = response.extensions_mut().remove::<ErrorResponse>() {
+        let (mut parts, _) = response.into_parts();
+        let problem = ProblemDetails::new(parts.status, error);
+        let body = serde_json::to_vec(&problem).expect("Problem documents serialize to JSON");
+        parts
+            .headers
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        parts.headers.remove(header::CONTENT_LENGTH);
+        response = Response::from_parts(parts, body.into());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::router::TestRouter;
+    use crate::{AllowedFns, Configuration};
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use tower::ServiceExt;
+
+    const MALFORMED_URI: &str = "/dna_hash/coordinator/zome_name/fn_name";
+
+    async fn request(router: &TestRouter, accept: Option<&str>) -> (HeaderValue, String) {
+        let mut request = axum::http::Request::builder()
+            .uri(MALFORMED_URI)
+            .header("x-request-id", "problem-id");
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        let response = (*router)
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let content_type = response.headers()[header::CONTENT_TYPE].clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn assert_problem(content_type: HeaderValue, body: &str) {
+        assert_eq!(content_type, PROBLEM_JSON);
+        let problem: ProblemDetails = serde_json::from_str(body).unwrap();
+        assert_eq!(problem.problem_type, "about:blank");
+        assert_eq!(problem.title, "Bad Request");
+        assert_eq!(problem.status, 400);
+        assert!(problem.detail.starts_with("Request is malformed"));
+        assert_eq!(problem.instance.as_deref(), Some("problem-id"));
+    }
+
+    #[tokio::test]
+    async fn errors_are_problem_documents_when_accepted() {
+        let router = TestRouter::new();
+
+        let (content_type, body) = request(&router, None).await;
+        assert_eq!(content_type, "application/json");
+        let response: ErrorResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.request_id.as_deref(), Some("problem-id"));
+
+        let (content_type, body) = request(
+            &router,
+            Some("application/json;q=0.5, application/problem+json"),
+        )
+        .await;
+        assert_problem(content_type, &body);
+    }
+
+    #[tokio::test]
+    async fn errors_are_problem_documents_when_configured() {
+        let mut config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "coordinator",
+            HashMap::from([("coordinator".to_string(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap();
+        config.error_format = "problem".parse().unwrap();
+        let router = TestRouter::new_with_config(config);
+
+        let (content_type, body) = request(&router, None).await;
+        assert_problem(content_type, &body);
     }
 }
@@ -5,16 +5,54 @@ use holochain_client::{
 };
 use holochain_conductor_api::{
     AppAuthenticationTokenIssued, AppInterfaceInfo, AppStatusFilter,
-    IssueAppAuthenticationTokenPayload,
+    IssueAppAuthenticationTokenPayload, StorageInfo,
 };
-use holochain_types::app::InstalledAppId;
+use holochain_types::app::{AppStatus, InstalledAppId};
+use holochain_types::dna::DnaHash;
+use holochain_types::prelude::CapSecret;
 use holochain_types::websocket::AllowedOrigins;
+use std::sync::Arc;
+use std::time::Duration;
 
 mod admin_conn;
 pub use admin_conn::AdminConn;
 
 mod app_conn_pool;
-pub use app_conn_pool::{AppConnPool, AppWebsocketWithState, HTTP_GW_ORIGIN};
+pub use app_conn_pool::{AppConnPool, AppConnectionStats, AppWebsocketWithState, HTTP_GW_ORIGIN};
+
+mod connection_counters;
+pub use connection_counters::ConnectionCounters;
+pub(crate) use connection_counters::ConnectionRecorder;
+
+mod conductors;
+pub use conductors::{ConductorHandle, ConductorHealth, Conductors};
+
+mod cap_secret_signer;
+
+mod lair_signer;
+
+mod recording;
+pub use recording::{Recorder, Recording};
+
+/// Whether an app has the status that a [`AdminCall::list_apps`] filter selects, for backends
+/// that filter the apps that they list themselves.
+pub(crate) fn matches_status_filter(
+    app: &AppInfo,
+    status_filter: Option<&AppStatusFilter>,
+) -> bool {
+    match status_filter {
+        None => true,
+        Some(AppStatusFilter::Enabled) => matches!(app.status, AppStatus::Enabled),
+        Some(AppStatusFilter::Disabled) => matches!(app.status, AppStatus::Disabled(_)),
+        Some(AppStatusFilter::AwaitingMemproofs) => {
+            matches!(app.status, AppStatus::AwaitingMemproofs)
+        }
+        Some(AppStatusFilter::AwaitingRestore) => matches!(app.status, AppStatus::AwaitingRestore),
+        Some(AppStatusFilter::Unrecoverable) => {
+            matches!(app.status, AppStatus::Unrecoverable(..))
+        }
+    }
+}
 
 /// A trait for making admin calls with an admin connection.
 #[cfg_attr(test, mockall::automock)]
@@ -51,6 +89,96 @@ pub trait AdminCall: std::fmt::Debug + Send + Sync {
         &self,
         status_filter: Option<AppStatusFilter>,
     ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<AppInfo>>>;
+
+    /// Call [`AdminWebsocket::storage_info`](holochain_client::AdminWebsocket::storage_info) and
+    /// return the result.
+    fn storage_info(&self) -> BoxFuture<'static, HcHttpGatewayResult<StorageInfo>>;
+
+    /// Call [`AdminWebsocket::agent_info`](holochain_client::AdminWebsocket::agent_info) for the
+    /// given DNAs, or all DNAs if none are given, and return the encoded agent infos.
+    fn agent_info(
+        &self,
+        dna_hashes: Option<Vec<DnaHash>>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<String>>>;
+
+    /// Call [`AdminWebsocket::dump_conductor_state`](holochain_client::AdminWebsocket::dump_conductor_state)
+    /// and return the result.
+    ///
+    /// The dump reveals the full state of the conductor, so this requires the
+    /// `conductor-state-dump` feature.
+    #[cfg(feature = "conductor-state-dump")]
+    fn dump_conductor_state(&self) -> BoxFuture<'static, HcHttpGatewayResult<String>>;
+
+    /// Close the open admin connections, for example when the gateway shuts down.
+    ///
+    /// A later admin call opens a new connection.
+    fn close_connections(&self) -> BoxFuture<'static, ()>;
+
+    /// Subscribe to the number of times that a lost admin connection was re-established, which
+    /// usually means that the conductor restarted.
+    fn subscribe_reconnects(&self) -> tokio::sync::watch::Receiver<u64>;
+
+    /// Counts of the admin connections that were opened and closed.
+    fn connection_counters(&self) -> ConnectionCounters;
+}
+
+/// A zome call to be made by an [`AppCall`].
+///
+/// The names and the payload are shared, so that the request can be retried, or made to another
+/// conductor, without copying them.
+#[derive(Debug, Clone)]
+pub struct ZomeCallRequest {
+    /// The app that the cell belongs to.
+    pub installed_app_id: InstalledAppId,
+    /// The cell to call, as found in the app info from the DNA hash of the request.
+    pub cell_id: CellId,
+    /// Name of the zome to call.
+    pub zome_name: Arc<str>,
+    /// Name of the function to call.
+    pub fn_name: Arc<str>,
+    /// The serialized payload of the call.
+    pub payload: Arc<ExternIO>,
+    /// Capability secret to present for the call instead of the capability secret of the
+    /// gateway's own signing credentials, if any.
+    pub cap_secret: Option<CapSecret>,
+    /// Time after which the call fails with [`HcHttpGatewayError::ZomeCallTimeout`], if any.
+    ///
+    /// [`HcHttpGatewayError::ZomeCallTimeout`]: crate::HcHttpGatewayError::ZomeCallTimeout
+    pub timeout: Option<Duration>,
+}
+
+impl ZomeCallRequest {
+    /// Create a request to call a function in a cell of an app, without a capability secret
+    /// or timeout.
+    pub fn new(
+        installed_app_id: impl Into<InstalledAppId>,
+        cell_id: CellId,
+        zome_name: impl Into<Arc<str>>,
+        fn_name: impl Into<Arc<str>>,
+        payload: impl Into<Arc<ExternIO>>,
+    ) -> Self {
+        Self {
+            installed_app_id: installed_app_id.into(),
+            cell_id,
+            zome_name: zome_name.into(),
+            fn_name: fn_name.into(),
+            payload: payload.into(),
+            cap_secret: None,
+            timeout: None,
+        }
+    }
+
+    /// Present the given capability secret for the call, if any.
+    pub fn with_cap_secret(mut self, cap_secret: Option<CapSecret>) -> Self {
+        self.cap_secret = cap_secret;
+        self
+    }
+
+    /// Fail the call when it doesn't complete within the given time.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
 }
 
 /// A trait for making zome calls with an app connection.
@@ -58,13 +186,44 @@ pub trait AdminCall: std::fmt::Debug + Send + Sync {
 /// Primarily used to allow the [`AppConnPool`] to be mocked in tests.
 #[cfg_attr(test, mockall::automock)]
 pub trait AppCall: std::fmt::Debug + Send + Sync {
-    /// Make a zome call by executing the provided function with an app websocket connection.
+    /// Make the requested zome call over an app websocket connection.
+    ///
+    /// Dropping the returned future cancels the zome call. The gateway drops it when the HTTP
+    /// client disconnects, so implementations must not detach the call into a spawned task.
     fn handle_zome_call(
         &self,
-        installed_app_id: InstalledAppId,
-        cell_id: CellId,
-        zome_name: String,
-        fn_name: String,
-        payload: ExternIO,
+        request: ZomeCallRequest,
     ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>>;
+
+    /// Close the open app connections that are no longer usable or that have been idle for too
+    /// long, so that the next zome call for the app opens a fresh connection.
+    fn evict_stale_connections(&self) -> BoxFuture<'static, ()>;
+
+    /// Close the app connection for the given app, if there is one.
+    fn remove_app_connection(&self, installed_app_id: InstalledAppId) -> BoxFuture<'static, ()>;
+
+    /// Close all open app connections, for example when the gateway shuts down.
+    fn close_connections(&self) -> BoxFuture<'static, ()>;
+
+    /// Recover from a restart of the conductor.
+    ///
+    /// The app interfaces and connections from before the restart are forgotten, and the app
+    /// interfaces are provisioned again, so that the next zome calls don't each have to
+    /// discover that their connection was lost.
+    fn reset_after_restart(&self) -> BoxFuture<'static, ()>;
+
+    /// The state of the open app connections, ordered by app id.
+    fn app_connection_stats(&self) -> Vec<AppConnectionStats>;
+
+    /// Forget the cached app interface ports, for example because they are known to be stale.
+    fn clear_cached_app_ports(&self);
+
+    /// Close the app connection for the given app, if there is one, and open a new one.
+    fn reconnect_app(
+        &self,
+        installed_app_id: InstalledAppId,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<()>>;
+
+    /// Counts of the app connections that were opened and closed.
+    fn connection_counters(&self) -> ConnectionCounters;
 }
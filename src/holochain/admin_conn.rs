@@ -1,5 +1,8 @@
-use crate::HcHttpGatewayError;
-use crate::{AdminCall, HcHttpGatewayResult};
+use crate::holochain::ConnectionRecorder;
+use crate::request_id::current_request_id;
+use crate::resolve::re_resolve_address_from_url;
+use crate::{AdminCall, ConnectionCounters, HcHttpGatewayResult};
+use crate::{HcHttpGatewayError, UpstreamConnection};
 use futures::future::BoxFuture;
 use holochain_client::{
     AdminWebsocket, AppInfo, AuthorizeSigningCredentialsPayload, ConductorApiError,
@@ -7,91 +10,213 @@ use holochain_client::{
 };
 use holochain_conductor_api::{
     AppAuthenticationTokenIssued, AppInterfaceInfo, AppStatusFilter,
-    IssueAppAuthenticationTokenPayload,
+    IssueAppAuthenticationTokenPayload, StorageInfo,
 };
+use holochain_types::dna::DnaHash;
 use holochain_types::websocket::AllowedOrigins;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock as StdRwLock};
+use tokio::sync::{RwLock, watch};
 
 /// A wrapper around AdminWebsocket that automatically handles reconnection
 /// when the connection is lost due to network issues or other failures.
+///
+/// The wrapper can maintain a small pool of connections, which are checked out in a round-robin
+/// fashion so that concurrent admin calls do not all have to go through a single connection.
+///
+/// When the URL of the admin interface is known, see [`AdminConn::with_admin_ws_url`], it is
+/// resolved again before each reconnect, so that the connection follows the conductor when its
+/// DNS record changes.
+///
+/// Reconnecting after a lost connection, which usually means that the conductor restarted, is
+/// counted once however many connections of the pool were lost, see
+/// [`AdminCall::subscribe_reconnects`].
 #[derive(Debug, Clone)]
 pub struct AdminConn {
-    /// The WebSocket URL to connect to
-    socket_addr: SocketAddr,
+    /// The socket address to connect to, as last resolved
+    socket_addr: Arc<StdRwLock<SocketAddr>>,
 
-    /// The handle to the AdminWebsocket connection - always contains a valid connection
-    handle: Arc<RwLock<Option<AdminWebsocket>>>,
+    /// The WebSocket URL that the socket address is resolved from, if any
+    admin_ws_url: Option<Arc<str>>,
+
+    /// The slots of the pool, each holding a handle to an AdminWebsocket connection once connected
+    handles: Arc<[RwLock<Option<OpenAdminWs>>]>,
+
+    /// Counter used to pick the next slot to check out
+    next_slot: Arc<AtomicUsize>,
+
+    /// The number of times that a lost connection was re-established
+    reconnects: Arc<watch::Sender<u64>>,
+
+    /// Whether a connection was lost since the last reconnect
+    lost: Arc<AtomicBool>,
+
+    /// Counts of the connections that were opened and closed
+    connections: Arc<ConnectionRecorder>,
+}
+
+/// An admin websocket in a slot of the pool.
+#[derive(Debug, Clone)]
+struct OpenAdminWs {
+    admin_ws: AdminWebsocket,
+    /// The number of reconnects when the websocket was opened
+    generation: u64,
 }
 
 impl AdminConn {
     /// Creates a new [`AdminConn`] that will attempt to maintain an [`AdminWebsocket`] connection
     /// to the specified socket address.
     pub fn new(socket_addr: SocketAddr) -> Self {
+        Self::with_pool_size(socket_addr, 1)
+    }
+
+    /// Creates a new [`AdminConn`] that will attempt to maintain up to `pool_size`
+    /// [`AdminWebsocket`] connections to the specified socket address.
+    ///
+    /// Connections are opened lazily, when a slot is first checked out. A pool size of zero is
+    /// treated as one.
+    pub fn with_pool_size(socket_addr: SocketAddr, pool_size: usize) -> Self {
         Self {
-            socket_addr,
-            handle: Default::default(),
+            socket_addr: Arc::new(StdRwLock::new(socket_addr)),
+            admin_ws_url: None,
+            handles: (0..pool_size.max(1)).map(|_| RwLock::new(None)).collect(),
+            next_slot: Default::default(),
+            reconnects: Arc::new(watch::Sender::new(0)),
+            lost: Default::default(),
+            connections: Default::default(),
         }
     }
 
+    /// Resolve the given admin websocket URL again whenever a connection has to be opened.
+    ///
+    /// The socket address given when creating the [`AdminConn`] is used until the URL resolves.
+    pub fn with_admin_ws_url(mut self, admin_ws_url: impl Into<String>) -> Self {
+        self.admin_ws_url = Some(admin_ws_url.into().into());
+        self
+    }
+
+    /// The socket address that connections are currently opened to.
+    pub fn socket_addr(&self) -> SocketAddr {
+        *self.socket_addr.read().expect("Invalid lock")
+    }
+
+    /// The number of connections that this [`AdminConn`] will maintain.
+    pub fn pool_size(&self) -> usize {
+        self.handles.len()
+    }
+
     /// Allows calling a method on the [`AdminWebsocket`], with automatic reconnection if needed
+    #[tracing::instrument(skip_all, fields(request_id = current_request_id().as_deref()))]
     async fn call<T>(
         &self,
         execute: impl Fn(AdminWebsocket) -> BoxFuture<'static, HcHttpGatewayResult<T>>,
     ) -> HcHttpGatewayResult<T> {
+        let handle = self.checkout();
+
         for _ in 0..2 {
-            let admin_ws = self.get_admin_ws().await?;
+            let open = self.get_admin_ws(handle).await?;
 
-            match execute(admin_ws).await {
+            match execute(open.admin_ws).await {
                 Ok(output) => return Ok(output),
                 Err(HcHttpGatewayError::HolochainError(ConductorApiError::WebsocketError(e))) => {
                     tracing::warn!(
                         ?e,
                         "Detected admin websocket disconnection. Attempting to reconnect"
                     );
-                    *self.handle.write().await = None;
+                    // Connections opened before the last reconnect were lost along with the one
+                    // that has already been re-established
+                    if open.generation == *self.reconnects.borrow() {
+                        self.lost.store(true, Ordering::Release);
+                    }
+                    if handle.write().await.take().is_some() {
+                        self.connections.record_evictions(1);
+                    }
                     continue;
                 }
                 Err(e) => return Err(e),
             }
         }
 
-        Err(HcHttpGatewayError::UpstreamUnavailable)
+        Err(HcHttpGatewayError::UpstreamUnavailable(
+            UpstreamConnection::Admin,
+        ))
+    }
+
+    /// Pick the slot of the pool to use for the next call.
+    fn checkout(&self) -> &RwLock<Option<OpenAdminWs>> {
+        let slot = self.next_slot.fetch_add(1, Ordering::Relaxed) % self.handles.len();
+        &self.handles[slot]
     }
 
-    async fn get_admin_ws(&self) -> HcHttpGatewayResult<AdminWebsocket> {
+    async fn get_admin_ws(
+        &self,
+        handle: &RwLock<Option<OpenAdminWs>>,
+    ) -> HcHttpGatewayResult<OpenAdminWs> {
         {
-            let lock = self.handle.read().await;
+            let lock = handle.read().await;
 
-            if let Some(admin_ws) = lock.as_ref() {
-                return Ok(admin_ws.clone());
+            if let Some(open) = lock.as_ref() {
+                return Ok(open.clone());
             }
         }
 
-        let mut lock = self.handle.write().await;
+        let mut lock = handle.write().await;
 
         // We might have been queued up behind another task that was holding the write lock, so we
         // need to check again after obtaining the write lock. Reconnecting if another task has
         // already reconnected risks closing the connection the other task just established.
-        if let Some(admin_ws) = lock.as_ref() {
-            return Ok(admin_ws.clone());
+        if let Some(open) = lock.as_ref() {
+            return Ok(open.clone());
         }
 
-        match AdminWebsocket::connect(self.socket_addr, None).await {
+        let socket_addr = self.resolve_socket_addr().await;
+        let connected = AdminWebsocket::connect(socket_addr, None).await;
+        self.connections.record_connect(connected.is_ok());
+        match connected {
             Ok(admin_ws) => {
                 tracing::info!("Connected a new Holochain admin websocket");
-                *lock = Some(admin_ws.clone());
-                Ok(admin_ws)
+                if self.lost.swap(false, Ordering::AcqRel) {
+                    self.reconnects.send_modify(|reconnects| *reconnects += 1);
+                }
+                let open = OpenAdminWs {
+                    admin_ws,
+                    generation: *self.reconnects.borrow(),
+                };
+                *lock = Some(open.clone());
+                Ok(open)
             }
             Err(e) => {
                 tracing::error!(?e, "Failed to connect Holochain admin websocket");
-                Err(HcHttpGatewayError::UpstreamUnavailable)
+                Err(HcHttpGatewayError::UpstreamUnavailable(
+                    UpstreamConnection::Admin,
+                ))
             }
         }
     }
 }
 
+impl AdminConn {
+    /// Resolve the admin websocket URL again, if there is one, and remember the address.
+    async fn resolve_socket_addr(&self) -> SocketAddr {
+        let last_known = self.socket_addr();
+        let Some(admin_ws_url) = &self.admin_ws_url else {
+            return last_known;
+        };
+
+        let socket_addr = re_resolve_address_from_url(admin_ws_url, last_known).await;
+        if socket_addr != last_known {
+            tracing::info!(
+                %last_known,
+                %socket_addr,
+                "Admin websocket URL resolved to a new address"
+            );
+            *self.socket_addr.write().expect("Invalid lock") = socket_addr;
+        }
+        socket_addr
+    }
+}
+
 impl AdminCall for AdminConn {
     fn list_app_interfaces(
         &self,
@@ -174,4 +299,64 @@ impl AdminCall for AdminConn {
             .await
         })
     }
+
+    fn storage_info(&self) -> BoxFuture<'static, HcHttpGatewayResult<StorageInfo>> {
+        let this = self.clone();
+        Box::pin(async move {
+            this.call(|admin_ws| Box::pin(async move { Ok(admin_ws.storage_info().await?) }))
+                .await
+        })
+    }
+
+    fn agent_info(
+        &self,
+        dna_hashes: Option<Vec<DnaHash>>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<String>>> {
+        let this = self.clone();
+        Box::pin(async move {
+            this.call(|admin_ws| {
+                let dna_hashes = dna_hashes.clone();
+
+                Box::pin(async move { Ok(admin_ws.agent_info(dna_hashes).await?) })
+            })
+            .await
+        })
+    }
+
+    #[cfg(feature = "conductor-state-dump")]
+    fn dump_conductor_state(&self) -> BoxFuture<'static, HcHttpGatewayResult<String>> {
+        let this = self.clone();
+        Box::pin(async move {
+            this.call(|admin_ws| {
+                Box::pin(async move { Ok(admin_ws.dump_conductor_state().await?) })
+            })
+            .await
+        })
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        let this = self.clone();
+        Box::pin(async move {
+            // The websocket is closed once the last handle to it is dropped, which may be held
+            // by a call that is still in progress
+            for handle in this.handles.iter() {
+                handle.write().await.take();
+            }
+            tracing::debug!("Closed admin websockets");
+        })
+    }
+
+    fn subscribe_reconnects(&self) -> watch::Receiver<u64> {
+        self.reconnects.subscribe()
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        // Slots that are being connected or closed are not counted
+        let open = self
+            .handles
+            .iter()
+            .filter(|handle| handle.try_read().is_ok_and(|open| open.is_some()))
+            .count();
+        self.connections.counters(open as u64)
+    }
 }
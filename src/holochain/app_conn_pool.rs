@@ -1,22 +1,50 @@
-use crate::config::{AllowedFns, Configuration};
-use crate::holochain::{AdminCall, AppCall};
-use crate::{HcHttpGatewayError, HcHttpGatewayResult};
+use crate::config::{AllowedFns, Configuration, SigningMode};
+use crate::holochain::cap_secret_signer::{CapSecretSigner, with_client_cap_secret};
+use crate::holochain::lair_signer::LairSigner;
+use crate::holochain::{AdminCall, AppCall, ConnectionRecorder, ZomeCallRequest};
+use crate::request_id::current_request_id;
+use crate::resolve::re_resolve_address_from_url;
+use crate::slow_request::{Phase, record_timing};
+use crate::{ConnectionCounters, HcHttpGatewayError, HcHttpGatewayResult, UpstreamConnection};
+use dashmap::DashMap;
 use futures::future::BoxFuture;
+use futures::{StreamExt, TryStreamExt};
 use holochain_client::{
-    AppWebsocket, AuthorizeSigningCredentialsPayload, CellId, CellInfo, ClientAgentSigner,
-    ConductorApiError, ConnectRequest, ExternIO, GrantedFunctions,
-    IssueAppAuthenticationTokenPayload, Timestamp, WebsocketConfig, ZomeCallTarget,
+    AgentSigner, AppWebsocket, AuthorizeSigningCredentialsPayload, CellId, CellInfo,
+    ClientAgentSigner, ConductorApiError, ConductorApiResult, ConnectRequest, ExternIO,
+    GrantedFunctions, IssueAppAuthenticationTokenPayload, Timestamp, WebsocketConfig,
+    ZomeCallTarget,
 };
+use holochain_conductor_api::AppInterfaceInfo;
 use holochain_types::app::InstalledAppId;
+use holochain_types::prelude::{FunctionName, ZomeName};
 use holochain_types::websocket::AllowedOrigins;
 use holochain_websocket::WebsocketError;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
 
 /// The origin that the gateway will use when connecting to Holochain app interfaces.
 pub const HTTP_GW_ORIGIN: &str = "hc-http-gw";
 
+/// Maximum number of signing credentials that are authorized concurrently when authorizing every
+/// cell of an app.
+const MAX_CONCURRENT_AUTHORIZATIONS: usize = 8;
+
+/// How long the app authentication tokens issued by the gateway remain valid.
+///
+/// Tokens are issued for multiple uses, so that reconnecting to an app doesn't require a new token
+/// from the admin interface.
+const APP_AUTH_TOKEN_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// How long before its expiry a cached app authentication token is replaced, so that a token
+/// doesn't expire while it is being used to connect.
+const APP_AUTH_TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
 /// A wrapper around an app websocket connection that includes state required to manage the
 /// connection.
 #[derive(Debug, Clone)]
@@ -25,53 +53,245 @@ pub struct AppWebsocketWithState {
     pub app_ws: AppWebsocket,
     /// The time at which the connection was opened.
     pub opened_at: Timestamp,
+    /// Monotonic time at which the connection was opened, used to measure its age.
+    opened: Instant,
+    /// The time at which the connection was last checked out for a call.
+    last_used: Arc<Mutex<Instant>>,
+    /// The number of times the connection has been checked out for a call.
+    request_count: Arc<AtomicU64>,
+    /// The number of zome calls currently in flight over the connection.
+    in_flight: Arc<AtomicU64>,
+    /// Signing credentials used for zome calls made over the connection.
+    signing: SigningState,
+}
+
+/// The signer of an app connection and the cells that it holds signing credentials for.
+#[derive(Clone)]
+struct SigningState {
+    signer: Arc<dyn AgentSigner + Send + Sync>,
+    // The signer that holds the credentials authorized by the gateway, if zome calls are not
+    // signed by a Lair keystore.
+    credentials: Option<ClientAgentSigner>,
+    // Held while authorizing so that concurrent calls don't authorize the same cell twice.
+    authorized_cells: Arc<tokio::sync::Mutex<HashSet<CellId>>>,
+}
+
+impl SigningState {
+    /// Sign with credentials that are authorized by the gateway.
+    fn with_credentials() -> Self {
+        let credentials = ClientAgentSigner::default();
+        Self {
+            signer: credentials.clone().into(),
+            credentials: Some(credentials),
+            authorized_cells: Default::default(),
+        }
+    }
+
+    /// Sign with a Lair keystore, so no credentials need to be authorized.
+    fn with_lair(lair_signer: &LairSigner) -> Self {
+        Self {
+            signer: lair_signer.agent_signer(),
+            credentials: None,
+            authorized_cells: Default::default(),
+        }
+    }
+}
+
+impl std::fmt::Debug for SigningState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningState")
+            .field("authorized_cells", &self.authorized_cells)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AppWebsocketWithState {
+    fn new(app_ws: AppWebsocket, signing: SigningState) -> Self {
+        Self {
+            app_ws,
+            opened_at: Timestamp::now(),
+            opened: Instant::now(),
+            last_used: Arc::new(Mutex::new(Instant::now())),
+            request_count: Default::default(),
+            in_flight: Default::default(),
+            signing,
+        }
+    }
+
+    /// How long ago the connection was opened.
+    pub fn age(&self) -> Duration {
+        self.opened.elapsed()
+    }
+
+    /// The time at which the connection was last checked out for a call.
+    pub fn last_used(&self) -> Instant {
+        *self.last_used.lock().expect("Invalid lock")
+    }
+
+    /// The number of times the connection has been checked out for a call.
+    pub fn request_count(&self) -> u64 {
+        self.request_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of zome calls currently in flight over the connection.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The cells that signing credentials have been authorized for on this connection.
+    pub async fn authorized_cells(&self) -> HashSet<CellId> {
+        self.signing.authorized_cells.lock().await.clone()
+    }
+
+    /// Check out the connection for a call, recording that it was used.
+    fn check_out(&self) -> Self {
+        *self.last_used.lock().expect("Invalid lock") = Instant::now();
+        self.request_count.fetch_add(1, Ordering::Relaxed);
+        self.clone()
+    }
+
+    /// Count a zome call as in flight over the connection until the returned guard is dropped.
+    fn start_call(&self) -> InFlightCall {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightCall(self.in_flight.clone())
+    }
+}
+
+/// A zome call in flight over an app connection, see [`AppWebsocketWithState::start_call`].
+struct InFlightCall(Arc<AtomicU64>);
+
+impl Drop for InFlightCall {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The state of the app connection of an app, see [`AppConnPool::app_connection_stats`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AppConnectionStats {
+    /// The app that the connection is for.
+    pub installed_app_id: InstalledAppId,
+    /// Time in milliseconds since the connection was opened.
+    pub age_ms: u64,
+    /// Time in milliseconds since the connection was last checked out for a call.
+    pub idle_ms: u64,
+    /// The number of times the connection has been checked out for a call.
+    pub request_count: u64,
+    /// The number of zome calls currently in flight over the connection.
+    #[serde(default)]
+    pub in_flight: u64,
+    /// The number of connections opened for the app before this one.
+    pub reconnects: u64,
+    /// Whether this is the overflow connection of the app, which takes the calls that its
+    /// connection has no room for.
+    #[serde(default)]
+    pub overflow: bool,
+}
+
+/// An app authentication token that can be used to open connections until it expires.
+#[derive(Clone)]
+struct CachedAppAuthToken {
+    token: Vec<u8>,
+    /// The time after which the token should no longer be used.
+    expires: Instant,
+}
+
+impl std::fmt::Debug for CachedAppAuthToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedAppAuthToken")
+            .field("expires", &self.expires)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The app interfaces of the conductor as last listed.
+#[derive(Debug)]
+struct CachedAppInterfaces {
+    interfaces: Vec<AppInterfaceInfo>,
+    listed: Instant,
 }
 
 /// A connection pool for app connections.
 ///
 /// This is a pool in the sense that it manages multiple connections to Holochain app interfaces,
-/// but it will manage exactly one connection per installed app.
+/// but it will manage one connection per installed app. Zome calls are multiplexed over that
+/// connection, so a slow call doesn't hold up the calls made after it. When the number of calls in
+/// flight over a connection is limited, the calls beyond the limit spill over to a second,
+/// overflow connection for the app.
+///
+/// The connections are kept in a concurrent map, so that looking up the connection for one app
+/// doesn't wait for another app to connect. Connecting is serialized per app by a connect lock.
 #[derive(Debug, Clone)]
 pub struct AppConnPool {
-    configuration: Configuration,
+    configuration: Arc<Configuration>,
     admin_call: Arc<dyn AdminCall>,
-    cached_app_port: Arc<RwLock<Option<u16>>>,
-    app_clients: Arc<tokio::sync::RwLock<HashMap<InstalledAppId, AppWebsocketWithState>>>,
+    cached_app_ports: Arc<RwLock<HashMap<Option<InstalledAppId>, u16>>>,
+    cached_app_interfaces: Arc<Mutex<Option<CachedAppInterfaces>>>,
+    // Held while listing the app interfaces, so that concurrent connects share a single listing.
+    list_app_interfaces_lock: Arc<tokio::sync::Mutex<()>>,
+    app_clients: Arc<DashMap<InstalledAppId, AppWebsocketWithState>>,
+    overflow_clients: Arc<DashMap<InstalledAppId, AppWebsocketWithState>>,
+    reconnects: Arc<DashMap<InstalledAppId, u64>>,
+    connect_locks: Arc<DashMap<InstalledAppId, Arc<tokio::sync::Mutex<()>>>>,
+    app_auth_tokens: Arc<Mutex<HashMap<InstalledAppId, CachedAppAuthToken>>>,
+    lair_signer: Arc<tokio::sync::OnceCell<LairSigner>>,
+    call_permits: Arc<Mutex<HashMap<InstalledAppId, Arc<tokio::sync::Semaphore>>>>,
+    connections: Arc<ConnectionRecorder>,
 }
 
 impl AppConnPool {
     /// Create a new app connection pool with the given configuration and admin call handle.
     pub fn new(configuration: Configuration, admin_call: Arc<dyn AdminCall>) -> Self {
         Self {
-            configuration,
+            configuration: Arc::new(configuration),
             admin_call,
-            cached_app_port: Default::default(),
+            cached_app_ports: Default::default(),
+            cached_app_interfaces: Default::default(),
+            list_app_interfaces_lock: Default::default(),
             app_clients: Default::default(),
+            overflow_clients: Default::default(),
+            reconnects: Default::default(),
+            connect_locks: Default::default(),
+            app_auth_tokens: Default::default(),
+            lair_signer: Default::default(),
+            call_permits: Default::default(),
+            connections: Default::default(),
         }
     }
 
     /// Call a function with an app client for the given installed app ID.
     ///
-    /// This function takes care of reconnecting to the app client if the connection is lost. Your
-    /// function is free to operate on the app client without worrying about the connection state.
+    /// This function takes care of connecting the app client, retrying with fresh settings if a
+    /// connection cannot be made. Your function is free to operate on the app client without
+    /// worrying about the connection state.
     ///
-    /// Exactly one attempt is made to reconnect and re-run the provided callback. If there is a
-    /// second failure after reconnecting, the error is returned to the caller.
+    /// The provided callback is run at most once. If the connection is lost while it runs, the
+    /// connection is removed so that the next call reconnects, and
+    /// [`HcHttpGatewayError::UpstreamLost`] is returned, since the call may already have been made.
     pub async fn call<T>(
         &self,
         installed_app_id: InstalledAppId,
         execute: impl Fn(AppWebsocket) -> BoxFuture<'static, HcHttpGatewayResult<T>>,
     ) -> HcHttpGatewayResult<T> {
-        // The first attempt may discover that the connection is invalid
+        self.call_with_client(installed_app_id, |client| execute(client.app_ws))
+            .await
+    }
+
+    /// Like [`AppConnPool::call`], but gives the callback access to the state of the connection.
+    async fn call_with_client<T>(
+        &self,
+        installed_app_id: InstalledAppId,
+        execute: impl Fn(AppWebsocketWithState) -> BoxFuture<'static, HcHttpGatewayResult<T>>,
+    ) -> HcHttpGatewayResult<T> {
+        // The first attempt may fail to connect using a cached app port
         // On the second attempt, we will reconnect without using a cached app port
         // On the third attempt, we will reconnect permitting that a new app interface can be created
+        let mut failed_connection = UpstreamConnection::App;
         for _ in 0..3 {
-            let app_ws = match self
-                .get_or_connect_app_client(installed_app_id.clone())
-                .await
-            {
-                Ok(app_ws) => app_ws,
-                Err(HcHttpGatewayError::UpstreamUnavailable) => {
+            let client = match self.check_out_app_client(installed_app_id.clone()).await {
+                Ok(client) => client,
+                Err(HcHttpGatewayError::UpstreamUnavailable(connection)) => {
+                    failed_connection = connection;
                     tracing::info!(
                         "Unable to connect app client, attempting to reconnect without cached settings"
                     );
@@ -82,7 +302,9 @@ impl AppConnPool {
                 }
                 Err(e) => return Err(e),
             };
-            match execute(app_ws).await {
+            let (client, overflow) = self.spill_over(&installed_app_id, client).await;
+            let _in_flight = client.start_call();
+            match execute(client).await {
                 Ok(response) => {
                     return Ok(response);
                 }
@@ -100,21 +322,29 @@ impl AppConnPool {
                 )) => {
                     tracing::warn!(
                         ?e,
-                        "Websocket error while executing call, attempting to reconnect",
+                        "Websocket error while executing call, removing the connection",
                     );
-                    self.remove_app_client(&installed_app_id).await;
+                    let clients = if overflow {
+                        &self.overflow_clients
+                    } else {
+                        &self.app_clients
+                    };
+                    if clients.remove(&installed_app_id).is_some() {
+                        self.connections.record_evictions(1);
+                    }
 
-                    // This is the first error we expect to encounter, that the app websocket
-                    // connection is no longer valid. We should attempt to reconnect.
-                    continue;
+                    // The app websocket connection is no longer valid, so the next call will
+                    // reconnect. The call may have been sent before the connection was lost, so
+                    // it is not made again.
+                    return Err(HcHttpGatewayError::UpstreamLost(UpstreamConnection::App));
                 }
                 Err(e) => return Err(e),
             }
         }
 
-        // Must mean we could not get anything other than a websocket error, otherwise we'd have
-        // got a response or some other error.
-        Err(HcHttpGatewayError::UpstreamUnavailable)
+        // Must mean we could not connect, otherwise we'd have got a response or an error from the
+        // call.
+        Err(HcHttpGatewayError::UpstreamUnavailable(failed_connection))
     }
 
     /// Get or connect an app client for the given installed app ID.
@@ -126,68 +356,371 @@ impl AppConnPool {
         &self,
         installed_app_id: InstalledAppId,
     ) -> HcHttpGatewayResult<AppWebsocket> {
-        {
-            let app_clients = self.app_clients.read().await;
+        self.check_out_app_client(installed_app_id)
+            .await
+            .map(|client| client.app_ws)
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(app_id = %installed_app_id, request_id = current_request_id().as_deref())
+    )]
+    async fn check_out_app_client(
+        &self,
+        installed_app_id: InstalledAppId,
+    ) -> HcHttpGatewayResult<AppWebsocketWithState> {
+        let (client, connected) = self
+            .check_out_or_connect(&self.app_clients, &installed_app_id)
+            .await?;
+        if !connected {
+            return Ok(client);
+        }
+
+        self.reconnects
+            .entry(installed_app_id.clone())
+            .and_modify(|reconnects| *reconnects += 1)
+            .or_insert(0);
+
+        // Overflow connections count towards the maximum as well
+        while self.open_connections() > self.configuration.max_app_connections as usize {
+            // Find and remove the least recently used connection of another app
+            let Some(least_recently_used) = self
+                .app_clients
+                .iter()
+                .filter(|client| client.key() != &installed_app_id)
+                .min_by_key(|client| client.last_used())
+                .map(|client| client.key().clone())
+            else {
+                break;
+            };
+
+            tracing::warn!(
+                "Reached maximum app connections, removing connection for app: {}",
+                least_recently_used
+            );
 
-            if let Some(client) = app_clients.get(&installed_app_id) {
-                return Ok(client.app_ws.clone());
+            self.app_clients.remove(&least_recently_used);
+            self.connections.record_evictions(1);
+            if self.overflow_clients.remove(&least_recently_used).is_some() {
+                self.connections.record_evictions(1);
             }
         }
 
-        let mut app_client_lock = self.app_clients.write().await;
+        Ok(client)
+    }
+
+    /// Number of app connections that are open, including overflow connections.
+    fn open_connections(&self) -> usize {
+        self.app_clients.len() + self.overflow_clients.len()
+    }
 
-        // We might have been queued up behind another task that was holding the write lock, so we
-        // need to check again after obtaining the write lock. Reconnecting if another task has
-        // already reconnected risks closing the connection the other task just established.
-        if let Some(client) = app_client_lock.get(&installed_app_id) {
-            return Ok(client.app_ws.clone());
+    /// Use the overflow connection of the app instead of the given connection, if the connection
+    /// has as many calls in flight as it is permitted and the overflow connection has fewer.
+    ///
+    /// Returns the connection to use and whether it is the overflow connection. Failing to open
+    /// the overflow connection is not an error, the call is made over the busy connection instead.
+    async fn spill_over(
+        &self,
+        installed_app_id: &InstalledAppId,
+        client: AppWebsocketWithState,
+    ) -> (AppWebsocketWithState, bool) {
+        let Some(max_in_flight) = self.configuration.app_max_in_flight else {
+            return (client, false);
+        };
+        if client.in_flight() < u64::from(max_in_flight) {
+            return (client, false);
+        }
+        // An overflow connection is only opened while there is room for it within the maximum
+        if !self.overflow_clients.contains_key(installed_app_id)
+            && self.open_connections() >= self.configuration.max_app_connections as usize
+        {
+            return (client, false);
         }
 
-        let app_ws = match app_client_lock.entry(installed_app_id.clone()) {
-            std::collections::hash_map::Entry::Occupied(client) => {
-                // Created by another thread while we were waiting for the lock
-                client.get().app_ws.clone()
+        match self
+            .check_out_or_connect(&self.overflow_clients, installed_app_id)
+            .await
+        {
+            Ok((overflow, _)) if overflow.in_flight() < client.in_flight() => (overflow, true),
+            Ok(_) => (client, false),
+            Err(e) => {
+                tracing::warn!(
+                    ?e,
+                    ?installed_app_id,
+                    "Failed to open an overflow app websocket"
+                );
+                (client, false)
             }
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                let app_ws = self.attempt_connect_app_ws(installed_app_id).await?;
+        }
+    }
+
+    /// Check out the connection for the app from the given connections, connecting if there is
+    /// none or if it is due to be recycled.
+    ///
+    /// Returns the connection and whether it was just opened.
+    async fn check_out_or_connect(
+        &self,
+        clients: &DashMap<InstalledAppId, AppWebsocketWithState>,
+        installed_app_id: &InstalledAppId,
+    ) -> HcHttpGatewayResult<(AppWebsocketWithState, bool)> {
+        // Map entries must not be held across an await point, so the client is cloned out of the map
+        let client = clients
+            .get(installed_app_id)
+            .map(|client| client.value().clone());
+        if let Some(client) = client
+            && !self.should_recycle(&client)
+        {
+            return Ok((client.check_out(), false));
+        }
 
-                entry.insert(AppWebsocketWithState {
-                    app_ws: app_ws.clone(),
-                    opened_at: Timestamp::now(),
-                });
+        // Only one task connects to an app at a time, without blocking calls to other apps
+        let connect_lock = self
+            .connect_locks
+            .entry(installed_app_id.clone())
+            .or_default()
+            .clone();
+        let _connecting = connect_lock.lock().await;
 
-                app_ws
+        // We might have been queued up behind another task that was connecting, so we need to
+        // check again after obtaining the connect lock. Reconnecting if another task has already
+        // reconnected risks closing the connection the other task just established.
+        let client = clients
+            .get(installed_app_id)
+            .map(|client| client.value().clone());
+        if let Some(client) = client {
+            if !self.should_recycle(&client) {
+                return Ok((client.check_out(), false));
             }
+
+            tracing::debug!(?installed_app_id, "Recycling app websocket");
+            clients.remove(installed_app_id);
+            self.connections.record_evictions(1);
+        }
+
+        let connect_started = Instant::now();
+        let client = self.attempt_connect_app_ws(installed_app_id.clone()).await;
+        record_timing(Phase::Connect, connect_started.elapsed());
+        self.connections.record_connect(client.is_ok());
+        let client = client?;
+        clients.insert(installed_app_id.clone(), client.clone());
+
+        Ok((client.check_out(), true))
+    }
+
+    /// Whether a connection should be closed and replaced by a new one before its next use.
+    fn should_recycle(&self, client: &AppWebsocketWithState) -> bool {
+        self.configuration
+            .app_max_age
+            .is_some_and(|max_age| client.age() > max_age)
+            || self
+                .configuration
+                .app_max_requests
+                .is_some_and(|max_requests| client.request_count() >= max_requests)
+    }
+
+    /// Wait for permission to make a zome call to the given app, if the number of concurrent calls
+    /// per app is limited.
+    ///
+    /// The permission is held until the returned permit is dropped. If the app is at its limit for
+    /// longer than the configured queue timeout, the call is rejected with
+    /// [`HcHttpGatewayError::AppBusy`].
+    async fn acquire_call_permit(
+        &self,
+        installed_app_id: &InstalledAppId,
+    ) -> HcHttpGatewayResult<Option<tokio::sync::OwnedSemaphorePermit>> {
+        let Some(max_concurrent_calls) = self.configuration.max_app_concurrent_calls else {
+            return Ok(None);
         };
 
-        if app_client_lock.len() > self.configuration.max_app_connections as usize {
-            // Find and remove the oldest connection
-            let installed_app_id = app_client_lock
-                .iter()
-                .min_by_key(|(_, v)| v.opened_at)
-                .map(|(k, _)| k.clone())
-                .expect("Invalid lock");
+        let semaphore = self
+            .call_permits
+            .lock()
+            .expect("Invalid lock")
+            .entry(installed_app_id.clone())
+            .or_insert_with(|| Arc::new(tokio::sync::Semaphore::new(max_concurrent_calls as usize)))
+            .clone();
 
-            tracing::warn!(
-                "Reached maximum app connections, removing connection for app: {}",
-                installed_app_id
-            );
+        match tokio::time::timeout(
+            self.configuration.app_call_queue_timeout,
+            semaphore.acquire_owned(),
+        )
+        .await
+        {
+            Ok(permit) => Ok(Some(permit.expect("Semaphore is never closed"))),
+            Err(_) => {
+                tracing::warn!(?installed_app_id, "Too many concurrent calls for app");
+                Err(HcHttpGatewayError::AppBusy(installed_app_id.clone()))
+            }
+        }
+    }
+
+    /// Forget the app interfaces, app authentication tokens and app clients from before a restart
+    /// of the conductor, then provision the app interfaces for the apps that had a client again.
+    ///
+    /// The conductor forgets its app interfaces and the tokens it issued when it restarts, so
+    /// none of them can be reused. Failing to provision an interface is not an error, the next
+    /// zome call for the app tries again.
+    pub async fn reset_after_restart(&self) {
+        let installed_app_ids = self
+            .app_clients
+            .iter()
+            .map(|client| client.key().clone())
+            .collect::<Vec<_>>();
+
+        self.cached_app_ports.write().expect("Invalid lock").clear();
+        self.forget_app_interfaces();
+        self.app_auth_tokens.lock().expect("Invalid lock").clear();
+        let evictions = self.open_connections() as u64;
+        self.app_clients.clear();
+        self.overflow_clients.clear();
+        self.connections.record_evictions(evictions);
+        tracing::info!(
+            ?installed_app_ids,
+            "Conductor restarted, closed app websockets"
+        );
 
-            app_client_lock.remove(&installed_app_id);
+        // Apps that share an app interface find it cached after the first one provisioned it
+        for installed_app_id in installed_app_ids {
+            if let Err(e) = self.get_app_port(&installed_app_id).await {
+                tracing::warn!(
+                    ?e,
+                    ?installed_app_id,
+                    "Failed to provision app interface after conductor restart"
+                );
+            }
         }
+    }
 
-        Ok(app_ws)
+    /// The state of the open app connections, ordered by app id, with the overflow connection of
+    /// an app after its connection.
+    pub fn app_connection_stats(&self) -> Vec<AppConnectionStats> {
+        let connections = self
+            .app_clients
+            .iter()
+            .map(|client| (client, false))
+            .chain(self.overflow_clients.iter().map(|client| (client, true)));
+        let mut stats = connections
+            .map(|(client, overflow)| AppConnectionStats {
+                installed_app_id: client.key().clone(),
+                age_ms: client.age().as_millis() as u64,
+                idle_ms: client.last_used().elapsed().as_millis() as u64,
+                request_count: client.request_count(),
+                in_flight: client.in_flight(),
+                reconnects: self
+                    .reconnects
+                    .get(client.key())
+                    .map(|reconnects| *reconnects)
+                    .unwrap_or_default(),
+                overflow,
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by(|a, b| {
+            (&a.installed_app_id, a.overflow).cmp(&(&b.installed_app_id, b.overflow))
+        });
+
+        stats
+    }
+
+    /// Counts of the app connections that were opened and closed.
+    pub fn connection_counters(&self) -> ConnectionCounters {
+        self.connections
+            .counters((self.app_clients.len() + self.overflow_clients.len()) as u64)
+    }
+
+    /// Forget the cached app interface ports and app interfaces, so that the next connection
+    /// looks up or attaches an app interface again.
+    pub fn clear_cached_app_ports(&self) {
+        self.cached_app_ports.write().expect("Invalid lock").clear();
+        self.forget_app_interfaces();
+        tracing::info!("Cleared cached app ports");
+    }
+
+    /// Close the app client for the given app, if there is one, and connect a new one.
+    pub async fn reconnect_app_client(
+        &self,
+        installed_app_id: InstalledAppId,
+    ) -> HcHttpGatewayResult<()> {
+        self.remove_app_client(&installed_app_id).await;
+        tracing::info!(?installed_app_id, "Reconnecting app websocket");
+        self.check_out_app_client(installed_app_id).await?;
+
+        Ok(())
     }
 
-    /// Remove an app client from the pool.
+    /// Remove an app client from the pool, along with its overflow client if it has one.
     pub async fn remove_app_client(&self, installed_app_id: &InstalledAppId) {
-        self.app_clients.write().await.remove(installed_app_id);
+        for clients in [&self.app_clients, &self.overflow_clients] {
+            if clients.remove(installed_app_id).is_some() {
+                self.connections.record_evictions(1);
+            }
+        }
+    }
+
+    /// Remove the app clients that have not been used for longer than the configured idle timeout.
+    ///
+    /// Does nothing if no idle timeout is configured.
+    pub async fn evict_idle_app_clients(&self) {
+        let Some(idle_timeout) = self.configuration.app_idle_timeout else {
+            return;
+        };
+
+        let mut evictions = 0;
+        for clients in [&self.app_clients, &self.overflow_clients] {
+            clients.retain(|installed_app_id, client| {
+                let idle = client.last_used().elapsed() > idle_timeout;
+                if idle {
+                    tracing::debug!(?installed_app_id, "Closing idle app websocket");
+                    evictions += 1;
+                }
+                !idle
+            });
+        }
+        self.connections.record_evictions(evictions);
+    }
+
+    /// Ping every open app client and remove the ones whose websocket is no longer usable.
+    ///
+    /// Requesting the app info is used as a cheap round trip to the conductor. Errors other than
+    /// websocket errors mean the connection still works, so those clients are kept.
+    pub async fn evict_dead_app_clients(&self) {
+        let app_clients = [&self.app_clients, &self.overflow_clients]
+            .into_iter()
+            .flat_map(|clients| {
+                clients
+                    .iter()
+                    .map(move |client| (clients, client.key().clone(), client.value().clone()))
+            })
+            .collect::<Vec<_>>();
+
+        let pings = app_clients
+            .into_iter()
+            .map(|(clients, installed_app_id, client)| async move {
+                match client.app_ws.app_info().await {
+                    Err(ConductorApiError::WebsocketError(e)) => {
+                        tracing::info!(?e, ?installed_app_id, "Evicting dead app websocket");
+                        Some((clients, installed_app_id, client.opened_at))
+                    }
+                    _ => None,
+                }
+            });
+        let dead = futures::future::join_all(pings).await;
+
+        for (clients, installed_app_id, opened_at) in dead.into_iter().flatten() {
+            // The connection may have been replaced by a call while we were pinging it, only
+            // remove the connection that was found to be dead.
+            if clients
+                .remove_if(&installed_app_id, |_, client| client.opened_at == opened_at)
+                .is_some()
+            {
+                self.connections.record_evictions(1);
+            }
+        }
     }
 
     async fn attempt_connect_app_ws(
         &self,
         installed_app_id: InstalledAppId,
-    ) -> HcHttpGatewayResult<AppWebsocket> {
+    ) -> HcHttpGatewayResult<AppWebsocketWithState> {
         tracing::debug!(
             "Attempting to connect to app client for {}",
             installed_app_id
@@ -197,21 +730,7 @@ impl AppConnPool {
         let app_port = self.get_app_port(&installed_app_id).await?;
         tracing::debug!("Using app port {}", app_port);
 
-        // Issue an app authentication token to allow us to connect a new client.
-        let issued = self
-            .admin_call
-            .issue_app_auth_token(IssueAppAuthenticationTokenPayload::for_installed_app_id(
-                installed_app_id.clone(),
-            ))
-            .await?;
-
-        // Build a connection request
-        let request = ConnectRequest::from(SocketAddr::new(
-            self.configuration.admin_socket_addr.ip(),
-            app_port,
-        ))
-        .try_set_header("Origin", HTTP_GW_ORIGIN)
-        .expect("Origin headers have gone out of fashion");
+        let (token, token_was_cached) = self.get_app_auth_token(&installed_app_id).await?;
 
         // Create a websocket client configuration and lower the default timeout. We are connecting
         // locally to a running Holochain. If requests take longer than the configured timeout then
@@ -221,56 +740,238 @@ impl AppConnPool {
         let mut config = WebsocketConfig::CLIENT_DEFAULT;
         config.default_request_timeout = self.configuration.zome_call_timeout;
 
-        let client_signer = ClientAgentSigner::default();
+        let config = Arc::new(config);
+
+        let signing = match &self.configuration.signing_mode {
+            SigningMode::Credentials => SigningState::with_credentials(),
+            SigningMode::Lair(lair_config) => {
+                // Connect to Lair once and share the connection between app connections.
+                let lair_signer = self
+                    .lair_signer
+                    .get_or_try_init(|| LairSigner::connect(lair_config))
+                    .await?;
+                SigningState::with_lair(lair_signer)
+            }
+        };
 
         // Attempt to connect to the app websocket
-        let app_ws = match AppWebsocket::connect_with_request_and_config(
-            request,
-            Arc::new(config),
-            issued.token,
-            client_signer.clone().into(),
-        )
-        .await
+        let mut connected = self
+            .connect_app_ws(app_port, config.clone(), token, &signing)
+            .await;
+        if let Err(e) = &connected
+            && token_was_cached
         {
+            // The conductor forgets the tokens it has issued when it restarts, so the cached token
+            // may have been rejected. Try once more with a newly issued token.
+            tracing::info!(
+                ?e,
+                ?installed_app_id,
+                "Failed to connect with a cached app authentication token, issuing a new token"
+            );
+            self.app_auth_tokens
+                .lock()
+                .expect("Invalid lock")
+                .remove(&installed_app_id);
+            let (token, _) = self.get_app_auth_token(&installed_app_id).await?;
+            connected = self.connect_app_ws(app_port, config, token, &signing).await;
+        }
+
+        let app_ws = match connected {
             Ok(client) => client,
             Err(e) => {
                 tracing::error!("Failed to connect to app websocket: {}", e);
 
                 // If we failed to make a connection, clear the cached app port so that the next
-                // attempt will re-check the app interfaces.
-                *self.cached_app_port.write().expect("Invalid lock") = None;
+                // attempt will re-check the app interfaces, and don't reuse a token that may have
+                // been rejected.
+                self.cached_app_ports
+                    .write()
+                    .expect("Invalid lock")
+                    .remove(&self.app_interface_for(&installed_app_id));
+                self.forget_app_interfaces();
+                self.app_auth_tokens
+                    .lock()
+                    .expect("Invalid lock")
+                    .remove(&installed_app_id);
 
                 // Mark the upstream as unavailable so that the caller can retry
-                return Err(HcHttpGatewayError::UpstreamUnavailable);
+                return Err(HcHttpGatewayError::UpstreamUnavailable(
+                    UpstreamConnection::App,
+                ));
             }
         };
         tracing::debug!("Connected to app websocket");
 
-        let app_info = app_ws.cached_app_info();
-        let cells = app_info
-            .cell_info
-            .values()
-            .flat_map(|cell_infos| {
-                cell_infos.iter().flat_map(|cell_info| {
-                    match cell_info {
+        let client = AppWebsocketWithState::new(app_ws, signing);
+
+        if self.configuration.eager_signing_authorization
+            && let Some(credentials) = &client.signing.credentials
+        {
+            let cells = client
+                .app_ws
+                .cached_app_info()
+                .cell_info
+                .values()
+                .flat_map(|cell_infos| {
+                    cell_infos.iter().flat_map(|cell_info| match cell_info {
                         CellInfo::Provisioned(provisioned) => Some(provisioned.cell_id.clone()),
-                        // TODO Provisioning of these wouldn't be dynamic, you'd have to
-                        //      restart the gateway or Holochain to get new credentials for
-                        //      new clones...
-                        //      See https://github.com/holochain/holochain/issues/4595
-                        // CellInfo::Cloned(clone_cell) => Some(clone_cell.cell_id.clone()),
+                        CellInfo::Cloned(cloned) if cloned.enabled => Some(cloned.cell_id.clone()),
                         _ => None,
+                    })
+                })
+                .collect::<Vec<_>>();
+            tracing::debug!("Collected cells to authorize: {:?}", cells);
+
+            let granted_functions =
+                self.granted_functions(&client.app_ws.cached_app_info().installed_app_id);
+
+            // Authorize the cells concurrently, but limit how many admin calls are in flight so
+            // that apps with many cells don't flood the admin interface.
+            let authorized = futures::stream::iter(cells)
+                .map(|cell_id| {
+                    let granted_functions = granted_functions.clone();
+                    async move {
+                        let credentials = self
+                            .admin_call
+                            .authorize_signing_credentials(AuthorizeSigningCredentialsPayload {
+                                cell_id: cell_id.clone(),
+                                functions: Some(granted_functions),
+                            })
+                            .await?;
+                        tracing::debug!("Authorized credentials for cell {}", cell_id);
+
+                        HcHttpGatewayResult::Ok((cell_id, credentials))
                     }
                 })
+                .buffer_unordered(MAX_CONCURRENT_AUTHORIZATIONS)
+                .try_collect::<Vec<_>>()
+                .await?;
+
+            let mut authorized_cells = client.signing.authorized_cells.lock().await;
+            for (cell_id, cell_credentials) in authorized {
+                credentials.add_credentials(cell_id.clone(), cell_credentials);
+                authorized_cells.insert(cell_id);
+            }
+        }
+
+        Ok(client)
+    }
+
+    /// Get an app authentication token for the given app, along with whether it was cached.
+    ///
+    /// A cached token is reused until it is close to expiring, otherwise a new multi-use token is
+    /// issued and cached.
+    async fn get_app_auth_token(
+        &self,
+        installed_app_id: &InstalledAppId,
+    ) -> HcHttpGatewayResult<(Vec<u8>, bool)> {
+        if let Some(cached) = self
+            .app_auth_tokens
+            .lock()
+            .expect("Invalid lock")
+            .get(installed_app_id)
+            && cached.expires > Instant::now()
+        {
+            tracing::debug!(?installed_app_id, "Using cached app authentication token");
+            return Ok((cached.token.clone(), true));
+        }
+
+        let issued = self
+            .admin_call
+            .issue_app_auth_token(
+                IssueAppAuthenticationTokenPayload::for_installed_app_id(installed_app_id.clone())
+                    .expiry_seconds(APP_AUTH_TOKEN_EXPIRY.as_secs())
+                    .single_use(false),
+            )
+            .await?;
+
+        self.app_auth_tokens.lock().expect("Invalid lock").insert(
+            installed_app_id.clone(),
+            CachedAppAuthToken {
+                token: issued.token.clone(),
+                expires: Instant::now() + APP_AUTH_TOKEN_EXPIRY - APP_AUTH_TOKEN_EXPIRY_MARGIN,
+            },
+        );
+
+        Ok((issued.token, false))
+    }
+
+    /// Open a websocket to the app interface on the given port, authenticating with the token.
+    async fn connect_app_ws(
+        &self,
+        app_port: u16,
+        config: Arc<WebsocketConfig>,
+        token: Vec<u8>,
+        signing: &SigningState,
+    ) -> ConductorApiResult<AppWebsocket> {
+        // The app interface is on the same host as the admin interface, which may have moved
+        let admin_socket_addr = match &self.configuration.admin_ws_url {
+            Some(admin_ws_url) => {
+                re_resolve_address_from_url(admin_ws_url, self.configuration.admin_socket_addr)
+                    .await
+            }
+            None => self.configuration.admin_socket_addr,
+        };
+
+        // Build a connection request
+        let request = ConnectRequest::from(SocketAddr::new(admin_socket_addr.ip(), app_port))
+            .try_set_header("Origin", HTTP_GW_ORIGIN)
+            .expect("Origin headers have gone out of fashion");
+
+        AppWebsocket::connect_with_request_and_config(
+            request,
+            config,
+            token,
+            Arc::new(CapSecretSigner::new(signing.signer.clone())),
+        )
+        .await
+    }
+
+    /// Authorize signing credentials for a cell on the given connection, unless that has already
+    /// been done.
+    ///
+    /// The credentials grant access to the allowed functions of the app that the connection is
+    /// for. Because this is checked on every call, clone cells created after the connection was
+    /// opened are authorized the first time they are called.
+    ///
+    /// Does nothing when zome calls are signed by a Lair keystore.
+    async fn authorize_cell(
+        &self,
+        client: &AppWebsocketWithState,
+        cell_id: &CellId,
+    ) -> HcHttpGatewayResult<()> {
+        let Some(credentials) = &client.signing.credentials else {
+            return Ok(());
+        };
+
+        let mut authorized_cells = client.signing.authorized_cells.lock().await;
+        if authorized_cells.contains(cell_id) {
+            return Ok(());
+        }
+
+        let granted_functions =
+            self.granted_functions(&client.app_ws.cached_app_info().installed_app_id);
+
+        let cell_credentials = self
+            .admin_call
+            .authorize_signing_credentials(AuthorizeSigningCredentialsPayload {
+                cell_id: cell_id.clone(),
+                functions: Some(granted_functions),
             })
-            .collect::<Vec<_>>();
-        tracing::debug!("Collected cells to authorize: {:?}", cells);
+            .await?;
+        tracing::debug!("Authorized credentials for cell {}", cell_id);
+
+        credentials.add_credentials(cell_id.clone(), cell_credentials);
+        authorized_cells.insert(cell_id.clone());
+
+        Ok(())
+    }
 
-        // Map the allowed functions to granted functions
-        //
+    /// Map the allowed functions of an app to the functions granted to its signing credentials.
+    fn granted_functions(&self, installed_app_id: &InstalledAppId) -> GrantedFunctions {
         // Direct access because we should already have checked that a zome call is allowed
         // for this app before getting an app connection.
-        let granted_functions = match &self.configuration.allowed_fns[&installed_app_id] {
+        let granted_functions = match &self.configuration.allowed_fns[installed_app_id] {
             AllowedFns::All => GrantedFunctions::All,
             AllowedFns::Restricted(fns) => GrantedFunctions::Listed(
                 fns.iter()
@@ -280,31 +981,31 @@ impl AppConnPool {
         };
         tracing::debug!("Granting access to functions: {:?}", granted_functions);
 
-        // For each cell in the app, authorize signing credentials for the granted functions
-        for cell_id in cells {
-            let credentials = self
-                .admin_call
-                .authorize_signing_credentials(AuthorizeSigningCredentialsPayload {
-                    cell_id: cell_id.clone(),
-                    functions: Some(granted_functions.clone()),
-                })
-                .await?;
-            tracing::debug!("Authorized credentials for cell {}", cell_id);
-
-            client_signer.add_credentials(cell_id, credentials);
-        }
+        granted_functions
+    }
 
-        Ok(app_ws)
+    /// The app that the app interface used for the given app is dedicated to, or `None` when
+    /// the app is connected to through the app interface shared by all apps.
+    fn app_interface_for(&self, installed_app_id: &InstalledAppId) -> Option<InstalledAppId> {
+        self.configuration
+            .dedicated_app_interfaces
+            .then(|| installed_app_id.clone())
     }
 
     async fn get_app_port(&self, installed_app_id: &InstalledAppId) -> HcHttpGatewayResult<u16> {
+        let dedicated_to = self.app_interface_for(installed_app_id);
         {
-            if let Some(app_port) = self.cached_app_port.read().expect("Invalid lock").as_ref() {
+            if let Some(app_port) = self
+                .cached_app_ports
+                .read()
+                .expect("Invalid lock")
+                .get(&dedicated_to)
+            {
                 return Ok(*app_port);
             }
         }
 
-        let app_interfaces = self.admin_call.list_app_interfaces().await?;
+        let app_interfaces = self.list_app_interfaces().await?;
 
         let selected_app_interface = app_interfaces.into_iter().find(|app_interface| {
             if let Some(ref for_app_id) = app_interface.installed_app_id
@@ -312,6 +1013,10 @@ impl AppConnPool {
             {
                 return false;
             }
+            // A dedicated app interface must only be usable by the app
+            if dedicated_to.is_some() && app_interface.installed_app_id.is_none() {
+                return false;
+            }
 
             app_interface.allowed_origins.is_allowed(HTTP_GW_ORIGIN)
         });
@@ -319,21 +1024,82 @@ impl AppConnPool {
         let app_port = match selected_app_interface {
             Some(app_interface) => app_interface.port,
             None => {
-                self.admin_call
-                    .attach_app_interface(0, AllowedOrigins::from(HTTP_GW_ORIGIN.to_string()), None)
-                    .await?
+                let app_port = self
+                    .admin_call
+                    .attach_app_interface(
+                        0,
+                        AllowedOrigins::from(HTTP_GW_ORIGIN.to_string()),
+                        dedicated_to.clone(),
+                    )
+                    .await?;
+                // The listing no longer includes every app interface
+                self.forget_app_interfaces();
+                app_port
             }
         };
-        *self.cached_app_port.write().expect("Invalid app port") = Some(app_port);
+        self.cached_app_ports
+            .write()
+            .expect("Invalid app port")
+            .insert(dedicated_to, app_port);
 
         Ok(app_port)
     }
 
+    /// List the app interfaces of the conductor, reusing a listing that is younger than the
+    /// configured TTL.
+    ///
+    /// Concurrent connects that find no listing to reuse wait for a single listing, so that a
+    /// burst of connects to apps without a cached app port lists the app interfaces once.
+    async fn list_app_interfaces(&self) -> HcHttpGatewayResult<Vec<AppInterfaceInfo>> {
+        let ttl = self.configuration.app_interfaces_cache_ttl;
+        if ttl.is_zero() {
+            return self.admin_call.list_app_interfaces().await;
+        }
+        if let Some(app_interfaces) = self.fresh_app_interfaces(ttl) {
+            return Ok(app_interfaces);
+        }
+
+        let _listing = self.list_app_interfaces_lock.lock().await;
+        // The app interfaces may have been listed while waiting for the lock
+        if let Some(app_interfaces) = self.fresh_app_interfaces(ttl) {
+            return Ok(app_interfaces);
+        }
+        let app_interfaces = self.admin_call.list_app_interfaces().await?;
+        *self.cached_app_interfaces.lock().expect("Invalid lock") = Some(CachedAppInterfaces {
+            interfaces: app_interfaces.clone(),
+            listed: Instant::now(),
+        });
+
+        Ok(app_interfaces)
+    }
+
+    fn fresh_app_interfaces(&self, ttl: Duration) -> Option<Vec<AppInterfaceInfo>> {
+        self.cached_app_interfaces
+            .lock()
+            .expect("Invalid lock")
+            .as_ref()
+            .filter(|cached| cached.listed.elapsed() < ttl)
+            .map(|cached| cached.interfaces.clone())
+    }
+
+    /// Forget the listed app interfaces, so that the next connect lists them again.
+    fn forget_app_interfaces(&self) {
+        *self.cached_app_interfaces.lock().expect("Invalid lock") = None;
+    }
+
+    /// Get the app authentication token cached for an app, for testing purposes.
+    #[cfg(feature = "test-utils")]
+    pub fn get_cached_app_auth_token(&self, installed_app_id: &InstalledAppId) -> Option<Vec<u8>> {
+        self.app_auth_tokens
+            .lock()
+            .expect("Invalid lock")
+            .get(installed_app_id)
+            .map(|cached| cached.token.clone())
+    }
+
     /// Get the inner pool for testing purposes.
     #[cfg(feature = "test-utils")]
-    pub fn get_inner_pool(
-        &self,
-    ) -> Arc<tokio::sync::RwLock<HashMap<InstalledAppId, AppWebsocketWithState>>> {
+    pub fn get_inner_pool(&self) -> Arc<DashMap<InstalledAppId, AppWebsocketWithState>> {
         self.app_clients.clone()
     }
 }
@@ -341,30 +1107,58 @@ impl AppConnPool {
 impl AppCall for AppConnPool {
     fn handle_zome_call(
         &self,
-        installed_app_id: InstalledAppId,
-        cell_id: CellId,
-        zome_name: String,
-        fn_name: String,
-        payload: ExternIO,
+        request: ZomeCallRequest,
     ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
         let this = self.clone();
+        let ZomeCallRequest {
+            installed_app_id,
+            cell_id,
+            zome_name,
+            fn_name,
+            payload,
+            cap_secret,
+            timeout,
+        } = request;
         let app_id = installed_app_id.clone();
-        Box::pin(async move {
-            this.call(installed_app_id, |app_ws| {
+        let span = tracing::info_span!(
+            "app_call",
+            app_id = %installed_app_id,
+            dna_hash = %cell_id.dna_hash(),
+            %zome_name,
+            %fn_name,
+            request_id = current_request_id().as_deref(),
+        );
+        let call = async move {
+            let _permit = this.acquire_call_permit(&installed_app_id).await?;
+
+            // The names are converted once and shared between attempts
+            let zome: ZomeName = zome_name.to_string().into();
+            let function: FunctionName = fn_name.to_string().into();
+            this.call_with_client(installed_app_id, |client| {
+                let this = this.clone();
                 let app_id = app_id.clone();
                 let cell_id = cell_id.clone();
                 let zome_name = zome_name.clone();
                 let fn_name = fn_name.clone();
+                let zome = zome.clone();
+                let function = function.clone();
                 let payload = payload.clone();
                 Box::pin(async move {
-                    let result = app_ws
-                        .call_zome(
+                    // Signing credentials are authorized the first time a cell is called
+                    this.authorize_cell(&client, &cell_id).await?;
+
+                    // The call takes its own copy of the payload, which is shared between attempts
+                    let result = with_client_cap_secret(
+                        cap_secret,
+                        client.app_ws.call_zome(
                             ZomeCallTarget::CellId(cell_id.clone()),
-                            zome_name.clone().into(),
-                            fn_name.clone().into(),
-                            payload,
-                        )
-                        .await;
+                            zome,
+                            function,
+                            ExternIO::clone(&payload),
+                        ),
+                    )
+                    .await
+                    .map_err(HcHttpGatewayError::from);
                     if let Err(err) = &result {
                         tracing::debug!(
                             ?err,
@@ -375,11 +1169,237 @@ impl AppCall for AppConnPool {
                             "Zome call error"
                         );
                     }
-                    let result = result?;
-                    Ok(result)
+                    result
                 })
             })
             .await
+        }
+        .instrument(span);
+
+        Box::pin(async move {
+            match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, call)
+                    .await
+                    .map_err(|_| HcHttpGatewayError::ZomeCallTimeout)?,
+                None => call.await,
+            }
+        })
+    }
+
+    fn evict_stale_connections(&self) -> BoxFuture<'static, ()> {
+        let this = self.clone();
+        Box::pin(async move {
+            // No need to ping the connections that are about to be closed anyway
+            this.evict_idle_app_clients().await;
+            this.evict_dead_app_clients().await;
+        })
+    }
+
+    fn remove_app_connection(&self, installed_app_id: InstalledAppId) -> BoxFuture<'static, ()> {
+        let this = self.clone();
+        Box::pin(async move { this.remove_app_client(&installed_app_id).await })
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        let this = self.clone();
+        Box::pin(async move {
+            let evictions = this.open_connections() as u64;
+            this.app_clients.clear();
+            this.overflow_clients.clear();
+            this.connections.record_evictions(evictions);
+            tracing::debug!("Closed app websockets");
         })
     }
+
+    fn reset_after_restart(&self) -> BoxFuture<'static, ()> {
+        let this = self.clone();
+        Box::pin(async move { this.reset_after_restart().await })
+    }
+
+    fn app_connection_stats(&self) -> Vec<AppConnectionStats> {
+        self.app_connection_stats()
+    }
+
+    fn clear_cached_app_ports(&self) {
+        self.clear_cached_app_ports()
+    }
+
+    fn reconnect_app(
+        &self,
+        installed_app_id: InstalledAppId,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<()>> {
+        let this = self.clone();
+        Box::pin(async move { this.reconnect_app_client(installed_app_id).await })
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        self.connection_counters()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockAdminCall;
+    use std::net::Ipv4Addr;
+
+    #[tokio::test]
+    async fn calls_beyond_the_concurrency_limit_are_rejected() {
+        let mut configuration = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "",
+            HashMap::new(),
+            "",
+            "",
+        )
+        .unwrap();
+        configuration.max_app_concurrent_calls = Some(1);
+        configuration.app_call_queue_timeout = Duration::from_millis(50);
+        let pool = AppConnPool::new(configuration, Arc::new(MockAdminCall::new()));
+
+        let permit = pool
+            .acquire_call_permit(&"app_1".to_string())
+            .await
+            .unwrap();
+        assert!(permit.is_some());
+
+        // The limit applies per app
+        pool.acquire_call_permit(&"app_2".to_string())
+            .await
+            .unwrap();
+
+        let result = pool.acquire_call_permit(&"app_1".to_string()).await;
+        assert!(matches!(result, Err(HcHttpGatewayError::AppBusy(app_id)) if app_id == "app_1"));
+
+        // A call waiting in the queue gets the permit once the call in progress finishes
+        let app_id = "app_1".to_string();
+        let waiting = pool.acquire_call_permit(&app_id);
+        let (result, _) = tokio::join!(waiting, async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(permit);
+        });
+        assert!(result.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn dedicated_app_interface_is_attached_for_the_app() {
+        let mut configuration = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "",
+            HashMap::new(),
+            "",
+            "",
+        )
+        .unwrap();
+        configuration.dedicated_app_interfaces = true;
+
+        let mut admin_call = MockAdminCall::new();
+        admin_call.expect_list_app_interfaces().returning(|| {
+            Box::pin(async {
+                // The shared interface is not used for apps with a dedicated interface
+                Ok(vec![AppInterfaceInfo {
+                    port: 1000,
+                    allowed_origins: AllowedOrigins::from(HTTP_GW_ORIGIN.to_string()),
+                    installed_app_id: None,
+                }])
+            })
+        });
+        admin_call
+            .expect_attach_app_interface()
+            .withf(|_, _, installed_app_id| installed_app_id.as_deref() == Some("app_1"))
+            .returning(|_, _, _| Box::pin(async { Ok(1001) }))
+            .once();
+        admin_call
+            .expect_attach_app_interface()
+            .withf(|_, _, installed_app_id| installed_app_id.as_deref() == Some("app_2"))
+            .returning(|_, _, _| Box::pin(async { Ok(1002) }))
+            .once();
+        let pool = AppConnPool::new(configuration, Arc::new(admin_call));
+
+        assert_eq!(pool.get_app_port(&"app_1".to_string()).await.unwrap(), 1001);
+        assert_eq!(pool.get_app_port(&"app_2".to_string()).await.unwrap(), 1002);
+        // The port of the dedicated interface is cached for the app
+        assert_eq!(pool.get_app_port(&"app_1".to_string()).await.unwrap(), 1001);
+    }
+
+    #[tokio::test]
+    async fn failed_connects_are_counted() {
+        let configuration = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "",
+            HashMap::new(),
+            "",
+            "",
+        )
+        .unwrap();
+        let mut admin_call = MockAdminCall::new();
+        admin_call.expect_list_app_interfaces().returning(|| {
+            Box::pin(async {
+                Err(HcHttpGatewayError::UpstreamUnavailable(
+                    UpstreamConnection::Admin,
+                ))
+            })
+        });
+        let pool = AppConnPool::new(configuration, Arc::new(admin_call));
+
+        pool.get_or_connect_app_client("app_1".to_string())
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            pool.connection_counters(),
+            ConnectionCounters {
+                open: 0,
+                connect_attempts: 1,
+                failed_connects: 1,
+                evictions: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn app_interfaces_are_listed_once_for_concurrent_connects() {
+        let mut configuration = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "",
+            HashMap::new(),
+            "",
+            "",
+        )
+        .unwrap();
+        configuration.dedicated_app_interfaces = true;
+
+        let mut admin_call = MockAdminCall::new();
+        admin_call
+            .expect_list_app_interfaces()
+            .returning(|| {
+                Box::pin(async {
+                    Ok(["app_1", "app_2"]
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, installed_app_id)| AppInterfaceInfo {
+                            port: 1001 + i as u16,
+                            allowed_origins: AllowedOrigins::from(HTTP_GW_ORIGIN.to_string()),
+                            installed_app_id: Some(installed_app_id.to_string()),
+                        })
+                        .collect())
+                })
+            })
+            .times(2);
+        let pool = AppConnPool::new(configuration, Arc::new(admin_call));
+
+        let (app_1_id, app_2_id) = ("app_1".to_string(), "app_2".to_string());
+        let (app_1, app_2) =
+            tokio::join!(pool.get_app_port(&app_1_id), pool.get_app_port(&app_2_id));
+        assert_eq!(app_1.unwrap(), 1001);
+        assert_eq!(app_2.unwrap(), 1002);
+
+        // Clearing the cached app ports lists the app interfaces again
+        pool.clear_cached_app_ports();
+        assert_eq!(pool.get_app_port(&"app_1".to_string()).await.unwrap(), 1001);
+    }
 }
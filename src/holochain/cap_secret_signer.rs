@@ -0,0 +1,58 @@
+use holochain_client::{AgentSigner, CellId};
+use holochain_types::prelude::{AgentPubKey, CapSecret, Signature};
+use std::future::Future;
+use std::sync::Arc;
+
+tokio::task_local! {
+    /// Capability secret supplied by the client for the zome call that is being made.
+    static CLIENT_CAP_SECRET: CapSecret;
+}
+
+/// Run a future that makes a zome call, presenting the given capability secret instead of the
+/// one held by the connection's signer, if one is given.
+pub(crate) async fn with_client_cap_secret<F: Future>(
+    cap_secret: Option<CapSecret>,
+    future: F,
+) -> F::Output {
+    match cap_secret {
+        Some(cap_secret) => CLIENT_CAP_SECRET.scope(cap_secret, future).await,
+        None => future.await,
+    }
+}
+
+/// A signer that defers to the signer of an app connection, except that it presents the
+/// capability secret supplied by the client when a zome call is made within
+/// [`with_client_cap_secret`].
+///
+/// The signer of an app connection is fixed when the connection is opened, so this is how a
+/// single connection serves calls made with different capability secrets.
+pub(crate) struct CapSecretSigner(Arc<dyn AgentSigner + Send + Sync>);
+
+impl CapSecretSigner {
+    pub(crate) fn new(signer: Arc<dyn AgentSigner + Send + Sync>) -> Self {
+        Self(signer)
+    }
+}
+
+#[async_trait::async_trait]
+impl AgentSigner for CapSecretSigner {
+    async fn sign(
+        &self,
+        cell_id: &CellId,
+        provenance: AgentPubKey,
+        data_to_sign: Arc<[u8]>,
+    ) -> anyhow::Result<Signature> {
+        self.0.sign(cell_id, provenance, data_to_sign).await
+    }
+
+    fn get_provenance(&self, cell_id: &CellId) -> Option<AgentPubKey> {
+        self.0.get_provenance(cell_id)
+    }
+
+    fn get_cap_secret(&self, cell_id: &CellId) -> Option<CapSecret> {
+        CLIENT_CAP_SECRET
+            .try_with(|cap_secret| *cap_secret)
+            .ok()
+            .or_else(|| self.0.get_cap_secret(cell_id))
+    }
+}
@@ -0,0 +1,564 @@
+#[cfg(feature = "alert-webhook")]
+use crate::AlertWebhook;
+#[cfg(feature = "alert-webhook")]
+use crate::alert::OutageAlert;
+use crate::app_selection::{AppInfoCache, NotInstalledCache, changed_app_ids};
+use crate::config::{AllowedAppIds, ConductorName, Configuration, DEFAULT_CONDUCTOR_NAME};
+use crate::holochain::recording::RecordingConn;
+use crate::{AdminCall, AdminConn, AppCall, AppConnPool, Recorder, Recording};
+use holochain_client::AppInfo;
+use holochain_types::app::{AppStatus, InstalledAppId};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Handles for making calls to a single Holochain conductor.
+#[derive(Debug, Clone)]
+pub struct ConductorHandle {
+    /// Name that identifies the conductor.
+    pub name: ConductorName,
+    /// Apps installed on this conductor that the gateway is permitted to access.
+    pub allowed_app_ids: AllowedAppIds,
+    /// Handle for making admin calls to the conductor.
+    pub admin_call: Arc<dyn AdminCall>,
+    /// Handle for making zome calls to the conductor.
+    pub app_call: Arc<dyn AppCall>,
+    /// Whether the conductor is currently reachable.
+    pub health: ConductorHealth,
+    /// The apps installed on the conductor, as last listed by the gateway.
+    pub(crate) app_info_cache: AppInfoCache,
+    /// Lookups that recently found no installed app on the conductor.
+    pub(crate) not_installed_cache: NotInstalledCache,
+}
+
+impl ConductorHandle {
+    /// Create a new conductor handle with an empty app info cache.
+    ///
+    /// Lookups that find no installed app are not cached, see
+    /// [`ConductorHandle::with_not_installed_cache_ttl`].
+    pub fn new(
+        name: impl Into<ConductorName>,
+        allowed_app_ids: AllowedAppIds,
+        admin_call: Arc<dyn AdminCall>,
+        app_call: Arc<dyn AppCall>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            allowed_app_ids,
+            admin_call,
+            app_call,
+            health: Default::default(),
+            app_info_cache: Default::default(),
+            not_installed_cache: Default::default(),
+        }
+    }
+
+    /// Cache lookups that find no installed app on the conductor for the given TTL.
+    pub fn with_not_installed_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.not_installed_cache = NotInstalledCache::new(ttl);
+        self
+    }
+
+    /// Post a notification to the given webhook whenever the conductor becomes unreachable or
+    /// reachable again.
+    #[cfg(feature = "alert-webhook")]
+    pub fn with_alert_webhook(mut self, webhook: AlertWebhook) -> Self {
+        self.health = self.health.clone().with_alert_webhook(webhook);
+        self
+    }
+
+    /// Apply the settings of the configuration that are common to all conductors.
+    fn configured(mut self, configuration: &Configuration) -> Self {
+        self = self.with_not_installed_cache_ttl(configuration.not_installed_cache_ttl);
+        #[cfg(feature = "alert-webhook")]
+        if let Some(alert_webhook) = &configuration.alert_webhook {
+            self = self.with_alert_webhook(alert_webhook.clone());
+        }
+        self
+    }
+}
+
+impl ConductorHandle {
+    /// Record whether the conductor could be reached, see [`ConductorHealth::record`].
+    pub fn record_health(&self, healthy: bool) {
+        self.health.record(&self.name, healthy);
+    }
+
+    /// Forget the cached app info and close the app connections of the given apps, so that the
+    /// next request for one of them sees the current state of the app on the conductor.
+    pub async fn invalidate_apps(&self, installed_app_ids: &HashSet<InstalledAppId>) {
+        tracing::info!(conductor = %self.name, ?installed_app_ids, "Invalidating changed apps");
+
+        self.app_info_cache
+            .write()
+            .await
+            .retain(|app| !installed_app_ids.contains(&app.installed_app_id));
+
+        for installed_app_id in installed_app_ids {
+            self.app_call
+                .remove_app_connection(installed_app_id.clone())
+                .await;
+        }
+    }
+
+    /// Forget all cached app info and lookups that found no installed app, so that the next
+    /// request for any app lists the apps on the conductor again.
+    pub async fn clear_app_info_cache(&self) {
+        self.app_info_cache.write().await.clear();
+        self.not_installed_cache.clear();
+        tracing::info!(conductor = %self.name, "Cleared app info cache");
+    }
+
+    /// Whether the app is in the cached app info.
+    pub async fn has_cached_app(&self, installed_app_id: &InstalledAppId) -> bool {
+        self.app_info_cache
+            .read()
+            .await
+            .iter()
+            .any(|app| &app.installed_app_id == installed_app_id)
+    }
+
+    /// Replace the cached app info with the enabled apps from a fresh listing of the apps.
+    ///
+    /// Lookups that previously found no installed app are forgotten, as the app may have been
+    /// installed since.
+    pub async fn refresh_app_info_cache(&self, apps: &[AppInfo]) {
+        *self.app_info_cache.write().await = apps
+            .iter()
+            .filter(|app| matches!(app.status, AppStatus::Enabled))
+            .cloned()
+            .collect();
+        self.not_installed_cache.clear();
+    }
+}
+
+/// Health of a conductor as last observed by the gateway.
+///
+/// A conductor starts out healthy. It is marked as unhealthy when the gateway fails to reach it,
+/// and as healthy again when a call to it succeeds. Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct ConductorHealth {
+    healthy: Arc<AtomicBool>,
+    unhealthy_since: Arc<Mutex<Option<Instant>>>,
+    #[cfg(feature = "alert-webhook")]
+    alert_webhook: Option<AlertWebhook>,
+}
+
+impl Default for ConductorHealth {
+    fn default() -> Self {
+        Self {
+            healthy: Arc::new(AtomicBool::new(true)),
+            unhealthy_since: Default::default(),
+            #[cfg(feature = "alert-webhook")]
+            alert_webhook: None,
+        }
+    }
+}
+
+impl ConductorHealth {
+    /// Post a notification to the given webhook on every change of health.
+    #[cfg(feature = "alert-webhook")]
+    pub fn with_alert_webhook(mut self, webhook: AlertWebhook) -> Self {
+        self.alert_webhook = Some(webhook);
+        self
+    }
+
+    /// Whether the conductor was reachable when it was last checked.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+
+    /// Record the outcome of reaching the named conductor, logging and alerting on any change of
+    /// health.
+    ///
+    /// Prefer [`ConductorHandle::record_health`], which supplies the name of the conductor.
+    pub fn record(&self, conductor: &str, healthy: bool) {
+        let was_healthy = self.healthy.swap(healthy, Ordering::AcqRel);
+
+        match (was_healthy, healthy) {
+            (true, false) => {
+                tracing::warn!(conductor, "Conductor is unhealthy");
+                *self.unhealthy_since.lock().expect("Invalid lock") = Some(Instant::now());
+                #[cfg(feature = "alert-webhook")]
+                if let Some(alert_webhook) = &self.alert_webhook {
+                    alert_webhook.notify(OutageAlert::unavailable(conductor));
+                }
+            }
+            (false, true) => {
+                let outage = self
+                    .unhealthy_since
+                    .lock()
+                    .expect("Invalid lock")
+                    .take()
+                    .map(|since| since.elapsed());
+                tracing::info!(conductor, ?outage, "Conductor is healthy again");
+                #[cfg(feature = "alert-webhook")]
+                if let Some(alert_webhook) = &self.alert_webhook {
+                    alert_webhook.notify(OutageAlert::recovered(conductor, outage));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The Holochain conductors that the gateway routes requests to, identified by name.
+///
+/// Conductors are kept in the order they were added, which is the order of preference when an app
+/// is available on more than one conductor.
+#[derive(Debug, Clone, Default)]
+pub struct Conductors(Vec<ConductorHandle>);
+
+impl Conductors {
+    /// Create the conductors for a configuration with a single conductor, using the given admin
+    /// and app call handles for it.
+    ///
+    /// Any additional conductors in the configuration are ignored.
+    pub fn single(
+        configuration: &Configuration,
+        admin_call: Arc<dyn AdminCall>,
+        app_call: Arc<dyn AppCall>,
+    ) -> Self {
+        let mut conductors = Self::default();
+        conductors.insert(
+            ConductorHandle::new(
+                DEFAULT_CONDUCTOR_NAME,
+                configuration.allowed_app_ids.clone(),
+                admin_call,
+                app_call,
+            )
+            .configured(configuration),
+        );
+        conductors
+    }
+
+    /// Create an [`AdminConn`] and an [`AppConnPool`] for every conductor in the configuration.
+    ///
+    /// No connections are opened until the first call is made to a conductor.
+    pub fn from_configuration(configuration: &Configuration) -> Self {
+        let mut conductors = Self::default();
+        for conductor in configuration.conductors() {
+            let mut admin_conn = AdminConn::with_pool_size(
+                conductor.admin_socket_addr,
+                configuration.admin_pool_size as usize,
+            );
+            if let Some(admin_ws_url) = &conductor.admin_ws_url {
+                admin_conn = admin_conn.with_admin_ws_url(admin_ws_url.clone());
+            }
+            let admin_call = Arc::new(admin_conn);
+            let app_call = Arc::new(AppConnPool::new(
+                configuration.for_conductor(&conductor),
+                admin_call.clone(),
+            ));
+            conductors.insert(
+                ConductorHandle::new(
+                    conductor.name,
+                    conductor.allowed_app_ids,
+                    admin_call,
+                    app_call,
+                )
+                .configured(configuration),
+            );
+        }
+        conductors
+    }
+
+    /// Create the conductors of the configuration, answering their admin and zome calls from the
+    /// recording instead of connecting to them.
+    pub fn replayed(configuration: &Configuration, recording: &Recording) -> Self {
+        let mut conductors = Self::default();
+        for conductor in configuration.conductors() {
+            let replay = Arc::new(recording.replay(&conductor.name));
+            conductors.insert(
+                ConductorHandle::new(
+                    conductor.name,
+                    conductor.allowed_app_ids,
+                    replay.clone(),
+                    replay,
+                )
+                .configured(configuration),
+            );
+        }
+        conductors
+    }
+
+    /// Record the apps that every conductor lists and the responses of the zome calls made to it,
+    /// so that they can be replayed later, see [`Conductors::replayed`].
+    pub fn recorded(mut self, recorder: &Recorder) -> Self {
+        for conductor in &mut self.0 {
+            let recording = Arc::new(RecordingConn::new(
+                conductor.name.clone(),
+                conductor.admin_call.clone(),
+                conductor.app_call.clone(),
+                recorder.clone(),
+            ));
+            conductor.admin_call = recording.clone();
+            conductor.app_call = recording;
+        }
+        self
+    }
+
+    /// Add a conductor, replacing any existing conductor with the same name.
+    pub fn insert(&mut self, conductor: ConductorHandle) {
+        match self
+            .0
+            .iter_mut()
+            .find(|existing| existing.name == conductor.name)
+        {
+            Some(existing) => *existing = conductor,
+            None => self.0.push(conductor),
+        }
+    }
+
+    /// Get the conductor with the given name.
+    pub fn get(&self, name: &str) -> Option<&ConductorHandle> {
+        self.0.iter().find(|conductor| conductor.name == name)
+    }
+
+    /// Iterate over all conductors, in order of preference.
+    pub fn iter(&self) -> impl Iterator<Item = &ConductorHandle> {
+        self.0.iter()
+    }
+
+    /// Spawn a task that checks the health of every conductor at the given interval.
+    ///
+    /// Listing the app interfaces is used as a cheap check that the admin interface of a conductor
+    /// is reachable. A conductor that does not respond within the interval is unhealthy.
+    ///
+    /// Checks go through the same reconnection logic as any other admin call, so a dropped admin
+    /// websocket is re-established by the health checks rather than by the next client request.
+    pub fn spawn_health_checks(&self, interval: Duration) -> JoinHandle<()> {
+        let conductors = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+
+                for conductor in conductors.iter() {
+                    let healthy = match tokio::time::timeout(
+                        interval,
+                        conductor.admin_call.list_app_interfaces(),
+                    )
+                    .await
+                    {
+                        Ok(Ok(_)) => true,
+                        Ok(Err(e)) => {
+                            tracing::debug!(conductor = %conductor.name, ?e, "Health check failed");
+                            false
+                        }
+                        Err(_) => {
+                            tracing::debug!(conductor = %conductor.name, "Health check timed out");
+                            false
+                        }
+                    };
+
+                    conductor.record_health(healthy);
+                }
+            }
+        })
+    }
+
+    /// Spawn a task that checks the open app connections of every conductor at the given
+    /// interval, see [`AppCall::evict_stale_connections`].
+    pub fn spawn_app_keepalives(&self, interval: Duration) -> JoinHandle<()> {
+        let conductors = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+
+                futures::future::join_all(
+                    conductors
+                        .iter()
+                        .map(|conductor| conductor.app_call.evict_stale_connections()),
+                )
+                .await;
+            }
+        })
+    }
+
+    /// Spawn a task that lists the apps on every conductor at the given interval, and invalidates
+    /// the apps that changed since the previous listing, see [`ConductorHandle::invalidate_apps`].
+    ///
+    /// This covers apps being installed, uninstalled, enabled or disabled, and clone cells being
+    /// created or removed. The cached app info is then refreshed from the listing, so that it
+    /// reflects the conductor even when requests keep finding the apps they need in the cache.
+    pub fn spawn_app_watchers(&self, interval: Duration) -> JoinHandle<()> {
+        let conductors = self.clone();
+
+        tokio::spawn(async move {
+            let mut previous_apps = HashMap::<ConductorName, Vec<AppInfo>>::new();
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+
+                for conductor in conductors.iter() {
+                    let apps = match conductor.admin_call.list_apps(None).await {
+                        Ok(apps) => apps,
+                        Err(e) => {
+                            tracing::debug!(conductor = %conductor.name, ?e, "Failed to list apps");
+                            continue;
+                        }
+                    };
+
+                    if let Some(previous) = previous_apps.get(&conductor.name) {
+                        let changed = changed_app_ids(previous, &apps);
+                        if !changed.is_empty() {
+                            conductor.invalidate_apps(&changed).await;
+                        }
+                    }
+                    conductor.refresh_app_info_cache(&apps).await;
+                    previous_apps.insert(conductor.name.clone(), apps);
+                }
+            }
+        })
+    }
+
+    /// Spawn a task that resets the app connections of a conductor once its admin connection was
+    /// re-established after being lost, see [`AppCall::reset_after_restart`].
+    ///
+    /// A lost admin connection usually means that the conductor restarted, which breaks every app
+    /// connection to it. Resetting them all at once spares each app's next zome call from
+    /// discovering and repairing that on its own.
+    pub fn spawn_restart_watchers(&self) -> JoinHandle<()> {
+        let conductors = self.clone();
+
+        tokio::spawn(async move {
+            futures::future::join_all(conductors.iter().map(|conductor| async move {
+                let mut reconnects = conductor.admin_call.subscribe_reconnects();
+                while reconnects.changed().await.is_ok() {
+                    tracing::info!(conductor = %conductor.name, "Admin websocket reconnected");
+                    conductor.app_call.reset_after_restart().await;
+                }
+            }))
+            .await;
+        })
+    }
+
+    /// Close the app and admin connections to every conductor.
+    pub async fn close_connections(&self) {
+        futures::future::join_all(self.iter().map(|conductor| async move {
+            conductor.app_call.close_connections().await;
+            conductor.admin_call.close_connections().await;
+        }))
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::data::new_test_app_info;
+    use crate::{MockAdminCall, MockAppCall};
+    use holochain_types::app::DisabledAppReason;
+    use holochain_types::dna::DnaHash;
+
+    #[tokio::test]
+    async fn close_connections_closes_app_and_admin_connections() {
+        let mut admin_call = MockAdminCall::new();
+        admin_call
+            .expect_close_connections()
+            .returning(|| Box::pin(async {}))
+            .once();
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_close_connections()
+            .returning(|| Box::pin(async {}))
+            .once();
+        let conductors = Conductors(vec![ConductorHandle::new(
+            DEFAULT_CONDUCTOR_NAME,
+            "app".parse().unwrap(),
+            Arc::new(admin_call),
+            Arc::new(app_call),
+        )]);
+
+        conductors.close_connections().await;
+    }
+
+    #[tokio::test]
+    async fn admin_reconnect_resets_app_connections() {
+        let reconnects = tokio::sync::watch::Sender::new(0);
+        let mut admin_call = MockAdminCall::new();
+        let receiver = reconnects.subscribe();
+        admin_call
+            .expect_subscribe_reconnects()
+            .return_once(move || receiver);
+        let (reset_tx, reset_rx) = tokio::sync::oneshot::channel();
+        let reset_tx = std::sync::Mutex::new(Some(reset_tx));
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_reset_after_restart()
+            .returning(move || {
+                if let Some(reset_tx) = reset_tx.lock().unwrap().take() {
+                    reset_tx.send(()).unwrap();
+                }
+                Box::pin(async {})
+            })
+            .once();
+        let conductors = Conductors(vec![ConductorHandle::new(
+            DEFAULT_CONDUCTOR_NAME,
+            "app".parse().unwrap(),
+            Arc::new(admin_call),
+            Arc::new(app_call),
+        )]);
+
+        let watcher = conductors.spawn_restart_watchers();
+        reconnects.send_modify(|reconnects| *reconnects += 1);
+        tokio::time::timeout(Duration::from_secs(1), reset_rx)
+            .await
+            .expect("App connections were not reset")
+            .unwrap();
+        watcher.abort();
+    }
+
+    #[tokio::test]
+    async fn invalidating_apps_closes_their_connections() {
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_remove_app_connection()
+            .withf(|installed_app_id| installed_app_id == "changed")
+            .returning(|_| Box::pin(async {}))
+            .once();
+        let conductor = ConductorHandle::new(
+            DEFAULT_CONDUCTOR_NAME,
+            "changed,unchanged".parse().unwrap(),
+            Arc::new(MockAdminCall::new()),
+            Arc::new(app_call),
+        );
+        *conductor.app_info_cache.write().await = vec![
+            new_test_app_info("changed", DnaHash::from_raw_32(vec![1; 32])),
+            new_test_app_info("unchanged", DnaHash::from_raw_32(vec![2; 32])),
+        ];
+
+        conductor
+            .invalidate_apps(&["changed".to_string()].into_iter().collect())
+            .await;
+
+        let cached = conductor.app_info_cache.read().await;
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].installed_app_id, "unchanged");
+    }
+
+    #[tokio::test]
+    async fn refreshed_app_info_cache_only_holds_enabled_apps() {
+        let conductor = ConductorHandle::new(
+            DEFAULT_CONDUCTOR_NAME,
+            "enabled,disabled".parse().unwrap(),
+            Arc::new(MockAdminCall::new()),
+            Arc::new(MockAppCall::new()),
+        );
+        let enabled = new_test_app_info("enabled", DnaHash::from_raw_32(vec![1; 32]));
+        let mut disabled = new_test_app_info("disabled", DnaHash::from_raw_32(vec![2; 32]));
+        disabled.status = AppStatus::Disabled(DisabledAppReason::User);
+
+        conductor
+            .refresh_app_info_cache(&[enabled.clone(), disabled])
+            .await;
+
+        assert_eq!(*conductor.app_info_cache.read().await, vec![enabled]);
+    }
+}
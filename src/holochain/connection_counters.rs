@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counts of the websocket connections that a connection handle opened and closed, reported in
+/// the metrics of the gateway.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConnectionCounters {
+    /// The number of connections that are open.
+    pub open: u64,
+    /// The number of attempts to open a connection, including reconnects.
+    pub connect_attempts: u64,
+    /// The number of attempts to open a connection that failed.
+    pub failed_connects: u64,
+    /// The number of connections that were closed because they were no longer usable, had been
+    /// idle or in use for too long, or to stay within the connection limit.
+    pub evictions: u64,
+}
+
+/// Records the [`ConnectionCounters`] of a connection handle, shared by its clones.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionRecorder {
+    connect_attempts: AtomicU64,
+    failed_connects: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl ConnectionRecorder {
+    /// Record an attempt to open a connection, and whether it succeeded.
+    pub(crate) fn record_connect(&self, connected: bool) {
+        self.connect_attempts.fetch_add(1, Ordering::Relaxed);
+        if !connected {
+            self.failed_connects.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record that connections were evicted.
+    pub(crate) fn record_evictions(&self, evictions: u64) {
+        self.evictions.fetch_add(evictions, Ordering::Relaxed);
+    }
+
+    /// The counters, with the given number of open connections.
+    pub(crate) fn counters(&self, open: u64) -> ConnectionCounters {
+        ConnectionCounters {
+            open,
+            connect_attempts: self.connect_attempts.load(Ordering::Relaxed),
+            failed_connects: self.failed_connects.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::config::LairSigningConfig;
+use crate::{HcHttpGatewayError, HcHttpGatewayResult, UpstreamConnection};
+use holochain_client::AgentSigner;
+use std::sync::Arc;
+
+/// A signer that signs zome calls with the agent keys held by a Lair keystore.
+///
+/// No signing credentials need to be authorized when using this signer, because zome calls are
+/// signed by the agent that owns the cell.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "lair-signing"), allow(dead_code))]
+pub(crate) struct LairSigner(Arc<dyn AgentSigner + Send + Sync>);
+
+impl std::fmt::Debug for LairSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LairSigner").finish_non_exhaustive()
+    }
+}
+
+impl LairSigner {
+    /// Connect to the Lair keystore with the given settings.
+    #[cfg(feature = "lair-signing")]
+    pub(crate) async fn connect(config: &LairSigningConfig) -> HcHttpGatewayResult<Self> {
+        use lair_keystore_api::dependencies::sodoken;
+        use lair_keystore_api::ipc_keystore::ipc_keystore_connect;
+        use std::sync::Mutex;
+
+        let passphrase = std::fs::read_to_string(&config.passphrase_file).map_err(|e| {
+            tracing::error!(?e, "Failed to read the Lair passphrase file");
+            HcHttpGatewayError::UpstreamUnavailable(UpstreamConnection::Keystore)
+        })?;
+        let passphrase = Arc::new(Mutex::new(sodoken::LockedArray::from(
+            passphrase.trim_end().as_bytes().to_vec(),
+        )));
+
+        let client = ipc_keystore_connect(config.connection_url.clone(), passphrase)
+            .await
+            .map_err(|e| {
+                tracing::error!(?e, "Failed to connect to the Lair keystore");
+                HcHttpGatewayError::UpstreamUnavailable(UpstreamConnection::Keystore)
+            })?;
+        tracing::info!("Connected to the Lair keystore");
+
+        Ok(Self(Arc::new(holochain_client::LairAgentSigner::new(
+            Arc::new(client),
+        ))))
+    }
+
+    /// Connect to the Lair keystore with the given settings.
+    ///
+    /// Always fails because the gateway was built without the `lair-signing` feature.
+    #[cfg(not(feature = "lair-signing"))]
+    pub(crate) async fn connect(_config: &LairSigningConfig) -> HcHttpGatewayResult<Self> {
+        tracing::error!("Signing with a Lair keystore requires the lair-signing feature");
+        Err(HcHttpGatewayError::UpstreamUnavailable(
+            UpstreamConnection::Keystore,
+        ))
+    }
+
+    /// The signer to use for an app websocket connection.
+    pub(crate) fn agent_signer(&self) -> Arc<dyn AgentSigner + Send + Sync> {
+        self.0.clone()
+    }
+}
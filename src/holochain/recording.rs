@@ -0,0 +1,591 @@
+//! Recording of the zome calls made to conductors, and replaying of them without a conductor.
+//!
+//! A recording is a file with a line of JSON per call: the apps that a conductor listed, and the
+//! responses of the zome calls that succeeded. A gateway that replays a recording answers the same
+//! requests with the same responses, which allows frontends to be developed offline and demos to
+//! run against real data that was captured earlier.
+
+use crate::config::ConductorName;
+use crate::holochain::matches_status_filter;
+use crate::{
+    AccessLog, AdminCall, AppCall, AppConnectionStats, ConnectionCounters, HcHttpGatewayError,
+    HcHttpGatewayResult, ZomeCallRequest,
+};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use futures::future::BoxFuture;
+use holochain_client::{
+    AppInfo, AuthorizeSigningCredentialsPayload, ConductorApiError, ExternIO, SigningCredentials,
+};
+use holochain_conductor_api::{
+    AppAuthenticationTokenIssued, AppInterfaceInfo, AppStatusFilter, ExternalApiWireError,
+    IssueAppAuthenticationTokenPayload, StorageInfo,
+};
+use holochain_types::app::InstalledAppId;
+use holochain_types::dna::DnaHash;
+use holochain_types::websocket::AllowedOrigins;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// A call recorded to a conductor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum RecordedCall {
+    /// The apps that the conductor listed.
+    ListApps {
+        conductor: ConductorName,
+        apps: Vec<AppInfo>,
+    },
+    /// A zome call that succeeded, with its payload and response as base64 encoded msgpack.
+    ZomeCall {
+        conductor: ConductorName,
+        installed_app_id: InstalledAppId,
+        dna_hash: DnaHash,
+        zome_name: String,
+        fn_name: String,
+        #[serde(with = "base64_bytes")]
+        payload: Vec<u8>,
+        #[serde(with = "base64_bytes")]
+        response: Vec<u8>,
+    },
+}
+
+mod base64_bytes {
+    use super::*;
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64_STANDARD.encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        BASE64_STANDARD
+            .decode(String::deserialize(deserializer)?)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Destination of a recording.
+///
+/// Clones share the same destination, so that the calls recorded from all conductors end up in
+/// one recording.
+#[derive(Debug, Clone)]
+pub struct Recorder(AccessLog);
+
+impl Recorder {
+    /// Append the recording to the file at the given path, creating it if it doesn't exist.
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self(AccessLog::file(path)?))
+    }
+
+    /// Write the recording to the given writer.
+    pub fn from_writer(writer: impl std::io::Write + Send + 'static) -> Self {
+        Self(AccessLog::from_writer(writer))
+    }
+
+    fn record(&self, call: &RecordedCall) {
+        self.0.write(call);
+    }
+}
+
+/// Admin and app calls to a conductor that record the apps it lists and its zome call responses.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordingConn {
+    conductor: ConductorName,
+    admin_call: Arc<dyn AdminCall>,
+    app_call: Arc<dyn AppCall>,
+    recorder: Recorder,
+}
+
+impl RecordingConn {
+    pub(crate) fn new(
+        conductor: ConductorName,
+        admin_call: Arc<dyn AdminCall>,
+        app_call: Arc<dyn AppCall>,
+        recorder: Recorder,
+    ) -> Self {
+        Self {
+            conductor,
+            admin_call,
+            app_call,
+            recorder,
+        }
+    }
+}
+
+impl AdminCall for RecordingConn {
+    fn list_app_interfaces(
+        &self,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<AppInterfaceInfo>>> {
+        self.admin_call.list_app_interfaces()
+    }
+
+    fn issue_app_auth_token(
+        &self,
+        payload: IssueAppAuthenticationTokenPayload,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<AppAuthenticationTokenIssued>> {
+        self.admin_call.issue_app_auth_token(payload)
+    }
+
+    fn authorize_signing_credentials(
+        &self,
+        payload: AuthorizeSigningCredentialsPayload,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<SigningCredentials>> {
+        self.admin_call.authorize_signing_credentials(payload)
+    }
+
+    fn attach_app_interface(
+        &self,
+        port: u16,
+        allowed_origins: AllowedOrigins,
+        installed_app_id: Option<String>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<u16>> {
+        self.admin_call
+            .attach_app_interface(port, allowed_origins, installed_app_id)
+    }
+
+    fn list_apps(
+        &self,
+        status_filter: Option<AppStatusFilter>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<AppInfo>>> {
+        let list_apps = self.admin_call.list_apps(status_filter);
+        let conductor = self.conductor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::pin(async move {
+            let apps = list_apps.await?;
+            recorder.record(&RecordedCall::ListApps {
+                conductor,
+                apps: apps.clone(),
+            });
+            Ok(apps)
+        })
+    }
+
+    fn storage_info(&self) -> BoxFuture<'static, HcHttpGatewayResult<StorageInfo>> {
+        self.admin_call.storage_info()
+    }
+
+    fn agent_info(
+        &self,
+        dna_hashes: Option<Vec<DnaHash>>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<String>>> {
+        self.admin_call.agent_info(dna_hashes)
+    }
+
+    #[cfg(feature = "conductor-state-dump")]
+    fn dump_conductor_state(&self) -> BoxFuture<'static, HcHttpGatewayResult<String>> {
+        self.admin_call.dump_conductor_state()
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        self.admin_call.close_connections()
+    }
+
+    fn subscribe_reconnects(&self) -> watch::Receiver<u64> {
+        self.admin_call.subscribe_reconnects()
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        self.admin_call.connection_counters()
+    }
+}
+
+impl AppCall for RecordingConn {
+    fn handle_zome_call(
+        &self,
+        request: ZomeCallRequest,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        let zome_call = self.app_call.handle_zome_call(request.clone());
+        let conductor = self.conductor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::pin(async move {
+            let response = zome_call.await?;
+            recorder.record(&RecordedCall::ZomeCall {
+                conductor,
+                installed_app_id: request.installed_app_id,
+                dna_hash: request.cell_id.dna_hash().clone(),
+                zome_name: request.zome_name.to_string(),
+                fn_name: request.fn_name.to_string(),
+                payload: request.payload.0.clone(),
+                response: response.0.clone(),
+            });
+            Ok(response)
+        })
+    }
+
+    fn evict_stale_connections(&self) -> BoxFuture<'static, ()> {
+        self.app_call.evict_stale_connections()
+    }
+
+    fn remove_app_connection(&self, installed_app_id: InstalledAppId) -> BoxFuture<'static, ()> {
+        self.app_call.remove_app_connection(installed_app_id)
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        self.app_call.close_connections()
+    }
+
+    fn reset_after_restart(&self) -> BoxFuture<'static, ()> {
+        self.app_call.reset_after_restart()
+    }
+
+    fn app_connection_stats(&self) -> Vec<AppConnectionStats> {
+        self.app_call.app_connection_stats()
+    }
+
+    fn clear_cached_app_ports(&self) {
+        self.app_call.clear_cached_app_ports()
+    }
+
+    fn reconnect_app(
+        &self,
+        installed_app_id: InstalledAppId,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<()>> {
+        self.app_call.reconnect_app(installed_app_id)
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        self.app_call.connection_counters()
+    }
+}
+
+/// The key of a recorded zome call response.
+type ZomeCallKey = (InstalledAppId, DnaHash, String, String, Vec<u8>);
+
+/// The calls recorded to the conductors, read from a recording.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    calls: Vec<RecordedCall>,
+}
+
+impl Recording {
+    /// Read the recording in the file at the given path.
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+
+    /// Read a recording from the given reader.
+    ///
+    /// Empty lines are skipped, and a line that isn't a recorded call fails the whole recording.
+    pub fn from_reader(reader: impl std::io::Read) -> std::io::Result<Self> {
+        let mut calls = Vec::new();
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            calls.push(serde_json::from_str(&line).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid recorded call on line {}: {e}", index + 1),
+                )
+            })?);
+        }
+
+        Ok(Self { calls })
+    }
+
+    /// The admin and app calls that replay the calls recorded to the named conductor.
+    pub(crate) fn replay(&self, conductor: &str) -> ReplayConn {
+        let mut apps = Vec::<AppInfo>::new();
+        let mut responses = HashMap::new();
+        for call in &self.calls {
+            match call {
+                RecordedCall::ListApps {
+                    conductor: recorded,
+                    apps: listed,
+                } if recorded == conductor => {
+                    // Apps that were listed with a status filter are kept from earlier lists
+                    for app in listed {
+                        match apps
+                            .iter_mut()
+                            .find(|known| known.installed_app_id == app.installed_app_id)
+                        {
+                            Some(known) => *known = app.clone(),
+                            None => apps.push(app.clone()),
+                        }
+                    }
+                }
+                RecordedCall::ZomeCall {
+                    conductor: recorded,
+                    installed_app_id,
+                    dna_hash,
+                    zome_name,
+                    fn_name,
+                    payload,
+                    response,
+                } if recorded == conductor => {
+                    responses.insert(
+                        (
+                            installed_app_id.clone(),
+                            dna_hash.clone(),
+                            zome_name.clone(),
+                            fn_name.clone(),
+                            payload.clone(),
+                        ),
+                        ExternIO(response.clone()),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        ReplayConn {
+            apps: Arc::new(apps),
+            responses: Arc::new(responses),
+            reconnects: Arc::new(watch::Sender::new(0)),
+        }
+    }
+}
+
+/// Admin and app calls that are answered from a recording, without a conductor.
+///
+/// The apps are those that the conductor last listed, and a zome call is answered with the
+/// response recorded for the same app, DNA, function and payload. Zome calls that weren't recorded
+/// fail.
+#[derive(Debug, Clone)]
+pub(crate) struct ReplayConn {
+    apps: Arc<Vec<AppInfo>>,
+    responses: Arc<HashMap<ZomeCallKey, ExternIO>>,
+    reconnects: Arc<watch::Sender<u64>>,
+}
+
+/// The error of a call that can't be replayed.
+fn not_recorded(message: String) -> HcHttpGatewayError {
+    HcHttpGatewayError::HolochainError(ConductorApiError::ExternalApiWireError(
+        ExternalApiWireError::InternalError(message),
+    ))
+}
+
+impl AdminCall for ReplayConn {
+    fn list_app_interfaces(
+        &self,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<AppInterfaceInfo>>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn issue_app_auth_token(
+        &self,
+        _payload: IssueAppAuthenticationTokenPayload,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<AppAuthenticationTokenIssued>> {
+        Box::pin(async {
+            Err(not_recorded(
+                "App authentication tokens are not replayed".to_string(),
+            ))
+        })
+    }
+
+    fn authorize_signing_credentials(
+        &self,
+        _payload: AuthorizeSigningCredentialsPayload,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<SigningCredentials>> {
+        Box::pin(async {
+            Err(not_recorded(
+                "Signing credentials are not replayed".to_string(),
+            ))
+        })
+    }
+
+    fn attach_app_interface(
+        &self,
+        _port: u16,
+        _allowed_origins: AllowedOrigins,
+        _installed_app_id: Option<String>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<u16>> {
+        Box::pin(async { Err(not_recorded("App interfaces are not replayed".to_string())) })
+    }
+
+    fn list_apps(
+        &self,
+        status_filter: Option<AppStatusFilter>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<AppInfo>>> {
+        let apps = self
+            .apps
+            .iter()
+            .filter(|app| matches_status_filter(app, status_filter.as_ref()))
+            .cloned()
+            .collect();
+
+        Box::pin(async move { Ok(apps) })
+    }
+
+    fn storage_info(&self) -> BoxFuture<'static, HcHttpGatewayResult<StorageInfo>> {
+        Box::pin(async { Err(not_recorded("Storage info is not replayed".to_string())) })
+    }
+
+    fn agent_info(
+        &self,
+        _dna_hashes: Option<Vec<DnaHash>>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<String>>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    #[cfg(feature = "conductor-state-dump")]
+    fn dump_conductor_state(&self) -> BoxFuture<'static, HcHttpGatewayResult<String>> {
+        Box::pin(async {
+            Err(not_recorded(
+                "The conductor state is not replayed".to_string(),
+            ))
+        })
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn subscribe_reconnects(&self) -> watch::Receiver<u64> {
+        self.reconnects.subscribe()
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        ConnectionCounters::default()
+    }
+}
+
+impl AppCall for ReplayConn {
+    fn handle_zome_call(
+        &self,
+        request: ZomeCallRequest,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        let key = (
+            request.installed_app_id,
+            request.cell_id.dna_hash().clone(),
+            request.zome_name.to_string(),
+            request.fn_name.to_string(),
+            request.payload.0.clone(),
+        );
+        let result = self.responses.get(&key).cloned().ok_or_else(|| {
+            not_recorded(format!(
+                "No response to {}/{} in app {} was recorded for this payload",
+                key.2, key.3, key.0
+            ))
+        });
+
+        Box::pin(async move { result })
+    }
+
+    fn evict_stale_connections(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn remove_app_connection(&self, _installed_app_id: InstalledAppId) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn reset_after_restart(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn app_connection_stats(&self) -> Vec<AppConnectionStats> {
+        Vec::new()
+    }
+
+    fn clear_cached_app_ports(&self) {}
+
+    fn reconnect_app(
+        &self,
+        _installed_app_id: InstalledAppId,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        ConnectionCounters::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::data::new_test_app_info;
+    use crate::{MockAdminCall, MockAppCall};
+    use holochain_types::prelude::{AgentPubKey, CellId};
+    use std::sync::Mutex;
+
+    /// A writer that can be read from after it has been handed to the recorder.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn recorded_zome_calls_are_replayed() {
+        let dna_hash = DnaHash::from_raw_32(vec![1; 32]);
+        let mut admin_call = MockAdminCall::new();
+        let app_info = new_test_app_info("app", dna_hash.clone());
+        admin_call
+            .expect_list_apps()
+            .returning(move |_| Box::pin(std::future::ready(Ok(vec![app_info.clone()]))));
+        let mut app_call = MockAppCall::new();
+        app_call.expect_handle_zome_call().returning(|request| {
+            let response = ExternIO::encode(format!("response to {}", request.fn_name)).unwrap();
+            Box::pin(async move { Ok(response) })
+        });
+
+        let buffer = Buffer::default();
+        let recording = RecordingConn::new(
+            "default".to_string(),
+            Arc::new(admin_call),
+            Arc::new(app_call),
+            Recorder::from_writer(buffer.clone()),
+        );
+        let request = ZomeCallRequest::new(
+            "app",
+            CellId::new(dna_hash.clone(), AgentPubKey::from_raw_32(vec![1; 32])),
+            "zome",
+            "get",
+            ExternIO::encode(1).unwrap(),
+        );
+        recording.list_apps(None).await.unwrap();
+        let response = recording.handle_zome_call(request.clone()).await.unwrap();
+
+        let recorded = buffer.0.lock().unwrap().clone();
+        let replay = Recording::from_reader(recorded.as_slice())
+            .unwrap()
+            .replay("default");
+        let apps = replay.list_apps(None).await.unwrap();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].installed_app_id, "app");
+        assert_eq!(
+            replay.handle_zome_call(request.clone()).await.unwrap().0,
+            response.0
+        );
+
+        // A different payload was not recorded
+        let other = ZomeCallRequest {
+            payload: Arc::new(ExternIO::encode(2).unwrap()),
+            ..request
+        };
+        replay.handle_zome_call(other).await.unwrap_err();
+
+        // Neither was anything for another conductor
+        assert!(
+            Recording::from_reader(recorded.as_slice())
+                .unwrap()
+                .replay("other")
+                .list_apps(None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
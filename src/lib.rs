@@ -2,21 +2,59 @@
 //! # Holochain HTTP gateway
 #![doc = include_str!("../spec.md")]
 
+mod access_log;
+#[cfg(feature = "alert-webhook")]
+mod alert;
 mod app_selection;
+mod audit;
+mod catch_panic;
 mod config;
+mod drain;
 mod error;
 mod holochain;
+mod listener;
+mod load_shed;
+mod metrics;
+mod request_id;
 mod resolve;
+mod response_cache;
+mod response_schema;
 mod router;
 mod routes;
+mod self_check;
 mod service;
+mod shutdown;
+mod slow_request;
+mod status;
+mod trace_context;
 mod transcode;
+mod transform;
 
 #[cfg(any(test, feature = "test-utils"))]
 pub mod test;
 
+pub use access_log::AccessLog;
+#[cfg(feature = "alert-webhook")]
+pub use alert::AlertWebhook;
+pub use audit::AuditLog;
 pub use config::*;
-pub use error::{ErrorResponse, HcHttpGatewayError, HcHttpGatewayResult};
+pub use drain::DrainHandle;
+pub use error::{
+    ErrorFormat, ErrorResponse, HcHttpGatewayError, HcHttpGatewayResult, PROBLEM_JSON,
+    ProblemDetails, UpstreamConnection,
+};
 pub use holochain::*;
+pub use listener::{LISTEN_FDS_ENV, LISTEN_PID_ENV, bind_listener, inherited_listener};
+pub use metrics::{
+    AppUsage, HEALTH_WINDOW, Metrics, OTHER_LABEL, StatsdExporter, StatsdFlavor, UsageExportFormat,
+    UsageReport,
+};
 pub use resolve::resolve_address_from_url;
-pub use service::HcHttpGatewayService;
+pub use response_schema::{ResponseSchemas, ResponseValidation, Schema};
+pub use service::{GatewayHandle, GatewayTasks, HcHttpGatewayRouter, HcHttpGatewayService};
+pub use shutdown::ShutdownHandle;
+pub use status::{ConductorStatus, GatewayStatus};
+pub use trace_context::{TRACEPARENT_HEADER, TRACESTATE_HEADER};
+#[cfg(feature = "scripting")]
+pub use transform::ScriptTransform;
+pub use transform::{Transform, TransformContext, Transforms};
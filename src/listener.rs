@@ -0,0 +1,140 @@
+//! Binding or inheriting the TCP listener of the gateway.
+//!
+//! For restarts without downtime, a new gateway process can either take over a listener that
+//! was bound by a previous process or a process manager, or bind the port alongside the running
+//! gateway with `SO_REUSEPORT` before the old process is drained.
+//!
+//! A listener bound to the unspecified IPv6 address `::` is dual-stack, so that it accepts IPv4
+//! connections as well, whatever the default of the platform is.
+
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpSocket};
+
+/// Environment variable holding the number of file descriptors passed to the process.
+pub const LISTEN_FDS_ENV: &str = "LISTEN_FDS";
+/// Environment variable holding the id of the process that the file descriptors are meant for.
+pub const LISTEN_PID_ENV: &str = "LISTEN_PID";
+
+/// The first file descriptor passed to the process, following the socket activation protocol.
+#[cfg(unix)]
+const LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+/// Take over the TCP listener that was passed to this process, if any.
+///
+/// Follows the socket activation protocol of systemd, which is also supported by other process
+/// managers: the listener is file descriptor 3, and `LISTEN_FDS` and `LISTEN_PID` are set for
+/// this process. Only the first passed file descriptor is used.
+#[cfg(unix)]
+pub fn inherited_listener() -> std::io::Result<Option<TcpListener>> {
+    use std::os::fd::FromRawFd;
+
+    let Some(listen_fds) = std::env::var(LISTEN_FDS_ENV).ok() else {
+        return Ok(None);
+    };
+    // The variables are inherited by child processes, that must not take over the listener
+    let listen_pid = std::env::var(LISTEN_PID_ENV).ok();
+    if listen_pid.is_some_and(|pid| pid != std::process::id().to_string()) {
+        return Ok(None);
+    }
+
+    let listen_fds = listen_fds.parse::<u32>().map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid {LISTEN_FDS_ENV}: {listen_fds}"),
+        )
+    })?;
+    if listen_fds == 0 {
+        return Ok(None);
+    }
+
+    // SAFETY: the process that started the gateway passed an open listening socket as the first
+    // file descriptor, and nothing else in this process owns it
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    tracing::info!(address = ?listener.local_addr()?, "Inherited listener");
+
+    Ok(Some(listener))
+}
+
+/// Take over the TCP listener that was passed to this process, if any.
+///
+/// Inheriting a listener is only supported on Unix.
+#[cfg(not(unix))]
+pub fn inherited_listener() -> std::io::Result<Option<TcpListener>> {
+    Ok(None)
+}
+
+/// Bind a TCP listener to the given address.
+///
+/// With `reuse_port`, the listener is bound with `SO_REUSEPORT`, so that another gateway process
+/// can bind the same address while this one is still running. The kernel then spreads new
+/// connections over the processes that are listening.
+///
+/// A listener bound to `::` also accepts IPv4 connections, whose clients have IPv4-mapped IPv6
+/// addresses.
+pub fn bind_listener(address: SocketAddr, reuse_port: bool) -> std::io::Result<TcpListener> {
+    let socket = match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let SocketAddr::V6(address) = address
+        && address.ip().is_unspecified()
+    {
+        socket2::SockRef::from(&socket).set_only_v6(false)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    if reuse_port {
+        #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+        socket.set_reuseport(true)?;
+        #[cfg(not(all(unix, not(target_os = "solaris"), not(target_os = "illumos"))))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "SO_REUSEPORT is not supported on this platform",
+        ));
+    }
+    socket.bind(address)?;
+
+    socket.listen(1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use tokio::net::TcpStream;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reuse_port_allows_binding_the_same_address_twice() {
+        let first = bind_listener((Ipv4Addr::LOCALHOST, 0).into(), true).unwrap();
+        let address = first.local_addr().unwrap();
+
+        let second = bind_listener(address, true).unwrap();
+        assert_eq!(second.local_addr().unwrap(), address);
+    }
+
+    #[tokio::test]
+    async fn binding_a_bound_address_fails_without_reuse_port() {
+        let first = bind_listener((Ipv4Addr::LOCALHOST, 0).into(), false).unwrap();
+        let address = first.local_addr().unwrap();
+
+        bind_listener(address, false).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn listener_on_the_unspecified_ipv6_address_accepts_ipv4_connections() {
+        let Ok(listener) = bind_listener((Ipv6Addr::UNSPECIFIED, 0).into(), false) else {
+            // IPv6 is not available on this host
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+
+        let _client = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        let (_, address) = listener.accept().await.unwrap();
+        assert_eq!(address.ip().to_canonical(), Ipv4Addr::LOCALHOST);
+    }
+}
@@ -0,0 +1,532 @@
+//! Gateway-wide load shedding for zome call requests.
+//!
+//! A limited number of requests are handled at the same time and a limited number of requests
+//! wait for their turn. Requests beyond that are rejected straight away, and requests that wait
+//! too long are rejected as well, so that the latency of the requests that are accepted doesn't
+//! grow without bound under load.
+//!
+//! Waiting requests are admitted taking turns between apps, so that a single popular app can't
+//! starve the other apps that share the gateway. Apps can be given priorities, in which case
+//! requests for higher priority apps are admitted first and requests for lower priority apps are
+//! shed earlier.
+//!
+//! Concurrency limits don't notice a conductor that slows down while the number of requests stays
+//! the same. So a share of new requests can also be shed while the 95th percentile of recent
+//! conductor latencies is above a threshold, which gives the conductor room to recover before
+//! requests start to time out and are retried.
+
+use crate::metrics::Metrics;
+use crate::{HcHttpGatewayError, HcHttpGatewayResult};
+use axum::extract::{RawPathParams, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Limits the number of requests in flight and the number of requests waiting to be handled.
+#[derive(Debug, Clone)]
+pub(crate) struct LoadShedder(Arc<Mutex<Schedule>>);
+
+/// The requests in flight and the requests waiting for their turn.
+#[derive(Debug)]
+struct Schedule {
+    max_in_flight: usize,
+    in_flight: usize,
+    queued: usize,
+    /// Time that a request waits for its turn before it is shed.
+    queue_timeout: Duration,
+    next_waiter_id: u64,
+    /// Priority of each app, apps that are not listed have priority 0.
+    priorities: HashMap<String, u32>,
+    /// Number of waiting requests beyond which requests of each priority are shed.
+    queue_limits: BTreeMap<u32, usize>,
+    /// Waiting requests for each app, in the order they arrived.
+    waiting: HashMap<String, VecDeque<(u64, oneshot::Sender<()>)>>,
+    /// Apps with waiting requests for each priority, in the order they take their turn.
+    turns: BTreeMap<u32, VecDeque<String>>,
+}
+
+impl Schedule {
+    fn priority(&self, app: &str) -> u32 {
+        self.priorities.get(app).copied().unwrap_or_default()
+    }
+
+    /// Pass the place of a finished request on to the next waiting request, if any.
+    ///
+    /// The apps with the highest priority take turns first.
+    fn release(&mut self) {
+        while let Some(turns) = self
+            .turns
+            .values_mut()
+            .rev()
+            .find(|turns| !turns.is_empty())
+        {
+            let app = turns.pop_front().expect("Turns are not empty");
+            let Some(queue) = self.waiting.get_mut(&app) else {
+                continue;
+            };
+            let Some((_, sender)) = queue.pop_front() else {
+                continue;
+            };
+            if queue.is_empty() {
+                self.waiting.remove(&app);
+            } else {
+                turns.push_back(app);
+            }
+            self.queued -= 1;
+
+            if sender.send(()).is_ok() {
+                return;
+            }
+        }
+
+        self.in_flight -= 1;
+    }
+}
+
+impl LoadShedder {
+    /// Create a load shedder that permits `max_in_flight` requests at a time, with up to
+    /// `max_queued` requests waiting for up to `queue_timeout`.
+    ///
+    /// Requests for apps with the highest of the given priorities may fill the whole queue. Each
+    /// lower priority may fill a smaller share of it, so with two priorities, requests for apps
+    /// with the lower priority are shed once the queue is half full.
+    pub(crate) fn new(
+        max_in_flight: u32,
+        max_queued: u32,
+        queue_timeout: Duration,
+        priorities: HashMap<String, u32>,
+    ) -> Self {
+        let mut tiers = priorities.values().copied().collect::<BTreeSet<_>>();
+        tiers.insert(0);
+        let tier_count = tiers.len();
+        let queue_limits = tiers
+            .into_iter()
+            .enumerate()
+            .map(|(tier, priority)| {
+                let limit = (max_queued as usize * (tier + 1)).div_ceil(tier_count);
+                (priority, limit)
+            })
+            .collect();
+
+        Self(Arc::new(Mutex::new(Schedule {
+            max_in_flight: max_in_flight as usize,
+            in_flight: 0,
+            queued: 0,
+            queue_timeout,
+            next_waiter_id: 0,
+            priorities,
+            queue_limits,
+            waiting: HashMap::new(),
+            turns: BTreeMap::new(),
+        })))
+    }
+
+    /// Wait for a request for the given app to be admitted, or reject it if the queue is full or
+    /// it isn't admitted in time.
+    ///
+    /// The request is in flight until the returned permit is dropped.
+    async fn admit(&self, app: &str) -> HcHttpGatewayResult<Permit> {
+        let (waiter, queue_timeout) = {
+            let mut schedule = self.0.lock().expect("Invalid lock");
+            // Borrow the fields of the schedule separately rather than through the guard
+            let schedule = &mut *schedule;
+            if schedule.in_flight < schedule.max_in_flight {
+                schedule.in_flight += 1;
+                return Ok(Permit(self.clone()));
+            }
+
+            let priority = schedule.priority(app);
+            if schedule.queued >= schedule.queue_limits[&priority] {
+                tracing::warn!(
+                    ?app,
+                    priority,
+                    "Too many requests in flight, shedding request"
+                );
+                return Err(HcHttpGatewayError::Overloaded);
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            let id = schedule.next_waiter_id;
+            schedule.next_waiter_id += 1;
+            let queue = schedule.waiting.entry(app.to_string()).or_default();
+            if queue.is_empty() {
+                schedule
+                    .turns
+                    .entry(priority)
+                    .or_default()
+                    .push_back(app.to_string());
+            }
+            queue.push_back((id, sender));
+            schedule.queued += 1;
+
+            let waiter = Waiter {
+                load_shedder: self.clone(),
+                app: app.to_string(),
+                id,
+                receiver,
+                admitted: false,
+            };
+            (waiter, schedule.queue_timeout)
+        };
+
+        // A request that times out leaves the queue as its waiter is dropped
+        tokio::time::timeout(queue_timeout, waiter.wait())
+            .await
+            .map_err(|_| {
+                tracing::warn!(
+                    ?app,
+                    "Request waited too long for its turn, shedding request"
+                );
+                HcHttpGatewayError::Overloaded
+            })
+    }
+}
+
+/// A place among the requests in flight, which is passed on when dropped.
+struct Permit(LoadShedder);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.0.lock().expect("Invalid lock").release();
+    }
+}
+
+/// A request waiting for its turn.
+///
+/// Requests can be cancelled while they wait, in which case the waiter leaves the queue, or passes
+/// its place on if it had just been admitted.
+struct Waiter {
+    load_shedder: LoadShedder,
+    app: String,
+    id: u64,
+    receiver: oneshot::Receiver<()>,
+    admitted: bool,
+}
+
+impl Waiter {
+    async fn wait(mut self) -> Permit {
+        (&mut self.receiver)
+            .await
+            .expect("Waiting requests are only removed from the queue by their waiter");
+        self.admitted = true;
+
+        Permit(self.load_shedder.clone())
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if self.admitted {
+            return;
+        }
+
+        let mut schedule = self.load_shedder.0.lock().expect("Invalid lock");
+        let position = schedule
+            .waiting
+            .get(&self.app)
+            .and_then(|queue| queue.iter().position(|(id, _)| *id == self.id));
+        match position {
+            Some(position) => {
+                let queue = schedule
+                    .waiting
+                    .get_mut(&self.app)
+                    .expect("Queue was just found");
+                queue.remove(position);
+                if queue.is_empty() {
+                    schedule.waiting.remove(&self.app);
+                    for turns in schedule.turns.values_mut() {
+                        turns.retain(|app| app != &self.app);
+                    }
+                }
+                schedule.queued -= 1;
+            }
+            None => {
+                // Admitted while being cancelled, so pass the place on.
+                if self.receiver.try_recv().is_ok() {
+                    schedule.release();
+                }
+            }
+        }
+    }
+}
+
+/// Middleware that sheds zome call requests when the gateway is overloaded.
+///
+/// Requests are scheduled per coordinator identifier, which identifies the app being called.
+pub(crate) async fn shed_load(
+    State(load_shedder): State<LoadShedder>,
+    path_params: RawPathParams,
+    request: Request,
+    next: Next,
+) -> HcHttpGatewayResult<Response> {
+    let app = path_params
+        .iter()
+        .find(|(name, _)| *name == "coordinator_identifier")
+        .map(|(_, value)| value)
+        .unwrap_or_default();
+    let _permit = load_shedder.admit(app).await?;
+
+    Ok(next.run(request).await)
+}
+
+/// Sheds a share of new requests while conductors respond slowly.
+#[derive(Debug, Clone)]
+pub(crate) struct LatencyShedder {
+    metrics: Arc<Metrics>,
+    threshold: Duration,
+    fraction: f64,
+    /// Number of requests seen while the latency was above the threshold.
+    slow_requests: Arc<AtomicU64>,
+}
+
+impl LatencyShedder {
+    /// Create a latency shedder that sheds `fraction` of new requests while the 95th percentile of
+    /// recent conductor latencies is above `threshold`.
+    pub(crate) fn new(metrics: Arc<Metrics>, threshold: Duration, fraction: f64) -> Self {
+        Self {
+            metrics,
+            threshold,
+            fraction,
+            slow_requests: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Whether to shed a new request.
+    ///
+    /// Rather than picking requests at random, every request that takes the count of shed
+    /// requests up to the next whole number is shed, so the fraction holds over any run of
+    /// requests.
+    fn should_shed(&self) -> bool {
+        let Some(p95) = self.metrics.recent_conductor_latency_p95() else {
+            return false;
+        };
+        if p95 <= self.threshold {
+            return false;
+        }
+
+        let seen = self.slow_requests.fetch_add(1, Ordering::Relaxed) as f64;
+        let shed = ((seen + 1.0) * self.fraction).floor() > (seen * self.fraction).floor();
+        if shed {
+            tracing::warn!(
+                p95_ms = p95.as_millis() as u64,
+                "Conductor latency is too high, shedding request"
+            );
+        }
+        shed
+    }
+}
+
+/// Middleware that sheds a share of zome call requests while conductors respond slowly.
+pub(crate) async fn shed_on_latency(
+    State(latency_shedder): State<LatencyShedder>,
+    request: Request,
+    next: Next,
+) -> HcHttpGatewayResult<Response> {
+    if latency_shedder.should_shed() {
+        return Err(HcHttpGatewayError::Overloaded);
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn requests_beyond_the_queue_are_shed() {
+        let load_shedder = LoadShedder::new(1, 1, QUEUE_TIMEOUT, HashMap::new());
+
+        let in_flight = load_shedder.admit("app").await.unwrap();
+
+        // The second request waits in the queue, so the third is shed
+        let queued = tokio::spawn({
+            let load_shedder = load_shedder.clone();
+            async move { load_shedder.admit("app").await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(
+            load_shedder.admit("app").await,
+            Err(HcHttpGatewayError::Overloaded)
+        ));
+
+        // Once the request in flight finishes, the queued request is admitted
+        drop(in_flight);
+        queued.await.unwrap().unwrap();
+        load_shedder.admit("app").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_requests_leave_the_queue() {
+        let load_shedder = LoadShedder::new(1, 1, QUEUE_TIMEOUT, HashMap::new());
+
+        let in_flight = load_shedder.admit("app").await.unwrap();
+
+        let result =
+            tokio::time::timeout(Duration::from_millis(10), load_shedder.admit("app")).await;
+        assert!(result.is_err());
+
+        // The queue has room again for a request to wait in
+        let queued = tokio::spawn({
+            let load_shedder = load_shedder.clone();
+            async move { load_shedder.admit("app").await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(in_flight);
+        queued.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_that_wait_too_long_are_shed() {
+        let load_shedder = LoadShedder::new(1, 1, Duration::from_millis(10), HashMap::new());
+
+        let in_flight = load_shedder.admit("app").await.unwrap();
+
+        assert!(matches!(
+            load_shedder.admit("app").await,
+            Err(HcHttpGatewayError::Overloaded)
+        ));
+
+        // The request that timed out left the queue, so another request can wait in it
+        let queued = tokio::spawn({
+            let load_shedder = load_shedder.clone();
+            async move { load_shedder.admit("app").await.map(|_| ()) }
+        });
+        drop(in_flight);
+        queued.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn a_share_of_requests_is_shed_while_conductors_are_slow() {
+        let metrics = Arc::new(Metrics::new(10));
+        let latency_shedder = LatencyShedder::new(metrics.clone(), Duration::from_secs(1), 0.25);
+        for _ in 0..20 {
+            metrics.record_zome_call(
+                "app",
+                "posts",
+                "list",
+                Duration::from_secs(2),
+                Duration::ZERO,
+            );
+        }
+
+        let shed = (0..100).filter(|_| latency_shedder.should_shed()).count();
+        assert_eq!(shed, 25);
+    }
+
+    #[test]
+    fn requests_are_not_shed_while_conductors_are_fast() {
+        let metrics = Arc::new(Metrics::new(10));
+        let latency_shedder = LatencyShedder::new(metrics.clone(), Duration::from_secs(1), 1.0);
+        assert!(!latency_shedder.should_shed());
+
+        for _ in 0..20 {
+            metrics.record_zome_call(
+                "app",
+                "posts",
+                "list",
+                Duration::from_millis(500),
+                Duration::ZERO,
+            );
+        }
+        assert!(!latency_shedder.should_shed());
+    }
+
+    #[tokio::test]
+    async fn waiting_requests_take_turns_between_apps() {
+        let load_shedder = LoadShedder::new(1, 10, QUEUE_TIMEOUT, HashMap::new());
+
+        let in_flight = load_shedder.admit("busy").await.unwrap();
+
+        let (admitted_tx, mut admitted_rx) = tokio::sync::mpsc::unbounded_channel();
+        for app in ["busy", "busy", "busy", "quiet"] {
+            tokio::spawn({
+                let load_shedder = load_shedder.clone();
+                let admitted_tx = admitted_tx.clone();
+                async move {
+                    let _permit = load_shedder.admit(app).await.unwrap();
+                    admitted_tx.send(app).unwrap();
+                }
+            });
+            // Make sure the requests are queued in order
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(in_flight);
+        let mut admitted = Vec::new();
+        for _ in 0..4 {
+            admitted.push(admitted_rx.recv().await.unwrap());
+        }
+        assert_eq!(admitted, vec!["busy", "quiet", "busy", "busy"]);
+    }
+
+    #[tokio::test]
+    async fn higher_priority_requests_are_admitted_first() {
+        let load_shedder = LoadShedder::new(
+            1,
+            10,
+            QUEUE_TIMEOUT,
+            HashMap::from([("paid".to_string(), 1)]),
+        );
+
+        let in_flight = load_shedder.admit("free").await.unwrap();
+
+        let (admitted_tx, mut admitted_rx) = tokio::sync::mpsc::unbounded_channel();
+        for app in ["free", "free", "paid"] {
+            tokio::spawn({
+                let load_shedder = load_shedder.clone();
+                let admitted_tx = admitted_tx.clone();
+                async move {
+                    let _permit = load_shedder.admit(app).await.unwrap();
+                    admitted_tx.send(app).unwrap();
+                }
+            });
+            // Make sure the requests are queued in order
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(in_flight);
+        let mut admitted = Vec::new();
+        for _ in 0..3 {
+            admitted.push(admitted_rx.recv().await.unwrap());
+        }
+        assert_eq!(admitted, vec!["paid", "free", "free"]);
+    }
+
+    #[tokio::test]
+    async fn lower_priority_requests_are_shed_earlier() {
+        let load_shedder = LoadShedder::new(
+            1,
+            4,
+            QUEUE_TIMEOUT,
+            HashMap::from([("paid".to_string(), 1)]),
+        );
+
+        let _in_flight = load_shedder.admit("free").await.unwrap();
+
+        // Requests for the lower priority app may fill half of the queue
+        let mut queued = Vec::new();
+        for _ in 0..2 {
+            queued.push(tokio::spawn({
+                let load_shedder = load_shedder.clone();
+                async move { load_shedder.admit("free").await.map(|_| ()) }
+            }));
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(
+            load_shedder.admit("free").await,
+            Err(HcHttpGatewayError::Overloaded)
+        ));
+
+        // While the higher priority app may still queue requests
+        let result =
+            tokio::time::timeout(Duration::from_millis(10), load_shedder.admit("paid")).await;
+        assert!(result.is_err(), "Expected the request to be queued");
+    }
+}
@@ -0,0 +1,565 @@
+//! Metrics of the gateway, rendered in the Prometheus text exposition format.
+//!
+//! Zome call durations are recorded per app, zome and function, split into the time spent waiting
+//! for the conductor and the time spent in the gateway itself, so that a slow zome function can be
+//! told apart from a gateway that is slow to transcode or to select an app.
+//!
+//! The connections to each conductor are counted by the connection handles themselves, and are
+//! read from them whenever the metrics are rendered, as are the metrics of the runtime and of the
+//! process.
+
+use crate::{Conductors, Configuration, ConnectionCounters};
+use accounting::UsageAccounts;
+pub use accounting::{AppUsage, UsageExportFormat, UsageReport};
+use latency::RecentLatencies;
+pub use rolling::HEALTH_WINDOW;
+use rolling::RecentOutcomes;
+use runtime::{render_process_metrics, render_runtime_metrics};
+pub use statsd::{StatsdExporter, StatsdFlavor};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use summary::UsageSummaries;
+pub(crate) use summary::record_usage;
+
+mod accounting;
+mod latency;
+mod rolling;
+mod runtime;
+mod statsd;
+mod summary;
+
+/// Upper bounds of the buckets of the duration histograms, in seconds.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Label value that zome and function names are replaced with once the limit of distinct
+/// functions is reached.
+pub const OTHER_LABEL: &str = "_other";
+
+const CONDUCTOR_DURATION: &str = "hc_gw_zome_call_conductor_duration_seconds";
+const GATEWAY_DURATION: &str = "hc_gw_zome_call_gateway_duration_seconds";
+const HANDLER_PANICS: &str = "hc_gw_handler_panics_total";
+const RESPONSE_CACHE_HITS: &str = "hc_gw_response_cache_hits_total";
+const RESPONSE_CACHE_MISSES: &str = "hc_gw_response_cache_misses_total";
+const STATSD_CONDUCTOR_DURATION: &str = "zome_call.conductor_duration";
+const STATSD_GATEWAY_DURATION: &str = "zome_call.gateway_duration";
+
+/// Media type of the Prometheus text exposition format.
+pub(crate) const METRICS_MEDIA_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct FunctionLabels {
+    app_id: String,
+    zome_name: String,
+    fn_name: String,
+}
+
+impl FunctionLabels {
+    fn render(&self) -> String {
+        format!(
+            "app_id=\"{}\",zome=\"{}\",fn=\"{}\"",
+            escape_label(&self.app_id),
+            escape_label(&self.zome_name),
+            escape_label(&self.fn_name)
+        )
+    }
+}
+
+/// A histogram with cumulative buckets, as Prometheus expects them.
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, upper_bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if seconds <= upper_bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (bucket, upper_bound) in self.buckets.iter().zip(DURATION_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"{upper_bound}\"}} {bucket}"
+            );
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct ZomeCallDurations {
+    conductor: Histogram,
+    gateway: Histogram,
+}
+
+/// Metrics collected by the gateway.
+#[derive(Debug)]
+pub struct Metrics {
+    max_functions: usize,
+    zome_calls: Mutex<HashMap<FunctionLabels, ZomeCallDurations>>,
+    statsd: Option<StatsdExporter>,
+    usage: Option<UsageSummaries>,
+    accounts: UsageAccounts,
+    recent: RecentOutcomes,
+    latencies: RecentLatencies,
+    panics: AtomicU64,
+    response_cache_hits: AtomicU64,
+    response_cache_misses: AtomicU64,
+}
+
+impl Metrics {
+    /// Create metrics that keep separate histograms for at most `max_functions` functions.
+    ///
+    /// Zome calls to further functions are recorded with [`OTHER_LABEL`] as the zome and function
+    /// name, so that clients calling many different functions can't grow the metrics without
+    /// bounds.
+    pub fn new(max_functions: u32) -> Self {
+        Self {
+            max_functions: max_functions as usize,
+            zome_calls: Mutex::new(HashMap::new()),
+            statsd: None,
+            usage: None,
+            accounts: UsageAccounts::default(),
+            recent: RecentOutcomes::default(),
+            latencies: RecentLatencies::default(),
+            panics: AtomicU64::new(0),
+            response_cache_hits: AtomicU64::new(0),
+            response_cache_misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Create the metrics that the given configuration asks for.
+    pub fn from_configuration(configuration: &Configuration) -> Self {
+        let mut metrics = Self::new(configuration.metrics_max_functions);
+        if let Some(statsd) = &configuration.statsd {
+            metrics = metrics.with_statsd(statsd.clone());
+        }
+        if configuration.usage_summary_interval.is_some() {
+            metrics = metrics.with_usage_summaries();
+        }
+        metrics
+    }
+
+    /// Also send zome call durations to StatsD as they are recorded.
+    pub fn with_statsd(mut self, statsd: StatsdExporter) -> Self {
+        self.statsd = Some(statsd);
+        self
+    }
+
+    /// Also count zome call requests per app for the usage summaries, see
+    /// [`spawn_usage_summaries`](Metrics::spawn_usage_summaries).
+    pub fn with_usage_summaries(mut self) -> Self {
+        self.usage = Some(UsageSummaries::default());
+        self
+    }
+
+    /// Record the durations of a zome call.
+    ///
+    /// `conductor` is the time spent waiting for the conductor to respond, and `gateway` the rest
+    /// of the time that the gateway took to handle the request.
+    pub fn record_zome_call(
+        &self,
+        app_id: &str,
+        zome_name: &str,
+        fn_name: &str,
+        conductor: Duration,
+        gateway: Duration,
+    ) {
+        self.latencies.record(conductor);
+
+        let mut labels = FunctionLabels {
+            app_id: app_id.to_string(),
+            zome_name: zome_name.to_string(),
+            fn_name: fn_name.to_string(),
+        };
+        let mut zome_calls = self.zome_calls.lock().expect("Metrics lock poisoned");
+        if !zome_calls.contains_key(&labels) && zome_calls.len() >= self.max_functions {
+            labels.zome_name = OTHER_LABEL.to_string();
+            labels.fn_name = OTHER_LABEL.to_string();
+        }
+
+        if let Some(statsd) = &self.statsd {
+            let labels = [
+                ("app_id", labels.app_id.as_str()),
+                ("zome", labels.zome_name.as_str()),
+                ("fn", labels.fn_name.as_str()),
+            ];
+            statsd.timing(STATSD_CONDUCTOR_DURATION, conductor, &labels);
+            statsd.timing(STATSD_GATEWAY_DURATION, gateway, &labels);
+        }
+
+        let durations = zome_calls.entry(labels).or_default();
+        durations.conductor.observe(conductor);
+        durations.gateway.observe(gateway);
+    }
+
+    /// Record that a request handler panicked.
+    pub(crate) fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record whether a request for a cached function was answered from the response cache.
+    pub(crate) fn record_response_cache(&self, hit: bool) {
+        if hit {
+            self.response_cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.response_cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Render the metrics, along with the connection counters of the given conductors and the
+    /// metrics of the runtime and the process, in the Prometheus text exposition format.
+    pub fn render(&self, conductors: &Conductors) -> String {
+        let zome_calls = self.zome_calls.lock().expect("Metrics lock poisoned");
+        let mut zome_calls = zome_calls.iter().collect::<Vec<_>>();
+        zome_calls.sort_by_key(|(key, _)| *key);
+
+        let mut out = String::new();
+        render_connection_counters(&mut out, conductors);
+        render_runtime_metrics(&mut out);
+        render_process_metrics(&mut out);
+        write_header(
+            &mut out,
+            "counter",
+            HANDLER_PANICS,
+            "Requests whose handler panicked, which were answered with status 500.",
+        );
+        let _ = writeln!(
+            out,
+            "{HANDLER_PANICS} {}",
+            self.panics.load(Ordering::Relaxed)
+        );
+        for (name, help, counter) in [
+            (
+                RESPONSE_CACHE_HITS,
+                "Requests for cached functions that were answered from the response cache.",
+                &self.response_cache_hits,
+            ),
+            (
+                RESPONSE_CACHE_MISSES,
+                "Requests for cached functions that were passed on to the conductor.",
+                &self.response_cache_misses,
+            ),
+        ] {
+            write_header(&mut out, "counter", name, help);
+            let _ = writeln!(out, "{name} {}", counter.load(Ordering::Relaxed));
+        }
+        write_header(
+            &mut out,
+            "histogram",
+            CONDUCTOR_DURATION,
+            "Time spent waiting for the conductor to respond to zome calls.",
+        );
+        for (labels, durations) in &zome_calls {
+            durations
+                .conductor
+                .render(&mut out, CONDUCTOR_DURATION, &labels.render());
+        }
+        write_header(
+            &mut out,
+            "histogram",
+            GATEWAY_DURATION,
+            "Time spent in the gateway handling zome call requests, excluding the conductor.",
+        );
+        for (labels, durations) in &zome_calls {
+            durations
+                .gateway
+                .render(&mut out, GATEWAY_DURATION, &labels.render());
+        }
+
+        out
+    }
+}
+
+/// The name, type and help text of a metric derived from the connection counters, with the
+/// counter it reports.
+type ConnectionMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&ConnectionCounters) -> u64,
+);
+
+/// The metrics derived from the connection counters.
+const CONNECTION_METRICS: [ConnectionMetric; 4] = [
+    (
+        "connections",
+        "gauge",
+        "Open {} websocket connections.",
+        |c| c.open,
+    ),
+    (
+        "connect_attempts_total",
+        "counter",
+        "Attempts to open a {} websocket connection, including reconnects.",
+        |c| c.connect_attempts,
+    ),
+    (
+        "connect_failures_total",
+        "counter",
+        "Attempts to open a {} websocket connection that failed.",
+        |c| c.failed_connects,
+    ),
+    (
+        "connection_evictions_total",
+        "counter",
+        "Open {} websocket connections that were closed because they were stale or over the limit.",
+        |c| c.evictions,
+    ),
+];
+
+/// Render the connection counters of the admin and app connections of every conductor.
+fn render_connection_counters(out: &mut String, conductors: &Conductors) {
+    for interface in ["admin", "app"] {
+        let counters = conductors
+            .iter()
+            .map(|conductor| {
+                let counters = if interface == "admin" {
+                    conductor.admin_call.connection_counters()
+                } else {
+                    conductor.app_call.connection_counters()
+                };
+                let labels = format!("conductor=\"{}\"", escape_label(&conductor.name));
+                (labels, counters)
+            })
+            .collect::<Vec<_>>();
+
+        for (suffix, metric_type, help, value) in CONNECTION_METRICS {
+            let name = format!("hc_gw_{interface}_{suffix}");
+            write_header(out, metric_type, &name, &help.replace("{}", interface));
+            for (labels, counters) in &counters {
+                let _ = writeln!(out, "{name}{{{labels}}} {}", value(counters));
+            }
+        }
+    }
+}
+
+fn write_header(out: &mut String, metric_type: &str, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {metric_type}");
+}
+
+/// Escape a label value for the Prometheus text exposition format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Times a zome call request, and records its durations when dropped.
+///
+/// Requests are only recorded once the conductor has been called, so that rejected requests don't
+/// count towards the functions of an app. Requests that are cancelled while the conductor is
+/// called are not recorded either.
+pub(crate) struct ZomeCallTimer<'a> {
+    metrics: &'a Metrics,
+    app_id: &'a str,
+    zome_name: &'a str,
+    fn_name: &'a str,
+    started: Instant,
+    conductor: Option<Duration>,
+}
+
+impl<'a> ZomeCallTimer<'a> {
+    /// Start timing a request that was received at `started`.
+    pub(crate) fn new(
+        metrics: &'a Metrics,
+        app_id: &'a str,
+        zome_name: &'a str,
+        fn_name: &'a str,
+        started: Instant,
+    ) -> Self {
+        Self {
+            metrics,
+            app_id,
+            zome_name,
+            fn_name,
+            started,
+            conductor: None,
+        }
+    }
+
+    /// Note the time spent waiting for the conductor.
+    pub(crate) fn conductor_responded(&mut self, conductor: Duration) {
+        self.conductor = Some(conductor);
+    }
+}
+
+impl Drop for ZomeCallTimer<'_> {
+    fn drop(&mut self) {
+        if let Some(conductor) = self.conductor {
+            let gateway = self.started.elapsed().saturating_sub(conductor);
+            self.metrics.record_zome_call(
+                self.app_id,
+                self.zome_name,
+                self.fn_name,
+                conductor,
+                gateway,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConductorHandle, MockAdminCall, MockAppCall};
+    use std::sync::Arc;
+
+    #[test]
+    fn durations_are_rendered_as_histograms() {
+        let metrics = Metrics::new(10);
+        metrics.record_zome_call(
+            "app",
+            "posts",
+            "get_post",
+            Duration::from_millis(20),
+            Duration::from_millis(2),
+        );
+        metrics.record_zome_call(
+            "app",
+            "posts",
+            "get_post",
+            Duration::from_millis(200),
+            Duration::from_millis(3),
+        );
+
+        let rendered = metrics.render(&Conductors::default());
+        let labels = "app_id=\"app\",zome=\"posts\",fn=\"get_post\"";
+        for line in [
+            "# TYPE hc_gw_zome_call_conductor_duration_seconds histogram".to_string(),
+            format!("hc_gw_zome_call_conductor_duration_seconds_bucket{{{labels},le=\"0.01\"}} 0"),
+            format!("hc_gw_zome_call_conductor_duration_seconds_bucket{{{labels},le=\"0.025\"}} 1"),
+            format!("hc_gw_zome_call_conductor_duration_seconds_bucket{{{labels},le=\"0.25\"}} 2"),
+            format!("hc_gw_zome_call_conductor_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 2"),
+            format!("hc_gw_zome_call_conductor_duration_seconds_count{{{labels}}} 2"),
+            format!("hc_gw_zome_call_gateway_duration_seconds_bucket{{{labels},le=\"0.001\"}} 0"),
+            format!("hc_gw_zome_call_gateway_duration_seconds_bucket{{{labels},le=\"0.005\"}} 2"),
+            format!("hc_gw_zome_call_gateway_duration_seconds_count{{{labels}}} 2"),
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {line}");
+        }
+    }
+
+    #[test]
+    fn functions_beyond_the_limit_are_collapsed() {
+        let metrics = Metrics::new(2);
+        for fn_name in ["a", "b", "c", "d", "a"] {
+            metrics.record_zome_call("app", "zome", fn_name, Duration::ZERO, Duration::ZERO);
+        }
+
+        let rendered = metrics.render(&Conductors::default());
+        let count = |zome: &str, fn_name: &str| {
+            let prefix = format!(
+                "{CONDUCTOR_DURATION}_count{{app_id=\"app\",zome=\"{zome}\",fn=\"{fn_name}\"}} "
+            );
+            rendered
+                .lines()
+                .find_map(|line| line.strip_prefix(&prefix))
+                .map(|count| count.to_string())
+        };
+        assert_eq!(count("zome", "a").as_deref(), Some("2"));
+        assert_eq!(count("zome", "b").as_deref(), Some("1"));
+        assert_eq!(count("zome", "c"), None);
+        assert_eq!(count(OTHER_LABEL, OTHER_LABEL).as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn connection_counters_are_rendered_per_conductor() {
+        let mut admin_call = MockAdminCall::new();
+        admin_call
+            .expect_connection_counters()
+            .returning(|| ConnectionCounters {
+                open: 1,
+                connect_attempts: 3,
+                failed_connects: 2,
+                evictions: 0,
+            });
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_connection_counters()
+            .returning(|| ConnectionCounters {
+                open: 2,
+                connect_attempts: 4,
+                failed_connects: 1,
+                evictions: 2,
+            });
+        let mut conductors = Conductors::default();
+        conductors.insert(ConductorHandle::new(
+            "a",
+            "".parse().unwrap(),
+            Arc::new(admin_call),
+            Arc::new(app_call),
+        ));
+
+        let rendered = Metrics::new(10).render(&conductors);
+        for line in [
+            "# TYPE hc_gw_admin_connections gauge",
+            "hc_gw_admin_connections{conductor=\"a\"} 1",
+            "# TYPE hc_gw_admin_connect_attempts_total counter",
+            "hc_gw_admin_connect_attempts_total{conductor=\"a\"} 3",
+            "hc_gw_admin_connect_failures_total{conductor=\"a\"} 2",
+            "hc_gw_app_connections{conductor=\"a\"} 2",
+            "hc_gw_app_connect_attempts_total{conductor=\"a\"} 4",
+            "hc_gw_app_connect_failures_total{conductor=\"a\"} 1",
+            "hc_gw_app_connection_evictions_total{conductor=\"a\"} 2",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {line}");
+        }
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn cancelled_calls_are_not_recorded() {
+        let metrics = Metrics::new(10);
+        drop(ZomeCallTimer::new(
+            &metrics,
+            "app",
+            "zome",
+            "a",
+            Instant::now(),
+        ));
+        let mut timer = ZomeCallTimer::new(&metrics, "app", "zome", "b", Instant::now());
+        timer.conductor_responded(Duration::from_millis(1));
+        drop(timer);
+
+        let rendered = metrics.render(&Conductors::default());
+        assert!(!rendered.contains("fn=\"a\""));
+        assert!(rendered.contains("fn=\"b\""));
+    }
+
+    #[test]
+    fn handler_panics_are_counted() {
+        let metrics = Metrics::new(10);
+        metrics.record_panic();
+
+        let rendered = metrics.render(&Conductors::default());
+        assert!(rendered.contains("# TYPE hc_gw_handler_panics_total counter"));
+        assert!(
+            rendered
+                .lines()
+                .any(|line| line == "hc_gw_handler_panics_total 1")
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Usage accounting, for hosting providers that bill hApp owners for the use of the gateway.
+//!
+//! The zome calls made to every app are counted along with the bytes of their payloads and
+//! responses, as sent to and received from the conductor. The totals since the gateway started are
+//! served at the management endpoint, and the usage of every interval can be appended to a file as
+//! CSV or JSON lines.
+
+use super::Metrics;
+use crate::config::{ConfigParseError, ConfigParseResult};
+use holochain_types::prelude::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Header of the usage exported as CSV.
+const CSV_HEADER: &str = "from,to,app_id,calls,payload_bytes,response_bytes";
+
+/// Format of the exported usage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UsageExportFormat {
+    /// A CSV row per app and interval, under a header that is written when the file is created.
+    #[default]
+    Csv,
+    /// A [`UsageReport`] per interval, as a line of JSON.
+    Json,
+}
+
+impl FromStr for UsageExportFormat {
+    type Err = ConfigParseError;
+
+    /// Expected format
+    /// - One of `csv` or `json`
+    fn from_str(s: &str) -> ConfigParseResult<Self> {
+        match s.trim() {
+            "csv" => Ok(UsageExportFormat::Csv),
+            "json" => Ok(UsageExportFormat::Json),
+            s => Err(ConfigParseError::Other(format!(
+                "Unknown usage export format {s:?}, expected \"csv\" or \"json\""
+            ))),
+        }
+    }
+}
+
+/// The usage of an app.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppUsage {
+    /// Installed app id of the app.
+    pub app_id: String,
+    /// Zome calls made to the app, whether they succeeded or not.
+    pub calls: u64,
+    /// Bytes of the payloads sent to the app.
+    pub payload_bytes: u64,
+    /// Bytes of the responses received from the app.
+    pub response_bytes: u64,
+}
+
+impl AppUsage {
+    fn since(&self, earlier: Option<&AppUsage>) -> AppUsage {
+        let Some(earlier) = earlier else {
+            return self.clone();
+        };
+        AppUsage {
+            app_id: self.app_id.clone(),
+            calls: self.calls - earlier.calls,
+            payload_bytes: self.payload_bytes - earlier.payload_bytes,
+            response_bytes: self.response_bytes - earlier.response_bytes,
+        }
+    }
+}
+
+/// The usage of every app that was called over a period.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageReport {
+    /// Start of the period, as an RFC 3339 timestamp.
+    pub from: String,
+    /// End of the period, as an RFC 3339 timestamp.
+    pub to: String,
+    /// The usage of every app that was called in the period, ordered by app id.
+    pub apps: Vec<AppUsage>,
+}
+
+impl UsageReport {
+    /// Render the report as CSV rows, without a header.
+    fn to_csv_rows(&self) -> String {
+        self.apps
+            .iter()
+            .map(|usage| {
+                format!(
+                    "{},{},{},{},{},{}\n",
+                    self.from,
+                    self.to,
+                    csv_field(&usage.app_id),
+                    usage.calls,
+                    usage.payload_bytes,
+                    usage.response_bytes
+                )
+            })
+            .collect()
+    }
+
+    /// Render the report as CSV, with a header.
+    pub fn to_csv(&self) -> String {
+        format!("{CSV_HEADER}\n{}", self.to_csv_rows())
+    }
+}
+
+/// Quote a CSV field if it contains characters that would otherwise break the row.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The usage of every app since the gateway started, and as of the last export.
+#[derive(Debug)]
+pub(crate) struct UsageAccounts {
+    started: Timestamp,
+    totals: Mutex<HashMap<String, AppUsage>>,
+    exported: Mutex<(Timestamp, HashMap<String, AppUsage>)>,
+}
+
+impl Default for UsageAccounts {
+    fn default() -> Self {
+        let now = Timestamp::now();
+        Self {
+            started: now,
+            totals: Mutex::default(),
+            exported: Mutex::new((now, HashMap::new())),
+        }
+    }
+}
+
+impl UsageAccounts {
+    pub(crate) fn record(&self, app_id: &str, payload_bytes: usize, response_bytes: usize) {
+        let mut totals = self.totals.lock().expect("Usage accounts lock poisoned");
+        let usage = totals
+            .entry(app_id.to_string())
+            .or_insert_with(|| AppUsage {
+                app_id: app_id.to_string(),
+                ..Default::default()
+            });
+        usage.calls += 1;
+        usage.payload_bytes += payload_bytes as u64;
+        usage.response_bytes += response_bytes as u64;
+    }
+
+    fn totals(&self) -> HashMap<String, AppUsage> {
+        self.totals
+            .lock()
+            .expect("Usage accounts lock poisoned")
+            .clone()
+    }
+
+    /// The usage of every app since the gateway started.
+    pub(crate) fn report(&self) -> UsageReport {
+        report(self.started, Timestamp::now(), self.totals().into_values())
+    }
+
+    /// Append the usage of every app since the last export to the file at the given path.
+    ///
+    /// The usage is only considered exported once it has been written, so usage that failed to
+    /// be written is included in the next export.
+    pub(crate) fn export(&self, path: &Path, format: UsageExportFormat) -> std::io::Result<()> {
+        let mut exported = self.exported.lock().expect("Usage accounts lock poisoned");
+        let (exported_at, exported_totals) = &mut *exported;
+        let now = Timestamp::now();
+        let totals = self.totals();
+        let report = report(
+            *exported_at,
+            now,
+            totals
+                .values()
+                .map(|usage| usage.since(exported_totals.get(&usage.app_id)))
+                .filter(|usage| usage.calls > 0),
+        );
+
+        if !report.apps.is_empty() {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            let text = match format {
+                UsageExportFormat::Csv if file.metadata()?.len() == 0 => report.to_csv(),
+                UsageExportFormat::Csv => report.to_csv_rows(),
+                UsageExportFormat::Json => format!("{}\n", serde_json::to_string(&report)?),
+            };
+            file.write_all(text.as_bytes())?;
+        }
+
+        *exported_at = now;
+        *exported_totals = totals;
+        Ok(())
+    }
+}
+
+fn report(from: Timestamp, to: Timestamp, apps: impl IntoIterator<Item = AppUsage>) -> UsageReport {
+    let mut apps = apps.into_iter().collect::<Vec<_>>();
+    apps.sort_by(|a, b| a.app_id.cmp(&b.app_id));
+
+    UsageReport {
+        from: from.to_string(),
+        to: to.to_string(),
+        apps,
+    }
+}
+
+impl Metrics {
+    /// Record a zome call to an app for the usage accounting, with the size of its payload and of
+    /// its response, if there was one.
+    pub fn record_app_usage(&self, app_id: &str, payload_bytes: usize, response_bytes: usize) {
+        self.accounts.record(app_id, payload_bytes, response_bytes);
+    }
+
+    /// The usage of every app since the gateway started.
+    pub fn usage_report(&self) -> UsageReport {
+        self.accounts.report()
+    }
+
+    /// Append the usage of every app since the last export to the file at the given path.
+    pub fn export_usage(&self, path: &Path, format: UsageExportFormat) -> std::io::Result<()> {
+        self.accounts.export(path, format)
+    }
+
+    /// Spawn a task that exports the usage of every app at the given interval, see
+    /// [`export_usage`](Metrics::export_usage).
+    pub fn spawn_usage_export(
+        self: &Arc<Self>,
+        path: PathBuf,
+        format: UsageExportFormat,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let this = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately, when there is nothing to export yet
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = this.export_usage(&path, format) {
+                    tracing::warn!(?e, ?path, "Failed to export usage");
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_is_accounted_per_app() {
+        let accounts = UsageAccounts::default();
+        accounts.record("b", 10, 100);
+        accounts.record("a", 1, 0);
+        accounts.record("b", 20, 200);
+
+        let report = accounts.report();
+        assert_eq!(
+            report.apps,
+            vec![
+                AppUsage {
+                    app_id: "a".to_string(),
+                    calls: 1,
+                    payload_bytes: 1,
+                    response_bytes: 0,
+                },
+                AppUsage {
+                    app_id: "b".to_string(),
+                    calls: 2,
+                    payload_bytes: 30,
+                    response_bytes: 300,
+                },
+            ]
+        );
+        assert_eq!(
+            report.to_csv().lines().skip(1).collect::<Vec<_>>(),
+            vec![
+                format!("{},{},a,1,1,0", report.from, report.to),
+                format!("{},{},b,2,30,300", report.from, report.to),
+            ]
+        );
+    }
+
+    #[test]
+    fn app_ids_are_quoted_in_csv() {
+        assert_eq!(csv_field("my-app"), "my-app");
+        assert_eq!(csv_field("my,\"app\""), "\"my,\"\"app\"\"\"");
+    }
+
+    #[test]
+    fn usage_since_the_last_export_is_appended() {
+        let path =
+            std::env::temp_dir().join(format!("hc-http-gw-usage-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let accounts = UsageAccounts::default();
+
+        accounts.record("a", 1, 2);
+        accounts.export(&path, UsageExportFormat::Csv).unwrap();
+        // Nothing is written for an interval without calls
+        accounts.export(&path, UsageExportFormat::Csv).unwrap();
+        accounts.record("a", 3, 4);
+        accounts.record("b", 5, 6);
+        accounts.export(&path, UsageExportFormat::Csv).unwrap();
+
+        let exported = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows = exported
+            .lines()
+            .map(|line| line.split(',').skip(2).collect::<Vec<_>>().join(","))
+            .collect::<Vec<_>>();
+        assert_eq!(exported.lines().next(), Some(CSV_HEADER));
+        assert_eq!(rows[1..], ["a,1,1,2", "a,1,3,4", "b,1,5,6"]);
+
+        // The totals are not affected by exports
+        assert_eq!(accounts.report().apps[0].calls, 2);
+    }
+
+    #[test]
+    fn usage_is_exported_as_json_lines() {
+        let path =
+            std::env::temp_dir().join(format!("hc-http-gw-usage-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let accounts = UsageAccounts::default();
+
+        accounts.record("a", 1, 2);
+        accounts.export(&path, UsageExportFormat::Json).unwrap();
+
+        let exported = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let reports = exported
+            .lines()
+            .map(|line| serde_json::from_str::<UsageReport>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].apps[0].calls, 1);
+    }
+}
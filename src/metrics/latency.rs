@@ -0,0 +1,118 @@
+//! Conductor latencies of recent zome calls, from which the gateway tells that a conductor is
+//! slowing down under load before requests start to time out.
+
+use super::Metrics;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The window that recent latencies are kept for.
+const LATENCY_WINDOW: Duration = Duration::from_secs(10);
+
+/// Maximum number of latencies kept, the oldest are dropped beyond that.
+const MAX_LATENCY_SAMPLES: usize = 1000;
+
+/// Minimum number of latencies in the window for the percentile to be meaningful.
+const MIN_LATENCY_SAMPLES: usize = 20;
+
+/// Time for which a computed percentile is reused, so that it isn't computed for every request.
+const PERCENTILE_TTL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+struct Samples {
+    /// Latencies with the time they were recorded at, oldest first.
+    latencies: VecDeque<(Instant, Duration)>,
+    /// The last computed 95th percentile, with the time it was computed at.
+    p95: Option<(Instant, Option<Duration>)>,
+}
+
+/// Conductor latencies of the zome calls in the last [`LATENCY_WINDOW`].
+#[derive(Debug, Default)]
+pub(crate) struct RecentLatencies(Mutex<Samples>);
+
+impl RecentLatencies {
+    /// Record the time that a conductor took to respond to a zome call.
+    pub(crate) fn record(&self, latency: Duration) {
+        let mut samples = self.0.lock().expect("Recent latencies lock poisoned");
+        if samples.latencies.len() >= MAX_LATENCY_SAMPLES {
+            samples.latencies.pop_front();
+        }
+        samples.latencies.push_back((Instant::now(), latency));
+    }
+
+    /// The latency below which 95 percent of the latencies in the window fall, by nearest rank.
+    ///
+    /// `None` if there are too few latencies in the window to tell.
+    pub(crate) fn p95(&self) -> Option<Duration> {
+        let mut samples = self.0.lock().expect("Recent latencies lock poisoned");
+        if let Some((computed, p95)) = samples.p95
+            && computed.elapsed() < PERCENTILE_TTL
+        {
+            return p95;
+        }
+
+        while samples
+            .latencies
+            .front()
+            .is_some_and(|(recorded, _)| recorded.elapsed() >= LATENCY_WINDOW)
+        {
+            samples.latencies.pop_front();
+        }
+        let p95 = (samples.latencies.len() >= MIN_LATENCY_SAMPLES).then(|| {
+            let mut sorted = samples
+                .latencies
+                .iter()
+                .map(|(_, latency)| *latency)
+                .collect::<Vec<_>>();
+            sorted.sort_unstable();
+            sorted[(sorted.len() * 95).div_ceil(100) - 1]
+        });
+        samples.p95 = Some((Instant::now(), p95));
+
+        p95
+    }
+}
+
+impl Metrics {
+    /// The 95th percentile of the time that conductors took to respond to recent zome calls, or
+    /// `None` if there were too few zome calls recently to tell.
+    pub fn recent_conductor_latency_p95(&self) -> Option<Duration> {
+        self.latencies.p95()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn p95_is_computed_once_there_are_enough_latencies() {
+        let latencies = RecentLatencies::default();
+        for ms in 1..MIN_LATENCY_SAMPLES as u64 {
+            latencies.record(Duration::from_millis(ms));
+        }
+        assert_eq!(latencies.p95(), None);
+
+        let latencies = RecentLatencies::default();
+        for ms in 1..=100 {
+            latencies.record(Duration::from_millis(ms));
+        }
+        assert_eq!(latencies.p95(), Some(Duration::from_millis(95)));
+    }
+
+    #[test]
+    fn latencies_older_than_the_window_are_dropped() {
+        let latencies = RecentLatencies::default();
+        {
+            let mut samples = latencies.0.lock().unwrap();
+            for _ in 0..MIN_LATENCY_SAMPLES {
+                samples
+                    .latencies
+                    .push_back((Instant::now() - LATENCY_WINDOW, Duration::from_secs(5)));
+            }
+        }
+
+        assert_eq!(latencies.p95(), None);
+        assert!(latencies.0.lock().unwrap().latencies.is_empty());
+    }
+}
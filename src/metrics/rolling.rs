@@ -0,0 +1,159 @@
+//! Outcomes of recent zome call requests and recent failed connects, from which the deep health
+//! check tells a gateway that is up but struggling from one that works normally.
+
+use super::Metrics;
+use axum::http::StatusCode;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Duration of each bucket that outcomes are counted in.
+const BUCKET: Duration = Duration::from_secs(1);
+
+/// Number of buckets in the window, which makes a window of a minute.
+const BUCKETS: usize = 60;
+
+/// The window that recent outcomes are counted over.
+pub const HEALTH_WINDOW: Duration = Duration::from_secs(BUCKET.as_secs() * BUCKETS as u64);
+
+/// Requests and server errors counted in a bucket.
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    /// Index of the bucket since the outcomes started being counted.
+    index: u64,
+    requests: u64,
+    server_errors: u64,
+}
+
+/// Zome call requests and server errors in the last [`HEALTH_WINDOW`].
+#[derive(Debug)]
+pub(crate) struct RecentOutcomes {
+    started: Instant,
+    buckets: Mutex<[Bucket; BUCKETS]>,
+    /// Total failed connects as sampled at the start of each bucket, oldest first.
+    failed_connects: Mutex<VecDeque<(u64, u64)>>,
+}
+
+impl Default for RecentOutcomes {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            buckets: Mutex::new([Bucket::default(); BUCKETS]),
+            failed_connects: Mutex::default(),
+        }
+    }
+}
+
+impl RecentOutcomes {
+    fn current_index(&self) -> u64 {
+        (self.started.elapsed().as_nanos() / BUCKET.as_nanos()) as u64
+    }
+
+    /// Record the response status of a request.
+    pub(crate) fn record(&self, status: StatusCode) {
+        let index = self.current_index();
+        let mut buckets = self.buckets.lock().expect("Recent outcomes lock poisoned");
+        let bucket = &mut buckets[index as usize % BUCKETS];
+        if bucket.index != index {
+            *bucket = Bucket {
+                index,
+                ..Default::default()
+            };
+        }
+
+        bucket.requests += 1;
+        if status.is_server_error() {
+            bucket.server_errors += 1;
+        }
+    }
+
+    /// The number of requests and of server errors in the window.
+    pub(crate) fn requests_and_server_errors(&self) -> (u64, u64) {
+        let index = self.current_index();
+        let buckets = self.buckets.lock().expect("Recent outcomes lock poisoned");
+
+        buckets
+            .iter()
+            .filter(|bucket| index - bucket.index < BUCKETS as u64)
+            .fold((0, 0), |(requests, server_errors), bucket| {
+                (
+                    requests + bucket.requests,
+                    server_errors + bucket.server_errors,
+                )
+            })
+    }
+
+    /// Sample the total number of failed connects, and return how many of them failed in the
+    /// window, as far as the samples go back.
+    pub(crate) fn failed_connects_in_window(&self, total: u64) -> u64 {
+        let index = self.current_index();
+        let mut samples = self
+            .failed_connects
+            .lock()
+            .expect("Recent outcomes lock poisoned");
+        while samples
+            .front()
+            .is_some_and(|(sampled, _)| index - sampled >= BUCKETS as u64)
+        {
+            samples.pop_front();
+        }
+        if samples.back().is_none_or(|(sampled, _)| *sampled != index) {
+            samples.push_back((index, total));
+        }
+
+        samples
+            .front()
+            .map_or(0, |(_, oldest)| total.saturating_sub(*oldest))
+    }
+}
+
+impl Metrics {
+    /// The number of zome call requests and of server errors in the last [`HEALTH_WINDOW`].
+    pub fn recent_requests_and_server_errors(&self) -> (u64, u64) {
+        self.recent.requests_and_server_errors()
+    }
+
+    /// Sample the total number of failed connects over all conductors, and return how many of
+    /// them failed in the last [`HEALTH_WINDOW`], as far as the samples go back.
+    pub fn recent_failed_connects(&self, total: u64) -> u64 {
+        self.recent.failed_connects_in_window(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes_are_counted_in_the_window() {
+        let outcomes = RecentOutcomes::default();
+        outcomes.record(StatusCode::OK);
+        outcomes.record(StatusCode::NOT_FOUND);
+        outcomes.record(StatusCode::SERVICE_UNAVAILABLE);
+
+        assert_eq!(outcomes.requests_and_server_errors(), (3, 1));
+    }
+
+    #[test]
+    fn outcomes_older_than_the_window_are_not_counted() {
+        let outcomes = RecentOutcomes {
+            started: Instant::now() - HEALTH_WINDOW,
+            ..Default::default()
+        };
+        // Outcomes recorded in the first bucket, a window ago
+        outcomes.buckets.lock().unwrap()[0] = Bucket {
+            index: 0,
+            requests: 5,
+            server_errors: 5,
+        };
+
+        assert_eq!(outcomes.requests_and_server_errors(), (0, 0));
+    }
+
+    #[test]
+    fn failed_connects_are_counted_since_the_oldest_sample() {
+        let outcomes = RecentOutcomes::default();
+        assert_eq!(outcomes.failed_connects_in_window(3), 0);
+        assert_eq!(outcomes.failed_connects_in_window(5), 2);
+    }
+}
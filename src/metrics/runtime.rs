@@ -0,0 +1,147 @@
+//! Metrics of the Tokio runtime and of the gateway process.
+//!
+//! A saturated gateway often shows up in the runtime, as busy workers and tasks queueing up,
+//! before it shows up in the latency of requests.
+
+use super::write_header;
+use std::fmt::Write;
+
+/// Render the metrics of the Tokio runtime that the metrics are rendered on, if any.
+pub(super) fn render_runtime_metrics(out: &mut String) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    let metrics = runtime.metrics();
+
+    write_header(
+        out,
+        "gauge",
+        "hc_gw_tokio_workers",
+        "Worker threads of the Tokio runtime.",
+    );
+    let _ = writeln!(out, "hc_gw_tokio_workers {}", metrics.num_workers());
+    write_header(
+        out,
+        "gauge",
+        "hc_gw_tokio_alive_tasks",
+        "Tasks of the Tokio runtime that have not finished.",
+    );
+    let _ = writeln!(out, "hc_gw_tokio_alive_tasks {}", metrics.num_alive_tasks());
+    write_header(
+        out,
+        "gauge",
+        "hc_gw_tokio_global_queue_depth",
+        "Tasks waiting in the global queue of the Tokio runtime to be picked up by a worker.",
+    );
+    let _ = writeln!(
+        out,
+        "hc_gw_tokio_global_queue_depth {}",
+        metrics.global_queue_depth()
+    );
+    write_header(
+        out,
+        "counter",
+        "hc_gw_tokio_worker_busy_seconds_total",
+        "Time that each worker of the Tokio runtime spent running tasks.",
+    );
+    for worker in 0..metrics.num_workers() {
+        let _ = writeln!(
+            out,
+            "hc_gw_tokio_worker_busy_seconds_total{{worker=\"{worker}\"}} {}",
+            metrics.worker_total_busy_duration(worker).as_secs_f64()
+        );
+    }
+}
+
+/// Render the memory use and open file descriptors of the process, where `/proc` is available.
+pub(super) fn render_process_metrics(out: &mut String) {
+    if let Some(resident_memory) = resident_memory_bytes() {
+        write_header(
+            out,
+            "gauge",
+            "process_resident_memory_bytes",
+            "Resident memory size in bytes.",
+        );
+        let _ = writeln!(out, "process_resident_memory_bytes {resident_memory}");
+    }
+    if let Ok(fds) = std::fs::read_dir("/proc/self/fd") {
+        write_header(
+            out,
+            "gauge",
+            "process_open_fds",
+            "Number of open file descriptors.",
+        );
+        let _ = writeln!(out, "process_open_fds {}", fds.count());
+    }
+    if let Some(max_fds) = max_fds() {
+        write_header(
+            out,
+            "gauge",
+            "process_max_fds",
+            "Maximum number of open file descriptors.",
+        );
+        let _ = writeln!(out, "process_max_fds {max_fds}");
+    }
+}
+
+/// The resident set size of the process, from the `VmRSS` line of `/proc/self/status`.
+fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
+
+/// The soft limit on open file descriptors, from the `Max open files` line of `/proc/self/limits`.
+fn max_fds() -> Option<u64> {
+    let limits = std::fs::read_to_string("/proc/self/limits").ok()?;
+    limits
+        .lines()
+        .find_map(|line| line.strip_prefix("Max open files"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn runtime_metrics_are_rendered() {
+        let mut out = String::new();
+        render_runtime_metrics(&mut out);
+
+        assert!(out.lines().any(|line| line == "hc_gw_tokio_workers 2"));
+        assert!(out.contains("# TYPE hc_gw_tokio_global_queue_depth gauge"));
+        assert!(
+            out.lines()
+                .any(|line| line.starts_with("hc_gw_tokio_worker_busy_seconds_total{worker=\"1\"}"))
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn process_metrics_are_rendered() {
+        let mut out = String::new();
+        render_process_metrics(&mut out);
+
+        let value = |name: &str| {
+            out.lines()
+                .find_map(|line| line.strip_prefix(&format!("{name} ")))
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap()
+        };
+        assert!(value("process_resident_memory_bytes") > 0);
+        assert!(value("process_open_fds") > 0);
+        assert!(value("process_max_fds") >= value("process_open_fds"));
+    }
+}
@@ -0,0 +1,226 @@
+//! Pushing of metrics over StatsD, for hosting environments that only collect metrics by push.
+//!
+//! Zome call durations are sent as timings as they are recorded. The connection counters and the
+//! metrics of the runtime are sent as gauges at a fixed interval.
+
+use super::CONNECTION_METRICS;
+use crate::Conductors;
+use crate::config::{ConfigParseError, ConfigParseResult};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// The dialect of StatsD that metrics are sent in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatsdFlavor {
+    /// Plain StatsD, which has no tags. Label values are appended to the metric name instead,
+    /// for example `hc_gw.zome_call.conductor_duration.my_app.posts.get_post`.
+    #[default]
+    Statsd,
+    /// DogStatsD, which sends label values as tags, for example
+    /// `hc_gw.zome_call.conductor_duration:12|ms|#app_id:my_app,zome:posts,fn:get_post`.
+    Dogstatsd,
+}
+
+impl FromStr for StatsdFlavor {
+    type Err = ConfigParseError;
+
+    /// Expected format
+    /// - One of `statsd` or `dogstatsd`
+    fn from_str(s: &str) -> ConfigParseResult<Self> {
+        match s.trim() {
+            "statsd" => Ok(StatsdFlavor::Statsd),
+            "dogstatsd" => Ok(StatsdFlavor::Dogstatsd),
+            s => Err(ConfigParseError::Other(format!(
+                "Unknown StatsD flavor {s:?}, expected \"statsd\" or \"dogstatsd\""
+            ))),
+        }
+    }
+}
+
+/// Sends metrics to a StatsD server over UDP.
+///
+/// Sending never blocks, metrics that can't be sent are dropped.
+#[derive(Debug, Clone)]
+pub struct StatsdExporter {
+    socket: Arc<UdpSocket>,
+    prefix: String,
+    flavor: StatsdFlavor,
+}
+
+impl StatsdExporter {
+    /// Create an exporter that sends metrics to the StatsD server at the given address, with
+    /// names starting with `prefix`.
+    pub fn new(
+        address: impl ToSocketAddrs,
+        prefix: impl Into<String>,
+        flavor: StatsdFlavor,
+    ) -> std::io::Result<Self> {
+        let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "StatsD address did not resolve",
+            )
+        })?;
+        let unspecified: SocketAddr = if address.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(unspecified)?;
+        socket.connect(address)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket: Arc::new(socket),
+            prefix: prefix.into(),
+            flavor,
+        })
+    }
+
+    /// Send a timing in milliseconds.
+    pub(crate) fn timing(&self, name: &str, duration: Duration, labels: &[(&str, &str)]) {
+        self.send(
+            name,
+            &(duration.as_secs_f64() * 1000.0).to_string(),
+            "ms",
+            labels,
+        );
+    }
+
+    /// Send the current value of a gauge.
+    pub(crate) fn gauge(&self, name: &str, value: impl ToString, labels: &[(&str, &str)]) {
+        self.send(name, &value.to_string(), "g", labels);
+    }
+
+    fn send(&self, name: &str, value: &str, metric_type: &str, labels: &[(&str, &str)]) {
+        let line = match self.flavor {
+            StatsdFlavor::Statsd => {
+                let mut name = format!("{}.{name}", self.prefix);
+                for (_, value) in labels {
+                    name.push('.');
+                    name.push_str(&sanitize(value, |c| {
+                        c.is_ascii_alphanumeric() || c == '_' || c == '-'
+                    }));
+                }
+                format!("{name}:{value}|{metric_type}")
+            }
+            StatsdFlavor::Dogstatsd => {
+                let tags = labels
+                    .iter()
+                    .map(|(label, value)| {
+                        let value =
+                            sanitize(value, |c| !matches!(c, ':' | '|' | ',' | '#' | '@' | ' '));
+                        format!("{label}:{value}")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+                if tags.is_empty() {
+                    format!("{}.{name}:{value}|{metric_type}", self.prefix)
+                } else {
+                    format!("{}.{name}:{value}|{metric_type}|#{tags}", self.prefix)
+                }
+            }
+        };
+
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            tracing::trace!(?e, "Failed to send StatsD metric");
+        }
+    }
+
+    /// Spawn a task that sends the connection counters of the given conductors and the metrics of
+    /// the runtime as gauges at the given interval.
+    pub fn spawn_gauges(&self, conductors: Conductors, interval: Duration) -> JoinHandle<()> {
+        let this = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                this.send_gauges(&conductors);
+            }
+        })
+    }
+
+    fn send_gauges(&self, conductors: &Conductors) {
+        for conductor in conductors.iter() {
+            let labels = [("conductor", conductor.name.as_str())];
+            for (interface, counters) in [
+                ("admin", conductor.admin_call.connection_counters()),
+                ("app", conductor.app_call.connection_counters()),
+            ] {
+                for (suffix, _, _, value) in CONNECTION_METRICS {
+                    self.gauge(&format!("{interface}.{suffix}"), value(&counters), &labels);
+                }
+            }
+        }
+
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let metrics = runtime.metrics();
+            self.gauge("tokio.workers", metrics.num_workers(), &[]);
+            self.gauge("tokio.alive_tasks", metrics.num_alive_tasks(), &[]);
+            self.gauge(
+                "tokio.global_queue_depth",
+                metrics.global_queue_depth(),
+                &[],
+            );
+        }
+    }
+}
+
+/// Replace the characters of a label value that are not allowed with `_`.
+fn sanitize(value: &str, allowed: impl Fn(char) -> bool) -> String {
+    value
+        .chars()
+        .map(|c| if allowed(c) { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(server: &UdpSocket) -> String {
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn metrics_are_sent_in_the_configured_flavor() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let labels = [("app_id", "my app"), ("fn", "get:post")];
+
+        let statsd =
+            StatsdExporter::new(server.local_addr().unwrap(), "gw", StatsdFlavor::Statsd).unwrap();
+        statsd.timing("duration", Duration::from_millis(12), &labels);
+        assert_eq!(receive(&server), "gw.duration.my_app.get_post:12|ms");
+        statsd.gauge("connections", 3, &[]);
+        assert_eq!(receive(&server), "gw.connections:3|g");
+
+        let dogstatsd =
+            StatsdExporter::new(server.local_addr().unwrap(), "gw", StatsdFlavor::Dogstatsd)
+                .unwrap();
+        dogstatsd.timing("duration", Duration::from_millis(12), &labels);
+        assert_eq!(
+            receive(&server),
+            "gw.duration:12|ms|#app_id:my_app,fn:get_post"
+        );
+        dogstatsd.gauge("connections", 3, &[]);
+        assert_eq!(receive(&server), "gw.connections:3|g");
+    }
+
+    #[test]
+    fn flavor_is_parsed() {
+        assert_eq!(
+            "dogstatsd".parse::<StatsdFlavor>().unwrap(),
+            StatsdFlavor::Dogstatsd
+        );
+        "datadog".parse::<StatsdFlavor>().unwrap_err();
+    }
+}
@@ -0,0 +1,231 @@
+//! Periodic usage summaries, for deployments that have no metrics stack and only look at logs.
+//!
+//! Zome call requests are counted per app over an interval, and at the end of the interval a log
+//! line is written for every app that was called, with the number of requests, the number of
+//! errors and latency percentiles. The counts start over for the next interval.
+
+use super::{Metrics, OTHER_LABEL};
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Maximum number of apps that are summarized separately in an interval, further apps are
+/// summarized together as [`OTHER_LABEL`].
+const MAX_APPS: usize = 1000;
+
+/// Maximum number of latencies that are kept per app in an interval for the percentiles.
+const MAX_SAMPLES: usize = 10_000;
+
+/// The requests to an app in the current interval.
+#[derive(Debug, Default)]
+struct AppUsage {
+    requests: u64,
+    client_errors: u64,
+    server_errors: u64,
+    latencies: Vec<Duration>,
+}
+
+/// The summary of the requests to an app over an interval.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct UsageSummary {
+    pub(crate) app_id: String,
+    pub(crate) requests: u64,
+    pub(crate) client_errors: u64,
+    pub(crate) server_errors: u64,
+    pub(crate) p50: Duration,
+    pub(crate) p95: Duration,
+    pub(crate) p99: Duration,
+}
+
+/// The usage of every app in the current interval.
+#[derive(Debug, Default)]
+pub(crate) struct UsageSummaries {
+    apps: Mutex<HashMap<String, AppUsage>>,
+}
+
+impl UsageSummaries {
+    /// Record a request to an app.
+    pub(crate) fn record(&self, app_id: &str, status: StatusCode, latency: Duration) {
+        let mut apps = self.apps.lock().expect("Usage summaries lock poisoned");
+        let app_id = if apps.contains_key(app_id) || apps.len() < MAX_APPS {
+            app_id
+        } else {
+            OTHER_LABEL
+        };
+
+        let usage = apps.entry(app_id.to_string()).or_default();
+        usage.requests += 1;
+        if status.is_client_error() {
+            usage.client_errors += 1;
+        } else if status.is_server_error() {
+            usage.server_errors += 1;
+        }
+        // Once enough latencies are kept, the percentiles are representative enough
+        if usage.latencies.len() < MAX_SAMPLES {
+            usage.latencies.push(latency);
+        }
+    }
+
+    /// Summarize the usage of every app since the last summary, and start over.
+    pub(crate) fn take(&self) -> Vec<UsageSummary> {
+        let apps = std::mem::take(&mut *self.apps.lock().expect("Usage summaries lock poisoned"));
+        let mut summaries = apps
+            .into_iter()
+            .map(|(app_id, mut usage)| {
+                usage.latencies.sort_unstable();
+                UsageSummary {
+                    app_id,
+                    requests: usage.requests,
+                    client_errors: usage.client_errors,
+                    server_errors: usage.server_errors,
+                    p50: percentile(&usage.latencies, 50),
+                    p95: percentile(&usage.latencies, 95),
+                    p99: percentile(&usage.latencies, 99),
+                }
+            })
+            .collect::<Vec<_>>();
+        summaries.sort_by(|a, b| a.app_id.cmp(&b.app_id));
+
+        summaries
+    }
+}
+
+/// The latency below which `percent` percent of the sorted latencies fall, by nearest rank.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+impl Metrics {
+    /// Spawn a task that logs a summary of the usage of every app at the given interval.
+    ///
+    /// Does nothing unless usage summaries were enabled with
+    /// [`with_usage_summaries`](Metrics::with_usage_summaries).
+    pub fn spawn_usage_summaries(self: &Arc<Self>, interval: Duration) -> Option<JoinHandle<()>> {
+        self.usage.as_ref()?;
+        let this = self.clone();
+
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately, when there is nothing to summarize yet
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(usage) = &this.usage else {
+                    return;
+                };
+                for summary in usage.take() {
+                    tracing::info!(
+                        app_id = summary.app_id,
+                        requests = summary.requests,
+                        client_errors = summary.client_errors,
+                        server_errors = summary.server_errors,
+                        error_rate = (summary.client_errors + summary.server_errors) as f64
+                            / summary.requests as f64,
+                        p50_ms = summary.p50.as_millis() as u64,
+                        p95_ms = summary.p95.as_millis() as u64,
+                        p99_ms = summary.p99.as_millis() as u64,
+                        "Usage summary"
+                    );
+                }
+            }
+        }))
+    }
+}
+
+/// Middleware that records zome call requests for the usage summaries and the recent outcomes.
+///
+/// The app is taken from the path of the request, so that requests that are rejected before the
+/// app is looked up are counted as well.
+pub(crate) async fn record_usage(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let app_id = metrics.usage.is_some().then(|| {
+        request
+            .uri()
+            .path()
+            .split('/')
+            .nth(2)
+            .unwrap_or_default()
+            .to_string()
+    });
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+    metrics.recent.record(response.status());
+    if let (Some(usage), Some(app_id)) = (&metrics.usage, app_id) {
+        usage.record(&app_id, response.status(), started.elapsed());
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_is_summarized_per_app() {
+        let summaries = UsageSummaries::default();
+        for millis in 1..=100 {
+            summaries.record("app", StatusCode::OK, Duration::from_millis(millis));
+        }
+        for status in [
+            StatusCode::OK,
+            StatusCode::NOT_FOUND,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ] {
+            summaries.record("other", status, Duration::from_millis(7));
+        }
+
+        let taken = summaries.take();
+        assert_eq!(
+            taken,
+            vec![
+                UsageSummary {
+                    app_id: "app".to_string(),
+                    requests: 100,
+                    client_errors: 0,
+                    server_errors: 0,
+                    p50: Duration::from_millis(50),
+                    p95: Duration::from_millis(95),
+                    p99: Duration::from_millis(99),
+                },
+                UsageSummary {
+                    app_id: "other".to_string(),
+                    requests: 3,
+                    client_errors: 1,
+                    server_errors: 1,
+                    p50: Duration::from_millis(7),
+                    p95: Duration::from_millis(7),
+                    p99: Duration::from_millis(7),
+                },
+            ]
+        );
+
+        // Every interval starts over
+        assert!(summaries.take().is_empty());
+    }
+
+    #[test]
+    fn apps_beyond_the_limit_are_summarized_together() {
+        let summaries = UsageSummaries::default();
+        for app in 0..=MAX_APPS {
+            summaries.record(&app.to_string(), StatusCode::OK, Duration::ZERO);
+        }
+
+        let taken = summaries.take();
+        assert_eq!(taken.len(), MAX_APPS + 1);
+        assert!(taken.iter().any(|summary| summary.app_id == OTHER_LABEL));
+    }
+}
@@ -0,0 +1,178 @@
+//! Request ids, for correlating a failure that a user reports with the logs of the gateway.
+//!
+//! Every request gets an id, either the one that the client or a proxy in front of the gateway
+//! put in the request id header, or a newly generated one. The id is recorded on the span of the
+//! request, returned in the same header of the response, and included in error responses.
+
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Maximum length of a request id supplied by a client, longer ids are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request that is being handled by the current task, if any.
+pub(crate) fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Middleware that assigns an id to every request, see the [module documentation](self).
+///
+/// An id supplied by the client is used if it consists of at most 128 visible ASCII characters.
+pub(crate) async fn assign_request_id(
+    State(header_name): State<HeaderName>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let request_id = request
+        .headers()
+        .get(&header_name)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| is_valid_request_id(value))
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    tracing::Span::current().record("request_id", request_id.as_str());
+    let header_value =
+        HeaderValue::from_str(&request_id).expect("Request ids are visible ASCII characters");
+
+    let mut response = REQUEST_ID.scope(request_id, next.run(request)).await;
+    response.headers_mut().insert(header_name, header_value);
+
+    response
+}
+
+fn is_valid_request_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Generate a request id of 32 hex digits.
+///
+/// The ids are derived from a counter with keys that are random for each process, so that they
+/// are unique within a process, are unlikely to repeat across processes, and don't reveal how
+/// many requests the gateway has handled.
+fn generate_request_id() -> String {
+    static KEYS: OnceLock<(RandomState, u64)> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let (keys, prefix) = KEYS.get_or_init(|| {
+        let keys = RandomState::new();
+        let prefix = keys.hash_one(std::process::id());
+        (keys, prefix)
+    });
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    format!("{prefix:016x}{:016x}", keys.hash_one(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::router::TestRouter;
+    use crate::{Configuration, ErrorResponse};
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use tower::ServiceExt;
+
+    async fn request(
+        router: &TestRouter,
+        uri: &str,
+        headers: &[(&str, &str)],
+    ) -> (HeaderValue, String) {
+        let mut request = axum::http::Request::builder().uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = (*router)
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let header_value = response.headers()["x-request-id"].clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (header_value, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn generated_request_ids_are_unique() {
+        let first = generate_request_id();
+        let second = generate_request_id();
+
+        assert_eq!(first.len(), 32);
+        assert!(is_valid_request_id(&first));
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn request_id_is_included_in_error_responses() {
+        let router = TestRouter::new();
+
+        let (header_value, body) =
+            request(&router, "/dna_hash/coordinator/zome_name/fn_name", &[]).await;
+        let response: ErrorResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            response.request_id.as_deref(),
+            Some(header_value.to_str().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn request_id_of_the_client_is_honored() {
+        let router = TestRouter::new();
+
+        let (header_value, body) = request(
+            &router,
+            "/dna_hash/coordinator/zome_name/fn_name",
+            &[("x-request-id", "client-id-1")],
+        )
+        .await;
+        assert_eq!(header_value, "client-id-1");
+        let response: ErrorResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.request_id.as_deref(), Some("client-id-1"));
+
+        // Invalid ids are replaced
+        let (header_value, _) = request(&router, "/health", &[("x-request-id", "client id")]).await;
+        assert_ne!(header_value, "client id");
+    }
+
+    #[tokio::test]
+    async fn request_id_header_is_configurable() {
+        let mut config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "",
+            HashMap::new(),
+            "",
+            "",
+        )
+        .unwrap();
+        config.request_id_header = HeaderName::from_static("x-correlation-id");
+        let router = TestRouter::new_with_config(config);
+
+        let response = (*router)
+            .clone()
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/health")
+                    .header("x-correlation-id", "abc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-correlation-id"], "abc");
+        assert!(!response.headers().contains_key("x-request-id"));
+    }
+}
@@ -1,8 +1,9 @@
+use std::net::SocketAddr;
 use tokio::net::lookup_host;
 use url::Url;
 
 /// Resolve a URL to a socket address
-pub async fn resolve_address_from_url(url: &str) -> std::io::Result<std::net::SocketAddr> {
+pub async fn resolve_address_from_url(url: &str) -> std::io::Result<SocketAddr> {
     let url = Url::parse(url).map_err(|e| std::io::Error::other(format!("Invalid URL: {e}")))?;
 
     let host = url
@@ -20,6 +21,18 @@ pub async fn resolve_address_from_url(url: &str) -> std::io::Result<std::net::So
     }
 }
 
+/// Resolve a URL to a socket address again, falling back to the last known address if the URL
+/// can't be resolved.
+pub(crate) async fn re_resolve_address_from_url(url: &str, last_known: SocketAddr) -> SocketAddr {
+    match resolve_address_from_url(url).await {
+        Ok(addr) => addr,
+        Err(e) => {
+            tracing::warn!(?e, url, %last_known, "Failed to resolve URL, using the last known address");
+            last_known
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -46,4 +59,16 @@ mod tests {
         let url = "http://something-that-is-not-a-real-host:8080";
         resolve_address_from_url(url).await.unwrap_err();
     }
+
+    #[tokio::test]
+    async fn re_resolve_falls_back_to_last_known_address() {
+        let last_known = SocketAddr::from(([10, 0, 0, 1], 8080));
+
+        let addr = re_resolve_address_from_url("http://127.0.0.1:9090", last_known).await;
+        assert_eq!(addr, SocketAddr::from(([127, 0, 0, 1], 9090)));
+
+        let url = "http://something-that-is-not-a-real-host:8080";
+        let addr = re_resolve_address_from_url(url, last_known).await;
+        assert_eq!(addr, last_known);
+    }
 }
@@ -0,0 +1,456 @@
+//! In-memory cache of zome call responses, for read-only functions of public gateways.
+//!
+//! Responses of the functions that are given a TTL with `HC_GW_CACHE_TTL_{app-id}` are kept for
+//! that long, and requests for the same function with the same payload are answered from the cache
+//! without calling the conductor. Only successful responses are cached.
+//!
+//! The responses are cached as the conductor returned them, so the response transforms and the
+//! transcoding still apply to cached responses.
+//!
+//! Responses are cached in memory, or in Redis with the `redis` feature, so that the replicas of a
+//! gateway share them and a restarted gateway doesn't call the conductor for every cached function
+//! at once.
+//!
+//! A response that has expired is still served for up to `HC_GW_CACHE_MAX_STALE_MS` after its
+//! expiry, while a single request refreshes it in the background, so that requests for a cached
+//! function don't wait for the conductor once the response has been cached.
+
+use crate::{Configuration, HcHttpGatewayResult};
+#[cfg(feature = "redis")]
+use crate::{HcHttpGatewayError, UpstreamConnection};
+use holochain_client::ExternIO;
+use holochain_types::dna::DnaHash;
+use holochain_types::prelude::CapSecret;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+
+#[cfg(feature = "redis")]
+mod redis_store;
+
+/// What a zome call response is cached for.
+///
+/// The payload is part of the key as the BLAKE2b-256 hash of it as sent to the conductor, so that
+/// the payload isn't copied for every request. The names are shared with the request rather than
+/// copied. The capability secret is part of the key, so that a response that required a secret
+/// isn't served to requests without it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    pub(crate) installed_app_id: Arc<str>,
+    pub(crate) dna_hash: DnaHash,
+    pub(crate) zome_name: Arc<str>,
+    pub(crate) fn_name: Arc<str>,
+    pub(crate) payload_hash: [u8; 32],
+    pub(crate) cap_secret: Option<CapSecret>,
+}
+
+impl CacheKey {
+    /// The hex encoded hash that identifies the payload when purging cached responses.
+    pub(crate) fn payload_hash_hex(&self) -> String {
+        hex(&self.payload_hash)
+    }
+}
+
+/// Lowercase hex encoding of a hash.
+fn hex(hash: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 * hash.len());
+    for byte in hash {
+        write!(hex, "{byte:02x}").expect("Writing to a String can't fail");
+    }
+    hex
+}
+
+/// The BLAKE2b-256 hash of a payload as sent to the conductor.
+pub(crate) fn payload_hash(payload: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(
+        blake2b_simd::Params::new()
+            .hash_length(32)
+            .hash(payload)
+            .as_bytes(),
+    );
+    hash
+}
+
+/// Which cached responses to purge, as the query parameters of the purge endpoint.
+///
+/// The responses of the app are purged, narrowed down to those of a zome, of a function, or for a
+/// payload, when they are given.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PurgeFilter {
+    /// Id of the app whose responses are purged
+    pub app_id: String,
+    /// Name of the zome whose responses are purged
+    pub zome_name: Option<String>,
+    /// Name of the function whose responses are purged
+    pub fn_name: Option<String>,
+    /// Hash of the payload whose responses are purged, as returned in the `X-Hc-Payload-Hash`
+    /// header of responses of cached functions
+    pub payload_hash: Option<String>,
+}
+
+impl PurgeFilter {
+    fn matches(
+        &self,
+        installed_app_id: &str,
+        zome_name: &str,
+        fn_name: &str,
+        payload_hash: &[u8],
+    ) -> bool {
+        self.app_id == installed_app_id
+            && self
+                .zome_name
+                .as_deref()
+                .is_none_or(|name| name == zome_name)
+            && self.fn_name.as_deref().is_none_or(|name| name == fn_name)
+            && self
+                .payload_hash
+                .as_deref()
+                .is_none_or(|hash| hash.eq_ignore_ascii_case(&hex(payload_hash)))
+    }
+}
+
+/// A response found in the cache.
+#[derive(Debug, PartialEq)]
+pub(crate) struct CacheHit {
+    pub(crate) response: ExternIO,
+    /// Whether the response has expired and the request that found it should refresh it.
+    pub(crate) refresh: bool,
+}
+
+/// Cache of zome call responses, shared by its clones.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    max_stale: Duration,
+    store: Store,
+}
+
+/// Where the cached responses are kept.
+#[derive(Debug, Clone)]
+enum Store {
+    Memory(MemoryStore),
+    #[cfg(feature = "redis")]
+    Redis(redis_store::RedisStore),
+}
+
+impl ResponseCache {
+    /// Create a cache that holds at most `max_entries` responses in memory, and serves them for up
+    /// to `max_stale` after they expire.
+    pub fn new(max_entries: usize, max_stale: Duration) -> Self {
+        Self {
+            max_stale,
+            store: Store::Memory(MemoryStore {
+                max_entries,
+                entries: Default::default(),
+            }),
+        }
+    }
+
+    /// Create the cache that the configuration asks for.
+    ///
+    /// Responses are cached in Redis when a Redis URL is configured and the gateway is built with
+    /// the `redis` feature, and in memory otherwise.
+    pub(crate) fn from_configuration(configuration: &Configuration) -> Self {
+        let cache = Self::new(
+            configuration.response_cache_max_entries,
+            configuration.cache_max_stale,
+        );
+        match &configuration.response_cache_redis_url {
+            Some(redis_url) => cache.in_redis(redis_url),
+            None => cache,
+        }
+    }
+
+    #[cfg(feature = "redis")]
+    fn in_redis(self, redis_url: &Url) -> Self {
+        match redis_store::RedisStore::new(redis_url.clone()) {
+            Ok(store) => Self {
+                store: Store::Redis(store),
+                ..self
+            },
+            Err(e) => {
+                tracing::error!(?e, "Invalid Redis URL, caching responses in memory");
+                self
+            }
+        }
+    }
+
+    #[cfg(not(feature = "redis"))]
+    fn in_redis(self, redis_url: &Url) -> Self {
+        tracing::error!(
+            %redis_url,
+            "Caching responses in Redis requires the redis feature, caching them in memory"
+        );
+        self
+    }
+
+    /// The cached response for the key, if it hasn't expired or is stale for less than the
+    /// maximum staleness.
+    ///
+    /// Only the first request to find a stale response is asked to refresh it, until the refresh
+    /// fails or a new response is cached.
+    pub(crate) async fn get(&self, key: &CacheKey) -> Option<CacheHit> {
+        match &self.store {
+            Store::Memory(store) => store.get(key, self.max_stale),
+            #[cfg(feature = "redis")]
+            Store::Redis(store) => store.get(key, self.max_stale).await,
+        }
+    }
+
+    /// Cache a response for the given TTL.
+    pub(crate) async fn insert(&self, key: CacheKey, response: ExternIO, ttl: Duration) {
+        match &self.store {
+            Store::Memory(store) => store.insert(key, response, ttl, self.max_stale),
+            #[cfg(feature = "redis")]
+            Store::Redis(store) => store.insert(&key, response, ttl, self.max_stale).await,
+        }
+    }
+
+    /// Let the next request that finds the stale response for the key refresh it, after a
+    /// refresh failed.
+    pub(crate) async fn refresh_failed(&self, key: &CacheKey) {
+        match &self.store {
+            Store::Memory(store) => store.refresh_failed(key),
+            #[cfg(feature = "redis")]
+            Store::Redis(store) => store.refresh_failed(key).await,
+        }
+    }
+
+    /// The number of responses cached in memory, or `None` if they are cached in Redis.
+    pub(crate) fn len(&self) -> Option<usize> {
+        match &self.store {
+            Store::Memory(store) => Some(
+                store
+                    .entries
+                    .lock()
+                    .expect("Response cache lock poisoned")
+                    .len(),
+            ),
+            #[cfg(feature = "redis")]
+            Store::Redis(_) => None,
+        }
+    }
+
+    /// Remove the cached responses that match the filter, and return how many were removed.
+    ///
+    /// Responses cached in memory are only removed from this gateway, while responses cached in
+    /// Redis are removed for all replicas.
+    pub(crate) async fn purge(&self, filter: &PurgeFilter) -> HcHttpGatewayResult<usize> {
+        match &self.store {
+            Store::Memory(store) => Ok(store.purge(filter)),
+            #[cfg(feature = "redis")]
+            Store::Redis(store) => store.purge(filter).await.map_err(|e| {
+                tracing::error!(?e, "Failed to purge cached responses from Redis");
+                HcHttpGatewayError::UpstreamUnavailable(UpstreamConnection::ResponseCache)
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CachedResponse {
+    response: ExternIO,
+    expires: Instant,
+    /// Whether a request is refreshing the response since it expired.
+    refreshing: bool,
+}
+
+/// Responses cached in the memory of the gateway.
+#[derive(Debug, Clone)]
+struct MemoryStore {
+    max_entries: usize,
+    entries: Arc<Mutex<HashMap<CacheKey, CachedResponse>>>,
+}
+
+impl MemoryStore {
+    fn get(&self, key: &CacheKey, max_stale: Duration) -> Option<CacheHit> {
+        let mut entries = self.entries.lock().expect("Response cache lock poisoned");
+        let cached = entries.get_mut(key)?;
+        let now = Instant::now();
+        if cached.expires > now {
+            return Some(CacheHit {
+                response: cached.response.clone(),
+                refresh: false,
+            });
+        }
+        if cached.expires + max_stale <= now {
+            return None;
+        }
+
+        let refresh = !cached.refreshing;
+        cached.refreshing = true;
+        Some(CacheHit {
+            response: cached.response.clone(),
+            refresh,
+        })
+    }
+
+    /// When the store is full, the responses that are too stale to be served are removed first. If
+    /// it is still full, the response is not cached.
+    fn insert(&self, key: CacheKey, response: ExternIO, ttl: Duration, max_stale: Duration) {
+        let mut entries = self.entries.lock().expect("Response cache lock poisoned");
+        if !entries.contains_key(&key) && entries.len() >= self.max_entries {
+            let now = Instant::now();
+            entries.retain(|_, cached| cached.expires + max_stale > now);
+            if entries.len() >= self.max_entries {
+                tracing::debug!(
+                    max_entries = self.max_entries,
+                    "Response cache is full, not caching the response"
+                );
+                return;
+            }
+        }
+
+        entries.insert(
+            key,
+            CachedResponse {
+                response,
+                expires: Instant::now() + ttl,
+                refreshing: false,
+            },
+        );
+    }
+
+    fn refresh_failed(&self, key: &CacheKey) {
+        if let Some(cached) = self
+            .entries
+            .lock()
+            .expect("Response cache lock poisoned")
+            .get_mut(key)
+        {
+            cached.refreshing = false;
+        }
+    }
+
+    fn purge(&self, filter: &PurgeFilter) -> usize {
+        let mut entries = self.entries.lock().expect("Response cache lock poisoned");
+        let before = entries.len();
+        entries.retain(|key, _| {
+            !filter.matches(
+                &key.installed_app_id,
+                &key.zome_name,
+                &key.fn_name,
+                &key.payload_hash,
+            )
+        });
+        before - entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(payload: &[u8]) -> CacheKey {
+        CacheKey {
+            installed_app_id: "app".into(),
+            dna_hash: DnaHash::from_raw_32(vec![1; 32]),
+            zome_name: "posts".into(),
+            fn_name: "list".into(),
+            payload_hash: payload_hash(payload),
+            cap_secret: None,
+        }
+    }
+
+    fn hit(response: u8, refresh: bool) -> Option<CacheHit> {
+        Some(CacheHit {
+            response: ExternIO(vec![response]),
+            refresh,
+        })
+    }
+
+    #[tokio::test]
+    async fn responses_are_cached_until_they_expire() {
+        let cache = ResponseCache::new(10, Duration::ZERO);
+        cache
+            .insert(key(b"a"), ExternIO(vec![1]), Duration::from_secs(60))
+            .await;
+        cache
+            .insert(key(b"b"), ExternIO(vec![2]), Duration::ZERO)
+            .await;
+
+        assert_eq!(cache.get(&key(b"a")).await, hit(1, false));
+        assert_eq!(cache.get(&key(b"b")).await, None);
+        assert_eq!(cache.get(&key(b"c")).await, None);
+    }
+
+    #[tokio::test]
+    async fn stale_responses_are_refreshed_by_a_single_request() {
+        let cache = ResponseCache::new(10, Duration::from_secs(60));
+        cache
+            .insert(key(b"a"), ExternIO(vec![1]), Duration::ZERO)
+            .await;
+
+        assert_eq!(cache.get(&key(b"a")).await, hit(1, true));
+        assert_eq!(cache.get(&key(b"a")).await, hit(1, false));
+        // Another request takes over when the refresh fails
+        cache.refresh_failed(&key(b"a")).await;
+        assert_eq!(cache.get(&key(b"a")).await, hit(1, true));
+
+        cache
+            .insert(key(b"a"), ExternIO(vec![2]), Duration::from_secs(60))
+            .await;
+        assert_eq!(cache.get(&key(b"a")).await, hit(2, false));
+    }
+
+    #[tokio::test]
+    async fn full_cache_makes_room_by_removing_expired_responses() {
+        let cache = ResponseCache::new(2, Duration::ZERO);
+        cache
+            .insert(key(b"a"), ExternIO(vec![1]), Duration::ZERO)
+            .await;
+        cache
+            .insert(key(b"b"), ExternIO(vec![2]), Duration::from_secs(60))
+            .await;
+
+        cache
+            .insert(key(b"c"), ExternIO(vec![3]), Duration::from_secs(60))
+            .await;
+        assert_eq!(cache.get(&key(b"c")).await, hit(3, false));
+        // No room is left, so the response is not cached
+        cache
+            .insert(key(b"d"), ExternIO(vec![4]), Duration::from_secs(60))
+            .await;
+        assert_eq!(cache.get(&key(b"d")).await, None);
+        assert_eq!(cache.get(&key(b"b")).await, hit(2, false));
+    }
+
+    #[tokio::test]
+    async fn responses_are_purged_by_function_and_payload_hash() {
+        let cache = ResponseCache::new(10, Duration::ZERO);
+        for payload in [b"a", b"b"] {
+            cache
+                .insert(key(payload), ExternIO(vec![1]), Duration::from_secs(60))
+                .await;
+        }
+        let other_fn = CacheKey {
+            fn_name: "get".into(),
+            ..key(b"a")
+        };
+        cache
+            .insert(other_fn.clone(), ExternIO(vec![2]), Duration::from_secs(60))
+            .await;
+
+        let filter = PurgeFilter {
+            app_id: "app".to_string(),
+            zome_name: Some("posts".to_string()),
+            fn_name: Some("list".to_string()),
+            payload_hash: Some(key(b"a").payload_hash_hex()),
+        };
+        assert_eq!(cache.purge(&filter).await.unwrap(), 1);
+        assert_eq!(cache.get(&key(b"a")).await, None);
+        assert_eq!(cache.get(&key(b"b")).await, hit(1, false));
+
+        let filter = PurgeFilter {
+            app_id: "app".to_string(),
+            zome_name: None,
+            fn_name: None,
+            payload_hash: None,
+        };
+        assert_eq!(cache.purge(&filter).await.unwrap(), 2);
+        assert_eq!(cache.get(&other_fn).await, None);
+    }
+}
@@ -0,0 +1,289 @@
+//! Responses cached in Redis, shared by the replicas of a gateway.
+//!
+//! Each response is stored with the time at which it expires, and Redis removes it once it is too
+//! stale to be served. A replica claims the refresh of a stale response by setting a refresh key,
+//! so that a single request over all replicas refreshes it.
+//!
+//! Redis failures don't fail requests. They are logged, and the response is then neither found
+//! nor cached.
+
+use super::{CacheHit, CacheKey, PurgeFilter};
+use holochain_client::ExternIO;
+use holochain_types::prelude::blake2b_256;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{Client, RedisResult};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// Time after which a Redis command fails, so that a slow Redis doesn't hold up requests.
+const REDIS_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Prefix of the keys that responses are stored under.
+const RESPONSE_KEY_PREFIX: &[u8] = b"hc_gw:response:";
+
+/// Prefix of the keys that claim the refresh of a stale response.
+const REFRESH_KEY_PREFIX: &[u8] = b"hc_gw:refresh:";
+
+/// Responses cached in Redis.
+#[derive(Clone)]
+pub(super) struct RedisStore {
+    connection: ConnectionManager,
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore").finish_non_exhaustive()
+    }
+}
+
+impl RedisStore {
+    /// Create a store for the Redis server at the given URL, which is connected to on first use.
+    pub(super) fn new(url: Url) -> RedisResult<Self> {
+        let connection = ConnectionManager::new_lazy_with_config(
+            Client::open(url)?,
+            ConnectionManagerConfig::new()
+                .set_connection_timeout(Some(REDIS_TIMEOUT))
+                .set_response_timeout(Some(REDIS_TIMEOUT))
+                .set_number_of_retries(1),
+        )?;
+
+        Ok(Self { connection })
+    }
+
+    pub(super) async fn get(&self, key: &CacheKey, max_stale: Duration) -> Option<CacheHit> {
+        match self.try_get(key, max_stale).await {
+            Ok(hit) => hit,
+            Err(e) => {
+                tracing::warn!(?e, "Failed to get a cached response from Redis");
+                None
+            }
+        }
+    }
+
+    async fn try_get(&self, key: &CacheKey, max_stale: Duration) -> RedisResult<Option<CacheHit>> {
+        let mut connection = self.connection.clone();
+        let Some(value) = redis::cmd("GET")
+            .arg(redis_key(RESPONSE_KEY_PREFIX, key))
+            .query_async::<Option<Vec<u8>>>(&mut connection)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let Some((expires, response)) = value.split_first_chunk::<8>() else {
+            return Ok(None);
+        };
+        let expires = u64::from_be_bytes(*expires);
+        let response = ExternIO(response.to_vec());
+
+        let now = unix_millis();
+        if expires > now {
+            return Ok(Some(CacheHit {
+                response,
+                refresh: false,
+            }));
+        }
+        if expires + max_stale.as_millis() as u64 <= now {
+            return Ok(None);
+        }
+
+        // Claim the refresh, unless a request to this or another replica has claimed it already.
+        // The claim lapses when the response is too stale to be served anyway.
+        let claimed = redis::cmd("SET")
+            .arg(redis_key(REFRESH_KEY_PREFIX, key))
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg(max_stale.as_millis() as u64)
+            .query_async::<Option<String>>(&mut connection)
+            .await?;
+        Ok(Some(CacheHit {
+            response,
+            refresh: claimed.is_some(),
+        }))
+    }
+
+    pub(super) async fn insert(
+        &self,
+        key: &CacheKey,
+        response: ExternIO,
+        ttl: Duration,
+        max_stale: Duration,
+    ) {
+        let mut value = (unix_millis() + ttl.as_millis() as u64)
+            .to_be_bytes()
+            .to_vec();
+        value.extend(response.0);
+
+        let mut connection = self.connection.clone();
+        if let Err(e) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(redis_key(RESPONSE_KEY_PREFIX, key))
+            .arg(value)
+            .arg("PX")
+            .arg((ttl + max_stale).as_millis() as u64)
+            .ignore()
+            .cmd("DEL")
+            .arg(redis_key(REFRESH_KEY_PREFIX, key))
+            .ignore()
+            .query_async::<()>(&mut connection)
+            .await
+        {
+            tracing::warn!(?e, "Failed to cache a response in Redis");
+        }
+    }
+
+    pub(super) async fn refresh_failed(&self, key: &CacheKey) {
+        let mut connection = self.connection.clone();
+        if let Err(e) = redis::cmd("DEL")
+            .arg(redis_key(REFRESH_KEY_PREFIX, key))
+            .query_async::<()>(&mut connection)
+            .await
+        {
+            tracing::warn!(
+                ?e,
+                "Failed to release the refresh of a cached response in Redis"
+            );
+        }
+    }
+
+    /// Remove the responses that match the filter, and return how many were removed.
+    ///
+    /// The keys of the responses of the app are scanned, and those that match the filter are
+    /// deleted.
+    pub(super) async fn purge(&self, filter: &PurgeFilter) -> RedisResult<usize> {
+        let mut pattern = RESPONSE_KEY_PREFIX.to_vec();
+        for byte in filter.app_id.bytes() {
+            if matches!(byte, b'*' | b'?' | b'[' | b']' | b'\\') {
+                pattern.push(b'\\');
+            }
+            pattern.push(byte);
+        }
+        pattern.extend_from_slice(b"\0*");
+
+        let mut connection = self.connection.clone();
+        let mut purged = 0;
+        let mut cursor = 0;
+        loop {
+            let (next_cursor, keys) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query_async::<(u64, Vec<Vec<u8>>)>(&mut connection)
+                .await?;
+            let matching = keys
+                .into_iter()
+                .filter(|key| {
+                    parse_response_key(key).is_some_and(
+                        |(installed_app_id, zome_name, fn_name, payload_hash)| {
+                            filter.matches(installed_app_id, zome_name, fn_name, payload_hash)
+                        },
+                    )
+                })
+                .collect::<Vec<_>>();
+            if !matching.is_empty() {
+                purged += redis::cmd("DEL")
+                    .arg(matching)
+                    .query_async::<usize>(&mut connection)
+                    .await?;
+            }
+
+            if next_cursor == 0 {
+                return Ok(purged);
+            }
+            cursor = next_cursor;
+        }
+    }
+}
+
+/// The Redis key for a cache key, starting with the given prefix.
+///
+/// The app, zome and function come first, separated by NUL bytes, so that the responses of an app
+/// or of a function share a key prefix. The capability secret follows, marked by whether it is
+/// present. The hash of the payload comes last.
+///
+/// Capability secrets grant access to the functions of an app, so only the blake2b hash of a
+/// secret is part of the key, never the secret itself.
+fn redis_key(prefix: &[u8], key: &CacheKey) -> Vec<u8> {
+    let mut redis_key = prefix.to_vec();
+    for name in [&key.installed_app_id, &key.zome_name, &key.fn_name] {
+        redis_key.extend_from_slice(name.as_bytes());
+        redis_key.push(0);
+    }
+    redis_key.extend_from_slice(key.dna_hash.get_raw_39());
+    match &key.cap_secret {
+        Some(cap_secret) => {
+            redis_key.push(1);
+            redis_key.extend_from_slice(&blake2b_256(cap_secret.as_ref()));
+        }
+        None => redis_key.push(0),
+    }
+    redis_key.extend_from_slice(&key.payload_hash);
+    redis_key
+}
+
+/// The app id, zome name, function name and payload hash of the key of a response.
+fn parse_response_key(key: &[u8]) -> Option<(&str, &str, &str, &[u8])> {
+    let rest = key.strip_prefix(RESPONSE_KEY_PREFIX)?;
+    let mut names = rest.splitn(4, |byte| *byte == 0);
+    let installed_app_id = std::str::from_utf8(names.next()?).ok()?;
+    let zome_name = std::str::from_utf8(names.next()?).ok()?;
+    let fn_name = std::str::from_utf8(names.next()?).ok()?;
+    // The DNA hash and the hash of the capability secret, if any, come before the payload hash
+    let rest = names.next()?.get(39..)?;
+    let payload_hash = match rest.split_first()? {
+        (0, payload_hash) => payload_hash,
+        (_, rest) => rest.get(32..)?,
+    };
+
+    Some((installed_app_id, zome_name, fn_name, payload_hash))
+}
+
+/// The current time in milliseconds since the Unix epoch, which replicas compare expiry times in.
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use holochain_types::dna::DnaHash;
+    use holochain_types::prelude::CapSecret;
+
+    #[test]
+    fn keys_of_a_function_share_a_prefix_and_are_parsed_back() {
+        let key = CacheKey {
+            installed_app_id: "app".into(),
+            dna_hash: DnaHash::from_raw_32(vec![1; 32]),
+            zome_name: "posts".into(),
+            fn_name: "list".into(),
+            payload_hash: [9; 32],
+            cap_secret: None,
+        };
+        let with_secret = CacheKey {
+            cap_secret: Some(CapSecret::from([7; 64])),
+            ..key.clone()
+        };
+
+        let response_key = redis_key(RESPONSE_KEY_PREFIX, &key);
+        assert!(response_key.starts_with(b"hc_gw:response:app\0posts\0list\0"));
+        assert!(response_key.ends_with(&[9; 32]));
+
+        let secret_key = redis_key(RESPONSE_KEY_PREFIX, &with_secret);
+        assert_ne!(response_key, secret_key);
+        // The secret itself is never stored
+        assert!(!secret_key.windows(64).any(|window| window == [7; 64]));
+
+        for key in [key, with_secret] {
+            assert_eq!(
+                parse_response_key(&redis_key(RESPONSE_KEY_PREFIX, &key)),
+                Some(("app", "posts", "list", &[9; 32][..]))
+            );
+        }
+    }
+}
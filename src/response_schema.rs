@@ -0,0 +1,321 @@
+//! Validation of zome call responses against registered schemas.
+//!
+//! Schemas are written in a subset of JSON Schema, so that operators notice when an upgrade of a
+//! hApp changes the shape of the responses that clients rely on.
+
+use crate::config::{AppId, ConfigParseError, ConfigParseResult};
+use crate::{HcHttpGatewayError, HcHttpGatewayResult};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// What the gateway does with a zome call response that doesn't match its registered schema.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResponseValidation {
+    /// Responses are not validated.
+    #[default]
+    Off,
+    /// Mismatches are logged as warnings, and the response is returned as is.
+    Warn,
+    /// Mismatches are logged as warnings, and the request fails with status 502.
+    Strict,
+}
+
+impl FromStr for ResponseValidation {
+    type Err = ConfigParseError;
+
+    /// Expected format
+    /// - One of `off`, `warn` or `strict`
+    fn from_str(s: &str) -> ConfigParseResult<Self> {
+        match s.trim() {
+            "off" => Ok(ResponseValidation::Off),
+            "warn" => Ok(ResponseValidation::Warn),
+            "strict" => Ok(ResponseValidation::Strict),
+            s => Err(ConfigParseError::Other(format!(
+                "Unknown response validation {s:?}, expected \"off\", \"warn\" or \"strict\""
+            ))),
+        }
+    }
+}
+
+/// The registered response schemas, by app and by function.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ResponseSchemas(HashMap<AppId, HashMap<String, Schema>>);
+
+impl ResponseSchemas {
+    /// Parse the schemas from a JSON document of the form
+    /// `{"<app-id>": {"<zome-name>/<fn-name>": <schema>}}`.
+    pub fn from_json_str(contents: &str) -> ConfigParseResult<Self> {
+        serde_json::from_str(contents)
+            .map_err(|e| ConfigParseError::Other(format!("Invalid response schemas: {e}")))
+    }
+
+    /// The schema registered for a function of an app, if any.
+    pub fn get(&self, app_id: &str, zome_name: &str, fn_name: &str) -> Option<&Schema> {
+        self.0.get(app_id)?.get(&format!("{zome_name}/{fn_name}"))
+    }
+
+    /// Whether the responses of a function are validated.
+    pub fn validates(
+        &self,
+        validation: ResponseValidation,
+        app_id: &str,
+        zome_name: &str,
+        fn_name: &str,
+    ) -> bool {
+        validation != ResponseValidation::Off && self.get(app_id, zome_name, fn_name).is_some()
+    }
+
+    /// Check the response of a function against its registered schema, if there is one.
+    ///
+    /// Mismatches are logged, and are an error with [`ResponseValidation::Strict`].
+    pub fn check(
+        &self,
+        validation: ResponseValidation,
+        app_id: &str,
+        zome_name: &str,
+        fn_name: &str,
+        response: &serde_json::Value,
+    ) -> HcHttpGatewayResult<()> {
+        if validation == ResponseValidation::Off {
+            return Ok(());
+        }
+        let Some(schema) = self.get(app_id, zome_name, fn_name) else {
+            return Ok(());
+        };
+
+        match schema.validate(response) {
+            Ok(()) => Ok(()),
+            Err(mismatch) => {
+                tracing::warn!(
+                    app_id,
+                    zome_name,
+                    fn_name,
+                    %mismatch,
+                    "Zome call response doesn't match its schema"
+                );
+                if validation == ResponseValidation::Strict {
+                    Err(HcHttpGatewayError::ResponseSchemaMismatch(mismatch))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+/// A schema in a subset of JSON Schema.
+///
+/// The supported keywords are `type`, `enum`, `properties`, `required`, `additionalProperties`
+/// and `items`. Other keywords are ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Schema {
+    #[serde(rename = "type")]
+    types: Option<SchemaTypes>,
+    #[serde(rename = "enum")]
+    allowed_values: Option<Vec<serde_json::Value>>,
+    #[serde(default)]
+    properties: HashMap<String, Schema>,
+    #[serde(default)]
+    required: Vec<String>,
+    additional_properties: Option<bool>,
+    items: Option<Box<Schema>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum SchemaTypes {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl SchemaTypes {
+    fn allows(&self, value: &serde_json::Value) -> bool {
+        match self {
+            SchemaTypes::One(schema_type) => type_allows(schema_type, value),
+            SchemaTypes::Many(schema_types) => schema_types
+                .iter()
+                .any(|schema_type| type_allows(schema_type, value)),
+        }
+    }
+}
+
+fn type_allows(schema_type: &str, value: &serde_json::Value) -> bool {
+    match schema_type {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    }
+}
+
+impl Schema {
+    /// Check that the value matches the schema.
+    ///
+    /// The error describes the first mismatch that was found, with the JSON pointer to the
+    /// mismatching value.
+    pub fn validate(&self, value: &serde_json::Value) -> Result<(), String> {
+        self.validate_at(value, "")
+    }
+
+    fn validate_at(&self, value: &serde_json::Value, path: &str) -> Result<(), String> {
+        if let Some(types) = &self.types
+            && !types.allows(value)
+        {
+            return Err(format!("{} has an unexpected type", display_path(path)));
+        }
+        if let Some(allowed_values) = &self.allowed_values
+            && !allowed_values.contains(value)
+        {
+            return Err(format!(
+                "{} is not one of the allowed values",
+                display_path(path)
+            ));
+        }
+
+        if let Some(object) = value.as_object() {
+            for field in &self.required {
+                if !object.contains_key(field) {
+                    return Err(format!(
+                        "{} is missing the required field {field:?}",
+                        display_path(path)
+                    ));
+                }
+            }
+            for (field, field_value) in object {
+                let field_path = format!("{path}/{field}");
+                match self.properties.get(field) {
+                    Some(field_schema) => field_schema.validate_at(field_value, &field_path)?,
+                    None if self.additional_properties == Some(false) => {
+                        return Err(format!("{field_path} is not an allowed field"));
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        if let Some(items) = &self.items
+            && let Some(array) = value.as_array()
+        {
+            for (index, item) in array.iter().enumerate() {
+                items.validate_at(item, &format!("{path}/{index}"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn display_path(path: &str) -> &str {
+    if path.is_empty() {
+        "The response"
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schemas() -> ResponseSchemas {
+        ResponseSchemas::from_json_str(
+            r#"{
+                "app": {
+                    "posts/get_post": {
+                        "type": "object",
+                        "required": ["title", "tags"],
+                        "additionalProperties": false,
+                        "properties": {
+                            "title": { "type": "string" },
+                            "likes": { "type": ["integer", "null"] },
+                            "tags": { "type": "array", "items": { "enum": ["news", "blog"] } }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn matching_response_is_valid() {
+        let schemas = schemas();
+        let schema = schemas.get("app", "posts", "get_post").unwrap();
+
+        schema
+            .validate(&json!({ "title": "Hello", "likes": null, "tags": ["news"] }))
+            .unwrap();
+        assert!(schemas.get("app", "posts", "get_posts").is_none());
+    }
+
+    #[test]
+    fn mismatches_are_reported_with_their_path() {
+        let schemas = schemas();
+        let schema = schemas.get("app", "posts", "get_post").unwrap();
+
+        assert_eq!(
+            schema.validate(&json!([])).unwrap_err(),
+            "The response has an unexpected type"
+        );
+        assert_eq!(
+            schema.validate(&json!({ "title": "Hello" })).unwrap_err(),
+            "The response is missing the required field \"tags\""
+        );
+        assert_eq!(
+            schema
+                .validate(&json!({ "title": 1, "tags": [] }))
+                .unwrap_err(),
+            "/title has an unexpected type"
+        );
+        assert_eq!(
+            schema
+                .validate(&json!({ "title": "Hello", "tags": ["news", "other"] }))
+                .unwrap_err(),
+            "/tags/1 is not one of the allowed values"
+        );
+        assert_eq!(
+            schema
+                .validate(&json!({ "title": "Hello", "tags": [], "author": "me" }))
+                .unwrap_err(),
+            "/author is not an allowed field"
+        );
+    }
+
+    #[test]
+    fn mismatches_fail_only_in_strict_mode() {
+        let schemas = schemas();
+        let response = json!({ "title": "Hello" });
+
+        for validation in [ResponseValidation::Off, ResponseValidation::Warn] {
+            schemas
+                .check(validation, "app", "posts", "get_post", &response)
+                .unwrap();
+        }
+        let result = schemas.check(
+            ResponseValidation::Strict,
+            "app",
+            "posts",
+            "get_post",
+            &response,
+        );
+        assert2::assert!(let Err(HcHttpGatewayError::ResponseSchemaMismatch(_)) = result);
+
+        // Functions without a schema are not validated
+        schemas
+            .check(
+                ResponseValidation::Strict,
+                "app",
+                "posts",
+                "other",
+                &response,
+            )
+            .unwrap();
+    }
+}
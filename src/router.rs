@@ -1,40 +1,137 @@
-use crate::holochain::AppCall;
 use crate::{
-    AdminCall,
-    config::Configuration,
-    routes::{health_check, zome_call},
+    access_log::log_access,
+    audit::audit_management,
+    catch_panic::catch_panic,
+    drain::{DrainHandle, reject_when_draining},
+    error::{format_errors, retry_upstream_unavailable},
+    load_shed::{LatencyShedder, LoadShedder, shed_load, shed_on_latency},
+    metrics::record_usage,
+    request_id::assign_request_id,
+    routes::{
+        app_connections, clear_app_info_cache, clear_app_ports, gateway_status, health_check,
+        limit_payload_size, metrics, purge_response_cache, reconnect_app, require_management_token,
+        usage, zome_call,
+    },
     service::AppState,
+    slow_request::log_slow_requests,
+    trace_context::trace_request,
 };
-use axum::{Router, http::StatusCode, routing::get};
-use std::sync::Arc;
+use axum::{
+    Router,
+    http::StatusCode,
+    middleware::{from_fn, from_fn_with_state},
+    routing::{get, post},
+};
+
+pub fn hc_http_gateway_router(state: AppState, drain: DrainHandle) -> Router {
+    let configuration = state.configuration.clone();
+    let mut zome_call_route = get(zome_call).route_layer(from_fn_with_state(
+        configuration.health_check_interval,
+        retry_upstream_unavailable,
+    ));
+    if let Some(max_in_flight_requests) = configuration.max_in_flight_requests {
+        let load_shedder = LoadShedder::new(
+            max_in_flight_requests,
+            configuration.max_queued_requests,
+            configuration.queue_timeout,
+            configuration.app_priorities.clone(),
+        );
+        zome_call_route = zome_call_route.route_layer(from_fn_with_state(load_shedder, shed_load));
+    }
+    if let Some(latency_shed_threshold) = configuration.latency_shed_threshold {
+        let latency_shedder = LatencyShedder::new(
+            state.metrics.clone(),
+            latency_shed_threshold,
+            configuration.latency_shed_fraction,
+        );
+        zome_call_route =
+            zome_call_route.route_layer(from_fn_with_state(latency_shedder, shed_on_latency));
+    }
+    // Oversized payloads are rejected before they take up a place in the load shedder's queue
+    zome_call_route = zome_call_route.route_layer(from_fn_with_state(
+        configuration.payload_limit_bytes,
+        limit_payload_size,
+    ));
+    // Rejected requests count towards the usage of an app and the recent error rate as well
+    zome_call_route =
+        zome_call_route.route_layer(from_fn_with_state(state.metrics.clone(), record_usage));
 
-pub fn hc_http_gateway_router(
-    configuration: Configuration,
-    admin_call: Arc<dyn AdminCall>,
-    app_call: Arc<dyn AppCall>,
-) -> Router {
-    let state = AppState {
-        configuration,
-        admin_call,
-        app_call,
-        app_info_cache: Default::default(),
-    };
+    let management_token = configuration.management_token.clone();
+    let request_id_header = configuration.request_id_header.clone();
+    let error_format = configuration.error_format;
+    let access_log = configuration.access_log.clone();
+    let audit_log = configuration.audit_log.clone();
+    let slow_request_threshold = configuration.slow_request_threshold;
+    let panic_metrics = state.metrics.clone();
 
-    Router::new()
+    let mut router = Router::new();
+    // Management endpoints are only served when a token is configured to protect them
+    if let Some(management_token) = management_token {
+        let mut management_router = Router::new()
+            .route("/app-connections", get(app_connections))
+            .route("/status", get(gateway_status))
+            .route(
+                "/app-connections/{installed_app_id}/reconnect",
+                post(reconnect_app),
+            )
+            .route("/app-info-cache/clear", post(clear_app_info_cache))
+            .route("/app-ports/clear", post(clear_app_ports))
+            .route("/response-cache/purge", post(purge_response_cache))
+            .route("/metrics", get(metrics))
+            .route("/usage", get(usage))
+            .route_layer(from_fn_with_state(
+                management_token,
+                require_management_token,
+            ));
+        // Requests rejected for lacking the token are audited as well
+        if let Some(audit_log) = audit_log {
+            management_router =
+                management_router.route_layer(from_fn_with_state(audit_log, audit_management));
+        }
+        router = router.nest("/management", management_router);
+    }
+
+    let mut router = router
         .route("/health", get(health_check))
         .route(
             "/{dna_hash}/{coordinator_identifier}/{zome_name}/{fn_name}",
-            get(zome_call),
+            zome_call_route,
         )
         .method_not_allowed_fallback(|| async { (StatusCode::METHOD_NOT_ALLOWED, ()) })
         .with_state(state)
+        .layer(from_fn_with_state(panic_metrics, catch_panic))
+        .layer(from_fn_with_state(drain, reject_when_draining));
+    if let Some(access_log) = access_log {
+        router = router.layer(from_fn_with_state(access_log, log_access));
+    }
+    if let Some(slow_request_threshold) = slow_request_threshold {
+        router = router.layer(from_fn_with_state(
+            slow_request_threshold,
+            log_slow_requests,
+        ));
+    }
+
+    router
+        .layer(from_fn_with_state(error_format, format_errors))
+        .layer(from_fn_with_state(request_id_header, assign_request_id))
+        .layer(from_fn(trace_request))
 }
 
 #[cfg(test)]
 mod tests {
+    use super::hc_http_gateway_router;
+    use crate::Conductors;
+    use crate::config::{AllowedFns, Configuration};
+    use crate::drain::DrainHandle;
+    use crate::metrics::Metrics;
+    use crate::service::AppState;
     use crate::test::router::TestRouter;
+    use crate::transform::Transforms;
     use axum::{body::Body, http::Request};
     use reqwest::StatusCode;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
     use tower::ServiceExt;
 
     #[tokio::test]
@@ -77,4 +174,74 @@ mod tests {
             .unwrap();
         assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
     }
+
+    #[tokio::test]
+    async fn requests_are_rejected_when_draining() {
+        let config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "coordinator",
+            HashMap::from([("coordinator".to_string(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap();
+        let drain = DrainHandle::default();
+        let router = hc_http_gateway_router(
+            AppState::new(
+                Arc::new(config),
+                Conductors::default(),
+                Arc::new(Metrics::new(10)),
+                Transforms::default(),
+            ),
+            drain.clone(),
+        );
+        let request = || {
+            Request::builder()
+                .uri("/health")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        drain.drain();
+        let response = router.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["connection"], "close");
+    }
+
+    #[tokio::test]
+    async fn zome_calls_are_shed_when_overloaded() {
+        let mut config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "coordinator",
+            HashMap::from([("coordinator".to_string(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap();
+        config.max_in_flight_requests = Some(0);
+        config.max_queued_requests = 0;
+        let router = TestRouter::new_with_config(config);
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/uhC0kAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQF-z86-/coordinator/zome_name/fn_name")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "1");
+
+        // Health checks are not shed
+        let (status_code, _) = router.request("/health").await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
 }
@@ -1,5 +1,12 @@
 mod health_check;
+mod management;
 mod zome_call;
 
 pub use health_check::health_check;
+pub(crate) use management::presents_management_token;
+pub use management::{
+    app_connections, clear_app_info_cache, clear_app_ports, gateway_status, metrics,
+    purge_response_cache, reconnect_app, require_management_token, usage,
+};
+pub(crate) use zome_call::limit_payload_size;
 pub use zome_call::zome_call;
@@ -1,12 +1,160 @@
-#[tracing::instrument]
-pub async fn health_check() -> &'static str {
-    "Ok"
+use crate::HEALTH_WINDOW;
+use crate::service::AppState;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+
+/// Query parameters of the health check.
+#[derive(Debug, Deserialize)]
+pub struct HealthCheckQuery {
+    /// Any value, including an empty one, requests a deep health check.
+    deep: Option<String>,
+}
+
+/// Number of recent zome call requests below which the error rate is not taken into account.
+const MIN_REQUESTS_FOR_ERROR_RATE: u64 = 20;
+
+/// Overall health of the gateway, from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Every conductor can be reached and requests succeed.
+    Ok,
+    /// Up, but requests fail more often than they should.
+    Degraded,
+    /// A conductor can't be reached, or most requests fail.
+    Unhealthy,
+}
+
+/// Health of the gateway and status of the upstream conductors, returned by a deep health check.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeepHealthResponse {
+    pub status: HealthStatus,
+    /// Why the status is not `ok`.
+    pub reasons: Vec<String>,
+    pub conductors: Vec<ConductorStatus>,
+}
+
+/// Health of a single conductor, as last observed by the health monitor.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ConductorStatus {
+    pub name: String,
+    pub healthy: bool,
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn health_check(
+    State(state): State<AppState>,
+    Query(query): Query<HealthCheckQuery>,
+) -> Response {
+    if query.deep.is_none() {
+        return "Ok".into_response();
+    }
+
+    let conductors = state
+        .conductors
+        .iter()
+        .map(|conductor| ConductorStatus {
+            name: conductor.name.clone(),
+            healthy: conductor.health.is_healthy(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut status = HealthStatus::Ok;
+    let mut reasons = Vec::new();
+    // The gateway is only fully functional when every conductor can be reached.
+    for conductor in conductors.iter().filter(|conductor| !conductor.healthy) {
+        status = HealthStatus::Unhealthy;
+        reasons.push(format!("Conductor {} is unreachable", conductor.name));
+    }
+
+    let window_secs = HEALTH_WINDOW.as_secs();
+    let (requests, server_errors) = state.metrics.recent_requests_and_server_errors();
+    if requests >= MIN_REQUESTS_FOR_ERROR_RATE {
+        let error_rate = server_errors as f64 / requests as f64;
+        let error_status = if error_rate >= state.configuration.unhealthy_error_rate {
+            HealthStatus::Unhealthy
+        } else if error_rate >= state.configuration.degraded_error_rate {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Ok
+        };
+        if error_status != HealthStatus::Ok {
+            status = status.max(error_status);
+            reasons.push(format!(
+                "{server_errors} of {requests} zome call requests failed with a server error in the last {window_secs}s"
+            ));
+        }
+    }
+
+    let failed_connects = state.metrics.recent_failed_connects(
+        state
+            .conductors
+            .iter()
+            .map(|conductor| {
+                conductor.admin_call.connection_counters().failed_connects
+                    + conductor.app_call.connection_counters().failed_connects
+            })
+            .sum(),
+    );
+    if failed_connects >= state.configuration.degraded_failed_connects {
+        status = status.max(HealthStatus::Degraded);
+        reasons.push(format!(
+            "{failed_connects} connects to conductors failed in the last {window_secs}s"
+        ));
+    }
+
+    // A degraded gateway still serves requests, so it is only taken out of rotation when unhealthy.
+    let status_code = if status == HealthStatus::Unhealthy {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+
+    (
+        status_code,
+        Json(DeepHealthResponse {
+            status,
+            reasons,
+            conductors,
+        }),
+    )
+        .into_response()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{DeepHealthResponse, HealthStatus};
+    use crate::test::data::new_test_app_info;
     use crate::test::router::TestRouter;
+    use crate::{
+        AllowedFns, ConductorHandle, Conductors, Configuration, ConnectionCounters,
+        HcHttpGatewayError, MockAdminCall, MockAppCall,
+    };
+    use holochain_types::prelude::DnaHash;
     use reqwest::StatusCode;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn admin_call() -> MockAdminCall {
+        let mut admin_call = MockAdminCall::new();
+        admin_call
+            .expect_connection_counters()
+            .returning(ConnectionCounters::default);
+        admin_call
+    }
+
+    fn app_call() -> MockAppCall {
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_connection_counters()
+            .returning(ConnectionCounters::default);
+        app_call
+    }
 
     #[tokio::test]
     async fn get_request_health_check_succeeds() {
@@ -15,4 +163,113 @@ mod tests {
         assert_eq!(status_code, StatusCode::OK);
         assert_eq!(body, "Ok");
     }
+
+    #[tokio::test]
+    async fn deep_health_check_reports_conductor_status() {
+        let config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "",
+            HashMap::new(),
+            "",
+            "",
+        )
+        .unwrap();
+        let mut conductors = Conductors::default();
+        for name in ["a", "b"] {
+            conductors.insert(ConductorHandle::new(
+                name,
+                "".parse().unwrap(),
+                Arc::new(admin_call()),
+                Arc::new(app_call()),
+            ));
+        }
+        // Clones of the conductors share their health with the router.
+        let router = TestRouter::new_with_config_and_conductors(config, conductors.clone());
+
+        let (status_code, body) = router.request("/health?deep").await;
+        assert_eq!(status_code, StatusCode::OK);
+        let response: DeepHealthResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.status, HealthStatus::Ok);
+        assert!(response.reasons.is_empty());
+        assert_eq!(response.conductors.len(), 2);
+        assert!(response.conductors.iter().all(|c| c.healthy));
+
+        conductors.get("b").unwrap().record_health(false);
+
+        let (status_code, body) = router.request("/health?deep=true").await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        let response: DeepHealthResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.status, HealthStatus::Unhealthy);
+        assert_eq!(response.reasons, vec!["Conductor b is unreachable"]);
+        assert_eq!(response.conductors[0].name, "a");
+        assert!(response.conductors[0].healthy);
+        assert_eq!(response.conductors[1].name, "b");
+        assert!(!response.conductors[1].healthy);
+    }
+
+    #[tokio::test]
+    async fn deep_health_check_reports_degradation() {
+        let mut config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "coordinator",
+            HashMap::from([("coordinator".to_string(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap();
+        config.unhealthy_error_rate = 0.9;
+        let mut admin_call = MockAdminCall::new();
+        admin_call.expect_list_apps().returning(|_| {
+            Box::pin(async {
+                let app_info = new_test_app_info("coordinator", DnaHash::from_raw_32(vec![1; 32]));
+                Ok(vec![app_info])
+            })
+        });
+        let failed_connects = Arc::new(AtomicU64::new(0));
+        admin_call.expect_connection_counters().returning({
+            let failed_connects = failed_connects.clone();
+            move || ConnectionCounters {
+                failed_connects: failed_connects.load(Ordering::Relaxed),
+                ..Default::default()
+            }
+        });
+        let mut app_call = app_call();
+        app_call
+            .expect_handle_zome_call()
+            .returning(|_| Box::pin(async { Err(HcHttpGatewayError::ZomeCallTimeout) }));
+        let router = TestRouter::new_with_config_and_interfaces(
+            config,
+            Arc::new(admin_call),
+            Arc::new(app_call),
+        );
+
+        let (status_code, body) = router.request("/health?deep").await;
+        assert_eq!(status_code, StatusCode::OK);
+        let response: DeepHealthResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.status, HealthStatus::Ok);
+
+        // Failed connects since the first check make the gateway degraded
+        failed_connects.store(10, Ordering::Relaxed);
+        let (status_code, body) = router.request("/health?deep").await;
+        assert_eq!(status_code, StatusCode::OK);
+        let response: DeepHealthResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.status, HealthStatus::Degraded);
+        assert_eq!(response.reasons.len(), 1);
+
+        // As do requests failing with server errors, up to the unhealthy error rate
+        for _ in 0..20 {
+            let (status_code, _) = router
+                .request("/uhC0kAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQF-z86-/coordinator/zome_name/fn_name")
+                .await;
+            assert_eq!(status_code, StatusCode::GATEWAY_TIMEOUT);
+        }
+        let (status_code, body) = router.request("/health?deep").await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        let response: DeepHealthResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.status, HealthStatus::Unhealthy);
+        assert_eq!(response.reasons.len(), 2);
+        assert!(response.reasons[0].starts_with("20 of 20 zome call requests failed"));
+    }
 }
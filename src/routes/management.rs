@@ -0,0 +1,426 @@
+use crate::app_selection::AppSelectionError;
+use crate::config::ManagementToken;
+use crate::metrics::METRICS_MEDIA_TYPE;
+use crate::response_cache::PurgeFilter;
+use crate::service::AppState;
+use crate::{
+    AppConnectionStats, GatewayStatus, HcHttpGatewayError, HcHttpGatewayResult, UsageExportFormat,
+    UsageReport,
+};
+use axum::Json;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use holochain_types::app::InstalledAppId;
+use serde::{Deserialize, Serialize};
+
+/// The open app connections of every conductor.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AppConnectionsResponse {
+    pub conductors: Vec<ConductorAppConnections>,
+}
+
+/// The open app connections of a single conductor.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ConductorAppConnections {
+    pub name: String,
+    pub app_connections: Vec<AppConnectionStats>,
+}
+
+/// Reject management requests that don't carry the management token as a bearer token.
+pub async fn require_management_token(
+    State(token): State<ManagementToken>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !presents_management_token(request.headers(), &token) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Whether the headers of a request carry the management token as a bearer token.
+pub(crate) fn presents_management_token(headers: &HeaderMap, token: &ManagementToken) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| token.matches(presented))
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn app_connections(State(state): State<AppState>) -> Json<AppConnectionsResponse> {
+    let conductors = state
+        .conductors
+        .iter()
+        .map(|conductor| ConductorAppConnections {
+            name: conductor.name.clone(),
+            app_connections: conductor.app_call.app_connection_stats(),
+        })
+        .collect();
+
+    Json(AppConnectionsResponse { conductors })
+}
+
+/// The status of the gateway and of its connections to Holochain.
+#[tracing::instrument(skip(state))]
+pub async fn gateway_status(State(state): State<AppState>) -> Json<GatewayStatus> {
+    Json(state.status().await)
+}
+
+/// The metrics of the gateway, in the Prometheus text exposition format.
+#[tracing::instrument(skip(state))]
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    (
+        [(header::CONTENT_TYPE, METRICS_MEDIA_TYPE)],
+        state.metrics.render(&state.conductors),
+    )
+        .into_response()
+}
+
+/// Query parameters of the usage endpoint.
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    /// `csv` or `json`, defaults to `json`.
+    pub format: Option<String>,
+}
+
+/// The usage of every app since the gateway started, as JSON or CSV.
+#[tracing::instrument(skip(state))]
+pub async fn usage(
+    State(state): State<AppState>,
+    Query(query): Query<UsageQuery>,
+) -> HcHttpGatewayResult<Response> {
+    let format = match query.format.as_deref() {
+        None => UsageExportFormat::Json,
+        Some(format) => format
+            .parse::<UsageExportFormat>()
+            .map_err(|e| HcHttpGatewayError::RequestMalformed(e.to_string()))?,
+    };
+
+    let report: UsageReport = state.metrics.usage_report();
+    Ok(match format {
+        UsageExportFormat::Json => Json(report).into_response(),
+        UsageExportFormat::Csv => (
+            [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+            report.to_csv(),
+        )
+            .into_response(),
+    })
+}
+
+/// Forget the cached app info of every conductor.
+#[tracing::instrument(skip(state))]
+pub async fn clear_app_info_cache(State(state): State<AppState>) -> StatusCode {
+    for conductor in state.conductors.iter() {
+        conductor.clear_app_info_cache().await;
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+/// Forget the cached app interface ports of every conductor.
+#[tracing::instrument(skip(state))]
+pub async fn clear_app_ports(State(state): State<AppState>) -> StatusCode {
+    for conductor in state.conductors.iter() {
+        conductor.app_call.clear_cached_app_ports();
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+/// The number of cached responses that a purge removed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PurgeResponse {
+    pub purged: usize,
+}
+
+/// Remove the cached zome call responses that match the filter given as query parameters.
+#[tracing::instrument(skip(state))]
+pub async fn purge_response_cache(
+    State(state): State<AppState>,
+    Query(filter): Query<PurgeFilter>,
+) -> HcHttpGatewayResult<Json<PurgeResponse>> {
+    let purged = state.response_cache.purge(&filter).await?;
+    tracing::info!(purged, "Purged cached responses");
+
+    Ok(Json(PurgeResponse { purged }))
+}
+
+/// Close and reopen the app connection of the given app on every conductor that the app is known
+/// to be installed on.
+#[tracing::instrument(skip(state))]
+pub async fn reconnect_app(
+    State(state): State<AppState>,
+    Path(installed_app_id): Path<InstalledAppId>,
+) -> HcHttpGatewayResult<StatusCode> {
+    let mut found = false;
+    for conductor in state.conductors.iter() {
+        let connected = conductor
+            .app_call
+            .app_connection_stats()
+            .iter()
+            .any(|stats| stats.installed_app_id == installed_app_id);
+        if !connected && !conductor.has_cached_app(&installed_app_id).await {
+            continue;
+        }
+
+        found = true;
+        conductor
+            .app_call
+            .reconnect_app(installed_app_id.clone())
+            .await?;
+    }
+
+    if !found {
+        return Err(AppSelectionError::NotInstalled.into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AppConnectionsResponse;
+    use crate::config::ManagementToken;
+    use crate::test::router::TestRouter;
+    use crate::{
+        AllowedFns, AppConnectionStats, ConductorHandle, Conductors, Configuration, GatewayStatus,
+        MockAdminCall, MockAppCall, UsageReport,
+    };
+    use axum::{body::Body, http::Request};
+    use reqwest::StatusCode;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn management_config() -> Configuration {
+        let mut config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "",
+            HashMap::new(),
+            "",
+            "",
+        )
+        .unwrap();
+        config.management_token = Some(ManagementToken::new("secret"));
+        config
+    }
+
+    async fn post(router: &TestRouter, uri: &str) -> StatusCode {
+        (*router)
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .header("authorization", "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn app_connections_are_listed_per_conductor() {
+        let stats = AppConnectionStats {
+            installed_app_id: "app".to_string(),
+            age_ms: 2000,
+            idle_ms: 100,
+            request_count: 3,
+            in_flight: 0,
+            reconnects: 1,
+            overflow: false,
+        };
+        let mut app_call = MockAppCall::new();
+        app_call.expect_app_connection_stats().returning({
+            let stats = stats.clone();
+            move || vec![stats.clone()]
+        });
+        let mut conductors = Conductors::default();
+        conductors.insert(ConductorHandle::new(
+            "a",
+            "".parse().unwrap(),
+            Arc::new(MockAdminCall::new()),
+            Arc::new(app_call),
+        ));
+        let router = TestRouter::new_with_config_and_conductors(management_config(), conductors);
+
+        let (status_code, body) = router
+            .request_with_headers(
+                "/management/app-connections",
+                &[("authorization", "Bearer secret")],
+            )
+            .await;
+        assert_eq!(status_code, StatusCode::OK);
+        let response: AppConnectionsResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.conductors.len(), 1);
+        assert_eq!(response.conductors[0].name, "a");
+        assert_eq!(response.conductors[0].app_connections, vec![stats]);
+    }
+
+    #[tokio::test]
+    async fn gateway_status_is_served() {
+        let router =
+            TestRouter::new_with_config_and_conductors(management_config(), Conductors::default());
+
+        let (status_code, body) = router
+            .request_with_headers("/management/status", &[("authorization", "Bearer secret")])
+            .await;
+        assert_eq!(status_code, StatusCode::OK);
+        let status: GatewayStatus = serde_json::from_str(&body).unwrap();
+        assert_eq!(status.response_cache_entries, Some(0));
+        assert!(status.conductors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn management_requests_require_the_token() {
+        let router =
+            TestRouter::new_with_config_and_conductors(management_config(), Conductors::default());
+
+        let (status_code, _) = router.request("/management/app-connections").await;
+        assert_eq!(status_code, StatusCode::UNAUTHORIZED);
+
+        let (status_code, _) = router
+            .request_with_headers(
+                "/management/app-connections",
+                &[("authorization", "Bearer wrong")],
+            )
+            .await;
+        assert_eq!(status_code, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn management_endpoints_are_disabled_without_a_token() {
+        let router = TestRouter::new();
+
+        let (status_code, _) = router
+            .request_with_headers(
+                "/management/app-connections",
+                &[("authorization", "Bearer secret")],
+            )
+            .await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn connected_app_is_reconnected() {
+        let mut app_call = MockAppCall::new();
+        app_call.expect_app_connection_stats().returning(|| {
+            vec![AppConnectionStats {
+                installed_app_id: "app".to_string(),
+                age_ms: 0,
+                idle_ms: 0,
+                request_count: 0,
+                in_flight: 0,
+                reconnects: 0,
+                overflow: false,
+            }]
+        });
+        app_call
+            .expect_reconnect_app()
+            .withf(|installed_app_id| installed_app_id == "app")
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(()) }));
+        let config = management_config();
+        let conductors =
+            Conductors::single(&config, Arc::new(MockAdminCall::new()), Arc::new(app_call));
+        let router = TestRouter::new_with_config_and_conductors(config, conductors);
+
+        let status_code = post(&router, "/management/app-connections/app/reconnect").await;
+        assert_eq!(status_code, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn reconnecting_an_unknown_app_is_not_found() {
+        let mut app_call = MockAppCall::new();
+        app_call.expect_app_connection_stats().returning(Vec::new);
+        app_call.expect_reconnect_app().never();
+        let config = management_config();
+        let conductors =
+            Conductors::single(&config, Arc::new(MockAdminCall::new()), Arc::new(app_call));
+        let router = TestRouter::new_with_config_and_conductors(config, conductors);
+
+        let status_code = post(&router, "/management/app-connections/other/reconnect").await;
+        assert_eq!(status_code, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn usage_is_reported_per_app() {
+        let mut config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "coordinator",
+            HashMap::from([("coordinator".to_string(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap();
+        config.management_token = Some(ManagementToken::new("secret"));
+        let router = TestRouter::new_with_config(config);
+
+        let (status_code, _) = router
+            .request("/uhC0kAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQF-z86-/coordinator/zome_name/fn_name")
+            .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, body) = router
+            .request_with_headers("/management/usage", &[("authorization", "Bearer secret")])
+            .await;
+        assert_eq!(status_code, StatusCode::OK);
+        let report: UsageReport = serde_json::from_str(&body).unwrap();
+        assert_eq!(report.apps.len(), 1);
+        assert_eq!(report.apps[0].app_id, "coordinator");
+        assert_eq!(report.apps[0].calls, 1);
+
+        let (status_code, body) = router
+            .request_with_headers(
+                "/management/usage?format=csv",
+                &[("authorization", "Bearer secret")],
+            )
+            .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(
+            body.lines().next(),
+            Some("from,to,app_id,calls,payload_bytes,response_bytes")
+        );
+        assert_eq!(body.lines().count(), 2);
+
+        let (status_code, _) = router
+            .request_with_headers(
+                "/management/usage?format=xml",
+                &[("authorization", "Bearer secret")],
+            )
+            .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn caches_are_cleared() {
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_clear_cached_app_ports()
+            .times(1)
+            .return_const(());
+        let config = management_config();
+        let conductors =
+            Conductors::single(&config, Arc::new(MockAdminCall::new()), Arc::new(app_call));
+        let router = TestRouter::new_with_config_and_conductors(config, conductors);
+
+        let status_code = post(&router, "/management/app-ports/clear").await;
+        assert_eq!(status_code, StatusCode::NO_CONTENT);
+
+        let status_code = post(&router, "/management/app-info-cache/clear").await;
+        assert_eq!(status_code, StatusCode::NO_CONTENT);
+    }
+}
@@ -1,22 +1,59 @@
-use crate::app_selection::try_get_valid_app;
+use crate::app_selection::{AppSelectionError, find_cell_id, try_get_valid_app_from_conductors};
 use crate::{
-    HcHttpGatewayError, HcHttpGatewayResult,
+    ConductorHandle, HcHttpGatewayError, HcHttpGatewayResult, TransformContext, UpstreamConnection,
+    ZomeCallRequest,
+    access_log::record_app_id,
+    config::ManagementToken,
+    metrics::ZomeCallTimer,
+    request_id::current_request_id,
+    response_cache::{CacheHit, CacheKey, PurgeFilter, payload_hash},
+    routes::presents_management_token,
     service::AppState,
-    transcode::{base64_json_to_hsb, hsb_to_json},
+    slow_request::{Phase, record_timing},
+    transcode::{
+        TranscodeOptions, base64_json_to_hsb, base64_msgpack_to_hsb, hsb_to_json, hsb_to_json_body,
+        hsb_to_json_string,
+    },
 };
-use axum::extract::{FromRequestParts, Path, Query, State};
-use holochain_client::CellInfo;
+use axum::extract::{FromRequestParts, Path, Query, Request, State};
+use axum::http::{HeaderMap, HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::{Engine, prelude::BASE64_URL_SAFE};
+use holochain_client::{AppInfo, ConductorApiError, ExternIO};
 use holochain_types::dna::DnaHash;
+use holochain_types::prelude::CapSecret;
 use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Instant;
 
 const MAX_IDENTIFIER_CHARS: u8 = 100;
 
-#[derive(Debug, Deserialize)]
+/// Header through which a client can supply the capability secret to use for a zome call, as URL
+/// safe base64.
+pub const CAP_SECRET_HEADER: &str = "x-hc-cap-secret";
+
+/// Header in which the responses of cached functions carry the hash of their payload, by which
+/// the cached response can be purged.
+pub const PAYLOAD_HASH_HEADER: &str = "x-hc-payload-hash";
+
+/// Media type of msgpack, which a client accepts to have the payload and the response of a zome
+/// call passed through without transcoding.
+pub const MSGPACK_MEDIA_TYPE: &str = "application/msgpack";
+
+/// Size of a msgpack encoded zome call response above which the JSON response is streamed.
+const STREAMING_THRESHOLD_BYTES: usize = 1024 * 1024;
+
+/// Parameters of a zome call from the request path.
+///
+/// The names are shared with the zome calls and the response cache key rather than copied.
+#[derive(Debug)]
 pub struct ZomeCallParams {
     dna_hash: DnaHash,
     coordinator_identifier: String,
-    zome_name: String,
-    fn_name: String,
+    zome_name: Arc<str>,
+    fn_name: Arc<str>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -69,8 +106,8 @@ where
         Ok(ZomeCallParams {
             dna_hash,
             coordinator_identifier,
-            zome_name,
-            fn_name,
+            zome_name: zome_name.into(),
+            fn_name: fn_name.into(),
         })
     }
 }
@@ -80,85 +117,483 @@ pub struct PayloadQuery {
     pub payload: Option<String>,
 }
 
-#[tracing::instrument(skip(state))]
+/// Middleware that rejects zome calls whose payload exceeds the configured limit.
+///
+/// The limit applies to the decoded payload, that is the JSON text or msgpack bytes that the
+/// `payload` query parameter encodes, regardless of the base64 alphabet and padding.
+pub(crate) async fn limit_payload_size(
+    State(payload_limit_bytes): State<u32>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // A malformed query is rejected by the handler
+    if let Ok(Query(query)) = Query::<PayloadQuery>::try_from_uri(request.uri())
+        && let Some(payload) = &query.payload
+        && base64_decoded_len(payload) > payload_limit_bytes as usize
+    {
+        return HcHttpGatewayError::RequestMalformed(format!(
+            "Payload exceeds {payload_limit_bytes} bytes"
+        ))
+        .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// The number of bytes that a base64 string decodes to, with or without padding.
+///
+/// For a string that isn't valid base64, this is the number of bytes that a valid string of the
+/// same length would decode to.
+fn base64_decoded_len(encoded: &str) -> usize {
+    let len = encoded.trim_end_matches('=').len();
+    len / 4 * 3 + len % 4 * 3 / 4
+}
+
+/// Parse the capability secret supplied by the client, if there is one.
+fn parse_cap_secret(headers: &HeaderMap) -> HcHttpGatewayResult<Option<CapSecret>> {
+    let Some(value) = headers.get(CAP_SECRET_HEADER) else {
+        return Ok(None);
+    };
+
+    let invalid = || HcHttpGatewayError::RequestMalformed("Invalid capability secret".to_string());
+    let bytes = value
+        .to_str()
+        .ok()
+        .and_then(|value| BASE64_URL_SAFE.decode(value).ok())
+        .ok_or_else(invalid)?;
+    let bytes: [u8; 64] = bytes.try_into().map_err(|_| invalid())?;
+
+    Ok(Some(CapSecret::from(bytes)))
+}
+
+/// Whether the client asked for the response cache to be bypassed with `Cache-Control: no-cache`.
+///
+/// Only requests that carry the management token may bypass the cache, so that public clients
+/// can't make the gateway call the conductor for every request of a cached function.
+fn bypasses_cache(headers: &HeaderMap, management_token: Option<&ManagementToken>) -> bool {
+    let no_cache = headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"));
+
+    no_cache && management_token.is_some_and(|token| presents_management_token(headers, token))
+}
+
+/// Whether the client asked for the payload and the response to be passed through as msgpack.
+fn accepts_msgpack(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| {
+            media_type
+                .split(';')
+                .next()
+                .is_some_and(|media_type| media_type.trim() == MSGPACK_MEDIA_TYPE)
+        })
+}
+
+/// Logs when a zome call is cancelled because its request was dropped before the call completed.
+///
+/// Axum drops the request when the HTTP client disconnects or gives up waiting for the response,
+/// which drops the zome call future and with it the pending request on the app websocket.
+struct CancelledCallLog {
+    completed: bool,
+}
+
+impl Drop for CancelledCallLog {
+    fn drop(&mut self) {
+        if !self.completed {
+            tracing::info!("Client disconnected, cancelling the zome call");
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        app_id = tracing::field::Empty,
+        dna_hash = %params.dna_hash,
+        zome_name = %params.zome_name,
+        fn_name = %params.fn_name,
+        request_id = current_request_id().as_deref(),
+    )
+)]
 pub async fn zome_call(
     params: ZomeCallParams,
     State(state): State<AppState>,
     Query(query): Query<PayloadQuery>,
-) -> HcHttpGatewayResult<String> {
+    headers: HeaderMap,
+) -> HcHttpGatewayResult<Response> {
+    let started = Instant::now();
     let ZomeCallParams {
         dna_hash,
         coordinator_identifier,
         zome_name,
         fn_name,
     } = params;
-    // Check payload byte length does not exceed configured maximum.
-    if let Some(payload) = &query.payload {
-        // `len()` of a string is not the number of characters, but the number of bytes.
-        if payload.len() > state.configuration.payload_limit_bytes as usize {
-            return Err(HcHttpGatewayError::RequestMalformed(format!(
-                "Payload exceeds {} bytes",
-                state.configuration.payload_limit_bytes
-            )));
-        }
-    }
-
-    let app_info = try_get_valid_app(
-        dna_hash.clone(),
-        coordinator_identifier.clone(),
-        state.app_info_cache.clone(),
-        &state.configuration.allowed_app_ids,
-        state.admin_call.clone(),
+    let candidates = try_get_valid_app_from_conductors(
+        &dna_hash,
+        &coordinator_identifier,
+        &state.conductors,
+        state.configuration.conductor_failover,
     )
     .await?;
+    record_timing(Phase::Selection, started.elapsed());
+
+    // All candidates are installations of the same app, so the first one is representative.
+    let installed_app_id = candidates[0].1.installed_app_id.as_str();
+    record_app_id(installed_app_id);
+    tracing::Span::current().record("app_id", installed_app_id);
 
     // Check if function name is allowed.
     if !state
         .configuration
-        .is_function_allowed(&app_info.installed_app_id, &zome_name, &fn_name)
+        .is_function_allowed(installed_app_id, &zome_name, &fn_name)
     {
         return Err(HcHttpGatewayError::UnauthorizedFunction {
-            app_id: app_info.installed_app_id,
-            zome_name,
-            fn_name,
+            app_id: installed_app_id.to_string(),
+            zome_name: zome_name.to_string(),
+            fn_name: fn_name.to_string(),
         });
     }
 
-    // Transcode payload from base64 encoded JSON to ExternIO.
-    let zome_call_payload = base64_json_to_hsb(query.payload)?;
-
-    // Get cell id to call from app info.
-    let cell_id = app_info
-        .cell_info
-        .values()
-        .flatten()
-        .find_map(|cell_info| match cell_info {
-            CellInfo::Provisioned(provisioned_cell) => {
-                if *provisioned_cell.cell_id.dna_hash() == dna_hash {
-                    Some(provisioned_cell.cell_id.clone())
-                } else {
-                    None
-                }
+    let cap_secret = parse_cap_secret(&headers)?;
+    let raw_msgpack = accepts_msgpack(&headers);
+
+    // Transcode payload from base64 encoded JSON to ExternIO, unless the client passes msgpack.
+    let transcode_options = TranscodeOptions::for_function(
+        &state.configuration,
+        installed_app_id,
+        &zome_name,
+        &fn_name,
+    );
+    let transcode_started = Instant::now();
+    let mut zome_call_payload = if raw_msgpack {
+        base64_msgpack_to_hsb(query.payload, transcode_options.empty_payload)?
+    } else {
+        base64_json_to_hsb(query.payload, transcode_options)?
+    };
+    record_timing(Phase::Transcode, transcode_started.elapsed());
+
+    // The context is only needed, and the headers only copied, when transforms are registered.
+    let transform_context = (!state.transforms.is_empty()).then(|| TransformContext {
+        installed_app_id: installed_app_id.to_string(),
+        zome_name: zome_name.to_string(),
+        fn_name: fn_name.to_string(),
+        headers: headers.clone(),
+    });
+    if let Some(transform_context) = &transform_context {
+        zome_call_payload = state
+            .transforms
+            .payload(transform_context, zome_call_payload)
+            .await?;
+    }
+    // Shared with the zome calls to each candidate and with the refresh of a cached response
+    let zome_call_payload = Arc::new(zome_call_payload);
+
+    // Responses of functions that are cached are keyed by the hash of the payload as sent to the
+    // conductor
+    let cache_ttl = state
+        .configuration
+        .cache_ttl(installed_app_id, &zome_name, &fn_name);
+    let cache_key = cache_ttl.map(|_| CacheKey {
+        installed_app_id: installed_app_id.into(),
+        dna_hash: dna_hash.clone(),
+        zome_name: zome_name.clone(),
+        fn_name: fn_name.clone(),
+        payload_hash: payload_hash(&zome_call_payload.0),
+        cap_secret,
+    });
+    let mut cached_response = None;
+    if let Some(cache_key) = &cache_key
+        && !bypasses_cache(&headers, state.configuration.management_token.as_ref())
+    {
+        cached_response = state.response_cache.get(cache_key).await;
+        state
+            .metrics
+            .record_response_cache(cached_response.is_some());
+    }
+    if let Some(CacheHit { refresh: true, .. }) = &cached_response
+        && let (Some(cache_key), Some(cache_ttl)) = (&cache_key, cache_ttl)
+    {
+        spawn_cache_refresh(
+            &state,
+            &candidates,
+            cache_key.clone(),
+            zome_call_payload.clone(),
+            cache_ttl,
+        );
+    }
+
+    let mut serialized_response = match cached_response {
+        Some(cached_response) => cached_response.response,
+        None => {
+            let mut cancelled_call_log = CancelledCallLog { completed: false };
+            // Records the durations once the response has been built, whether the call succeeded
+            // or not
+            let mut timer = ZomeCallTimer::new(
+                &state.metrics,
+                installed_app_id,
+                &zome_name,
+                &fn_name,
+                started,
+            );
+            let conductor_started = Instant::now();
+            let result = call_candidates(
+                &candidates,
+                &dna_hash,
+                &zome_name,
+                &fn_name,
+                &zome_call_payload,
+                cap_secret,
+            )
+            .await;
+            cancelled_call_log.completed = true;
+            state.metrics.record_app_usage(
+                installed_app_id,
+                zome_call_payload.0.len(),
+                result.as_ref().map_or(0, |response| response.0.len()),
+            );
+            timer.conductor_responded(conductor_started.elapsed());
+            record_timing(Phase::Call, conductor_started.elapsed());
+            if is_missing_app(&result) {
+                purge_missing_app(&state, installed_app_id).await;
             }
-            _ => None,
-        })
-        // The app info has been found based on the DNA hash, so the cell must exist
-        // and be unique.
-        .unwrap();
-
-    let serialized_response = state
-        .app_call
-        .handle_zome_call(
-            app_info.installed_app_id,
-            cell_id,
-            zome_name,
-            fn_name,
-            zome_call_payload,
+            let response = result?;
+            if let (Some(cache_key), Some(cache_ttl)) = (&cache_key, cache_ttl) {
+                state
+                    .response_cache
+                    .insert(cache_key.clone(), response.clone(), cache_ttl)
+                    .await;
+            }
+            response
+        }
+    };
+    if let Some(transform_context) = &transform_context {
+        serialized_response = state
+            .transforms
+            .response(transform_context, serialized_response)
+            .await?;
+    }
+
+    // Responses of cached functions carry the hash of their payload, by which they can be purged
+    let payload_hash = cache_key.as_ref().map(CacheKey::payload_hash_hex);
+    let mut response = 'response: {
+        if raw_msgpack {
+            break 'response (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(MSGPACK_MEDIA_TYPE),
+                )],
+                serialized_response.0,
+            )
+                .into_response();
+        }
+
+        let validated = state.configuration.response_schemas.validates(
+            state.configuration.response_validation,
+            installed_app_id,
+            &zome_name,
+            &fn_name,
+        );
+
+        // Stream large responses that aren't validated, so that the JSON text of many large
+        // responses isn't held in memory at the same time.
+        if serialized_response.0.len() > STREAMING_THRESHOLD_BYTES && !validated {
+            let body = hsb_to_json_body(
+                &serialized_response,
+                state.configuration.clone(),
+                installed_app_id.to_string(),
+                zome_name.to_string(),
+                fn_name.to_string(),
+            )?;
+            break 'response (
+                [(
+                    header::CONTENT_TYPE,
+                    // Like the transcoded responses that aren't streamed
+                    HeaderValue::from_static("text/plain; charset=utf-8"),
+                )],
+                body,
+            )
+                .into_response();
+        }
+
+        // Transcode ExternIO response to JSON. Only validated responses need a JSON value, the
+        // others are rendered straight to text.
+        let transcode_started = Instant::now();
+        if !validated {
+            let response = hsb_to_json_string(&serialized_response, transcode_options)?;
+            record_timing(Phase::Transcode, transcode_started.elapsed());
+            break 'response response.into_response();
+        }
+        let response = hsb_to_json(&serialized_response, transcode_options)?;
+        record_timing(Phase::Transcode, transcode_started.elapsed());
+        state.configuration.response_schemas.check(
+            state.configuration.response_validation,
+            installed_app_id,
+            &zome_name,
+            &fn_name,
+            &response,
+        )?;
+
+        response.to_string().into_response()
+    };
+    if let Some(payload_hash) = payload_hash
+        && let Ok(value) = HeaderValue::from_str(&payload_hash)
+    {
+        response.headers_mut().insert(PAYLOAD_HASH_HEADER, value);
+    }
+
+    Ok(response)
+}
+
+/// Make the zome call to the candidates in order of preference, moving on to the next one only
+/// when a conductor cannot be connected to.
+///
+/// There is more than one candidate only when failover is enabled.
+async fn call_candidates(
+    candidates: &[(&ConductorHandle, AppInfo)],
+    dna_hash: &DnaHash,
+    zome_name: &Arc<str>,
+    fn_name: &Arc<str>,
+    zome_call_payload: &Arc<ExternIO>,
+    cap_secret: Option<CapSecret>,
+) -> HcHttpGatewayResult<ExternIO> {
+    let mut result = Err(HcHttpGatewayError::UpstreamUnavailable(
+        UpstreamConnection::App,
+    ));
+    for (conductor, app_info) in candidates {
+        // Get cell id to call from app info.
+        // The app info has been found based on the DNA hash, so the cell is expected to exist.
+        let cell_id = find_cell_id(app_info, dna_hash).ok_or(
+            HcHttpGatewayError::AppSelectionError(AppSelectionError::NotInstalled),
+        )?;
+
+        result = conductor
+            .app_call
+            .handle_zome_call(
+                ZomeCallRequest::new(
+                    app_info.installed_app_id.clone(),
+                    cell_id,
+                    zome_name.clone(),
+                    fn_name.clone(),
+                    zome_call_payload.clone(),
+                )
+                .with_cap_secret(cap_secret),
+            )
+            .await;
+
+        if is_missing_app(&result) {
+            conductor
+                .invalidate_apps(&HashSet::from([app_info.installed_app_id.clone()]))
+                .await;
+        }
+
+        // A call is only made to the next candidate when it was not sent to this one, since a
+        // call that was sent may have been made even though the connection was lost.
+        let connected = !matches!(result, Err(HcHttpGatewayError::UpstreamUnavailable(_)));
+        conductor.record_health(
+            connected && !matches!(result, Err(HcHttpGatewayError::UpstreamLost(_))),
+        );
+        if connected {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Whether the call failed because the app or cell that was selected for it from the cached app
+/// info is gone, because the app was uninstalled, disabled or changed since it was cached.
+fn is_missing_app(result: &HcHttpGatewayResult<ExternIO>) -> bool {
+    matches!(
+        result,
+        Err(HcHttpGatewayError::HolochainError(
+            ConductorApiError::AppNotFound | ConductorApiError::CellNotFound
+        ))
+    )
+}
+
+/// Purge the cached responses of an app that is gone from its conductor, so that they are not
+/// served in place of the errors of calls to it.
+async fn purge_missing_app(state: &AppState, installed_app_id: &str) {
+    let filter = PurgeFilter {
+        app_id: installed_app_id.to_string(),
+        zome_name: None,
+        fn_name: None,
+        payload_hash: None,
+    };
+    // Failures are logged by the cache
+    if let Ok(purged) = state.response_cache.purge(&filter).await {
+        tracing::info!(
+            app_id = installed_app_id,
+            purged,
+            "Purged the cached responses of a missing app"
+        );
+    }
+}
+
+/// Refresh a stale cached response in the background, while the request is answered with the
+/// stale response.
+///
+/// The refresh is not cancelled when the request completes or is dropped.
+fn spawn_cache_refresh(
+    state: &AppState,
+    candidates: &[(&ConductorHandle, AppInfo)],
+    cache_key: CacheKey,
+    zome_call_payload: Arc<ExternIO>,
+    cache_ttl: std::time::Duration,
+) {
+    let state = state.clone();
+    let (conductors, app_infos): (Vec<_>, Vec<_>) = candidates
+        .iter()
+        .map(|(conductor, app_info)| ((*conductor).clone(), app_info.clone()))
+        .unzip();
+    tokio::spawn(async move {
+        let candidates = conductors.iter().zip(app_infos).collect::<Vec<_>>();
+        let result = call_candidates(
+            &candidates,
+            &cache_key.dna_hash,
+            &cache_key.zome_name,
+            &cache_key.fn_name,
+            &zome_call_payload,
+            cache_key.cap_secret,
         )
-        .await?;
+        .await;
+        state.metrics.record_app_usage(
+            &cache_key.installed_app_id,
+            zome_call_payload.0.len(),
+            result.as_ref().map_or(0, |response| response.0.len()),
+        );
 
-    // Transcode ExternIO response to JSON.
-    hsb_to_json(&serialized_response)
+        if is_missing_app(&result) {
+            // The stale response is purged along with the other responses of the app
+            purge_missing_app(&state, &cache_key.installed_app_id).await;
+            return;
+        }
+        match result {
+            Ok(response) => {
+                state
+                    .response_cache
+                    .insert(cache_key, response, cache_ttl)
+                    .await
+            }
+            Err(e) => {
+                tracing::warn!(
+                    ?e,
+                    app_id = %cache_key.installed_app_id,
+                    zome_name = %cache_key.zome_name,
+                    fn_name = %cache_key.fn_name,
+                    "Failed to refresh a cached response"
+                );
+                state.response_cache.refresh_failed(&cache_key).await;
+            }
+        }
+    });
 }
 
 #[cfg(test)]
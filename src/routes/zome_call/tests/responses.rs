@@ -1,23 +1,37 @@
 use super::DNA_HASH;
-use crate::config::{AllowedFns, Configuration};
+use crate::config::{AllowedFns, Configuration, ManagementToken};
+use crate::routes::zome_call::{CAP_SECRET_HEADER, PAYLOAD_HASH_HEADER};
 use crate::test::data::new_test_app_info;
-use crate::test::router::TestRouter;
-use crate::{MockAdminCall, MockAppCall};
+use crate::test::mock_conductor::MockConductor;
+use crate::test::router::{TEST_REQUEST_ID, TestRouter};
+use crate::{
+    AppCall, Conductors, ConnectionCounters, HcHttpGatewayError, HcHttpGatewayResult,
+    MockAdminCall, MockAppCall, ResponseSchemas, ResponseValidation, Transform, TransformContext,
+    Transforms, UpstreamConnection, ZomeCallRequest,
+};
+use axum::body::Body;
+use axum::http::Request;
+use base64::{Engine, prelude::BASE64_URL_SAFE};
+use futures::future::BoxFuture;
 use holochain::holochain_wasmer_host::prelude::WasmErrorInner;
 use holochain_client::{ConductorApiError, ExternIO};
 use holochain_conductor_api::ExternalApiWireError;
-use holochain_types::prelude::DnaHash;
+use holochain_types::prelude::{AgentPubKey, CapSecret, CellId, DnaHash};
+use http_body_util::BodyExt;
 use reqwest::StatusCode;
 use std::collections::HashMap;
 use std::net::{Ipv4Addr, SocketAddr};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tower::ServiceExt;
 
 const APP_ID: &str = "tapp";
 
-fn create_test_router(app_call: MockAppCall) -> TestRouter {
+fn test_config() -> Configuration {
     let mut allowed_fns = HashMap::new();
     allowed_fns.insert(APP_ID.into(), AllowedFns::All);
-    let config = Configuration::try_new(
+    Configuration::try_new(
         SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
         "1024",
         APP_ID,
@@ -25,8 +39,14 @@ fn create_test_router(app_call: MockAppCall) -> TestRouter {
         "",
         "",
     )
-    .unwrap();
+    .unwrap()
+}
+
+fn create_test_router(app_call: MockAppCall) -> TestRouter {
+    create_test_router_with_config(app_call, test_config())
+}
 
+fn create_test_router_with_config(app_call: MockAppCall, config: Configuration) -> TestRouter {
     let mut admin_call = MockAdminCall::new();
     admin_call.expect_list_apps().returning(move |_| {
         Box::pin(async move {
@@ -34,6 +54,9 @@ fn create_test_router(app_call: MockAppCall) -> TestRouter {
             Ok(vec![app_info])
         })
     });
+    admin_call
+        .expect_connection_counters()
+        .returning(ConnectionCounters::default);
     let admin_call = Arc::new(admin_call);
     let app_call = Arc::new(app_call);
     TestRouter::new_with_config_and_interfaces(config, admin_call, app_call)
@@ -44,9 +67,7 @@ async fn happy_zome_call() {
     let mut app_call = MockAppCall::new();
     app_call
         .expect_handle_zome_call()
-        .returning(|_, _, _, _, _| {
-            Box::pin(async move { Ok(ExternIO::encode("return_value").unwrap()) })
-        });
+        .returning(|_| Box::pin(async move { Ok(ExternIO::encode("return_value").unwrap()) }));
     let router = create_test_router(app_call);
     let (status_code, body) = router
         .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
@@ -56,174 +77,810 @@ async fn happy_zome_call() {
 }
 
 #[tokio::test]
-async fn ribosome_errors_are_returned() {
+async fn client_cap_secret_is_used_for_zome_call() {
     let mut app_call = MockAppCall::new();
     app_call
         .expect_handle_zome_call()
-        .returning(|_, _, _, _, _| {
-            Box::pin(async move {
-                // A bit contrived this error, but close enough to reality.
-                Err(crate::HcHttpGatewayError::HolochainError(
-                    ConductorApiError::ExternalApiWireError(ExternalApiWireError::RibosomeError(
-                        format!(
-                            "{:?}",
-                            WasmErrorInner::Guest("could not find record xyz".to_string())
-                        ),
-                    )),
-                ))
-            })
-        });
+        .withf(|request| request.cap_secret == Some(CapSecret::from([7; 64])))
+        .returning(|_| Box::pin(async move { Ok(ExternIO::encode("return_value").unwrap()) }));
+    let router = create_test_router(app_call);
+    let cap_secret = BASE64_URL_SAFE.encode([7; 64]);
+    let (status_code, body) = router
+        .request_with_headers(
+            &format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"),
+            &[(CAP_SECRET_HEADER, &cap_secret)],
+        )
+        .await;
+    assert_eq!(status_code, StatusCode::OK);
+    assert_eq!(body, r#""return_value""#);
+}
+
+#[tokio::test]
+async fn ribosome_errors_are_returned() {
+    let mut app_call = MockAppCall::new();
+    app_call.expect_handle_zome_call().returning(|_| {
+        Box::pin(async move {
+            // A bit contrived this error, but close enough to reality.
+            Err(crate::HcHttpGatewayError::HolochainError(
+                ConductorApiError::ExternalApiWireError(ExternalApiWireError::RibosomeError(
+                    format!(
+                        "{:?}",
+                        WasmErrorInner::Guest("could not find record xyz".to_string())
+                    ),
+                )),
+            ))
+        })
+    });
     let router = create_test_router(app_call);
     let (status_code, body) = router
         .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
         .await;
     assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
-    assert_eq!(body, r#"{"error":"Guest(\"could not find record xyz\")"}"#);
+    assert_eq!(
+        body,
+        r#"{"error":"Guest(\"could not find record xyz\")","request_id":"test-request-id"}"#
+    );
 }
 
 #[tokio::test]
 async fn app_not_found() {
     let mut app_call = MockAppCall::new();
+    // The cached app state is invalidated, which closes the app connection
     app_call
-        .expect_handle_zome_call()
-        .returning(|_, _, _, _, _| {
-            Box::pin(async move {
-                Err(crate::HcHttpGatewayError::HolochainError(
-                    ConductorApiError::AppNotFound,
-                ))
-            })
-        });
+        .expect_remove_app_connection()
+        .returning(|_| Box::pin(async {}));
+    app_call.expect_handle_zome_call().returning(|_| {
+        Box::pin(async move {
+            Err(crate::HcHttpGatewayError::HolochainError(
+                ConductorApiError::AppNotFound,
+            ))
+        })
+    });
     let router = create_test_router(app_call);
     let (status_code, body) = router
         .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
         .await;
-    // The app must have been found earlier when looking it up for the call,
-    // so this must have been an internal error of some kind.
-    assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
-    assert_eq!(body, r#"{"error":"Something went wrong"}"#);
+    // The app was found earlier when looking it up for the call, so it has been uninstalled or
+    // disabled since.
+    assert_eq!(status_code, StatusCode::NOT_FOUND);
+    assert_eq!(
+        body,
+        r#"{"error":"The app was not found on the conductor, it may have been uninstalled or disabled","request_id":"test-request-id"}"#
+    );
 }
 
 #[tokio::test]
 async fn cell_not_found() {
     let mut app_call = MockAppCall::new();
     app_call
-        .expect_handle_zome_call()
-        .returning(|_, _, _, _, _| {
-            Box::pin(async move {
-                Err(crate::HcHttpGatewayError::HolochainError(
-                    ConductorApiError::CellNotFound,
-                ))
-            })
-        });
+        .expect_remove_app_connection()
+        .returning(|_| Box::pin(async {}));
+    app_call.expect_handle_zome_call().returning(|_| {
+        Box::pin(async move {
+            Err(crate::HcHttpGatewayError::HolochainError(
+                ConductorApiError::CellNotFound,
+            ))
+        })
+    });
     let router = create_test_router(app_call);
     let (status_code, body) = router
         .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
         .await;
-    assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
-    assert_eq!(body, r#"{"error":"Something went wrong"}"#);
+    assert_eq!(status_code, StatusCode::CONFLICT);
+    assert_eq!(
+        body,
+        r#"{"error":"The cell was not found in the app, the app may have been disabled or changed","request_id":"test-request-id"}"#
+    );
 }
 
 #[tokio::test]
 async fn other_external_api_wire_error() {
     let mut app_call = MockAppCall::new();
-    app_call
-        .expect_handle_zome_call()
-        .returning(|_, _, _, _, _| {
-            Box::pin(async move {
-                Err(crate::HcHttpGatewayError::HolochainError(
-                    ConductorApiError::ExternalApiWireError(
-                        ExternalApiWireError::ZomeCallUnauthorized("unauthorized".to_string()),
-                    ),
-                ))
-            })
-        });
+    app_call.expect_handle_zome_call().returning(|_| {
+        Box::pin(async move {
+            Err(crate::HcHttpGatewayError::HolochainError(
+                ConductorApiError::ExternalApiWireError(
+                    ExternalApiWireError::ZomeCallUnauthorized("unauthorized".to_string()),
+                ),
+            ))
+        })
+    });
     let router = create_test_router(app_call);
     let (status_code, body) = router
         .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
         .await;
     assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
-    assert_eq!(body, r#"{"error":"Something went wrong"}"#);
+    assert_eq!(
+        body,
+        r#"{"error":"Something went wrong","request_id":"test-request-id"}"#
+    );
 }
 
 #[tokio::test]
 async fn fresh_nonce_error() {
     let mut app_call = MockAppCall::new();
-    app_call
-        .expect_handle_zome_call()
-        .returning(|_, _, _, _, _| {
-            Box::pin(async move {
-                Err(crate::HcHttpGatewayError::HolochainError(
-                    ConductorApiError::FreshNonceError("nonce_kaputt".into()),
-                ))
-            })
-        });
+    app_call.expect_handle_zome_call().returning(|_| {
+        Box::pin(async move {
+            Err(crate::HcHttpGatewayError::HolochainError(
+                ConductorApiError::FreshNonceError("nonce_kaputt".into()),
+            ))
+        })
+    });
     let router = create_test_router(app_call);
     let (status_code, body) = router
         .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
         .await;
     assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
-    assert_eq!(body, r#"{"error":"Something went wrong"}"#);
+    assert_eq!(
+        body,
+        r#"{"error":"Something went wrong","request_id":"test-request-id"}"#
+    );
 }
 
 #[tokio::test]
 async fn io_error() {
     let mut app_call = MockAppCall::new();
-    app_call
-        .expect_handle_zome_call()
-        .returning(|_, _, _, _, _| {
-            Box::pin(async move {
-                Err(crate::HcHttpGatewayError::HolochainError(
-                    ConductorApiError::IoError(std::io::Error::other("ssd not found")),
-                ))
-            })
-        });
+    app_call.expect_handle_zome_call().returning(|_| {
+        Box::pin(async move {
+            Err(crate::HcHttpGatewayError::HolochainError(
+                ConductorApiError::IoError(std::io::Error::other("ssd not found")),
+            ))
+        })
+    });
     let router = create_test_router(app_call);
     let (status_code, body) = router
         .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
         .await;
     assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
-    assert_eq!(body, r#"{"error":"Something went wrong"}"#);
+    assert_eq!(
+        body,
+        r#"{"error":"Something went wrong","request_id":"test-request-id"}"#
+    );
 }
 
 #[tokio::test]
 async fn sign_zome_call_error() {
     let mut app_call = MockAppCall::new();
-    app_call
-        .expect_handle_zome_call()
-        .returning(|_, _, _, _, _| {
-            Box::pin(async move {
-                Err(crate::HcHttpGatewayError::HolochainError(
-                    ConductorApiError::SignZomeCallError("unsigned".to_string()),
-                ))
-            })
-        });
+    app_call.expect_handle_zome_call().returning(|_| {
+        Box::pin(async move {
+            Err(crate::HcHttpGatewayError::HolochainError(
+                ConductorApiError::SignZomeCallError("unsigned".to_string()),
+            ))
+        })
+    });
     let router = create_test_router(app_call);
     let (status_code, body) = router
         .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
         .await;
     assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
-    assert_eq!(body, r#"{"error":"Something went wrong"}"#);
+    assert_eq!(
+        body,
+        r#"{"error":"Something went wrong","request_id":"test-request-id"}"#
+    );
 }
 
 #[tokio::test]
 async fn websocket_error() {
+    let mut app_call = MockAppCall::new();
+    app_call.expect_handle_zome_call().returning(|_| {
+        Box::pin(async move {
+            Err(crate::HcHttpGatewayError::HolochainError(
+                ConductorApiError::WebsocketError(
+                    // WebsocketError is not exposed.
+                    std::io::Error::other("websocket closed").into(),
+                ),
+            ))
+        })
+    });
+    let router = create_test_router(app_call);
+    let (status_code, body) = router
+        .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
+        .await;
+    assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        body,
+        r#"{"error":"Something went wrong","request_id":"test-request-id"}"#
+    );
+}
+
+#[tokio::test]
+async fn undecodable_response_returns_bad_gateway() {
     let mut app_call = MockAppCall::new();
     app_call
         .expect_handle_zome_call()
-        .returning(|_, _, _, _, _| {
-            Box::pin(async move {
-                Err(crate::HcHttpGatewayError::HolochainError(
-                    ConductorApiError::WebsocketError(
-                        // WebsocketError is not exposed.
-                        std::io::Error::other("websocket closed").into(),
-                    ),
+        // 0xc1 is never used in msgpack
+        .returning(|_| Box::pin(async move { Ok(ExternIO(vec![0xc1])) }));
+    let router = create_test_router(app_call);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    // Retrying won't make the response decodable
+    assert!(!response.headers().contains_key("retry-after"));
+}
+
+#[tokio::test]
+async fn unreachable_upstream_returns_bad_gateway() {
+    let mut app_call = MockAppCall::new();
+    app_call.expect_handle_zome_call().returning(|_| {
+        Box::pin(async move {
+            Err(crate::HcHttpGatewayError::UpstreamUnavailable(
+                UpstreamConnection::Admin,
+            ))
+        })
+    });
+    let router = create_test_router(app_call);
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
+                .header("x-request-id", TEST_REQUEST_ID)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    // Retry once the conductor has been checked again
+    assert_eq!(response.headers()["retry-after"], "5");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        body,
+        r#"{"error":"Could not connect to Holochain","upstream":"admin","request_id":"test-request-id"}"#
+    );
+}
+
+#[tokio::test]
+async fn upstream_lost_during_call_returns_bad_gateway_without_retry() {
+    let mut app_call = MockAppCall::new();
+    app_call.expect_handle_zome_call().times(1).returning(|_| {
+        Box::pin(async move {
+            Err(crate::HcHttpGatewayError::UpstreamLost(
+                UpstreamConnection::App,
+            ))
+        })
+    });
+    let router = create_test_router(app_call);
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
+                .header("x-request-id", TEST_REQUEST_ID)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    // The call may have been made, so it must not be retried automatically
+    assert!(!response.headers().contains_key("retry-after"));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        body,
+        r#"{"error":"Lost the connection to Holochain during the call","upstream":"app","request_id":"test-request-id"}"#
+    );
+}
+
+#[tokio::test]
+async fn response_schema_mismatch_returns_bad_gateway_in_strict_mode() {
+    let mut app_call = MockAppCall::new();
+    app_call
+        .expect_handle_zome_call()
+        .returning(|_| Box::pin(async move { Ok(ExternIO::encode(42).unwrap()) }));
+    let mut config = test_config();
+    config.response_validation = ResponseValidation::Strict;
+    config.response_schemas =
+        ResponseSchemas::from_json_str(r#"{"tapp": {"coordinator/fn_name": {"type": "string"}}}"#)
+            .unwrap();
+    let router = create_test_router_with_config(app_call, config);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
+                .header("x-request-id", TEST_REQUEST_ID)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    // Retrying won't change the shape of the response
+    assert!(!response.headers().contains_key("retry-after"));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        body,
+        r#"{"error":"The zome call response doesn't match its schema: The response has an unexpected type","request_id":"test-request-id"}"#
+    );
+}
+
+#[tokio::test]
+async fn client_disconnect_cancels_zome_call() {
+    let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+    let (dropped_tx, dropped_rx) = tokio::sync::oneshot::channel::<()>();
+    let signals = Mutex::new(Some((started_tx, dropped_tx)));
+    let mut app_call = MockAppCall::new();
+    app_call.expect_handle_zome_call().returning(move |_| {
+        let (started_tx, dropped_tx) = signals.lock().unwrap().take().unwrap();
+        Box::pin(async move {
+            let _dropped_tx = dropped_tx;
+            started_tx.send(()).unwrap();
+            std::future::pending::<crate::HcHttpGatewayResult<ExternIO>>().await
+        })
+    });
+    let router = create_test_router(app_call);
+
+    // The client gives up waiting for the response
+    let uri = format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name");
+    let request = router.request(&uri);
+    let result = tokio::time::timeout(Duration::from_millis(100), request).await;
+    assert!(result.is_err());
+    started_rx.await.expect("The zome call was not made");
+
+    // The zome call is dropped along with the request, rather than left running
+    let dropped = tokio::time::timeout(Duration::from_secs(1), dropped_rx)
+        .await
+        .expect("The zome call was not cancelled");
+    assert!(dropped.is_err());
+}
+
+#[tokio::test]
+async fn zome_call_timeout_returns_gateway_timeout() {
+    let mut app_call = MockAppCall::new();
+    app_call
+        .expect_handle_zome_call()
+        .returning(|_| Box::pin(async move { Err(crate::HcHttpGatewayError::ZomeCallTimeout) }));
+    let router = create_test_router(app_call);
+    let (status_code, body) = router
+        .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
+        .await;
+    assert_eq!(status_code, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(
+        body,
+        r#"{"error":"The zome call timed out","request_id":"test-request-id"}"#
+    );
+}
+
+#[tokio::test]
+async fn busy_app_returns_service_unavailable() {
+    let mut app_call = MockAppCall::new();
+    app_call.expect_handle_zome_call().returning(|request| {
+        Box::pin(async move { Err(crate::HcHttpGatewayError::AppBusy(request.installed_app_id)) })
+    });
+    let router = create_test_router(app_call);
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
+                .header("x-request-id", TEST_REQUEST_ID)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()["retry-after"], "1");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        body,
+        r#"{"error":"Too many concurrent requests for app tapp","request_id":"test-request-id"}"#
+    );
+}
+
+#[tokio::test]
+async fn msgpack_is_passed_through_when_accepted() {
+    // Not valid JSON once transcoded, and passed to the zome unchanged
+    let payload = ExternIO::encode(vec![1u8, 2, 3]).unwrap();
+    let mut app_call = MockAppCall::new();
+    app_call.expect_handle_zome_call().returning({
+        let payload = payload.clone();
+        move |request| {
+            assert_eq!(*request.payload, payload);
+            Box::pin(async move { Ok(ExternIO::encode(("return_value", 5)).unwrap()) })
+        }
+    });
+    let router = create_test_router(app_call);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!(
+                    "/{DNA_HASH}/{APP_ID}/coordinator/fn_name?payload={}",
+                    BASE64_URL_SAFE.encode(&payload.0)
                 ))
-            })
-        });
+                .header("accept", "application/msgpack")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/msgpack");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        ExternIO(body.to_vec()).decode::<(String, u32)>().unwrap(),
+        ("return_value".to_string(), 5)
+    );
+}
+
+#[tokio::test]
+async fn large_response_is_streamed() {
+    let values = vec![u32::MAX; 500_000];
+    let mut app_call = MockAppCall::new();
+    app_call.expect_handle_zome_call().returning({
+        let values = values.clone();
+        move |_| {
+            let values = values.clone();
+            Box::pin(async move { Ok(ExternIO::encode(values).unwrap()) })
+        }
+    });
     let router = create_test_router(app_call);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/plain; charset=utf-8"
+    );
+    // The length isn't known up front
+    assert!(!response.headers().contains_key("content-length"));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, serde_json::to_string(&values).unwrap());
+}
+
+/// Injects the user named in a request header into the payload, and strips the secret from the
+/// response.
+#[derive(Debug)]
+struct InjectUser;
+
+impl Transform for InjectUser {
+    fn transform_payload(
+        &self,
+        context: &TransformContext,
+        _payload: ExternIO,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        let user = context.headers["x-user"].to_str().unwrap().to_string();
+        Box::pin(async move { Ok(ExternIO::encode(HashMap::from([("user", user)])).unwrap()) })
+    }
+
+    fn transform_response(
+        &self,
+        context: &TransformContext,
+        response: ExternIO,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        assert_eq!(context.fn_name, "fn_name");
+        Box::pin(async move {
+            let mut response = response.decode::<HashMap<String, String>>().unwrap();
+            response.remove("secret");
+            Ok(ExternIO::encode(response).unwrap())
+        })
+    }
+}
+
+#[tokio::test]
+async fn payload_and_response_are_transformed() {
+    let mut app_call = MockAppCall::new();
+    app_call.expect_handle_zome_call().returning(|request| {
+        let payload = request.payload.decode::<HashMap<String, String>>().unwrap();
+        assert_eq!(payload["user"], "alice");
+        Box::pin(async move {
+            Ok(ExternIO::encode(HashMap::from([("name", "Alice"), ("secret", "1234")])).unwrap())
+        })
+    });
+    let mut admin_call = MockAdminCall::new();
+    admin_call.expect_list_apps().returning(move |_| {
+        Box::pin(async move {
+            let app_info = new_test_app_info(APP_ID, DnaHash::from_raw_32(vec![1; 32]));
+            Ok(vec![app_info])
+        })
+    });
+    let config = test_config();
+    let conductors = Conductors::single(&config, Arc::new(admin_call), Arc::new(app_call));
+    let mut transforms = Transforms::default();
+    transforms.push(Arc::new(InjectUser));
+    let router =
+        TestRouter::new_with_config_conductors_and_transforms(config, conductors, transforms);
+
     let (status_code, body) = router
+        .request_with_headers(
+            &format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"),
+            &[("x-user", "alice")],
+        )
+        .await;
+    assert_eq!(status_code, StatusCode::OK);
+    assert_eq!(body, r#"{"name":"Alice"}"#);
+}
+
+#[tokio::test]
+async fn zome_call_durations_are_exposed_as_metrics() {
+    let mut app_call = MockAppCall::new();
+    app_call
+        .expect_handle_zome_call()
+        .returning(|_| Box::pin(async move { Ok(ExternIO::encode("return_value").unwrap()) }));
+    app_call
+        .expect_connection_counters()
+        .returning(ConnectionCounters::default);
+    let mut config = test_config();
+    config.management_token = Some(ManagementToken::new("secret"));
+    let router = create_test_router_with_config(app_call, config);
+
+    let (status_code, _) = router
         .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name"))
         .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    let (status_code, body) = router
+        .request_with_headers("/management/metrics", &[("authorization", "Bearer secret")])
+        .await;
+    assert_eq!(status_code, StatusCode::OK);
+    let labels = format!("app_id=\"{APP_ID}\",zome=\"coordinator\",fn=\"fn_name\"");
+    for metric in [
+        "hc_gw_zome_call_conductor_duration_seconds",
+        "hc_gw_zome_call_gateway_duration_seconds",
+    ] {
+        let line = format!("{metric}_count{{{labels}}} 1");
+        assert!(body.lines().any(|l| l == line), "missing {line}");
+    }
+}
+
+#[tokio::test]
+async fn cached_responses_are_served_without_a_zome_call() {
+    let mut app_call = MockAppCall::new();
+    app_call
+        .expect_handle_zome_call()
+        .returning(|request| {
+            Box::pin(async move { Ok(ExternIO::encode(request.payload.0.len()).unwrap()) })
+        })
+        .times(2);
+    app_call
+        .expect_connection_counters()
+        .returning(ConnectionCounters::default);
+    let mut config = test_config();
+    config.management_token = Some(ManagementToken::new("secret"));
+    config.cache_ttl_fns.insert(
+        APP_ID.to_string(),
+        "coordinator/fn_name=60000".parse().unwrap(),
+    );
+    let router = create_test_router_with_config(app_call, config);
+
+    for payload in [r#"{"a":1}"#, r#"{"a":1}"#, r#"{"a":12}"#] {
+        let (status_code, _) = router
+            .request(&format!(
+                "/{DNA_HASH}/{APP_ID}/coordinator/fn_name?payload={}",
+                BASE64_URL_SAFE.encode(payload)
+            ))
+            .await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
+
+    let (_, body) = router
+        .request_with_headers("/management/metrics", &[("authorization", "Bearer secret")])
+        .await;
+    for line in [
+        "hc_gw_response_cache_hits_total 1",
+        "hc_gw_response_cache_misses_total 2",
+    ] {
+        assert!(body.lines().any(|l| l == line), "missing {line}");
+    }
+}
+
+#[tokio::test]
+async fn stale_responses_are_served_while_they_are_refreshed() {
+    let calls = AtomicU8::new(0);
+    let mut app_call = MockAppCall::new();
+    app_call
+        .expect_handle_zome_call()
+        .returning(move |_| {
+            let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Box::pin(async move { Ok(ExternIO::encode(call).unwrap()) })
+        })
+        .times(2);
+    let mut config = test_config();
+    config.cache_ttl_fns.insert(
+        APP_ID.to_string(),
+        "coordinator/fn_name=100".parse().unwrap(),
+    );
+    let router = create_test_router_with_config(app_call, config);
+    let uri = format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name");
+
+    let (_, body) = router.request(&uri).await;
+    assert_eq!(body, "1");
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    // The expired response is served, and refreshed in the background
+    let (_, body) = router.request(&uri).await;
+    assert_eq!(body, "1");
+    let mut refreshed = false;
+    for _ in 0..50 {
+        let (_, body) = router.request(&uri).await;
+        if body == "2" {
+            refreshed = true;
+            break;
+        }
+        assert_eq!(body, "1");
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    assert!(refreshed);
+}
+
+#[tokio::test]
+async fn cached_responses_can_be_bypassed_and_purged() {
+    let mut app_call = MockAppCall::new();
+    app_call
+        .expect_handle_zome_call()
+        .returning(|_| Box::pin(async move { Ok(ExternIO::encode("return_value").unwrap()) }))
+        .times(3);
+    let mut config = test_config();
+    config.management_token = Some(ManagementToken::new("secret"));
+    config.cache_ttl_fns.insert(
+        APP_ID.to_string(),
+        "coordinator/fn_name=60000".parse().unwrap(),
+    );
+    let router = create_test_router_with_config(app_call, config);
+    let uri = format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name");
+
+    let response = router
+        .clone()
+        .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let payload_hash = response.headers()[PAYLOAD_HASH_HEADER]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    // Only clients with the management token can bypass the cache
+    let (status_code, _) = router
+        .request_with_headers(&uri, &[("cache-control", "no-cache")])
+        .await;
+    assert_eq!(status_code, StatusCode::OK);
+    let (status_code, _) = router
+        .request_with_headers(
+            &uri,
+            &[
+                ("cache-control", "no-cache"),
+                ("authorization", "Bearer secret"),
+            ],
+        )
+        .await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!(
+                    "/management/response-cache/purge?app_id={APP_ID}&payload_hash={payload_hash}"
+                ))
+                .header("authorization", "Bearer secret")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, r#"{"purged":1}"#);
+
+    // The purged response is fetched from the conductor again
+    let (status_code, _) = router.request(&uri).await;
+    assert_eq!(status_code, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn mock_conductor_responses_and_failures_are_served() {
+    let conductor =
+        MockConductor::new().with_app(new_test_app_info(APP_ID, DnaHash::from_raw_32(vec![1; 32])));
+    conductor.respond("coordinator", "fn_name", "return_value");
+    let config = test_config();
+    let router =
+        TestRouter::new_with_config_and_conductors(config.clone(), conductor.conductors(&config));
+    let uri = format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name");
+
+    let (status_code, body) = router.request(&uri).await;
+    assert_eq!(status_code, StatusCode::OK);
+    assert_eq!(body, r#""return_value""#);
+
+    conductor.fail_zome_calls(|| HcHttpGatewayError::UpstreamUnavailable(UpstreamConnection::App));
+    let (status_code, _) = router.request(&uri).await;
+    assert_eq!(status_code, StatusCode::BAD_GATEWAY);
+
+    conductor.recover();
+    let (status_code, _) = router.request(&uri).await;
+    assert_eq!(status_code, StatusCode::OK);
+
+    let (status_code, _) = router
+        .request(&format!("/{DNA_HASH}/{APP_ID}/coordinator/other_fn"))
+        .await;
     assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
-    assert_eq!(body, r#"{"error":"Something went wrong"}"#);
+    assert_eq!(&*conductor.zome_calls().last().unwrap().fn_name, "other_fn");
+}
+
+#[tokio::test]
+async fn missing_app_invalidates_caches() {
+    let conductor =
+        MockConductor::new().with_app(new_test_app_info(APP_ID, DnaHash::from_raw_32(vec![1; 32])));
+    conductor.respond("coordinator", "fn_name", "return_value");
+    let mut config = test_config();
+    config.cache_ttl_fns.insert(
+        APP_ID.to_string(),
+        "coordinator/fn_name=60000".parse().unwrap(),
+    );
+    let conductors = conductor.conductors(&config);
+    let router = TestRouter::new_with_config_and_conductors(config, conductors.clone());
+    let cached_uri = format!("/{DNA_HASH}/{APP_ID}/coordinator/fn_name");
+
+    let (status_code, _) = router.request(&cached_uri).await;
+    assert_eq!(status_code, StatusCode::OK);
+    let conductor_handle = conductors.iter().next().unwrap();
+    assert!(conductor_handle.has_cached_app(&APP_ID.to_string()).await);
+
+    // The app is uninstalled after it was selected for a call
+    conductor.on_zome_call("coordinator", "fn_name", |_| {
+        Err(HcHttpGatewayError::HolochainError(
+            ConductorApiError::AppNotFound,
+        ))
+    });
+    let (status_code, _) = router
+        .request(&format!(
+            "/{DNA_HASH}/{APP_ID}/coordinator/fn_name?payload={}",
+            BASE64_URL_SAFE.encode("1")
+        ))
+        .await;
+    assert_eq!(status_code, StatusCode::NOT_FOUND);
+    assert!(!conductor_handle.has_cached_app(&APP_ID.to_string()).await);
+
+    // The cached response of the app was purged
+    let calls = conductor.zome_calls().len();
+    let (status_code, _) = router.request(&cached_uri).await;
+    assert_eq!(status_code, StatusCode::NOT_FOUND);
+    assert_eq!(conductor.zome_calls().len(), calls + 1);
+}
+
+#[tokio::test]
+async fn mock_conductor_latency_times_out_zome_calls() {
+    let conductor = MockConductor::new();
+    conductor.respond("coordinator", "fn_name", ());
+    conductor.set_latency(Duration::from_millis(100));
+    let request = ZomeCallRequest::new(
+        APP_ID,
+        CellId::new(
+            DnaHash::from_raw_32(vec![1; 32]),
+            AgentPubKey::from_raw_32(vec![1; 32]),
+        ),
+        "coordinator",
+        "fn_name",
+        ExternIO::encode(()).unwrap(),
+    );
+
+    let result = conductor
+        .handle_zome_call(request.clone().with_timeout(Duration::from_millis(10)))
+        .await;
+    assert!(matches!(result, Err(HcHttpGatewayError::ZomeCallTimeout)));
+
+    conductor.handle_zome_call(request).await.unwrap();
 }
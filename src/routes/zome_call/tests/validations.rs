@@ -3,9 +3,12 @@ use crate::test::router::TestRouter;
 use crate::test::test_tracing::initialize_testing_tracing_subscriber;
 use crate::{
     config::{AllowedFns, Configuration},
-    routes::zome_call::MAX_IDENTIFIER_CHARS,
+    routes::zome_call::{CAP_SECRET_HEADER, MAX_IDENTIFIER_CHARS},
+};
+use base64::{
+    Engine,
+    prelude::{BASE64_URL_SAFE, BASE64_URL_SAFE_NO_PAD},
 };
-use base64::{Engine, prelude::BASE64_URL_SAFE};
 use reqwest::StatusCode;
 use std::collections::HashMap;
 use std::net::{Ipv4Addr, SocketAddr};
@@ -31,7 +34,7 @@ async fn invalid_dna_hash_is_rejected() {
     assert_eq!(status_code, StatusCode::BAD_REQUEST);
     assert_eq!(
         body,
-        r#"{"error":"Request is malformed: Invalid DNA hash"}"#
+        r#"{"error":"Request is malformed: Invalid DNA hash","request_id":"test-request-id"}"#
     );
 }
 
@@ -47,7 +50,7 @@ async fn coordinator_identifier_with_excess_length_is_rejected() {
     assert_eq!(
         body,
         format!(
-            r#"{{"error":"Request is malformed: Identifier {coordinator} longer than {MAX_IDENTIFIER_CHARS} characters"}}"#
+            r#"{{"error":"Request is malformed: Identifier {coordinator} longer than {MAX_IDENTIFIER_CHARS} characters","request_id":"test-request-id"}}"#
         )
     );
 }
@@ -64,7 +67,7 @@ async fn zome_name_with_excess_length_is_rejected() {
     assert_eq!(
         body,
         format!(
-            r#"{{"error":"Request is malformed: Identifier {zome_name} longer than {MAX_IDENTIFIER_CHARS} characters"}}"#
+            r#"{{"error":"Request is malformed: Identifier {zome_name} longer than {MAX_IDENTIFIER_CHARS} characters","request_id":"test-request-id"}}"#
         )
     );
 }
@@ -81,7 +84,7 @@ async fn function_name_with_excess_length_is_rejected() {
     assert_eq!(
         body,
         format!(
-            r#"{{"error":"Request is malformed: Identifier {fn_name} longer than {MAX_IDENTIFIER_CHARS} characters"}}"#
+            r#"{{"error":"Request is malformed: Identifier {fn_name} longer than {MAX_IDENTIFIER_CHARS} characters","request_id":"test-request-id"}}"#
         )
     );
 }
@@ -99,38 +102,70 @@ async fn unauthorized_function_name_is_rejected() {
     assert_eq!(
         body,
         format!(
-            r#"{{"error":"Function {fn_name} in zome zome_name in app coordinator is not allowed"}}"#
+            r#"{{"error":"Function {fn_name} in zome zome_name in app coordinator is not allowed","request_id":"test-request-id"}}"#
         )
     );
 }
 
-#[tokio::test]
-async fn payload_with_excess_length_is_rejected() {
-    initialize_testing_tracing_subscriber();
-
+fn router_with_payload_limit(payload_limit_bytes: &str) -> TestRouter {
     let mut allowed_fns = HashMap::new();
     allowed_fns.insert("coordinator".to_string(), AllowedFns::All);
 
     let config = Configuration::try_new(
         SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
-        "10",
-        "",
+        payload_limit_bytes,
+        "coordinator",
         allowed_fns,
         "",
         "",
     )
     .unwrap();
-    let router = TestRouter::new_with_config(config);
+    TestRouter::new_with_config(config)
+}
+
+#[tokio::test]
+async fn payload_with_excess_length_is_rejected() {
+    initialize_testing_tracing_subscriber();
+
+    let router = router_with_payload_limit("10");
     let payload = BASE64_URL_SAFE.encode(vec![1; 11]);
     let uri = format!("/{DNA_HASH}/coordinator/zome_name/fn_name?payload={payload}");
     let (status_code, body) = router.request(&uri).await;
     assert_eq!(status_code, StatusCode::BAD_REQUEST);
     assert_eq!(
         body,
-        format!(r#"{{"error":"Request is malformed: Payload exceeds 10 bytes"}}"#)
+        format!(
+            r#"{{"error":"Request is malformed: Payload exceeds 10 bytes","request_id":"test-request-id"}}"#
+        )
     );
 }
 
+#[tokio::test]
+async fn payload_limit_applies_to_decoded_length() {
+    initialize_testing_tracing_subscriber();
+
+    let router = router_with_payload_limit("10");
+    // 10 bytes of JSON, which is 16 characters of base64 with padding and 14 without
+    for payload in [
+        BASE64_URL_SAFE.encode(r#""12345678""#),
+        BASE64_URL_SAFE_NO_PAD.encode(r#""12345678""#),
+    ] {
+        let uri = format!("/{DNA_HASH}/coordinator/zome_name/fn_name?payload={payload}");
+        let (status_code, _) = router.request(&uri).await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
+
+    // 11 bytes of JSON, with and without padding
+    for payload in [
+        BASE64_URL_SAFE.encode(r#""123456789""#),
+        BASE64_URL_SAFE_NO_PAD.encode(r#""123456789""#),
+    ] {
+        let uri = format!("/{DNA_HASH}/coordinator/zome_name/fn_name?payload={payload}");
+        let (status_code, _) = router.request(&uri).await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+    }
+}
+
 #[tokio::test]
 async fn payload_with_invalid_base64_encoding_is_rejected() {
     initialize_testing_tracing_subscriber();
@@ -142,7 +177,7 @@ async fn payload_with_invalid_base64_encoding_is_rejected() {
     assert_eq!(status_code, StatusCode::BAD_REQUEST);
     assert_eq!(
         body,
-        r#"{"error":"Request is malformed: Invalid base64 encoding"}"#
+        r#"{"error":"Request is malformed: Invalid base64 encoding","request_id":"test-request-id"}"#
     );
 }
 
@@ -157,6 +192,23 @@ async fn payload_with_invalid_json_is_rejected() {
     assert_eq!(status_code, StatusCode::BAD_REQUEST);
     assert_eq!(
         body,
-        r#"{"error":"Request is malformed: Invalid JSON value"}"#
+        r#"{"error":"Request is malformed: Invalid JSON value: key must be a string at line 1 column 2, near \"{invalid}\"","request_id":"test-request-id"}"#
+    );
+}
+
+#[tokio::test]
+async fn cap_secret_with_invalid_length_is_rejected() {
+    initialize_testing_tracing_subscriber();
+
+    let router = TestRouter::new();
+    let cap_secret = BASE64_URL_SAFE.encode([7; 32]);
+    let uri = format!("/{DNA_HASH}/coordinator/zome_name/fn_name");
+    let (status_code, body) = router
+        .request_with_headers(&uri, &[(CAP_SECRET_HEADER, &cap_secret)])
+        .await;
+    assert_eq!(status_code, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body,
+        r#"{"error":"Request is malformed: Invalid capability secret","request_id":"test-request-id"}"#
     );
 }
@@ -0,0 +1,263 @@
+//! Periodic self-check of the gateway, logged as a heartbeat.
+//!
+//! A gateway that hangs, for example on a deadlocked lock of a connection pool, keeps its port open
+//! but produces no errors and no logs. The self-check regularly verifies that the listener accepts
+//! connections, that the conductors are reachable and that the caches and connection pools respond,
+//! and logs a heartbeat line with the key gauges. Failed checks are logged as warnings, and a
+//! missing heartbeat shows that the gateway itself is stuck.
+
+use crate::Conductors;
+use crate::metrics::Metrics;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+/// Outcome of a self-check.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SelfCheck {
+    listener_reachable: bool,
+    conductors: usize,
+    healthy_conductors: usize,
+    cached_apps: usize,
+    open_admin_connections: u64,
+    open_app_connections: u64,
+    recent_requests: u64,
+    recent_server_errors: u64,
+    /// What failed, if anything.
+    problems: Vec<String>,
+}
+
+/// Spawn a task that checks the gateway at the given interval and logs a heartbeat.
+///
+/// The listener is checked by connecting to the given address, that the gateway listens on. A
+/// check that doesn't complete within the interval fails.
+pub(crate) fn spawn_self_check(
+    address: SocketAddr,
+    conductors: Conductors,
+    metrics: Arc<Metrics>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately, before the server is started
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+
+            let check = self_check(address, &conductors, &metrics, interval).await;
+            tracing::info!(
+                ok = check.problems.is_empty(),
+                listener_reachable = check.listener_reachable,
+                conductors = check.conductors,
+                healthy_conductors = check.healthy_conductors,
+                cached_apps = check.cached_apps,
+                open_admin_connections = check.open_admin_connections,
+                open_app_connections = check.open_app_connections,
+                recent_requests = check.recent_requests,
+                recent_server_errors = check.recent_server_errors,
+                "Heartbeat"
+            );
+            for problem in &check.problems {
+                tracing::warn!(problem, "Self-check failed");
+            }
+        }
+    })
+}
+
+async fn self_check(
+    address: SocketAddr,
+    conductors: &Conductors,
+    metrics: &Metrics,
+    timeout: Duration,
+) -> SelfCheck {
+    let mut check = SelfCheck {
+        listener_reachable: matches!(
+            tokio::time::timeout(timeout, TcpStream::connect(connectable(address))).await,
+            Ok(Ok(_))
+        ),
+        ..Default::default()
+    };
+    if !check.listener_reachable {
+        check.problems.push(format!(
+            "Listener on {address} is not accepting connections"
+        ));
+    }
+
+    for conductor in conductors.iter() {
+        check.conductors += 1;
+        if conductor.health.is_healthy() {
+            check.healthy_conductors += 1;
+        } else {
+            check
+                .problems
+                .push(format!("Conductor {} is unreachable", conductor.name));
+        }
+
+        match tokio::time::timeout(timeout, conductor.app_info_cache.read()).await {
+            Ok(cached_apps) => check.cached_apps += cached_apps.len(),
+            Err(_) => check.problems.push(format!(
+                "App info cache of conductor {} is locked",
+                conductor.name
+            )),
+        }
+
+        // The connection counters are read under the locks of the connection pools, which would
+        // block the runtime if they were deadlocked. A blocked thread is left behind in that case.
+        let handles = conductor.clone();
+        let counters = tokio::task::spawn_blocking(move || {
+            (
+                handles.admin_call.connection_counters(),
+                handles.app_call.connection_counters(),
+            )
+        });
+        match tokio::time::timeout(timeout, counters).await {
+            Ok(Ok((admin, app))) => {
+                check.open_admin_connections += admin.open;
+                check.open_app_connections += app.open;
+            }
+            Ok(Err(e)) => check.problems.push(format!(
+                "Reading the connection pools of conductor {} failed: {e}",
+                conductor.name
+            )),
+            Err(_) => check.problems.push(format!(
+                "Connection pools of conductor {} are not responding",
+                conductor.name
+            )),
+        }
+    }
+
+    (check.recent_requests, check.recent_server_errors) =
+        metrics.recent_requests_and_server_errors();
+
+    check
+}
+
+/// The address to connect to for reaching a listener bound to the given address.
+///
+/// A listener bound to the unspecified address is reached over the loopback interface.
+fn connectable(address: SocketAddr) -> SocketAddr {
+    match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), address.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), address.port())
+        }
+        _ => address,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_CONDUCTOR_NAME;
+    use crate::test::data::new_test_app_info;
+    use crate::{ConductorHandle, ConnectionCounters, MockAdminCall, MockAppCall};
+    use holochain_types::dna::DnaHash;
+    use tokio::net::TcpListener;
+
+    fn conductors() -> Conductors {
+        let mut admin_call = MockAdminCall::new();
+        admin_call
+            .expect_connection_counters()
+            .returning(|| ConnectionCounters {
+                open: 1,
+                ..Default::default()
+            });
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_connection_counters()
+            .returning(|| ConnectionCounters {
+                open: 2,
+                ..Default::default()
+            });
+        let mut conductors = Conductors::default();
+        conductors.insert(ConductorHandle::new(
+            DEFAULT_CONDUCTOR_NAME,
+            "app".parse().unwrap(),
+            Arc::new(admin_call),
+            Arc::new(app_call),
+        ));
+        conductors
+    }
+
+    #[tokio::test]
+    async fn healthy_gateway_passes_the_self_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let conductors = conductors();
+        *conductors
+            .iter()
+            .next()
+            .unwrap()
+            .app_info_cache
+            .write()
+            .await = vec![new_test_app_info("app", DnaHash::from_raw_32(vec![1; 32]))];
+
+        let check = self_check(
+            listener.local_addr().unwrap(),
+            &conductors,
+            &Metrics::new(10),
+            Duration::from_secs(1),
+        )
+        .await;
+        assert_eq!(
+            check,
+            SelfCheck {
+                listener_reachable: true,
+                conductors: 1,
+                healthy_conductors: 1,
+                cached_apps: 1,
+                open_admin_connections: 1,
+                open_app_connections: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn hangs_and_outages_fail_the_self_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let conductors = conductors();
+        let conductor = conductors.iter().next().unwrap();
+        conductor.record_health(false);
+        let _locked = conductor.app_info_cache.write().await;
+
+        let check = self_check(
+            address,
+            &conductors,
+            &Metrics::new(10),
+            Duration::from_millis(100),
+        )
+        .await;
+        assert!(!check.listener_reachable);
+        assert_eq!(check.healthy_conductors, 0);
+        assert_eq!(
+            check.problems,
+            vec![
+                format!("Listener on {address} is not accepting connections"),
+                "Conductor default is unreachable".to_string(),
+                "App info cache of conductor default is locked".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn unspecified_addresses_are_reached_over_loopback() {
+        assert_eq!(
+            connectable("0.0.0.0:8090".parse().unwrap()),
+            "127.0.0.1:8090".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            connectable("[::]:8090".parse().unwrap()),
+            "[::1]:8090".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            connectable("192.0.2.1:8090".parse().unwrap()),
+            "192.0.2.1:8090".parse::<SocketAddr>().unwrap()
+        );
+    }
+}
@@ -1,31 +1,72 @@
 //! HTTP gateway service for Holochain
 
-use crate::app_selection::AppInfoCache;
-use crate::holochain::{AdminCall, AppCall};
+use crate::drain::DrainHandle;
+use crate::holochain::{AdminCall, AppCall, Conductors};
+use crate::metrics::Metrics;
+use crate::response_cache::ResponseCache;
+use crate::self_check::spawn_self_check;
+use crate::shutdown::ShutdownHandle;
+use crate::status::GatewayStatus;
+use crate::transform::{Transform, Transforms};
 use crate::{config::Configuration, router::hc_http_gateway_router};
 use axum::Router;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
 
 /// Core Holochain HTTP gateway service
 #[derive(Debug)]
 pub struct HcHttpGatewayService {
     listener: TcpListener,
-    router: Router,
+    state: AppState,
+    drain: DrainHandle,
+    shutdown: ShutdownHandle,
 }
 
 /// Shared application state
+///
+/// The state is cloned for every request, so the configuration is shared rather than copied.
 #[derive(Debug, Clone)]
 pub struct AppState {
-    pub configuration: Configuration,
-    pub admin_call: Arc<dyn AdminCall>,
-    pub app_call: Arc<dyn AppCall>,
-    pub app_info_cache: AppInfoCache,
+    pub configuration: Arc<Configuration>,
+    pub conductors: Conductors,
+    pub transforms: Transforms,
+    pub metrics: Arc<Metrics>,
+    pub response_cache: ResponseCache,
+    /// When the gateway was started.
+    pub started: Instant,
+}
+
+impl AppState {
+    /// Create the state of a gateway that starts now, with the response cache that the
+    /// configuration asks for.
+    pub fn new(
+        configuration: Arc<Configuration>,
+        conductors: Conductors,
+        metrics: Arc<Metrics>,
+        transforms: Transforms,
+    ) -> Self {
+        Self {
+            response_cache: ResponseCache::from_configuration(&configuration),
+            configuration,
+            conductors,
+            transforms,
+            metrics,
+            started: Instant::now(),
+        }
+    }
+
+    /// The status of the gateway, see [`GatewayStatus`].
+    pub async fn status(&self) -> GatewayStatus {
+        GatewayStatus::of(self).await
+    }
 }
 
 impl HcHttpGatewayService {
-    /// Create a new service instance bound to the given address and port
+    /// Create a new service instance bound to the given address and port, that routes requests
+    /// to a single conductor using the given admin and app call handles.
     pub async fn new(
         address: impl Into<IpAddr>,
         port: u16,
@@ -33,14 +74,62 @@ impl HcHttpGatewayService {
         admin_call: Arc<dyn AdminCall>,
         app_call: Arc<dyn AppCall>,
     ) -> std::io::Result<Self> {
-        tracing::info!("Configuration: {:?}", configuration);
+        let conductors = Conductors::single(&configuration, admin_call, app_call);
 
-        let router = hc_http_gateway_router(configuration, admin_call, app_call);
+        Self::with_conductors(address, port, configuration, conductors).await
+    }
 
+    /// Create a new service instance bound to the given address and port, that routes requests
+    /// to the given conductors.
+    pub async fn with_conductors(
+        address: impl Into<IpAddr>,
+        port: u16,
+        configuration: Configuration,
+        conductors: Conductors,
+    ) -> std::io::Result<Self> {
         let address = SocketAddr::new(address.into(), port);
         let listener = TcpListener::bind(address).await?;
 
-        Ok(HcHttpGatewayService { router, listener })
+        Ok(Self::with_listener(listener, configuration, conductors))
+    }
+
+    /// Create a new service instance that accepts connections on the given listener, and routes
+    /// requests to the given conductors.
+    ///
+    /// This allows the listener to be bound in a particular way, or to be inherited from the
+    /// process that started the gateway, see [`bind_listener`](crate::bind_listener) and
+    /// [`inherited_listener`](crate::inherited_listener).
+    pub fn with_listener(
+        listener: TcpListener,
+        configuration: Configuration,
+        conductors: Conductors,
+    ) -> Self {
+        tracing::info!("Configuration: {:?}", configuration);
+
+        let metrics = Arc::new(Metrics::from_configuration(&configuration));
+        HcHttpGatewayService {
+            listener,
+            state: AppState::new(
+                Arc::new(configuration),
+                conductors,
+                metrics,
+                Transforms::default(),
+            ),
+            drain: DrainHandle::default(),
+            shutdown: ShutdownHandle::default(),
+        }
+    }
+
+    /// Register a transform that rewrites the payloads and responses of zome calls, after the
+    /// transforms that are already registered, see [`Transforms`].
+    pub fn with_transform(mut self, transform: impl Transform + 'static) -> Self {
+        self.state.transforms.push(Arc::new(transform));
+        self
+    }
+
+    /// The status of the service, see [`GatewayStatus`].
+    pub async fn status(&self) -> GatewayStatus {
+        self.state.status().await
     }
 
     /// Get the socket address the service is configured to use
@@ -48,13 +137,260 @@ impl HcHttpGatewayService {
         self.listener.local_addr()
     }
 
-    /// Start the HTTP server and run until terminated
+    /// Get a handle for draining the service, see [`DrainHandle::drain`].
+    pub fn drain_handle(&self) -> DrainHandle {
+        self.drain.clone()
+    }
+
+    /// Get a handle for shutting down the service, see [`ShutdownHandle::shutdown`].
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Start the HTTP server in the background, and return a handle for checking on it and for
+    /// shutting it down.
+    ///
+    /// Must be called from within a Tokio runtime. Dropping the handle leaves the service running.
+    pub fn spawn(self) -> std::io::Result<GatewayHandle> {
+        let address = self.address()?;
+        let shutdown = self.shutdown.clone();
+        let state = self.state.clone();
+        let task = tokio::spawn(self.run());
+
+        Ok(GatewayHandle {
+            address,
+            shutdown,
+            state,
+            task: Some(task),
+        })
+    }
+
+    /// Start the HTTP server and run until terminated.
+    ///
+    /// Once the service starts draining, this returns as soon as the requests in flight have
+    /// finished, or when the configured drain timeout has passed.
+    ///
+    /// Once shutdown is requested, the service stops accepting connections and returns when the
+    /// open connections have finished their requests, or when the drain timeout has passed. The
+    /// connections to Holochain are closed before returning.
     pub async fn run(self) -> std::io::Result<()> {
         let address = self.address()?;
 
+        let configuration = self.state.configuration.clone();
+        let tasks = GatewayTasks::spawn(self.state.clone(), Some(address));
+
         tracing::info!("Starting server on {}", address);
-        axum::serve(self.listener, self.router).await?;
+        let shutdown = self.shutdown.clone();
+        let router = hc_http_gateway_router(self.state, self.drain.clone());
+        let server = axum::serve(
+            self.listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(async move { shutdown.requested().await });
+        let result = tokio::select! {
+            result = server.into_future() => result,
+            _ = self.drain.drained(configuration.drain_timeout) => Ok(()),
+            _ = async {
+                self.shutdown.requested().await;
+                tokio::time::sleep(configuration.drain_timeout).await;
+            } => {
+                tracing::warn!("Shutdown timeout elapsed with requests still in flight");
+                Ok(())
+            }
+        };
+
+        tasks.stop().await;
+
+        result
+    }
+}
+
+/// Handle to a gateway that runs in the background, see [`HcHttpGatewayService::spawn`].
+#[derive(Debug)]
+pub struct GatewayHandle {
+    address: SocketAddr,
+    shutdown: ShutdownHandle,
+    state: AppState,
+    /// The task that runs the service, until it has been waited for.
+    task: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl GatewayHandle {
+    /// The socket address that the gateway listens on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The status of the gateway, see [`GatewayStatus`].
+    pub async fn status(&self) -> GatewayStatus {
+        self.state.status().await
+    }
+
+    /// Get a handle for shutting down the gateway without waiting for it, for example from a
+    /// signal handler or a `Drop` implementation.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Shut the gateway down gracefully, and wait until it has stopped, see
+    /// [`HcHttpGatewayService::run`].
+    pub async fn shutdown(&mut self) -> std::io::Result<()> {
+        self.shutdown.shutdown();
+        self.stopped().await
+    }
+
+    /// Wait until the gateway has stopped, because it was shut down or drained, and return the
+    /// error that it stopped with, if any.
+    ///
+    /// Returns immediately once the gateway has been waited for before.
+    pub async fn stopped(&mut self) -> std::io::Result<()> {
+        let Some(task) = &mut self.task else {
+            return Ok(());
+        };
+        let result = task.await;
+        self.task = None;
+
+        result.map_err(std::io::Error::other)?
+    }
+}
+
+/// The gateway as a router, for serving it as part of another axum app rather than on a listener
+/// of its own.
+///
+/// The router can be nested under a path of the app, and shares the app's middleware and listener.
+/// The addresses of clients are only known to the gateway if the app is served with
+/// [`Router::into_make_service_with_connect_info`] for [`SocketAddr`].
+#[derive(Debug)]
+pub struct HcHttpGatewayRouter {
+    state: AppState,
+    drain: DrainHandle,
+}
+
+impl HcHttpGatewayRouter {
+    /// Create a gateway router that routes requests to the given conductors.
+    pub fn new(configuration: Configuration, conductors: Conductors) -> Self {
+        tracing::info!("Configuration: {:?}", configuration);
+
+        let metrics = Arc::new(Metrics::from_configuration(&configuration));
+        HcHttpGatewayRouter {
+            state: AppState::new(
+                Arc::new(configuration),
+                conductors,
+                metrics,
+                Transforms::default(),
+            ),
+            drain: DrainHandle::default(),
+        }
+    }
+
+    /// Register a transform that rewrites the payloads and responses of zome calls, after the
+    /// transforms that are already registered, see [`Transforms`].
+    pub fn with_transform(mut self, transform: impl Transform + 'static) -> Self {
+        self.state.transforms.push(Arc::new(transform));
+        self
+    }
+
+    /// Get a handle for draining the router, see [`DrainHandle::drain`].
+    pub fn drain_handle(&self) -> DrainHandle {
+        self.drain.clone()
+    }
+
+    /// Build the router, and start the tasks that keep the connections to Holochain healthy and
+    /// export metrics.
+    ///
+    /// Must be called from within a Tokio runtime. The tasks run until they are
+    /// [stopped](GatewayTasks::stop), which should be done once the router is no longer served.
+    pub fn build(self) -> (Router, GatewayTasks) {
+        let tasks = GatewayTasks::spawn(self.state.clone(), None);
+        let router = hc_http_gateway_router(self.state, self.drain);
+
+        (router, tasks)
+    }
+}
+
+/// The tasks that run alongside the router of the gateway, which keep the connections to Holochain
+/// healthy and export metrics.
+#[derive(Debug)]
+pub struct GatewayTasks {
+    state: AppState,
+    tasks: Vec<JoinHandle<()>>,
+    usage_export: Option<JoinHandle<()>>,
+}
+
+impl GatewayTasks {
+    /// Spawn the tasks. The self-check is only run for a gateway that is served on a listener of
+    /// its own, at the given address.
+    fn spawn(state: AppState, listener_address: Option<SocketAddr>) -> Self {
+        let AppState {
+            configuration,
+            conductors,
+            metrics,
+            ..
+        } = &state;
+        let mut tasks = vec![
+            conductors.spawn_health_checks(configuration.health_check_interval),
+            conductors.spawn_app_keepalives(configuration.app_keepalive_interval),
+            conductors.spawn_app_watchers(configuration.app_watch_interval),
+            conductors.spawn_restart_watchers(),
+        ];
+        tasks.extend(
+            configuration.statsd.as_ref().map(|statsd| {
+                statsd.spawn_gauges(conductors.clone(), configuration.statsd_interval)
+            }),
+        );
+        tasks.extend(
+            configuration
+                .usage_summary_interval
+                .and_then(|interval| metrics.spawn_usage_summaries(interval)),
+        );
+        let usage_export = configuration.usage_export_file.as_ref().map(|path| {
+            metrics.spawn_usage_export(
+                path.clone(),
+                configuration.usage_export_format,
+                configuration.usage_export_interval,
+            )
+        });
+        tasks.extend(listener_address.map(|address| {
+            spawn_self_check(
+                address,
+                conductors.clone(),
+                metrics.clone(),
+                configuration.self_check_interval,
+            )
+        }));
+
+        Self {
+            state,
+            tasks,
+            usage_export,
+        }
+    }
+
+    /// The status of the gateway that the tasks run for, see [`GatewayStatus`].
+    pub async fn status(&self) -> GatewayStatus {
+        self.state.status().await
+    }
 
-        Ok(())
+    /// Stop the tasks and close the connections to Holochain.
+    pub async fn stop(self) {
+        for task in self.tasks {
+            task.abort();
+        }
+        let AppState {
+            configuration,
+            conductors,
+            metrics,
+            ..
+        } = self.state;
+        if let Some(usage_export) = self.usage_export {
+            usage_export.abort();
+            // Export the usage since the last export, so that it isn't lost on a restart
+            if let Some(path) = &configuration.usage_export_file
+                && let Err(e) = metrics.export_usage(path, configuration.usage_export_format)
+            {
+                tracing::warn!(?e, ?path, "Failed to export usage");
+            }
+        }
+        conductors.close_connections().await;
     }
 }
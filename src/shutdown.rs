@@ -0,0 +1,65 @@
+//! Graceful shutdown of the gateway.
+//!
+//! Once shutdown is requested, the gateway stops accepting connections and closes idle ones,
+//! lets the requests in flight finish, and then closes its connections to Holochain.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Handle for shutting down the gateway.
+///
+/// Clones share the same state, so a handle can be taken from the service before it is run and
+/// used to shut it down from elsewhere, for example a signal handler or a test.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle(Arc<watch::Sender<bool>>);
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(false)))
+    }
+}
+
+impl ShutdownHandle {
+    /// Request the gateway to shut down. Does nothing if shutdown was already requested.
+    pub fn shutdown(&self) {
+        if !self.0.send_replace(true) {
+            tracing::info!("Shutting down");
+        }
+    }
+
+    /// Whether shutdown has been requested.
+    pub fn is_shutting_down(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait until shutdown is requested.
+    pub(crate) async fn requested(&self) {
+        let mut shutdown = self.0.subscribe();
+        // The sender is held by this handle, so it can't be closed while waiting
+        let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn requested_waits_for_shutdown() {
+        let shutdown = ShutdownHandle::default();
+        let requested = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.requested().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!requested.is_finished());
+
+        shutdown.shutdown();
+        assert!(shutdown.is_shutting_down());
+        tokio::time::timeout(Duration::from_secs(1), requested)
+            .await
+            .expect("Shutdown was not requested")
+            .unwrap();
+    }
+}
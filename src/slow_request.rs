@@ -0,0 +1,163 @@
+//! Detection of slow requests.
+//!
+//! Requests that take longer than the configured threshold are logged at WARN level, with the time
+//! that was spent in each phase of handling the request, so that latency regressions show up in
+//! the logs without a tracing backend.
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    static TIMINGS: Timings;
+}
+
+/// A phase of handling a zome call request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    /// Finding the app and conductor to call.
+    Selection,
+    /// Opening a websocket to the app.
+    Connect,
+    /// Calling the zome function, including any time spent connecting.
+    Call,
+    /// Transcoding the payload and the response.
+    Transcode,
+}
+
+/// The time spent in each phase of handling a request.
+#[derive(Debug, Default)]
+struct Timings {
+    selection: Cell<Duration>,
+    connect: Cell<Duration>,
+    call: Cell<Duration>,
+    transcode: Cell<Duration>,
+}
+
+impl Timings {
+    fn phase(&self, phase: Phase) -> &Cell<Duration> {
+        match phase {
+            Phase::Selection => &self.selection,
+            Phase::Connect => &self.connect,
+            Phase::Call => &self.call,
+            Phase::Transcode => &self.transcode,
+        }
+    }
+
+    /// The time spent calling the zome function, excluding the time spent connecting.
+    fn call_without_connect(&self) -> Duration {
+        self.call.get().saturating_sub(self.connect.get())
+    }
+}
+
+/// Add time spent in a phase to the timings of the current request.
+///
+/// Does nothing if slow request detection is disabled.
+pub(crate) fn record_timing(phase: Phase, duration: Duration) {
+    let _ = TIMINGS.try_with(|timings| {
+        let phase = timings.phase(phase);
+        phase.set(phase.get() + duration);
+    });
+}
+
+/// Middleware that logs requests that take longer than the given threshold.
+pub(crate) async fn log_slow_requests(
+    State(threshold): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    let (timings, response) = TIMINGS
+        .scope(Timings::default(), async move {
+            let response = next.run(request).await;
+            (TIMINGS.with(timings_ms), response)
+        })
+        .await;
+
+    let duration = started.elapsed();
+    if duration > threshold {
+        let [selection_ms, connect_ms, call_ms, transcode_ms] = timings;
+        tracing::warn!(
+            %method,
+            path,
+            status = response.status().as_u16(),
+            duration_ms = as_ms(duration),
+            selection_ms,
+            connect_ms,
+            call_ms,
+            transcode_ms,
+            "Slow request"
+        );
+    }
+
+    response
+}
+
+/// The selection, connect, call and transcode timings in milliseconds.
+fn timings_ms(timings: &Timings) -> [f64; 4] {
+    [
+        as_ms(timings.selection.get()),
+        as_ms(timings.connect.get()),
+        as_ms(timings.call_without_connect()),
+        as_ms(timings.transcode.get()),
+    ]
+}
+
+fn as_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::router::TestRouter;
+    use crate::{AllowedFns, Configuration};
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    #[tokio::test]
+    async fn timings_are_recorded_per_phase() {
+        let timings = TIMINGS
+            .scope(Timings::default(), async {
+                record_timing(Phase::Selection, Duration::from_millis(1));
+                record_timing(Phase::Connect, Duration::from_millis(2));
+                record_timing(Phase::Call, Duration::from_millis(5));
+                record_timing(Phase::Transcode, Duration::from_millis(1));
+                record_timing(Phase::Transcode, Duration::from_millis(1));
+                TIMINGS.with(timings_ms)
+            })
+            .await;
+
+        // The time spent connecting is not counted as part of the call
+        assert_eq!(timings, [1.0, 2.0, 3.0, 2.0]);
+
+        // Timings are not recorded outside of a request
+        record_timing(Phase::Call, Duration::from_millis(1));
+    }
+
+    #[tokio::test]
+    async fn slow_requests_are_answered() {
+        let mut config = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "coordinator",
+            HashMap::from([("coordinator".to_string(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap();
+        config.slow_request_threshold = Some(Duration::ZERO);
+        let router = TestRouter::new_with_config(config);
+
+        let (status_code, body) = router
+            .request("/uhC0kAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQF-z86-/coordinator/zome_name/fn_name")
+            .await;
+        assert_eq!(status_code, 200);
+        assert_eq!(body, "null");
+    }
+}
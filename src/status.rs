@@ -0,0 +1,148 @@
+//! Status of a running gateway, for the applications that embed it and for the management API.
+
+use crate::holochain::{AppConnectionStats, ConnectionCounters};
+use crate::service::AppState;
+use serde::{Deserialize, Serialize};
+
+/// Status of a gateway and of its connections to Holochain.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GatewayStatus {
+    /// Time in seconds since the gateway was started.
+    pub uptime_secs: u64,
+    /// The number of responses in the response cache, or `None` if responses are cached in Redis,
+    /// where the gateway doesn't count them.
+    pub response_cache_entries: Option<usize>,
+    /// Status of each conductor that the gateway routes requests to.
+    pub conductors: Vec<ConductorStatus>,
+}
+
+/// Status of the gateway's connections to a conductor.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConductorStatus {
+    /// Name that identifies the conductor.
+    pub name: String,
+    /// Whether the conductor was reachable when it was last checked.
+    pub healthy: bool,
+    /// The number of apps in the app info cache of the conductor.
+    pub cached_apps: usize,
+    /// Connections to the admin interface of the conductor.
+    pub admin_connections: ConnectionCounters,
+    /// App websocket connections to the conductor.
+    pub app_connections: ConnectionCounters,
+    /// The app websockets that are open in the connection pool, by app.
+    pub app_connection_pool: Vec<AppConnectionStats>,
+}
+
+impl GatewayStatus {
+    /// Read the status of the gateway with the given state.
+    pub(crate) async fn of(state: &AppState) -> Self {
+        let mut conductors = Vec::new();
+        for conductor in state.conductors.iter() {
+            conductors.push(ConductorStatus {
+                name: conductor.name.clone(),
+                healthy: conductor.health.is_healthy(),
+                cached_apps: conductor.app_info_cache.read().await.len(),
+                admin_connections: conductor.admin_call.connection_counters(),
+                app_connections: conductor.app_call.connection_counters(),
+                app_connection_pool: conductor.app_call.app_connection_stats(),
+            });
+        }
+
+        Self {
+            uptime_secs: state.started.elapsed().as_secs(),
+            response_cache_entries: state.response_cache.len(),
+            conductors,
+        }
+    }
+
+    /// Whether all conductors were reachable when they were last checked.
+    pub fn is_healthy(&self) -> bool {
+        self.conductors.iter().all(|conductor| conductor.healthy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_CONDUCTOR_NAME;
+    use crate::test::data::new_test_app_info;
+    use crate::{
+        AllowedFns, ConductorHandle, Conductors, Configuration, Metrics, MockAdminCall,
+        MockAppCall, Transforms,
+    };
+    use holochain_types::dna::DnaHash;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn status_is_read_from_the_state() {
+        let mut admin_call = MockAdminCall::new();
+        admin_call
+            .expect_connection_counters()
+            .returning(|| ConnectionCounters {
+                open: 1,
+                ..Default::default()
+            });
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_connection_counters()
+            .returning(|| ConnectionCounters {
+                open: 2,
+                connect_attempts: 3,
+                ..Default::default()
+            });
+        app_call.expect_app_connection_stats().returning(Vec::new);
+        let mut conductors = Conductors::default();
+        conductors.insert(ConductorHandle::new(
+            DEFAULT_CONDUCTOR_NAME,
+            "app".parse().unwrap(),
+            Arc::new(admin_call),
+            Arc::new(app_call),
+        ));
+        let conductor = conductors.iter().next().unwrap();
+        conductor.record_health(false);
+        *conductor.app_info_cache.write().await =
+            vec![new_test_app_info("app", DnaHash::from_raw_32(vec![1; 32]))];
+        let configuration = Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            "app",
+            HashMap::from([("app".to_string(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap();
+        let state = AppState::new(
+            Arc::new(configuration),
+            conductors,
+            Arc::new(Metrics::new(10)),
+            Transforms::default(),
+        );
+
+        let status = GatewayStatus::of(&state).await;
+        assert_eq!(
+            status,
+            GatewayStatus {
+                uptime_secs: 0,
+                response_cache_entries: Some(0),
+                conductors: vec![ConductorStatus {
+                    name: DEFAULT_CONDUCTOR_NAME.to_string(),
+                    healthy: false,
+                    cached_apps: 1,
+                    admin_connections: ConnectionCounters {
+                        open: 1,
+                        ..Default::default()
+                    },
+                    app_connections: ConnectionCounters {
+                        open: 2,
+                        connect_attempts: 3,
+                        ..Default::default()
+                    },
+                    app_connection_pool: Vec::new(),
+                }],
+            }
+        );
+        assert!(!status.is_healthy());
+    }
+}
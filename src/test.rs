@@ -1,6 +1,10 @@
 //! Test support module
 
+pub mod chaos;
 pub mod data;
+#[cfg(feature = "test-utils")]
+pub mod gateway;
+pub mod mock_conductor;
 #[cfg(test)]
 pub mod router;
 pub mod test_tracing;
@@ -0,0 +1,524 @@
+//! Fault injection into the calls that the gateway makes to its conductors.
+//!
+//! [`Chaos`] wraps the [`AdminCall`] and [`AppCall`] handles of conductors and injects latency,
+//! dropped websockets and error responses into their calls, so that the reconnection, eviction and
+//! failover behavior of the gateway can be tested without restarting a conductor. Faults are
+//! injected on a [`Schedule`] counted in calls rather than at random, so that tests using them are
+//! deterministic.
+
+use crate::{
+    AdminCall, AppCall, AppConnectionStats, Conductors, ConnectionCounters, HcHttpGatewayError,
+    HcHttpGatewayResult, ZomeCallRequest,
+};
+use futures::future::BoxFuture;
+use holochain_client::{
+    AppInfo, AuthorizeSigningCredentialsPayload, ConductorApiError, ExternIO, SigningCredentials,
+};
+use holochain_conductor_api::{
+    AppAuthenticationTokenIssued, AppInterfaceInfo, AppStatusFilter,
+    IssueAppAuthenticationTokenPayload, StorageInfo,
+};
+use holochain_types::app::InstalledAppId;
+use holochain_types::dna::DnaHash;
+use holochain_types::websocket::AllowedOrigins;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// The calls that a fault is injected into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Admin calls, such as the listing of apps and the health checks.
+    Admin,
+    /// Zome calls.
+    ZomeCall,
+}
+
+/// Which calls to a [`Target`] a fault is injected into, counted from when it was injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// The given number of next calls.
+    Next(usize),
+    /// Every call whose number is a multiple of the given number, such as every second call.
+    Every(usize),
+    /// Every call, until [`Chaos::clear`].
+    Always,
+}
+
+/// A fault that is injected into a call.
+#[derive(Clone)]
+pub enum Fault {
+    /// Delay the call by the given time.
+    ///
+    /// A zome call with a timeout shorter than the delay fails with
+    /// [`HcHttpGatewayError::ZomeCallTimeout`] once the timeout has passed.
+    Latency(Duration),
+    /// Fail the call as if its websocket was dropped by the conductor.
+    ///
+    /// A dropped admin websocket is announced as a reconnect, see
+    /// [`AdminCall::subscribe_reconnects`], as it would be once the connection was re-established.
+    /// A dropped app websocket evicts the app connection of the wrapped [`AppCall`], so that the
+    /// next zome call to the app opens a new one.
+    Drop,
+    /// Fail the call with the error that is given.
+    Error(Arc<dyn Fn() -> HcHttpGatewayError + Send + Sync>),
+}
+
+impl Fault {
+    /// Fail the call with the error that is given.
+    pub fn error(error: impl Fn() -> HcHttpGatewayError + Send + Sync + 'static) -> Self {
+        Self::Error(Arc::new(error))
+    }
+}
+
+impl std::fmt::Debug for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Latency(latency) => f.debug_tuple("Latency").field(latency).finish(),
+            Self::Drop => f.write_str("Drop"),
+            Self::Error(_) => f.write_str("Error"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Injection {
+    target: Target,
+    fault: Fault,
+    schedule: Schedule,
+    /// Number of calls to the target since the fault was injected.
+    calls: usize,
+}
+
+impl Injection {
+    /// Count a call to the target, and whether the fault applies to it.
+    fn applies(&mut self) -> bool {
+        self.calls += 1;
+        match self.schedule {
+            Schedule::Next(calls) => self.calls <= calls,
+            Schedule::Every(calls) => calls > 0 && self.calls.is_multiple_of(calls),
+            Schedule::Always => true,
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        matches!(self.schedule, Schedule::Next(calls) if self.calls >= calls)
+    }
+}
+
+/// The faults that apply to a single call.
+#[derive(Default)]
+struct Faults {
+    latency: Duration,
+    /// The first injected fault that fails the call, if any.
+    failure: Option<Fault>,
+}
+
+/// Injects faults into the calls of the conductors that it wraps.
+///
+/// Clones share the same faults, so that a test can keep a clone to inject faults while the
+/// gateway uses the wrapped conductors.
+#[derive(Debug, Clone, Default)]
+pub struct Chaos {
+    injections: Arc<Mutex<Vec<Injection>>>,
+}
+
+impl Chaos {
+    /// Create a fault injector without any faults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inject a fault into the calls to the target on the given schedule.
+    ///
+    /// Faults that apply to the same call are combined, their latencies add up and the call fails
+    /// with the failure that was injected first.
+    pub fn inject(&self, target: Target, fault: Fault, schedule: Schedule) {
+        self.injections().push(Injection {
+            target,
+            fault,
+            schedule,
+            calls: 0,
+        });
+    }
+
+    /// Remove every fault.
+    pub fn clear(&self) {
+        self.injections().clear();
+    }
+
+    /// Wrap the calls of every conductor, so that the faults are injected into them.
+    pub fn conductors(&self, conductors: Conductors) -> Conductors {
+        let mut wrapped = Conductors::default();
+        for conductor in conductors.iter() {
+            let chaos = Arc::new(ChaosConn::new(
+                self.clone(),
+                conductor.admin_call.clone(),
+                conductor.app_call.clone(),
+            ));
+            let mut conductor = conductor.clone();
+            conductor.admin_call = chaos.clone();
+            conductor.app_call = chaos;
+            wrapped.insert(conductor);
+        }
+        wrapped
+    }
+
+    fn injections(&self) -> std::sync::MutexGuard<'_, Vec<Injection>> {
+        self.injections.lock().expect("Invalid lock")
+    }
+
+    /// Count a call to the target, and get the faults that apply to it.
+    fn faults(&self, target: Target) -> Faults {
+        let mut injections = self.injections();
+        let mut faults = Faults::default();
+        for injection in injections
+            .iter_mut()
+            .filter(|injection| injection.target == target)
+        {
+            if !injection.applies() {
+                continue;
+            }
+            match &injection.fault {
+                Fault::Latency(latency) => faults.latency += *latency,
+                failure => {
+                    faults.failure.get_or_insert_with(|| failure.clone());
+                }
+            }
+        }
+        injections.retain(|injection| !injection.is_exhausted());
+
+        faults
+    }
+}
+
+/// The error of a call whose websocket was dropped.
+fn dropped() -> HcHttpGatewayError {
+    HcHttpGatewayError::HolochainError(ConductorApiError::WebsocketError(
+        std::io::Error::other("Websocket dropped by fault injection").into(),
+    ))
+}
+
+/// The admin and app calls of a conductor, with faults injected into them.
+#[derive(Debug)]
+struct ChaosConn {
+    chaos: Chaos,
+    admin_call: Arc<dyn AdminCall>,
+    app_call: Arc<dyn AppCall>,
+    reconnects: Arc<watch::Sender<u64>>,
+    /// Whether the reconnects of the wrapped admin call are forwarded to `reconnects`.
+    forwarding_reconnects: AtomicBool,
+}
+
+impl ChaosConn {
+    fn new(chaos: Chaos, admin_call: Arc<dyn AdminCall>, app_call: Arc<dyn AppCall>) -> Self {
+        Self {
+            chaos,
+            admin_call,
+            app_call,
+            reconnects: Arc::new(watch::Sender::new(0)),
+            forwarding_reconnects: AtomicBool::new(false),
+        }
+    }
+
+    /// Make an admin call once the injected latency has passed, unless an injected fault fails it.
+    fn admin_call<T: Send + 'static>(
+        &self,
+        call: impl FnOnce(Arc<dyn AdminCall>) -> BoxFuture<'static, HcHttpGatewayResult<T>>
+        + Send
+        + 'static,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<T>> {
+        let faults = self.chaos.faults(Target::Admin);
+        let admin_call = self.admin_call.clone();
+        let reconnects = self.reconnects.clone();
+
+        Box::pin(async move {
+            tokio::time::sleep(faults.latency).await;
+            match faults.failure {
+                None => call(admin_call).await,
+                Some(Fault::Error(error)) => Err(error()),
+                Some(_) => {
+                    reconnects.send_modify(|reconnects| *reconnects += 1);
+                    Err(dropped())
+                }
+            }
+        })
+    }
+}
+
+impl AdminCall for ChaosConn {
+    fn list_app_interfaces(
+        &self,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<AppInterfaceInfo>>> {
+        self.admin_call(|admin_call| admin_call.list_app_interfaces())
+    }
+
+    fn issue_app_auth_token(
+        &self,
+        payload: IssueAppAuthenticationTokenPayload,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<AppAuthenticationTokenIssued>> {
+        self.admin_call(move |admin_call| admin_call.issue_app_auth_token(payload))
+    }
+
+    fn authorize_signing_credentials(
+        &self,
+        payload: AuthorizeSigningCredentialsPayload,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<SigningCredentials>> {
+        self.admin_call(move |admin_call| admin_call.authorize_signing_credentials(payload))
+    }
+
+    fn attach_app_interface(
+        &self,
+        port: u16,
+        allowed_origins: AllowedOrigins,
+        installed_app_id: Option<String>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<u16>> {
+        self.admin_call(move |admin_call| {
+            admin_call.attach_app_interface(port, allowed_origins, installed_app_id)
+        })
+    }
+
+    fn list_apps(
+        &self,
+        status_filter: Option<AppStatusFilter>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<AppInfo>>> {
+        self.admin_call(move |admin_call| admin_call.list_apps(status_filter))
+    }
+
+    fn storage_info(&self) -> BoxFuture<'static, HcHttpGatewayResult<StorageInfo>> {
+        self.admin_call(|admin_call| admin_call.storage_info())
+    }
+
+    fn agent_info(
+        &self,
+        dna_hashes: Option<Vec<DnaHash>>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<String>>> {
+        self.admin_call(move |admin_call| admin_call.agent_info(dna_hashes))
+    }
+
+    #[cfg(feature = "conductor-state-dump")]
+    fn dump_conductor_state(&self) -> BoxFuture<'static, HcHttpGatewayResult<String>> {
+        self.admin_call(|admin_call| admin_call.dump_conductor_state())
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        self.admin_call.close_connections()
+    }
+
+    fn subscribe_reconnects(&self) -> watch::Receiver<u64> {
+        if !self.forwarding_reconnects.swap(true, Ordering::AcqRel) {
+            let mut wrapped = self.admin_call.subscribe_reconnects();
+            let reconnects = self.reconnects.clone();
+            tokio::spawn(async move {
+                while wrapped.changed().await.is_ok() {
+                    reconnects.send_modify(|reconnects| *reconnects += 1);
+                }
+            });
+        }
+
+        self.reconnects.subscribe()
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        self.admin_call.connection_counters()
+    }
+}
+
+impl AppCall for ChaosConn {
+    fn handle_zome_call(
+        &self,
+        request: ZomeCallRequest,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        let faults = self.chaos.faults(Target::ZomeCall);
+        let app_call = self.app_call.clone();
+
+        Box::pin(async move {
+            match request.timeout {
+                Some(timeout) if timeout < faults.latency => {
+                    tokio::time::sleep(timeout).await;
+                    return Err(HcHttpGatewayError::ZomeCallTimeout);
+                }
+                _ => tokio::time::sleep(faults.latency).await,
+            }
+            match faults.failure {
+                None => app_call.handle_zome_call(request).await,
+                Some(Fault::Error(error)) => Err(error()),
+                Some(_) => {
+                    app_call
+                        .remove_app_connection(request.installed_app_id)
+                        .await;
+                    Err(dropped())
+                }
+            }
+        })
+    }
+
+    fn evict_stale_connections(&self) -> BoxFuture<'static, ()> {
+        self.app_call.evict_stale_connections()
+    }
+
+    fn remove_app_connection(&self, installed_app_id: InstalledAppId) -> BoxFuture<'static, ()> {
+        self.app_call.remove_app_connection(installed_app_id)
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        self.app_call.close_connections()
+    }
+
+    fn reset_after_restart(&self) -> BoxFuture<'static, ()> {
+        self.app_call.reset_after_restart()
+    }
+
+    fn app_connection_stats(&self) -> Vec<AppConnectionStats> {
+        self.app_call.app_connection_stats()
+    }
+
+    fn clear_cached_app_ports(&self) {
+        self.app_call.clear_cached_app_ports()
+    }
+
+    fn reconnect_app(
+        &self,
+        installed_app_id: InstalledAppId,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<()>> {
+        self.app_call.reconnect_app(installed_app_id)
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        self.app_call.connection_counters()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::mock_conductor::MockConductor;
+    use crate::{AllowedFns, ConductorHandle, Configuration, MockAppCall, UpstreamConnection};
+    use holochain_types::prelude::{AgentPubKey, CellId};
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    const APP_ID: &str = "tapp";
+
+    fn test_config() -> Configuration {
+        Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8888),
+            "",
+            APP_ID,
+            HashMap::from([(APP_ID.into(), AllowedFns::All)]),
+            "",
+            "",
+        )
+        .unwrap()
+    }
+
+    fn zome_call() -> ZomeCallRequest {
+        ZomeCallRequest::new(
+            APP_ID,
+            CellId::new(
+                DnaHash::from_raw_32(vec![1; 32]),
+                AgentPubKey::from_raw_32(vec![1; 32]),
+            ),
+            "coordinator",
+            "fn_name",
+            ExternIO::encode(()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn faults_are_injected_on_schedule() {
+        let conductor = MockConductor::new();
+        conductor.respond("coordinator", "fn_name", ());
+        let chaos = Chaos::new();
+        let conductors = chaos.conductors(conductor.conductors(&test_config()));
+        let app_call = conductors.iter().next().unwrap().app_call.clone();
+
+        chaos.inject(
+            Target::ZomeCall,
+            Fault::error(|| HcHttpGatewayError::UpstreamUnavailable(UpstreamConnection::App)),
+            Schedule::Every(2),
+        );
+        let results =
+            futures::future::join_all((0..4).map(|_| app_call.handle_zome_call(zome_call())))
+                .await
+                .into_iter()
+                .map(|result| result.is_ok())
+                .collect::<Vec<_>>();
+        assert_eq!(results, [true, false, true, false]);
+
+        chaos.clear();
+        chaos.inject(
+            Target::ZomeCall,
+            Fault::Latency(Duration::from_millis(100)),
+            Schedule::Next(1),
+        );
+        let result = app_call
+            .handle_zome_call(zome_call().with_timeout(Duration::from_millis(10)))
+            .await;
+        assert!(matches!(result, Err(HcHttpGatewayError::ZomeCallTimeout)));
+        app_call
+            .handle_zome_call(zome_call().with_timeout(Duration::from_millis(10)))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_app_websocket_evicts_the_app_connection() {
+        let mut app_call = MockAppCall::new();
+        app_call
+            .expect_remove_app_connection()
+            .withf(|installed_app_id| installed_app_id == APP_ID)
+            .returning(|_| Box::pin(async {}))
+            .once();
+        let chaos = Chaos::new();
+        let conductors = chaos.conductors(Conductors::single(
+            &test_config(),
+            Arc::new(MockConductor::new()),
+            Arc::new(app_call),
+        ));
+        chaos.inject(Target::ZomeCall, Fault::Drop, Schedule::Next(1));
+
+        let result = conductors
+            .iter()
+            .next()
+            .unwrap()
+            .app_call
+            .handle_zome_call(zome_call())
+            .await;
+        assert!(matches!(
+            result,
+            Err(HcHttpGatewayError::HolochainError(
+                ConductorApiError::WebsocketError(_)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropped_admin_websocket_fails_health_checks_and_is_announced() {
+        let chaos = Chaos::new();
+        let conductors = chaos.conductors(MockConductor::new().conductors(&test_config()));
+        let conductor = conductors.iter().next().unwrap().clone();
+        let mut reconnects = conductor.admin_call.subscribe_reconnects();
+        chaos.inject(Target::Admin, Fault::Drop, Schedule::Next(1));
+
+        let health_checks = conductors.spawn_health_checks(Duration::from_millis(10));
+        tokio::time::timeout(Duration::from_secs(1), reconnects.changed())
+            .await
+            .expect("The dropped websocket was not announced")
+            .unwrap();
+        wait_for_health(&conductor, false).await;
+        wait_for_health(&conductor, true).await;
+        health_checks.abort();
+    }
+
+    async fn wait_for_health(conductor: &ConductorHandle, healthy: bool) {
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while conductor.health.is_healthy() != healthy {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("The health of the conductor did not change");
+    }
+}
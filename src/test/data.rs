@@ -3,7 +3,7 @@
 use holochain_client::{AgentPubKey, CellId, Timestamp};
 use holochain_conductor_api::{AppInfo, CellInfo};
 use holochain_types::app::{AppManifest, AppStatus};
-use holochain_types::prelude::{DnaHash, DnaModifiersBuilder};
+use holochain_types::prelude::{CloneId, DnaHash, DnaModifiersBuilder};
 
 /// Create a test [`AppInfo`] for use in tests
 pub fn new_test_app_info(app_id: impl ToString, dna_hash: DnaHash) -> AppInfo {
@@ -35,3 +35,21 @@ pub fn new_test_app_info(app_id: impl ToString, dna_hash: DnaHash) -> AppInfo {
         installed_at: Timestamp::now(),
     }
 }
+
+/// Add an enabled clone cell with the given DNA hash to a test [`AppInfo`]
+pub fn add_test_clone_cell(app_info: &mut AppInfo, dna_hash: DnaHash) {
+    let role_name = "test-role".to_string();
+    let cell_infos = app_info.cell_info.entry(role_name.clone()).or_default();
+    let clone_index = cell_infos.len() as u32;
+    cell_infos.push(CellInfo::new_cloned(
+        CellId::new(dna_hash, AgentPubKey::from_raw_32(vec![1; 32])),
+        CloneId::new(&role_name, clone_index),
+        DnaHash::from_raw_32(vec![0; 32]),
+        DnaModifiersBuilder::default()
+            .network_seed(format!("clone-{clone_index}"))
+            .build()
+            .unwrap(),
+        "test-clone".to_string(),
+        true,
+    ));
+}
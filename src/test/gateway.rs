@@ -0,0 +1,195 @@
+//! Test harness that runs the gateway against a conductor, such as a sweettest `SweetConductor`.
+//!
+//! hApp projects can use it to integration test the configuration of their gateway, by installing
+//! their hApp with [`TestApp::install`], spawning a gateway with [`TestGateway::spawn`] and making
+//! zome calls through it.
+
+use crate::{
+    AdminConn, AllowedFns, AppConnPool, Configuration, GatewayHandle, HcHttpGatewayService,
+};
+use holochain::conductor::Conductor;
+use holochain::prelude::DnaHash;
+use holochain_types::app::{AppBundleSource, InstallAppPayload, InstalledApp, InstalledAppId};
+use reqwest::{Client, Response, StatusCode};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A gateway listening on a random local port, which is shut down when it is dropped.
+pub struct TestGateway {
+    /// Address that the gateway listens on.
+    pub address: String,
+    /// Client that requests are made with.
+    pub client: Client,
+    /// Handle to the running gateway.
+    pub handle: GatewayHandle,
+}
+
+impl TestGateway {
+    /// Spawn a gateway connected to the conductor, that exposes the given functions of the given
+    /// apps.
+    pub async fn spawn(
+        conductor: &Conductor,
+        allowed_fns: impl IntoIterator<Item = (InstalledAppId, AllowedFns)>,
+    ) -> Self {
+        Self::spawn_with_config(Self::config(conductor, allowed_fns)).await
+    }
+
+    /// The configuration used by [`TestGateway::spawn`], for tests that need to adjust it.
+    pub fn config(
+        conductor: &Conductor,
+        allowed_fns: impl IntoIterator<Item = (InstalledAppId, AllowedFns)>,
+    ) -> Configuration {
+        let allowed_fns = allowed_fns.into_iter().collect::<HashMap<_, _>>();
+        let allowed_app_ids = allowed_fns.keys().cloned().collect::<Vec<_>>().join(",");
+        let admin_port = conductor
+            .get_arbitrary_admin_websocket_port()
+            .expect("The conductor has no admin interface");
+
+        Configuration::try_new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), admin_port),
+            "",
+            &allowed_app_ids,
+            allowed_fns,
+            "",
+            "",
+        )
+        .expect("Invalid test configuration")
+    }
+
+    /// Spawn a gateway with a custom configuration.
+    pub async fn spawn_with_config(config: Configuration) -> Self {
+        let admin_call = Arc::new(AdminConn::new(config.admin_socket_addr));
+        let app_call = Arc::new(AppConnPool::new(config.clone(), admin_call.clone()));
+
+        let service =
+            HcHttpGatewayService::new([127, 0, 0, 1], 0, config.clone(), admin_call, app_call)
+                .await
+                .expect("Failed to create the gateway");
+
+        // Run service in the background
+        let handle = service.spawn().expect("Failed to spawn the gateway");
+
+        TestGateway {
+            address: handle.address().to_string(),
+            client: Client::new(),
+            handle,
+        }
+    }
+
+    /// The URL of a path on the gateway, such as `/health`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.address)
+    }
+
+    /// Make a request to the zome call GET endpoint.
+    ///
+    /// The payload is expected to be base64url encoded already.
+    pub async fn call_zome(
+        &self,
+        dna_hash: &DnaHash,
+        coordinator_identifier: &str,
+        zome: &str,
+        zome_fn: &str,
+        payload: Option<&str>,
+    ) -> Response {
+        let mut url = self.url(&format!(
+            "/{dna_hash}/{coordinator_identifier}/{zome}/{zome_fn}"
+        ));
+        if let Some(payload) = payload {
+            url.push_str(&format!("?payload={payload}"));
+        }
+
+        self.client
+            .get(url)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+}
+
+impl Drop for TestGateway {
+    fn drop(&mut self) {
+        self.handle.shutdown_handle().shutdown();
+    }
+}
+
+/// An app installed and enabled on a conductor.
+pub struct TestApp {
+    /// The installed app.
+    pub app: InstalledApp,
+    /// Hash of the DNA of the first cell of the app, which zome calls are addressed to.
+    pub dna_hash: DnaHash,
+}
+
+impl TestApp {
+    /// Install the hApp bundle at the given path on the conductor, and enable it.
+    pub async fn install(
+        conductor: &Arc<Conductor>,
+        happ_path: impl Into<PathBuf>,
+        installed_app_id: Option<InstalledAppId>,
+    ) -> Self {
+        let app = conductor
+            .clone()
+            .install_app_bundle(InstallAppPayload {
+                source: AppBundleSource::Path(happ_path.into()),
+                agent_key: None,
+                installed_app_id,
+                network_seed: None,
+                roles_settings: None,
+                ignore_genesis_failure: false,
+                restore_from_dht: false,
+            })
+            .await
+            .expect("Failed to install the app");
+        conductor
+            .clone()
+            .enable_app(app.installed_app_id.clone())
+            .await
+            .expect("Failed to enable the app");
+
+        let dna_hash = app
+            .all_cells()
+            .next()
+            .expect("The app has no cells")
+            .dna_hash()
+            .clone();
+
+        Self { app, dna_hash }
+    }
+
+    /// Call a function of the app through the gateway, addressing it by the id of the app.
+    pub async fn call_zome(
+        &self,
+        gateway: &TestGateway,
+        zome: &str,
+        zome_fn: &str,
+        payload: Option<&str>,
+    ) -> Response {
+        gateway
+            .call_zome(
+                &self.dna_hash,
+                &self.app.installed_app_id,
+                zome,
+                zome_fn,
+                payload,
+            )
+            .await
+    }
+}
+
+/// Assert that the response has the expected status, and return its body.
+///
+/// The body is included in the panic message when the status differs, since it explains what
+/// went wrong.
+pub async fn assert_status(response: Response, expected: StatusCode) -> String {
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .expect("Failed to read the response body");
+    assert_eq!(status, expected, "Unexpected status, body: {body}");
+
+    body
+}
@@ -0,0 +1,347 @@
+//! A conductor simulated in memory, for testing the gateway without running Holochain.
+//!
+//! [`MockConductor`] implements both [`AdminCall`] and [`AppCall`], so that the routing, caching
+//! and limits of the gateway can be tested in milliseconds. The apps that it lists, the responses
+//! to zome calls, the time that calls take and the errors that they fail with can be changed at
+//! any time, and the zome calls that were made are recorded.
+
+use crate::holochain::matches_status_filter;
+use crate::{
+    AdminCall, AppCall, AppConnectionStats, Conductors, Configuration, ConnectionCounters,
+    HcHttpGatewayError, HcHttpGatewayResult, ZomeCallRequest,
+};
+use futures::future::BoxFuture;
+use holochain_client::{
+    AppInfo, AuthorizeSigningCredentialsPayload, ConductorApiError, ExternIO, SigningCredentials,
+};
+use holochain_conductor_api::{
+    AppAuthenticationTokenIssued, AppInterfaceInfo, AppStatusFilter, ExternalApiWireError,
+    IssueAppAuthenticationTokenPayload, StorageInfo,
+};
+use holochain_types::app::InstalledAppId;
+use holochain_types::dna::DnaHash;
+use holochain_types::websocket::AllowedOrigins;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+type ZomeCallHandler = Arc<dyn Fn(&ZomeCallRequest) -> HcHttpGatewayResult<ExternIO> + Send + Sync>;
+
+type Failure = Arc<dyn Fn() -> HcHttpGatewayError + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    apps: Vec<AppInfo>,
+    /// Handlers of zome calls by zome and function name.
+    handlers: HashMap<(String, String), ZomeCallHandler>,
+    latency: Duration,
+    admin_failure: Option<Failure>,
+    zome_call_failure: Option<Failure>,
+    zome_calls: Vec<ZomeCallRequest>,
+}
+
+/// A conductor that answers admin calls and zome calls from memory.
+///
+/// Clones share the same state, so that a test can keep a clone to script the conductor while the
+/// gateway uses another.
+#[derive(Clone)]
+pub struct MockConductor {
+    state: Arc<Mutex<State>>,
+    reconnects: Arc<watch::Sender<u64>>,
+}
+
+impl std::fmt::Debug for MockConductor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockConductor").finish_non_exhaustive()
+    }
+}
+
+impl Default for MockConductor {
+    fn default() -> Self {
+        Self {
+            state: Default::default(),
+            reconnects: Arc::new(watch::Sender::new(0)),
+        }
+    }
+}
+
+impl MockConductor {
+    /// Create a conductor without apps, on which every zome call fails as if the function didn't
+    /// exist.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an app to the apps that the conductor lists.
+    pub fn with_app(self, app: AppInfo) -> Self {
+        self.state().apps.push(app);
+        self
+    }
+
+    /// Replace the apps that the conductor lists, for example to simulate an app being uninstalled.
+    pub fn set_apps(&self, apps: Vec<AppInfo>) {
+        self.state().apps = apps;
+    }
+
+    /// Respond to every call of a function with the given value.
+    pub fn respond<T: Serialize + std::fmt::Debug>(
+        &self,
+        zome_name: &str,
+        fn_name: &str,
+        value: T,
+    ) {
+        let response = ExternIO::encode(value).expect("Failed to encode the response");
+        self.on_zome_call(zome_name, fn_name, move |_| Ok(response.clone()));
+    }
+
+    /// Handle every call of a function with the given handler, which is given the request.
+    pub fn on_zome_call(
+        &self,
+        zome_name: &str,
+        fn_name: &str,
+        handler: impl Fn(&ZomeCallRequest) -> HcHttpGatewayResult<ExternIO> + Send + Sync + 'static,
+    ) {
+        self.state().handlers.insert(
+            (zome_name.to_string(), fn_name.to_string()),
+            Arc::new(handler),
+        );
+    }
+
+    /// Delay every admin call and zome call by the given time.
+    ///
+    /// A zome call with a timeout shorter than the latency fails with
+    /// [`HcHttpGatewayError::ZomeCallTimeout`] once the timeout has passed.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state().latency = latency;
+    }
+
+    /// Fail every admin call with the error that is given, until [`MockConductor::recover`].
+    pub fn fail_admin_calls(&self, error: impl Fn() -> HcHttpGatewayError + Send + Sync + 'static) {
+        self.state().admin_failure = Some(Arc::new(error));
+    }
+
+    /// Fail every zome call with the error that is given, until [`MockConductor::recover`].
+    pub fn fail_zome_calls(&self, error: impl Fn() -> HcHttpGatewayError + Send + Sync + 'static) {
+        self.state().zome_call_failure = Some(Arc::new(error));
+    }
+
+    /// Stop failing admin calls and zome calls.
+    pub fn recover(&self) {
+        let mut state = self.state();
+        state.admin_failure = None;
+        state.zome_call_failure = None;
+    }
+
+    /// Simulate a restart of the conductor, which the gateway notices as a re-established admin
+    /// connection.
+    pub fn restart(&self) {
+        self.reconnects.send_modify(|reconnects| *reconnects += 1);
+    }
+
+    /// The zome calls that were made, oldest first, including those that failed.
+    pub fn zome_calls(&self) -> Vec<ZomeCallRequest> {
+        self.state().zome_calls.clone()
+    }
+
+    /// The conductors of a gateway that only routes to this conductor.
+    pub fn conductors(&self, configuration: &Configuration) -> Conductors {
+        Conductors::single(
+            configuration,
+            Arc::new(self.clone()),
+            Arc::new(self.clone()),
+        )
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("Invalid lock")
+    }
+
+    /// Answer an admin call after the latency, unless admin calls are failing.
+    fn admin_call<T: Send + 'static>(
+        &self,
+        answer: impl FnOnce(&State) -> HcHttpGatewayResult<T>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<T>> {
+        let state = self.state();
+        let latency = state.latency;
+        let result = match &state.admin_failure {
+            Some(failure) => Err(failure()),
+            None => answer(&state),
+        };
+
+        Box::pin(async move {
+            tokio::time::sleep(latency).await;
+            result
+        })
+    }
+}
+
+/// The error of a call that the mock conductor doesn't simulate.
+fn unsupported(call: &str) -> HcHttpGatewayError {
+    HcHttpGatewayError::HolochainError(ConductorApiError::ExternalApiWireError(
+        ExternalApiWireError::InternalError(format!(
+            "{call} is not supported by the mock conductor"
+        )),
+    ))
+}
+
+impl AdminCall for MockConductor {
+    fn list_app_interfaces(
+        &self,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<AppInterfaceInfo>>> {
+        self.admin_call(|_| Ok(Vec::new()))
+    }
+
+    fn issue_app_auth_token(
+        &self,
+        _payload: IssueAppAuthenticationTokenPayload,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<AppAuthenticationTokenIssued>> {
+        self.admin_call(|_| Err(unsupported("Issuing app authentication tokens")))
+    }
+
+    fn authorize_signing_credentials(
+        &self,
+        _payload: AuthorizeSigningCredentialsPayload,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<SigningCredentials>> {
+        self.admin_call(|_| Err(unsupported("Authorizing signing credentials")))
+    }
+
+    fn attach_app_interface(
+        &self,
+        _port: u16,
+        _allowed_origins: AllowedOrigins,
+        _installed_app_id: Option<String>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<u16>> {
+        self.admin_call(|_| Err(unsupported("Attaching app interfaces")))
+    }
+
+    fn list_apps(
+        &self,
+        status_filter: Option<AppStatusFilter>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<AppInfo>>> {
+        self.admin_call(|state| {
+            Ok(state
+                .apps
+                .iter()
+                .filter(|app| matches_status_filter(app, status_filter.as_ref()))
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn storage_info(&self) -> BoxFuture<'static, HcHttpGatewayResult<StorageInfo>> {
+        self.admin_call(|_| Err(unsupported("Storage info")))
+    }
+
+    fn agent_info(
+        &self,
+        _dna_hashes: Option<Vec<DnaHash>>,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<Vec<String>>> {
+        self.admin_call(|_| Ok(Vec::new()))
+    }
+
+    #[cfg(feature = "conductor-state-dump")]
+    fn dump_conductor_state(&self) -> BoxFuture<'static, HcHttpGatewayResult<String>> {
+        self.admin_call(|_| Err(unsupported("Dumping the conductor state")))
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn subscribe_reconnects(&self) -> watch::Receiver<u64> {
+        self.reconnects.subscribe()
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        ConnectionCounters::default()
+    }
+}
+
+impl AppCall for MockConductor {
+    fn handle_zome_call(
+        &self,
+        request: ZomeCallRequest,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        let (latency, failure, handler) = {
+            let mut state = self.state();
+            state.zome_calls.push(request.clone());
+            let handler = state
+                .handlers
+                .get(&(request.zome_name.to_string(), request.fn_name.to_string()))
+                .cloned();
+            (state.latency, state.zome_call_failure.clone(), handler)
+        };
+        // The handler is called without holding the lock, so that it may script the conductor
+        let result = match (failure, handler) {
+            (Some(failure), _) => Err(failure()),
+            (None, Some(handler)) => handler(&request),
+            (None, None) => Err(HcHttpGatewayError::HolochainError(
+                ConductorApiError::ExternalApiWireError(ExternalApiWireError::RibosomeError(
+                    format!(
+                        "Function {} in zome {} not found",
+                        request.fn_name, request.zome_name
+                    ),
+                )),
+            )),
+        };
+        let timeout = request.timeout;
+
+        Box::pin(async move {
+            match timeout {
+                Some(timeout) if timeout < latency => {
+                    tokio::time::sleep(timeout).await;
+                    Err(HcHttpGatewayError::ZomeCallTimeout)
+                }
+                _ => {
+                    tokio::time::sleep(latency).await;
+                    result
+                }
+            }
+        })
+    }
+
+    fn evict_stale_connections(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn remove_app_connection(&self, _installed_app_id: InstalledAppId) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn close_connections(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn reset_after_restart(&self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn app_connection_stats(&self) -> Vec<AppConnectionStats> {
+        Vec::new()
+    }
+
+    fn clear_cached_app_ports(&self) {}
+
+    fn reconnect_app(
+        &self,
+        _installed_app_id: InstalledAppId,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<()>> {
+        let state = self.state();
+        let latency = state.latency;
+        let result = match &state.zome_call_failure {
+            Some(failure) => Err(failure()),
+            None => Ok(()),
+        };
+
+        Box::pin(async move {
+            tokio::time::sleep(latency).await;
+            result
+        })
+    }
+
+    fn connection_counters(&self) -> ConnectionCounters {
+        ConnectionCounters::default()
+    }
+}
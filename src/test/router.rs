@@ -1,8 +1,12 @@
 //! A test router that can be used to test router handlers with mocked state.
 
 use crate::router::hc_http_gateway_router;
+use crate::service::AppState;
 use crate::test::data::new_test_app_info;
-use crate::{AdminCall, AllowedFns, AppCall, Configuration, MockAdminCall, MockAppCall, ZomeFn};
+use crate::{
+    AdminCall, AllowedFns, AppCall, Conductors, Configuration, DrainHandle, Metrics, MockAdminCall,
+    MockAppCall, Transforms, ZomeFn,
+};
 use axum::Router;
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
@@ -14,6 +18,9 @@ use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use tower::ServiceExt;
 
+/// Request id that [`TestRouter::request`] sends, unless the request has its own.
+pub const TEST_REQUEST_ID: &str = "test-request-id";
+
 /// Test router.
 pub struct TestRouter(Router);
 
@@ -59,7 +66,7 @@ impl TestRouter {
         let mut app_call = MockAppCall::new();
         app_call
             .expect_handle_zome_call()
-            .returning(|_, _, _, _, _| Box::pin(async move { Ok(ExternIO::encode(()).unwrap()) }));
+            .returning(|_| Box::pin(async move { Ok(ExternIO::encode(()).unwrap()) }));
         let app_call = Arc::new(app_call);
         Self::new_with_config_and_interfaces(config, admin_call, app_call)
     }
@@ -70,14 +77,53 @@ impl TestRouter {
         admin_call: Arc<dyn AdminCall>,
         app_call: Arc<dyn AppCall>,
     ) -> Self {
-        Self(hc_http_gateway_router(config, admin_call, app_call))
+        let conductors = Conductors::single(&config, admin_call, app_call);
+        Self::new_with_config_and_conductors(config, conductors)
+    }
+
+    /// Construct a test router with given configuration and conductors.
+    pub fn new_with_config_and_conductors(config: Configuration, conductors: Conductors) -> Self {
+        Self::new_with_config_conductors_and_transforms(config, conductors, Transforms::default())
+    }
+
+    /// Construct a test router with given configuration, conductors and transforms.
+    pub fn new_with_config_conductors_and_transforms(
+        config: Configuration,
+        conductors: Conductors,
+        transforms: Transforms,
+    ) -> Self {
+        let metrics = Arc::new(Metrics::from_configuration(&config));
+        Self(hc_http_gateway_router(
+            AppState::new(Arc::new(config), conductors, metrics, transforms),
+            DrainHandle::default(),
+        ))
     }
 
     /// Send request and return status code and body of response.
-    pub async fn request(self, uri: &str) -> (StatusCode, String) {
+    pub async fn request(&self, uri: &str) -> (StatusCode, String) {
+        self.request_with_headers(uri, &[]).await
+    }
+
+    /// Send request with the given headers and return status code and body of response.
+    pub async fn request_with_headers(
+        &self,
+        uri: &str,
+        headers: &[(&str, &str)],
+    ) -> (StatusCode, String) {
+        let mut request = Request::builder().uri(uri);
+        if !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("x-request-id"))
+        {
+            request = request.header("x-request-id", TEST_REQUEST_ID);
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
         let response = self
             .0
-            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
             .await
             .unwrap();
         let status_code = response.status();
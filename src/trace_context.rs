@@ -0,0 +1,191 @@
+//! Propagation of W3C Trace Context headers.
+//!
+//! When a request carries a valid `traceparent` header, its trace id and parent id are recorded
+//! on the span of the request, along with the `tracestate` header, so that the logs of the gateway
+//! can be joined with the distributed trace that an upstream service started. The `traceparent`
+//! header is echoed in the response, so that clients can tell that the trace was picked up.
+
+use axum::extract::Request;
+use axum::http::HeaderName;
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Instrument;
+
+/// Header holding the trace id, the id of the calling span and the trace flags.
+pub const TRACEPARENT_HEADER: HeaderName = HeaderName::from_static("traceparent");
+/// Header holding vendor specific trace information.
+pub const TRACESTATE_HEADER: HeaderName = HeaderName::from_static("tracestate");
+
+/// Maximum length of a `tracestate` header that is recorded, longer headers may be dropped
+/// following the Trace Context specification.
+const MAX_TRACESTATE_LEN: usize = 512;
+
+/// A parsed `traceparent` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TraceParent<'a> {
+    pub(crate) trace_id: &'a str,
+    pub(crate) parent_id: &'a str,
+    pub(crate) sampled: bool,
+}
+
+impl<'a> TraceParent<'a> {
+    /// Parse a `traceparent` header, returning `None` if it is not valid.
+    ///
+    /// Versions after `00` may append fields, which are ignored.
+    pub(crate) fn parse(value: &'a str) -> Option<Self> {
+        let mut fields = value.split('-');
+        let version = fields.next()?;
+        let trace_id = fields.next()?;
+        let parent_id = fields.next()?;
+        let flags = fields.next()?;
+        if (version == "00" && fields.next().is_some()) || version == "ff" {
+            return None;
+        }
+
+        let is_hex = |field: &str, len: usize| {
+            field.len() == len
+                && field
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        };
+        let is_zero = |field: &str| field.bytes().all(|b| b == b'0');
+        if !is_hex(version, 2)
+            || !is_hex(trace_id, 32)
+            || is_zero(trace_id)
+            || !is_hex(parent_id, 16)
+            || is_zero(parent_id)
+            || !is_hex(flags, 2)
+        {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+
+        Some(Self {
+            trace_id,
+            parent_id,
+            sampled: flags & 1 == 1,
+        })
+    }
+}
+
+/// Middleware that runs each request in a span, joined to the trace of the caller if the request
+/// carries a valid `traceparent` header.
+///
+/// Only the path of the request is recorded, so that payloads in the query don't end up in logs.
+pub(crate) async fn trace_request(request: Request, next: Next) -> Response {
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        trace_id = tracing::field::Empty,
+        parent_id = tracing::field::Empty,
+        sampled = tracing::field::Empty,
+        tracestate = tracing::field::Empty,
+        request_id = tracing::field::Empty,
+    );
+
+    let traceparent = request.headers().get(TRACEPARENT_HEADER).cloned();
+    let valid = match traceparent
+        .as_ref()
+        .and_then(|value| value.to_str().ok())
+        .and_then(TraceParent::parse)
+    {
+        Some(parsed) => {
+            span.record("trace_id", parsed.trace_id);
+            span.record("parent_id", parsed.parent_id);
+            span.record("sampled", parsed.sampled);
+            // The trace state is only meaningful along with a valid trace parent
+            if let Some(tracestate) = request
+                .headers()
+                .get(TRACESTATE_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| value.len() <= MAX_TRACESTATE_LEN)
+            {
+                span.record("tracestate", tracestate);
+            }
+            true
+        }
+        None => false,
+    };
+
+    let mut response = next.run(request).instrument(span).await;
+    if valid && let Some(traceparent) = traceparent {
+        response
+            .headers_mut()
+            .insert(TRACEPARENT_HEADER, traceparent);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::router::TestRouter;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn valid_traceparent_is_parsed() {
+        assert_eq!(
+            TraceParent::parse(TRACEPARENT),
+            Some(TraceParent {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736",
+                parent_id: "00f067aa0ba902b7",
+                sampled: true,
+            })
+        );
+
+        // Later versions may add fields
+        let parsed =
+            TraceParent::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra")
+                .unwrap();
+        assert!(!parsed.sampled);
+    }
+
+    #[test]
+    fn invalid_traceparent_is_rejected() {
+        for traceparent in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+        ] {
+            assert_eq!(TraceParent::parse(traceparent), None, "{traceparent}");
+        }
+    }
+
+    async fn traceparent_of_response(traceparent: Option<&str>) -> Option<String> {
+        let mut request = axum::http::Request::builder().uri("/health");
+        if let Some(traceparent) = traceparent {
+            request = request.header(TRACEPARENT_HEADER, traceparent);
+        }
+        let response = TestRouter::new()
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        response
+            .headers()
+            .get(TRACEPARENT_HEADER)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn valid_traceparent_is_echoed() {
+        assert_eq!(
+            traceparent_of_response(Some(TRACEPARENT)).await.as_deref(),
+            Some(TRACEPARENT)
+        );
+        assert_eq!(traceparent_of_response(Some("invalid")).await, None);
+        assert_eq!(traceparent_of_response(None).await, None);
+    }
+}
@@ -1,65 +1,920 @@
 //! Functions to transcode call payloads and responses.
 //!
 //! The incoming HTTP request's payload is a base64 encoded JSON string, which has
-//! to be transcoded to `ExternIO` to be passed through as zome call payload.
+//! to be transcoded to `ExternIO` to be passed through as zome call payload. Both the URL safe
+//! and the standard base64 alphabet are accepted, with or without padding.
 //!
 //! On the way out, the zome call response is `ExternIO` encoded and needs to be converted
-//! to a JSON string.
+//! to a JSON string. Holo hashes in the response are rendered in their canonical base64 form,
+//! for example `uhCkk...`, rather than as arrays of bytes. Other binary values are rendered
+//! according to the configured [`BinaryEncoding`]. Integers that JavaScript can't represent
+//! exactly can be rendered as strings, timestamps as RFC 3339 strings, enums as internally
+//! tagged objects, and the keys of objects in camelCase.
 
-use crate::{HcHttpGatewayError, HcHttpGatewayResult};
-use base64::{Engine, prelude::BASE64_URL_SAFE};
-use holochain_client::ConductorApiError;
-use holochain_types::prelude::ExternIO;
+use crate::{
+    BinaryEncoding, Configuration, EmptyPayload, HcHttpGatewayError, HcHttpGatewayResult,
+    PayloadLimits,
+};
+use axum::body::{Body, Bytes};
+use base64::{
+    DecodeError, Engine, alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    prelude::BASE64_STANDARD,
+};
+use holochain_types::prelude::{
+    ExternIO, HOLO_HASH_CORE_LEN, HOLO_HASH_FULL_LEN, HOLO_HASH_PREFIX_LEN, HoloHash,
+    PrimitiveHashType, Timestamp, hash_type,
+};
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Name of the field of the JSON object that holds a base64 encoded binary value.
+const BASE64_FIELD: &str = "$base64";
+
+/// Base64 engine config for payloads, which may or may not be padded.
+const PAYLOAD_BASE64_CONFIG: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+
+/// Base64 engine for payloads in the URL safe alphabet.
+const PAYLOAD_BASE64_URL_SAFE: GeneralPurpose =
+    GeneralPurpose::new(&alphabet::URL_SAFE, PAYLOAD_BASE64_CONFIG);
+
+/// Base64 engine for payloads in the standard alphabet.
+const PAYLOAD_BASE64_STANDARD: GeneralPurpose =
+    GeneralPurpose::new(&alphabet::STANDARD, PAYLOAD_BASE64_CONFIG);
+
+/// Number of characters on either side of a JSON parse error that are included in the error.
+const JSON_ERROR_SNIPPET_CHARS: usize = 20;
+
+/// Size of the chunks that a streamed JSON response body is written in.
+const JSON_CHUNK_BYTES: usize = 64 * 1024;
+
+/// The largest integer magnitude that a JSON number can hold without losing precision in
+/// JavaScript, `Number.MAX_SAFE_INTEGER`.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Options for transcoding payloads and responses.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TranscodeOptions<'a> {
+    /// How binary values other than holo hashes are rendered.
+    pub binary_encoding: BinaryEncoding,
+    /// Whether integers beyond [`MAX_SAFE_INTEGER`] are rendered as strings.
+    pub large_integers_as_strings: bool,
+    /// Names of the fields holding timestamps, which are rendered as RFC 3339 strings, if any.
+    pub timestamp_fields: Option<&'a HashSet<String>>,
+    /// Whether object keys are converted from camelCase in payloads and to camelCase in responses.
+    pub camel_case_keys: bool,
+    /// How the payload is encoded when there is none.
+    pub empty_payload: EmptyPayload,
+    /// Limits on the structure of the JSON payload.
+    pub payload_limits: PayloadLimits,
+    /// The fields holding enums that are internally tagged in JSON, if any.
+    pub enum_tagging: Option<EnumTagging<'a>>,
+}
+
+/// Fields holding enums that are internally tagged in JSON, `{"type": "Variant", ...}`, rather
+/// than externally tagged as they are encoded by zome functions, `{"Variant": {...}}`.
+#[derive(Debug, Clone, Copy)]
+pub struct EnumTagging<'a> {
+    /// Names of the fields holding enums.
+    pub fields: &'a HashSet<String>,
+    /// Name of the field holding the variant.
+    pub tag: &'a str,
+}
+
+impl<'a> TranscodeOptions<'a> {
+    /// The options for a call to the given function.
+    pub fn for_function(
+        configuration: &'a Configuration,
+        app_id: &str,
+        zome_name: &str,
+        fn_name: &str,
+    ) -> Self {
+        Self {
+            binary_encoding: configuration.binary_encoding,
+            large_integers_as_strings: configuration.large_integers_as_strings,
+            timestamp_fields: configuration
+                .formats_timestamps(app_id, zome_name, fn_name)
+                .then_some(&configuration.timestamp_fields),
+            camel_case_keys: configuration.camel_case_keys,
+            empty_payload: configuration.empty_payload(app_id, zome_name, fn_name),
+            payload_limits: configuration.payload_limits,
+            enum_tagging: configuration
+                .tags_enums_internally(app_id, zome_name, fn_name)
+                .then_some(EnumTagging {
+                    fields: &configuration.enum_fields,
+                    tag: &configuration.enum_tag,
+                }),
+        }
+    }
+
+    fn is_timestamp_field(&self, key: &str) -> bool {
+        self.timestamp_fields
+            .is_some_and(|timestamp_fields| timestamp_fields.contains(key))
+    }
+
+    /// The name of the field holding the variant, if the field holds an internally tagged enum.
+    fn enum_tag(&self, key: &str) -> Option<&'a str> {
+        self.enum_tagging
+            .filter(|enum_tagging| enum_tagging.fields.contains(key))
+            .map(|enum_tagging| enum_tagging.tag)
+    }
+}
 
 /// Function to transcode an optional base64 encoded payload to Holochain serialized bytes
-/// (type `ExternIO`). If no payload is passed in, a unit value will be serialized.
+/// (type `ExternIO`). If no payload is passed in, the empty payload of the `options` will be
+/// serialized.
+///
+/// Unless binary values are encoded as arrays, objects with just a `$base64` field are
+/// serialized as the binary value that the field holds. RFC 3339 strings in timestamp fields are
+/// serialized as timestamps.
 pub fn base64_json_to_hsb(
     maybe_base64_encoded_payload: Option<String>,
+    options: TranscodeOptions,
 ) -> HcHttpGatewayResult<ExternIO> {
-    let json_payload = if let Some(base64_encoded_payload) = maybe_base64_encoded_payload {
-        let base64_decoded_payload =
-            BASE64_URL_SAFE
-                .decode(base64_encoded_payload)
-                .map_err(|_| {
-                    HcHttpGatewayError::RequestMalformed("Invalid base64 encoding".to_string())
-                })?;
-        serde_json::from_slice::<serde_json::Value>(&base64_decoded_payload)
-            .map_err(|_| HcHttpGatewayError::RequestMalformed("Invalid JSON value".to_string()))?
-    } else {
-        serde_json::Value::Null
+    let Some(base64_encoded_payload) = maybe_base64_encoded_payload else {
+        return encode_empty_payload(options.empty_payload);
     };
-    let msgpack_encoded_payload = ExternIO::encode(json_payload).map_err(|err| {
+    let base64_decoded_payload = decode_base64_payload(&base64_encoded_payload)
+        .map_err(|_| HcHttpGatewayError::RequestMalformed("Invalid base64 encoding".to_string()))?;
+    let mut deserializer = serde_json::Deserializer::from_slice(&base64_decoded_payload);
+    let payload = JsonPayload {
+        options,
+        depth: 0,
+        field: PayloadField::Other,
+    }
+    .deserialize(&mut deserializer)
+    .and_then(|payload| deserializer.end().map(|()| payload))
+    .map_err(|err| {
+        if err.classify() == serde_json::error::Category::Data {
+            // The payload exceeds a limit or has a value that can't be converted
+            return HcHttpGatewayError::RequestMalformed(err.to_string());
+        }
+        let snippet = json_error_snippet(&String::from_utf8_lossy(&base64_decoded_payload), &err);
+        HcHttpGatewayError::RequestMalformed(format!("Invalid JSON value: {err}, near {snippet:?}"))
+    })?;
+    let msgpack_encoded_payload = ExternIO::encode(payload).map_err(|err| {
         HcHttpGatewayError::RequestMalformed(format!("Failure to serialize payload - {err}"))
     })?;
     Ok(msgpack_encoded_payload)
 }
 
+/// Function to decode an optional base64 encoded msgpack payload, which is passed through as
+/// zome call payload without transcoding. If no payload is passed in, the `empty_payload` will be
+/// serialized.
+pub fn base64_msgpack_to_hsb(
+    maybe_base64_encoded_payload: Option<String>,
+    empty_payload: EmptyPayload,
+) -> HcHttpGatewayResult<ExternIO> {
+    match maybe_base64_encoded_payload {
+        Some(base64_encoded_payload) => decode_base64_payload(&base64_encoded_payload)
+            .map(ExternIO)
+            .map_err(|_| {
+                HcHttpGatewayError::RequestMalformed("Invalid base64 encoding".to_string())
+            }),
+        None => encode_empty_payload(empty_payload),
+    }
+}
+
+fn encode_empty_payload(empty_payload: EmptyPayload) -> HcHttpGatewayResult<ExternIO> {
+    match empty_payload {
+        EmptyPayload::Unit => ExternIO::encode(()),
+        EmptyPayload::Nil => ExternIO::encode(None::<()>),
+        EmptyPayload::EmptyMap => ExternIO::encode(HashMap::<String, ()>::new()),
+    }
+    .map_err(|err| {
+        HcHttpGatewayError::RequestMalformed(format!("Failure to serialize payload - {err}"))
+    })
+}
+
+/// How a JSON value in a payload is converted, depending on the field that holds it.
+#[derive(Debug, Clone, Copy)]
+enum PayloadField<'k> {
+    /// The value is converted as it is.
+    Other,
+    /// The value is in the named timestamp field, where strings are RFC 3339 timestamps.
+    Timestamp(&'k str),
+    /// The value is an enum that may be internally tagged with the given tag field.
+    Enum(&'k str),
+}
+
+/// Deserializes a JSON payload straight to the value that is passed to the zome function,
+/// without building a [`serde_json::Value`] first.
+///
+/// The payload is checked against the payload limits while it is parsed, so that a payload that
+/// exceeds them is rejected before it has been parsed in full.
+struct JsonPayload<'a, 'k> {
+    options: TranscodeOptions<'a>,
+    depth: u32,
+    field: PayloadField<'k>,
+}
+
+impl<'a> JsonPayload<'a, '_> {
+    /// The seed for a value nested in an array or object at the current depth.
+    fn nested<'k, E: de::Error>(&self, field: PayloadField<'k>) -> Result<JsonPayload<'a, 'k>, E> {
+        let max_depth = self.options.payload_limits.max_depth;
+        if self.depth >= max_depth {
+            return Err(E::custom(format!(
+                "Payload exceeds the maximum depth of {max_depth}"
+            )));
+        }
+        Ok(JsonPayload {
+            options: self.options,
+            depth: self.depth + 1,
+            field,
+        })
+    }
+
+    fn check_string<E: de::Error>(&self, s: &str) -> Result<(), E> {
+        let max_string_bytes = self.options.payload_limits.max_string_bytes;
+        if s.len() > max_string_bytes as usize {
+            return Err(E::custom(format!(
+                "Payload string exceeds {max_string_bytes} bytes"
+            )));
+        }
+        Ok(())
+    }
+
+    /// How the value of an object field with the given key is converted.
+    fn field<'k>(&self, key: &'k str) -> PayloadField<'k>
+    where
+        'a: 'k,
+    {
+        if self.options.is_timestamp_field(key) {
+            PayloadField::Timestamp(key)
+        } else if let Some(tag) = self.options.enum_tag(key) {
+            PayloadField::Enum(tag)
+        } else {
+            PayloadField::Other
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for JsonPayload<'_, '_> {
+    type Value = HsbValue;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for JsonPayload<'_, '_> {
+    type Value = HsbValue;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(HsbValue::Bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(HsbValue::I64(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(HsbValue::U64(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(HsbValue::F64(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        self.check_string(v)?;
+        if let PayloadField::Timestamp(key) = self.field {
+            let timestamp = Timestamp::from_str(v)
+                .map_err(|_| E::custom(format!("Invalid RFC 3339 timestamp in {key} field")))?;
+            return Ok(HsbValue::I64(timestamp.as_micros()));
+        }
+        Ok(HsbValue::String(v.to_string()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(HsbValue::Null)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element_seed(self.nested(PayloadField::Other)?)? {
+            values.push(value);
+        }
+        Ok(HsbValue::Array(values))
+    }
+
+    /// Objects with just a `$base64` field become binary values unless binary values are encoded
+    /// as arrays, and internally tagged enums become externally tagged.
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let max_object_keys = self.options.payload_limits.max_object_keys;
+        let mut entries: Vec<(String, HsbValue)> = Vec::new();
+        let mut variant = None;
+        let mut key_count = 0;
+        while let Some(key) = map.next_key::<String>()? {
+            key_count += 1;
+            if key_count > max_object_keys {
+                return Err(de::Error::custom(format!(
+                    "Payload object has more than {max_object_keys} keys"
+                )));
+            }
+            self.check_string(&key)?;
+            if let PayloadField::Enum(tag) = self.field
+                && key == tag
+            {
+                variant = Some(map.next_value_seed(self.nested(PayloadField::Other)?)?);
+                continue;
+            }
+
+            let key = if self.options.camel_case_keys {
+                camel_to_snake(&key)
+            } else {
+                key
+            };
+            let value = map.next_value_seed(self.nested(self.field(&key))?)?;
+            // Like in a JSON object, the last value of a duplicate key wins
+            match entries.iter_mut().find(|(existing, _)| *existing == key) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key, value)),
+            }
+        }
+
+        if let PayloadField::Enum(tag) = self.field
+            && let Some(variant) = variant
+        {
+            let HsbValue::String(variant) = variant else {
+                return Err(de::Error::custom(format!(
+                    "Enum variant in {tag} field must be a string"
+                )));
+            };
+            if entries.is_empty() {
+                return Ok(HsbValue::String(variant));
+            }
+            return Ok(HsbValue::Map(vec![(variant, HsbValue::Map(entries))]));
+        }
+
+        if self.options.binary_encoding != BinaryEncoding::Array
+            && let [(key, HsbValue::String(encoded))] = entries.as_slice()
+            && key == BASE64_FIELD
+        {
+            let bytes = BASE64_STANDARD.decode(encoded).map_err(|_| {
+                de::Error::custom(format!("Invalid base64 encoding in {BASE64_FIELD} field"))
+            })?;
+            return Ok(HsbValue::Bytes(bytes));
+        }
+
+        Ok(HsbValue::Map(entries))
+    }
+}
+
+/// The part of the JSON text around the position of a parse error, at most
+/// [`JSON_ERROR_SNIPPET_CHARS`] characters on either side.
+fn json_error_snippet(json: &str, err: &serde_json::Error) -> String {
+    let line = json
+        .lines()
+        .nth(err.line().saturating_sub(1))
+        .unwrap_or_default();
+    let position = line
+        .char_indices()
+        // The column is 1-based
+        .take_while(|(index, _)| *index + 1 < err.column())
+        .count();
+    line.chars()
+        .skip(position.saturating_sub(JSON_ERROR_SNIPPET_CHARS))
+        .take(2 * JSON_ERROR_SNIPPET_CHARS)
+        .collect()
+}
+
+/// Decode a base64 encoded payload in either the URL safe or the standard alphabet, with or
+/// without padding.
+///
+/// A `+` of the standard alphabet that wasn't percent encoded in the query string arrives as a
+/// space, so spaces are read as `+`.
+fn decode_base64_payload(base64_encoded_payload: &str) -> Result<Vec<u8>, DecodeError> {
+    if base64_encoded_payload.contains(['+', '/', ' ']) {
+        PAYLOAD_BASE64_STANDARD.decode(base64_encoded_payload.replace(' ', "+"))
+    } else {
+        PAYLOAD_BASE64_URL_SAFE.decode(base64_encoded_payload)
+    }
+}
+
 /// Function to transcode a zome call response encoded as Holochain serialized bytes (type `ExternIO`)
-/// to a JSON string.
-pub fn hsb_to_json(hsb_encoded_response: &ExternIO) -> HcHttpGatewayResult<String> {
-    let json_value = hsb_encoded_response
-        .decode::<serde_json::Value>()
-        .map_err(|err| {
-            HcHttpGatewayError::HolochainError(ConductorApiError::WebsocketError(err.into()))
-        })?;
-    Ok(json_value.to_string())
+/// to a JSON value.
+///
+/// Binary values that are valid holo hashes are rendered as their canonical base64 string. Other
+/// binary values, integers that exceed the precision of a JSON number and timestamps are rendered
+/// according to the `options`.
+pub fn hsb_to_json(
+    hsb_encoded_response: &ExternIO,
+    options: TranscodeOptions,
+) -> HcHttpGatewayResult<serde_json::Value> {
+    Ok(decode_hsb(hsb_encoded_response)?.to_json(options))
+}
+
+/// Function to transcode a zome call response encoded as Holochain serialized bytes (type
+/// `ExternIO`) to JSON text.
+///
+/// The JSON is rendered like [`hsb_to_json`] does, but written straight to the text without
+/// building a [`serde_json::Value`] first.
+pub fn hsb_to_json_string(
+    hsb_encoded_response: &ExternIO,
+    options: TranscodeOptions,
+) -> HcHttpGatewayResult<String> {
+    Ok(decode_hsb(hsb_encoded_response)?.to_json_string(options))
+}
+
+/// Function to transcode a zome call response encoded as Holochain serialized bytes (type
+/// `ExternIO`) to a JSON response body, which is written in chunks while it is sent rather than
+/// held in memory as a whole.
+///
+/// The JSON is rendered on a blocking thread with the options for the given function, like
+/// [`hsb_to_json`] does. Rendering stops when the body is dropped, for example because the client
+/// disconnected.
+pub fn hsb_to_json_body(
+    hsb_encoded_response: &ExternIO,
+    configuration: Arc<Configuration>,
+    app_id: String,
+    zome_name: String,
+    fn_name: String,
+) -> HcHttpGatewayResult<Body> {
+    let value = decode_hsb(hsb_encoded_response)?;
+
+    let (chunks_tx, chunks_rx) = tokio::sync::mpsc::channel(1);
+    tokio::task::spawn_blocking(move || {
+        let options = TranscodeOptions::for_function(&configuration, &app_id, &zome_name, &fn_name);
+        let mut writer = BufWriter::with_capacity(JSON_CHUNK_BYTES, ChunkWriter(chunks_tx.clone()));
+        let result = serde_json::to_writer(
+            &mut writer,
+            &RenderedJson {
+                value: &value,
+                options,
+            },
+        )
+        .map_err(std::io::Error::from)
+        .and_then(|()| writer.flush());
+        if let Err(err) = result {
+            // Fails if the body was dropped, then there is no one left to tell
+            let _ = chunks_tx.blocking_send(Err(err));
+        }
+    });
+
+    Ok(Body::from_stream(futures::stream::unfold(
+        chunks_rx,
+        |mut chunks_rx| async move { chunks_rx.recv().await.map(|chunk| (chunk, chunks_rx)) },
+    )))
+}
+
+fn decode_hsb(hsb_encoded_response: &ExternIO) -> HcHttpGatewayResult<HsbValue> {
+    hsb_encoded_response
+        .decode::<HsbValue>()
+        .map_err(|err| HcHttpGatewayError::ResponseDecodeError(err.to_string()))
+}
+
+/// Writes JSON text to the channel that a response body is streamed from.
+struct ChunkWriter(tokio::sync::mpsc::Sender<std::io::Result<Bytes>>);
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    "The response body was dropped",
+                )
+            })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A decoded msgpack value, which unlike [`serde_json::Value`] keeps binary values apart from
+/// arrays, so that they can be rendered differently.
+#[derive(Debug)]
+pub(crate) enum HsbValue {
+    Null,
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Array(Vec<HsbValue>),
+    Map(Vec<(String, HsbValue)>),
+}
+
+impl HsbValue {
+    /// Convert a map key to the string that it is rendered as in a JSON object.
+    ///
+    /// Numbers and booleans are rendered as their JSON text, for example `"42"` or `"true"`, and
+    /// null as `"null"`. Binary values are rendered as the canonical string of a holo hash, or as
+    /// standard base64 otherwise. Arrays and maps are rendered as their compact JSON text.
+    fn into_key(self) -> String {
+        match self {
+            HsbValue::String(s) => s,
+            HsbValue::Bytes(bytes) => {
+                holo_hash_to_string(&bytes).unwrap_or_else(|| BASE64_STANDARD.encode(bytes))
+            }
+            value => value.to_json_string(TranscodeOptions::default()),
+        }
+    }
+
+    /// Render the value as JSON according to the `options`.
+    fn to_json(&self, options: TranscodeOptions) -> serde_json::Value {
+        serde_json::to_value(RenderedJson {
+            value: self,
+            options,
+        })
+        // Rendering only fails for map keys that aren't strings, and all keys are strings
+        .expect("Failed to render JSON value")
+    }
+
+    /// Render the value as JSON text according to the `options`.
+    fn to_json_string(&self, options: TranscodeOptions) -> String {
+        serde_json::to_string(&RenderedJson {
+            value: self,
+            options,
+        })
+        // Rendering only fails for map keys that aren't strings, and all keys are strings
+        .expect("Failed to render JSON text")
+    }
+}
+
+/// A value rendered as JSON according to the options, which is serialized without building a
+/// [`serde_json::Value`] first, so that it can be written out directly.
+struct RenderedJson<'a> {
+    value: &'a HsbValue,
+    options: TranscodeOptions<'a>,
+}
+
+impl Serialize for RenderedJson<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let options = self.options;
+        match self.value {
+            HsbValue::Null => serializer.serialize_unit(),
+            HsbValue::Bool(b) => serializer.serialize_bool(*b),
+            HsbValue::I64(i)
+                if options.large_integers_as_strings && i.unsigned_abs() > MAX_SAFE_INTEGER =>
+            {
+                serializer.collect_str(i)
+            }
+            HsbValue::I64(i) => serializer.serialize_i64(*i),
+            HsbValue::U64(u) if options.large_integers_as_strings && *u > MAX_SAFE_INTEGER => {
+                serializer.collect_str(u)
+            }
+            HsbValue::U64(u) => serializer.serialize_u64(*u),
+            // Non-finite numbers are rendered as null
+            HsbValue::F64(f) => serializer.serialize_f64(*f),
+            HsbValue::String(s) => serializer.serialize_str(s),
+            HsbValue::Bytes(bytes) => match holo_hash_to_string(bytes) {
+                Some(hash) => serializer.serialize_str(&hash),
+                None => match options.binary_encoding {
+                    BinaryEncoding::Array => serializer.collect_seq(bytes),
+                    BinaryEncoding::Base64Object => {
+                        let mut map = serializer.serialize_map(Some(1))?;
+                        map.serialize_entry(BASE64_FIELD, &BASE64_STANDARD.encode(bytes))?;
+                        map.end()
+                    }
+                    BinaryEncoding::Base64 => {
+                        serializer.serialize_str(&BASE64_STANDARD.encode(bytes))
+                    }
+                },
+            },
+            HsbValue::Array(values) => {
+                serializer.collect_seq(values.iter().map(|value| RenderedJson { value, options }))
+            }
+            HsbValue::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    serialize_rendered_entry(&mut map, key, value, options)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// Serialize an entry of a map with the key and the value rendered according to the `options`.
+fn serialize_rendered_entry<M: SerializeMap>(
+    map: &mut M,
+    key: &str,
+    value: &HsbValue,
+    options: TranscodeOptions,
+) -> Result<(), M::Error> {
+    let rendered_key = if options.camel_case_keys {
+        Cow::Owned(snake_to_camel(key))
+    } else {
+        Cow::Borrowed(key)
+    };
+    if let Some(tag) = options.enum_tag(key)
+        && let Some((variant, fields)) = externally_tagged_variant(value, tag)
+    {
+        return map.serialize_entry(
+            &rendered_key,
+            &InternallyTagged {
+                tag,
+                variant,
+                fields,
+                options,
+            },
+        );
+    }
+
+    match value {
+        HsbValue::I64(micros) if options.is_timestamp_field(key) => {
+            map.serialize_entry(&rendered_key, &Timestamp::from_micros(*micros).to_string())
+        }
+        HsbValue::U64(micros) if options.is_timestamp_field(key) && *micros <= i64::MAX as u64 => {
+            map.serialize_entry(
+                &rendered_key,
+                &Timestamp::from_micros(*micros as i64).to_string(),
+            )
+        }
+        value => map.serialize_entry(&rendered_key, &RenderedJson { value, options }),
+    }
+}
+
+/// The variant and the fields of an externally tagged enum, as zome functions encode them.
+///
+/// Unit variants are encoded as the name of the variant, and struct variants as a map from the
+/// name of the variant to the fields. Other values, including variants with fields that include
+/// the tag field, can't be internally tagged.
+fn externally_tagged_variant<'v>(
+    value: &'v HsbValue,
+    tag: &str,
+) -> Option<(&'v str, &'v [(String, HsbValue)])> {
+    match value {
+        HsbValue::String(variant) => Some((variant, &[])),
+        HsbValue::Map(variants) => match variants.as_slice() {
+            [(variant, HsbValue::Map(fields))]
+                if !fields.iter().any(|(field, _)| field.as_str() == tag) =>
+            {
+                Some((variant, fields))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// An enum variant rendered as an internally tagged object, with the name of the variant in the
+/// tag field alongside the fields of the variant.
+struct InternallyTagged<'a> {
+    tag: &'a str,
+    variant: &'a str,
+    fields: &'a [(String, HsbValue)],
+    options: TranscodeOptions<'a>,
+}
+
+impl Serialize for InternallyTagged<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.fields.len() + 1))?;
+        map.serialize_entry(self.tag, self.variant)?;
+        for (key, value) in self.fields {
+            serialize_rendered_entry(&mut map, key, value, self.options)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for HsbValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            HsbValue::Null => serializer.serialize_unit(),
+            HsbValue::Bool(b) => serializer.serialize_bool(*b),
+            HsbValue::I64(i) => serializer.serialize_i64(*i),
+            HsbValue::U64(u) => serializer.serialize_u64(*u),
+            HsbValue::F64(f) => serializer.serialize_f64(*f),
+            HsbValue::String(s) => serializer.serialize_str(s),
+            HsbValue::Bytes(bytes) => serializer.serialize_bytes(bytes),
+            HsbValue::Array(values) => serializer.collect_seq(values),
+            HsbValue::Map(entries) => {
+                serializer.collect_map(entries.iter().map(|(key, value)| (key, value)))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for HsbValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(HsbValueVisitor)
+    }
+}
+
+struct HsbValueVisitor;
+
+impl<'de> Visitor<'de> for HsbValueVisitor {
+    type Value = HsbValue;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a msgpack value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(HsbValue::Bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(HsbValue::I64(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(HsbValue::U64(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(HsbValue::F64(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(HsbValue::String(v.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+        Ok(HsbValue::String(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(HsbValue::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(HsbValue::Bytes(v))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(HsbValue::Null)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        HsbValue::deserialize(deserializer)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(HsbValue::Null)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        HsbValue::deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(HsbValue::Array(values))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or_default());
+        while let Some((key, value)) = map.next_entry::<HsbValue, HsbValue>()? {
+            entries.push((key.into_key(), value));
+        }
+        Ok(HsbValue::Map(entries))
+    }
+}
+
+/// The canonical base64 string of the given bytes if they are a holo hash of a known type.
+///
+/// Besides the prefix, the location bytes at the end of the hash must match its core, so other
+/// binary values of the same length are practically never mistaken for a hash.
+fn holo_hash_to_string(bytes: &[u8]) -> Option<String> {
+    if bytes.len() != HOLO_HASH_FULL_LEN {
+        return None;
+    }
+
+    holo_hash_of_type::<hash_type::Agent>(bytes)
+        .or_else(|| holo_hash_of_type::<hash_type::Action>(bytes))
+        .or_else(|| holo_hash_of_type::<hash_type::Entry>(bytes))
+        .or_else(|| holo_hash_of_type::<hash_type::Dna>(bytes))
+        .or_else(|| holo_hash_of_type::<hash_type::DhtOp>(bytes))
+        .or_else(|| holo_hash_of_type::<hash_type::External>(bytes))
+        .or_else(|| holo_hash_of_type::<hash_type::Wasm>(bytes))
+}
+
+fn holo_hash_of_type<T: PrimitiveHashType>(bytes: &[u8]) -> Option<String> {
+    if bytes[..HOLO_HASH_PREFIX_LEN] != *T::new().get_prefix() {
+        return None;
+    }
+
+    let core = bytes[HOLO_HASH_PREFIX_LEN..HOLO_HASH_PREFIX_LEN + HOLO_HASH_CORE_LEN].to_vec();
+    let hash = HoloHash::<T>::from_raw_32(core);
+    (hash.get_raw_39() == bytes).then(|| hash.to_string())
+}
+
+/// Convert a camelCase key to snake_case, for example `createdAt` to `created_at`.
+///
+/// Keys that don't start with a lowercase letter, such as the PascalCase names of enum variants,
+/// are left as they are.
+fn camel_to_snake(key: &str) -> String {
+    if !key.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return key.to_string();
+    }
+
+    let mut snake = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// Convert a snake_case key to camelCase, for example `created_at` to `createdAt`.
+///
+/// Only underscores followed by a lowercase letter are removed, so that converting the key back
+/// with [`camel_to_snake`] gives the original key. Keys that don't start with a lowercase letter
+/// are left as they are.
+fn snake_to_camel(key: &str) -> String {
+    if !key.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return key.to_string();
+    }
+
+    let mut camel = String::with_capacity(key.len());
+    let mut chars = key.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(next) if c == '_' && next.is_ascii_lowercase() => {
+                camel.push(next.to_ascii_uppercase());
+                chars.next();
+            }
+            _ => camel.push(c),
+        }
+    }
+    camel
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        HcHttpGatewayError,
-        transcode::{base64_json_to_hsb, hsb_to_json},
+        BinaryEncoding, EmptyPayload, HcHttpGatewayError, PayloadLimits,
+        transcode::{
+            EnumTagging, TranscodeOptions, base64_json_to_hsb, base64_msgpack_to_hsb,
+            camel_to_snake, hsb_to_json, snake_to_camel,
+        },
+    };
+    use base64::{
+        Engine,
+        prelude::{
+            BASE64_STANDARD, BASE64_STANDARD_NO_PAD, BASE64_URL_SAFE, BASE64_URL_SAFE_NO_PAD,
+        },
     };
-    use base64::{Engine, prelude::BASE64_URL_SAFE};
     use holochain_types::dna::ActionHash;
-    use holochain_types::prelude::ExternIO;
+    use holochain_types::prelude::{
+        AgentPubKey, EntryHash, ExternIO, HOLO_HASH_FULL_LEN, Timestamp,
+    };
     use serde::{Deserialize, Serialize};
+    use std::collections::{BTreeMap, HashMap, HashSet};
 
     #[test]
     fn happy_no_payload_encode() {
         // No payload needs to be encoded for zome call invocation too. Test that a unit value is encoded.
-        let hsb_encoded_payload = base64_json_to_hsb(None).unwrap();
+        let hsb_encoded_payload = base64_json_to_hsb(None, TranscodeOptions::default()).unwrap();
 
         // Deserializing the serialized bytes to the original struct should succeed.
         hsb_encoded_payload.decode::<()>().unwrap();
@@ -77,13 +932,110 @@ mod tests {
         let json_payload = serde_json::to_string(&payload).unwrap();
         let base64_encoded_payload = BASE64_URL_SAFE.encode(json_payload);
 
-        let hsb_encoded_payload = base64_json_to_hsb(Some(base64_encoded_payload)).unwrap();
+        let hsb_encoded_payload =
+            base64_json_to_hsb(Some(base64_encoded_payload), TranscodeOptions::default()).unwrap();
 
         // Deserializing the serialized bytes to the original struct should succeed.
         let decoded_payload = hsb_encoded_payload.decode::<ZomeCallPayload>().unwrap();
         assert_eq!(decoded_payload, payload);
     }
 
+    #[test]
+    fn empty_payload_is_encoded_as_configured() {
+        let options = |empty_payload| TranscodeOptions {
+            empty_payload,
+            ..Default::default()
+        };
+
+        let hsb_encoded_payload = base64_json_to_hsb(None, options(EmptyPayload::Nil)).unwrap();
+        assert_eq!(hsb_encoded_payload.decode::<Option<u32>>().unwrap(), None);
+
+        let hsb_encoded_payload =
+            base64_json_to_hsb(None, options(EmptyPayload::EmptyMap)).unwrap();
+        assert!(
+            hsb_encoded_payload
+                .decode::<HashMap<String, u32>>()
+                .unwrap()
+                .is_empty()
+        );
+
+        let hsb_encoded_payload = base64_msgpack_to_hsb(None, EmptyPayload::EmptyMap).unwrap();
+        assert!(
+            hsb_encoded_payload
+                .decode::<HashMap<String, u32>>()
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn payload_in_any_base64_alphabet_is_accepted() {
+        // Encodes to "Ij8_Ig==" in the URL safe alphabet, and "Ij8/Ig==" in the standard one
+        let json_payload = r#""??""#;
+
+        for base64_encoded_payload in [
+            BASE64_URL_SAFE.encode(json_payload),
+            BASE64_URL_SAFE_NO_PAD.encode(json_payload),
+            BASE64_STANDARD.encode(json_payload),
+            BASE64_STANDARD_NO_PAD.encode(json_payload),
+        ] {
+            let hsb_encoded_payload =
+                base64_json_to_hsb(Some(base64_encoded_payload), TranscodeOptions::default())
+                    .unwrap();
+            assert_eq!(hsb_encoded_payload.decode::<String>().unwrap(), "??");
+        }
+
+        // A `+` that wasn't percent encoded is decoded as a space from the query string
+        let json_payload = r#""~~~""#;
+        assert_eq!(BASE64_STANDARD.encode(json_payload), "In5+fiI=");
+        let hsb_encoded_payload =
+            base64_json_to_hsb(Some("In5 fiI=".to_string()), TranscodeOptions::default()).unwrap();
+        assert_eq!(hsb_encoded_payload.decode::<String>().unwrap(), "~~~");
+
+        // The alphabets can't be mixed
+        let result = base64_json_to_hsb(Some("In5+fi_=".to_string()), TranscodeOptions::default());
+        assert2::assert!(let HcHttpGatewayError::RequestMalformed(_) = result.unwrap_err());
+    }
+
+    #[test]
+    fn payload_exceeding_structural_limits_is_rejected() {
+        let options = TranscodeOptions {
+            payload_limits: PayloadLimits {
+                max_depth: 2,
+                max_object_keys: 2,
+                max_string_bytes: 5,
+            },
+            ..Default::default()
+        };
+        let transcode = |json_payload: &str| {
+            base64_json_to_hsb(Some(BASE64_URL_SAFE.encode(json_payload)), options)
+        };
+
+        transcode(r#"{"a":["hello"],"b":1}"#).unwrap();
+
+        for (json_payload, error) in [
+            (
+                r#"{"a":[[1]]}"#,
+                "Payload exceeds the maximum depth of 2 at line 1 column 7",
+            ),
+            (
+                r#"{"a":1,"b":2,"c":3}"#,
+                "Payload object has more than 2 keys at line 1 column 16",
+            ),
+            (
+                r#"["hello!"]"#,
+                "Payload string exceeds 5 bytes at line 1 column 9",
+            ),
+            (
+                r#"{"longer":1}"#,
+                "Payload string exceeds 5 bytes at line 1 column 9",
+            ),
+        ] {
+            assert2::assert!(let HcHttpGatewayError::RequestMalformed(err) = transcode(json_payload).unwrap_err());
+            assert_eq!(err, error, "{json_payload}");
+        }
+    }
+
     #[test]
     fn plain_json_to_hsb_fails() {
         #[derive(Debug, PartialEq, Deserialize, Serialize)]
@@ -94,7 +1046,7 @@ mod tests {
         let payload = ZomeCallPayload { field: false };
         let json_payload = serde_json::to_string(&payload).unwrap();
 
-        let result = base64_json_to_hsb(Some(json_payload));
+        let result = base64_json_to_hsb(Some(json_payload), TranscodeOptions::default());
         assert2::assert!(let HcHttpGatewayError::RequestMalformed(err) = result.unwrap_err());
         assert_eq!(err.to_string(), "Invalid base64 encoding");
     }
@@ -103,9 +1055,28 @@ mod tests {
     fn invalid_json_to_hsb_fails() {
         let base64_encoded_payload = BASE64_URL_SAFE.encode("invalid");
 
-        let result = base64_json_to_hsb(Some(base64_encoded_payload));
+        let result = base64_json_to_hsb(Some(base64_encoded_payload), TranscodeOptions::default());
         assert2::assert!(let HcHttpGatewayError::RequestMalformed(err) = result.unwrap_err());
-        assert_eq!(err.to_string(), "Invalid JSON value");
+        assert_eq!(
+            err.to_string(),
+            r#"Invalid JSON value: expected value at line 1 column 1, near "invalid""#
+        );
+
+        let json_payload = format!(r#"{{"text":"{}","count":}}"#, "a".repeat(40));
+        let base64_encoded_payload = BASE64_URL_SAFE.encode(json_payload);
+        let result = base64_json_to_hsb(Some(base64_encoded_payload), TranscodeOptions::default());
+        assert2::assert!(let HcHttpGatewayError::RequestMalformed(err) = result.unwrap_err());
+        assert_eq!(
+            err.to_string(),
+            r#"Invalid JSON value: expected value at line 1 column 60, near "aaaaaaaaaa\",\"count\":}""#
+        );
+    }
+
+    #[test]
+    fn undecodable_response_is_a_decode_error() {
+        // 0xc1 is never used in msgpack
+        let result = hsb_to_json(&ExternIO(vec![0xc1]), TranscodeOptions::default());
+        assert2::assert!(let Err(HcHttpGatewayError::ResponseDecodeError(_)) = result);
     }
 
     #[test]
@@ -120,22 +1091,391 @@ mod tests {
         };
         let msgpack_encoded_response = ExternIO::encode(response.clone()).unwrap();
 
-        let json_response = hsb_to_json(&msgpack_encoded_response).unwrap();
+        let json_response = hsb_to_json(&msgpack_encoded_response, TranscodeOptions::default())
+            .unwrap()
+            .to_string();
 
         let expected_json_response = serde_json::to_string(&response).unwrap();
         assert_eq!(json_response, expected_json_response);
     }
 
-    // TODO requires https://github.com/serde-rs/json/pull/1247
     #[test]
     fn deserialize_binary() {
-        let output = ExternIO::encode(ActionHash::from_raw_32(vec![2; 32])).unwrap();
+        let output = ExternIO::encode(serde_bytes::ByteBuf::from(vec![1, 2, 3])).unwrap();
+
+        let json = hsb_to_json(&output, TranscodeOptions::default())
+            .unwrap()
+            .to_string();
+
+        assert_eq!(json, "[1,2,3]");
+    }
+
+    #[test]
+    fn holo_hashes_are_rendered_as_base64() {
+        #[derive(Debug, Serialize)]
+        struct ZomeCallResponse {
+            action_hash: ActionHash,
+            author: AgentPubKey,
+            entry_hash: Option<EntryHash>,
+        }
+
+        let response = ZomeCallResponse {
+            action_hash: ActionHash::from_raw_32(vec![2; 32]),
+            author: AgentPubKey::from_raw_32(vec![3; 32]),
+            entry_hash: Some(EntryHash::from_raw_32(vec![4; 32])),
+        };
+        let output = ExternIO::encode(&response).unwrap();
+
+        let json = hsb_to_json(&output, TranscodeOptions::default())
+            .unwrap()
+            .to_string();
+
+        let expected = serde_json::json!({
+            "action_hash": response.action_hash.to_string(),
+            "author": response.author.to_string(),
+            "entry_hash": response.entry_hash.unwrap().to_string(),
+        });
+        assert_eq!(json, expected.to_string());
+        assert!(json.contains("\"uhCkk"));
+    }
+
+    #[test]
+    fn bytes_with_invalid_location_are_not_a_hash() {
+        let mut bytes = ActionHash::from_raw_32(vec![2; 32]).get_raw_39().to_vec();
+        bytes[HOLO_HASH_FULL_LEN - 1] ^= 1;
+        let output = ExternIO::encode(serde_bytes::ByteBuf::from(bytes.clone())).unwrap();
+
+        let json = hsb_to_json(&output, TranscodeOptions::default())
+            .unwrap()
+            .to_string();
+
+        assert_eq!(json, serde_json::Value::from(bytes).to_string());
+    }
+
+    #[test]
+    fn binary_is_rendered_as_base64() {
+        let output = ExternIO::encode(serde_bytes::ByteBuf::from(vec![1, 2, 3])).unwrap();
+
+        let json = hsb_to_json(
+            &output,
+            TranscodeOptions {
+                binary_encoding: BinaryEncoding::Base64Object,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .to_string();
+        assert_eq!(json, r#"{"$base64":"AQID"}"#);
+
+        let json = hsb_to_json(
+            &output,
+            TranscodeOptions {
+                binary_encoding: BinaryEncoding::Base64,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .to_string();
+        assert_eq!(json, r#""AQID""#);
+    }
+
+    #[test]
+    fn base64_objects_in_payload_are_binary() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct ZomeCallPayload {
+            data: serde_bytes::ByteBuf,
+            label: String,
+        }
+
+        let base64_encoded_payload =
+            BASE64_URL_SAFE.encode(r#"{"data":{"$base64":"AQID"},"label":"x"}"#);
+
+        let hsb_encoded_payload = base64_json_to_hsb(
+            Some(base64_encoded_payload),
+            TranscodeOptions {
+                binary_encoding: BinaryEncoding::Base64Object,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let decoded_payload = hsb_encoded_payload.decode::<ZomeCallPayload>().unwrap();
+        assert_eq!(
+            decoded_payload,
+            ZomeCallPayload {
+                data: serde_bytes::ByteBuf::from(vec![1, 2, 3]),
+                label: "x".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_base64_object_in_payload_fails() {
+        let base64_encoded_payload = BASE64_URL_SAFE.encode(r#"{"$base64":"not base64!"}"#);
+
+        let result = base64_json_to_hsb(
+            Some(base64_encoded_payload),
+            TranscodeOptions {
+                binary_encoding: BinaryEncoding::Base64,
+                ..Default::default()
+            },
+        );
+        assert2::assert!(let HcHttpGatewayError::RequestMalformed(err) = result.unwrap_err());
+        assert_eq!(
+            err.to_string(),
+            "Invalid base64 encoding in $base64 field at line 1 column 25"
+        );
+    }
+
+    #[test]
+    fn large_integers_are_rendered_as_strings() {
+        let output = ExternIO::encode((
+            9_007_199_254_740_991_u64,
+            9_007_199_254_740_992_u64,
+            -9_007_199_254_740_992_i64,
+            1_700_000_000_000_000_i64,
+        ))
+        .unwrap();
+
+        let json = hsb_to_json(&output, TranscodeOptions::default())
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            json,
+            "[9007199254740991,9007199254740992,-9007199254740992,1700000000000000]"
+        );
+
+        let json = hsb_to_json(
+            &output,
+            TranscodeOptions {
+                large_integers_as_strings: true,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .to_string();
+        assert_eq!(
+            json,
+            r#"[9007199254740991,"9007199254740992","-9007199254740992",1700000000000000]"#
+        );
+    }
+
+    #[test]
+    fn timestamps_are_rendered_as_rfc3339() {
+        #[derive(Debug, Serialize)]
+        struct ZomeCallResponse {
+            timestamp: Timestamp,
+            count: u32,
+        }
+
+        let timestamp = Timestamp::from_micros(1_700_000_000_123_456);
+        let output = ExternIO::encode(ZomeCallResponse {
+            timestamp,
+            count: 5,
+        })
+        .unwrap();
+        let timestamp_fields = HashSet::from(["timestamp".to_string()]);
+        let options = TranscodeOptions {
+            timestamp_fields: Some(&timestamp_fields),
+            ..Default::default()
+        };
+
+        let json = hsb_to_json(&output, options).unwrap().to_string();
+        assert_eq!(
+            json,
+            serde_json::json!({ "timestamp": timestamp.to_string(), "count": 5 }).to_string()
+        );
+
+        // Without timestamp fields the timestamp stays a number
+        let json = hsb_to_json(&output, TranscodeOptions::default())
+            .unwrap()
+            .to_string();
+        assert_eq!(json, r#"{"count":5,"timestamp":1700000000123456}"#);
+    }
+
+    #[test]
+    fn rfc3339_timestamps_in_payload_are_timestamps() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct ZomeCallPayload {
+            timestamp: Timestamp,
+        }
+
+        let timestamp_fields = HashSet::from(["timestamp".to_string()]);
+        let options = TranscodeOptions {
+            timestamp_fields: Some(&timestamp_fields),
+            ..Default::default()
+        };
+        let base64_encoded_payload =
+            BASE64_URL_SAFE.encode(r#"{"timestamp":"2024-01-01T00:00:00Z"}"#);
+
+        let hsb_encoded_payload =
+            base64_json_to_hsb(Some(base64_encoded_payload), options).unwrap();
+
+        let decoded_payload = hsb_encoded_payload.decode::<ZomeCallPayload>().unwrap();
+        assert_eq!(
+            decoded_payload.timestamp,
+            Timestamp::from_micros(1_704_067_200_000_000)
+        );
 
-        let json = hsb_to_json(&output).unwrap();
+        let base64_encoded_payload = BASE64_URL_SAFE.encode(r#"{"timestamp":"yesterday"}"#);
+        let result = base64_json_to_hsb(Some(base64_encoded_payload), options);
+        assert2::assert!(let HcHttpGatewayError::RequestMalformed(err) = result.unwrap_err());
+        assert_eq!(
+            err.to_string(),
+            "Invalid RFC 3339 timestamp in timestamp field at line 1 column 24"
+        );
+    }
+
+    #[test]
+    fn enums_are_converted_between_internal_and_external_tagging() {
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        enum Action {
+            Create { title: String },
+            Move(u32),
+            Delete,
+        }
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Change {
+            action: Action,
+        }
+
+        let enum_fields = HashSet::from(["action".to_string()]);
+        let options = TranscodeOptions {
+            enum_tagging: Some(EnumTagging {
+                fields: &enum_fields,
+                tag: "type",
+            }),
+            ..Default::default()
+        };
+
+        for (action, json) in [
+            (
+                Action::Create {
+                    title: "Hello".to_string(),
+                },
+                r#"{"action":{"title":"Hello","type":"Create"}}"#,
+            ),
+            (Action::Delete, r#"{"action":{"type":"Delete"}}"#),
+            // Variants without named fields can't be internally tagged
+            (Action::Move(3), r#"{"action":{"Move":3}}"#),
+        ] {
+            let output = ExternIO::encode(Change {
+                action: action.clone(),
+            })
+            .unwrap();
+            assert_eq!(hsb_to_json(&output, options).unwrap().to_string(), json);
+
+            let hsb_encoded_payload =
+                base64_json_to_hsb(Some(BASE64_URL_SAFE.encode(json)), options).unwrap();
+            assert_eq!(
+                hsb_encoded_payload.decode::<Change>().unwrap(),
+                Change { action }
+            );
+        }
+
+        let base64_encoded_payload = BASE64_URL_SAFE.encode(r#"{"action":{"type":1}}"#);
+        let result = base64_json_to_hsb(Some(base64_encoded_payload), options);
+        assert2::assert!(let HcHttpGatewayError::RequestMalformed(err) = result.unwrap_err());
+        assert_eq!(
+            err,
+            "Enum variant in type field must be a string at line 1 column 20"
+        );
+    }
+
+    #[test]
+    fn keys_are_converted_between_camel_and_snake_case() {
+        for (snake, camel) in [
+            ("created_at", "createdAt"),
+            ("author_pub_key", "authorPubKey"),
+            ("title", "title"),
+            ("field_2", "field_2"),
+            ("_private", "_private"),
+            ("Create", "Create"),
+            ("$base64", "$base64"),
+        ] {
+            assert_eq!(snake_to_camel(snake), camel);
+            assert_eq!(camel_to_snake(camel), snake);
+        }
+    }
+
+    #[test]
+    fn camel_case_keys_are_converted_in_payloads_and_responses() {
+        #[derive(Debug, PartialEq, Deserialize, Serialize)]
+        enum Action {
+            Create { created_at: Timestamp },
+        }
+
+        let timestamp_fields = HashSet::from(["created_at".to_string()]);
+        let options = TranscodeOptions {
+            timestamp_fields: Some(&timestamp_fields),
+            camel_case_keys: true,
+            ..Default::default()
+        };
+        let base64_encoded_payload =
+            BASE64_URL_SAFE.encode(r#"{"Create":{"createdAt":"2024-01-01T00:00:00Z"}}"#);
+
+        let hsb_encoded_payload =
+            base64_json_to_hsb(Some(base64_encoded_payload), options).unwrap();
+
+        let action = hsb_encoded_payload.decode::<Action>().unwrap();
+        assert_eq!(
+            action,
+            Action::Create {
+                created_at: Timestamp::from_micros(1_704_067_200_000_000)
+            }
+        );
+
+        let output = ExternIO::encode(action).unwrap();
+        let json = hsb_to_json(&output, options).unwrap().to_string();
+        let timestamp = Timestamp::from_micros(1_704_067_200_000_000);
+        assert_eq!(
+            json,
+            serde_json::json!({ "Create": { "createdAt": timestamp.to_string() } }).to_string()
+        );
+    }
+
+    #[test]
+    fn msgpack_payload_is_passed_through() {
+        let msgpack = ExternIO::encode(("value", 5)).unwrap();
+        let base64_encoded_payload = BASE64_URL_SAFE.encode(&msgpack.0);
+
+        let hsb_encoded_payload =
+            base64_msgpack_to_hsb(Some(base64_encoded_payload), EmptyPayload::Unit).unwrap();
+        assert_eq!(hsb_encoded_payload, msgpack);
+
+        base64_msgpack_to_hsb(None, EmptyPayload::Unit)
+            .unwrap()
+            .decode::<()>()
+            .unwrap();
+        let result = base64_msgpack_to_hsb(Some("not base64!".to_string()), EmptyPayload::Unit);
+        assert2::assert!(let HcHttpGatewayError::RequestMalformed(_) = result.unwrap_err());
+    }
+
+    #[test]
+    fn non_string_map_keys_are_stringified() {
+        let agent_pub_key = AgentPubKey::from_raw_32(vec![1; 32]);
+        let output = ExternIO::encode((
+            BTreeMap::from([(1u32, "one"), (20, "twenty")]),
+            BTreeMap::from([(false, 0)]),
+            BTreeMap::from([(agent_pub_key.clone(), 1)]),
+            BTreeMap::from([(serde_bytes::ByteBuf::from(vec![1, 2, 3]), 2)]),
+            BTreeMap::from([((1, 2), 3)]),
+        ))
+        .unwrap();
 
+        let json = hsb_to_json(&output, TranscodeOptions::default())
+            .unwrap()
+            .to_string();
         assert_eq!(
             json,
-            "[132,41,36,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,2,32,73,61,253]"
+            serde_json::json!([
+                { "1": "one", "20": "twenty" },
+                { "false": 0 },
+                { agent_pub_key.to_string(): 1 },
+                { "AQID": 2 },
+                { "[1,2]": 3 },
+            ])
+            .to_string()
         );
     }
 }
@@ -0,0 +1,160 @@
+//! Hooks for rewriting the payloads and responses of zome calls.
+//!
+//! Embedders register [`Transform`]s with
+//! [`HcHttpGatewayService::with_transform`](crate::HcHttpGatewayService::with_transform), for
+//! example to inject credentials into payloads, to enrich responses, or to strip fields from
+//! them, without forking the gateway. With the `scripting` feature, transforms can also be
+//! written as Rhai scripts, see `ScriptTransform`.
+
+use crate::HcHttpGatewayResult;
+use axum::http::HeaderMap;
+use futures::future::BoxFuture;
+use holochain_types::prelude::ExternIO;
+use std::sync::Arc;
+
+#[cfg(feature = "scripting")]
+mod script;
+#[cfg(feature = "scripting")]
+pub use script::ScriptTransform;
+
+/// The zome call that a payload or response is transformed for.
+#[derive(Debug, Clone)]
+pub struct TransformContext {
+    /// Id of the app that is called
+    pub installed_app_id: String,
+    /// Name of the zome that is called
+    pub zome_name: String,
+    /// Name of the function that is called
+    pub fn_name: String,
+    /// Headers of the HTTP request
+    pub headers: HeaderMap,
+}
+
+/// A hook that rewrites the payloads and responses of zome calls.
+///
+/// Payloads and responses are passed as the msgpack that is sent to and received from the zome
+/// function, whether the client sent JSON or msgpack, so that a transform can decode them to its
+/// own types. Both methods leave the value unchanged by default. An error fails the request, and
+/// is returned to the client like any other error of the gateway.
+pub trait Transform: std::fmt::Debug + Send + Sync {
+    /// Rewrite the payload of a zome call before the call is made.
+    fn transform_payload(
+        &self,
+        context: &TransformContext,
+        payload: ExternIO,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        let _ = context;
+        Box::pin(async move { Ok(payload) })
+    }
+
+    /// Rewrite the response of a zome call before it is returned to the client.
+    fn transform_response(
+        &self,
+        context: &TransformContext,
+        response: ExternIO,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        let _ = context;
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+/// The transforms that are applied to zome calls, in the order that they were registered.
+///
+/// Payloads pass through the transforms in order, and responses in reverse order, so that the
+/// first transform sees the payload first and the response last.
+#[derive(Debug, Clone, Default)]
+pub struct Transforms(Vec<Arc<dyn Transform>>);
+
+impl Transforms {
+    /// Register a transform after the ones that are already registered.
+    pub fn push(&mut self, transform: Arc<dyn Transform>) {
+        self.0.push(transform);
+    }
+
+    /// Whether no transforms are registered.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Pass the payload of a zome call through the transforms.
+    pub(crate) async fn payload(
+        &self,
+        context: &TransformContext,
+        mut payload: ExternIO,
+    ) -> HcHttpGatewayResult<ExternIO> {
+        for transform in &self.0 {
+            payload = transform.transform_payload(context, payload).await?;
+        }
+        Ok(payload)
+    }
+
+    /// Pass the response of a zome call through the transforms.
+    pub(crate) async fn response(
+        &self,
+        context: &TransformContext,
+        mut response: ExternIO,
+    ) -> HcHttpGatewayResult<ExternIO> {
+        for transform in self.0.iter().rev() {
+            response = transform.transform_response(context, response).await?;
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Appends its name to payloads and responses that are lists of names.
+    #[derive(Debug)]
+    struct AppendName(&'static str);
+
+    impl AppendName {
+        fn append(&self, value: ExternIO) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+            let name = self.0;
+            Box::pin(async move {
+                let mut names = value.decode::<Vec<String>>().unwrap();
+                names.push(name.to_string());
+                Ok(ExternIO::encode(names).unwrap())
+            })
+        }
+    }
+
+    impl Transform for AppendName {
+        fn transform_payload(
+            &self,
+            _context: &TransformContext,
+            payload: ExternIO,
+        ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+            self.append(payload)
+        }
+
+        fn transform_response(
+            &self,
+            _context: &TransformContext,
+            response: ExternIO,
+        ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+            self.append(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn responses_are_transformed_in_reverse_order() {
+        let mut transforms = Transforms::default();
+        transforms.push(Arc::new(AppendName("a")));
+        transforms.push(Arc::new(AppendName("b")));
+        let context = TransformContext {
+            installed_app_id: "app".to_string(),
+            zome_name: "zome".to_string(),
+            fn_name: "fn".to_string(),
+            headers: HeaderMap::new(),
+        };
+        let empty = || ExternIO::encode(Vec::<String>::new()).unwrap();
+
+        let payload = transforms.payload(&context, empty()).await.unwrap();
+        assert_eq!(payload.decode::<Vec<String>>().unwrap(), ["a", "b"]);
+
+        let response = transforms.response(&context, empty()).await.unwrap();
+        assert_eq!(response.decode::<Vec<String>>().unwrap(), ["b", "a"]);
+    }
+}
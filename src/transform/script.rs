@@ -0,0 +1,198 @@
+//! Transforms written as Rhai scripts, for users of the gateway binary who can't register
+//! transforms in Rust.
+
+use super::{Transform, TransformContext};
+use crate::config::{AppId, ConfigParseError, ConfigParseResult};
+use crate::transcode::HsbValue;
+use crate::{HcHttpGatewayError, HcHttpGatewayResult};
+use futures::future::BoxFuture;
+use holochain_types::prelude::ExternIO;
+use rhai::{AST, Dynamic, Engine, Scope};
+
+/// Name of the script function that rewrites payloads.
+const PAYLOAD_FN: &str = "transform_payload";
+/// Name of the script function that rewrites responses.
+const RESPONSE_FN: &str = "transform_response";
+
+/// The number of operations that a script may run for a single payload or response, so that a
+/// script that loops forever can't hold up the gateway.
+const MAX_SCRIPT_OPERATIONS: u64 = 100_000;
+
+/// A transform for the zome calls of an app, written as a Rhai script.
+///
+/// The script may define `transform_payload(zome_name, fn_name, payload)` and
+/// `transform_response(zome_name, fn_name, response)`, which return the rewritten payload or
+/// response. Payloads and responses are passed as Rhai values, with maps for objects and blobs
+/// for binary values. Payloads and responses that the script has no function for are left as
+/// they are.
+pub struct ScriptTransform {
+    app_id: AppId,
+    engine: Engine,
+    ast: AST,
+}
+
+impl std::fmt::Debug for ScriptTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScriptTransform")
+            .field("app_id", &self.app_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ScriptTransform {
+    /// Compile the script of the transform for the given app.
+    pub fn new(app_id: impl Into<AppId>, script: &str) -> ConfigParseResult<Self> {
+        let app_id = app_id.into();
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+        let ast = engine.compile(script).map_err(|err| {
+            ConfigParseError::Other(format!("Invalid transform script for app {app_id}: {err}"))
+        })?;
+
+        Ok(Self {
+            app_id,
+            engine,
+            ast,
+        })
+    }
+
+    fn defines(&self, script_fn: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == script_fn && function.params.len() == 3)
+    }
+
+    /// Pass a payload or response through a function of the script, if the script defines it.
+    fn call(
+        &self,
+        script_fn: &str,
+        context: &TransformContext,
+        value: ExternIO,
+    ) -> HcHttpGatewayResult<ExternIO> {
+        if context.installed_app_id != self.app_id || !self.defines(script_fn) {
+            return Ok(value);
+        }
+        let script_error = |err: Box<rhai::EvalAltResult>| {
+            HcHttpGatewayError::TransformError(format!("{script_fn} of app {}: {err}", self.app_id))
+        };
+
+        let value = value
+            .decode::<HsbValue>()
+            .map_err(|err| HcHttpGatewayError::TransformError(err.to_string()))?;
+        let value = rhai::serde::to_dynamic(value).map_err(script_error)?;
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(
+                &mut Scope::new(),
+                &self.ast,
+                script_fn,
+                (context.zome_name.clone(), context.fn_name.clone(), value),
+            )
+            .map_err(script_error)?;
+        let result = rhai::serde::from_dynamic::<HsbValue>(&result).map_err(script_error)?;
+
+        ExternIO::encode(result).map_err(|err| HcHttpGatewayError::TransformError(err.to_string()))
+    }
+}
+
+impl Transform for ScriptTransform {
+    fn transform_payload(
+        &self,
+        context: &TransformContext,
+        payload: ExternIO,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        let result = self.call(PAYLOAD_FN, context, payload);
+        Box::pin(async move { result })
+    }
+
+    fn transform_response(
+        &self,
+        context: &TransformContext,
+        response: ExternIO,
+    ) -> BoxFuture<'static, HcHttpGatewayResult<ExternIO>> {
+        let result = self.call(RESPONSE_FN, context, response);
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use std::collections::HashMap;
+
+    const SCRIPT: &str = r#"
+        fn transform_payload(zome_name, fn_name, payload) {
+            if fn_name == "create_post" && !("tags" in payload) {
+                payload.tags = [];
+            }
+            payload
+        }
+
+        fn transform_response(zome_name, fn_name, response) {
+            response.name = response.remove("title");
+            response
+        }
+    "#;
+
+    fn context(installed_app_id: &str) -> TransformContext {
+        TransformContext {
+            installed_app_id: installed_app_id.to_string(),
+            zome_name: "posts".to_string(),
+            fn_name: "create_post".to_string(),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn payloads_and_responses_are_rewritten_by_the_script() {
+        let transform = ScriptTransform::new("app", SCRIPT).unwrap();
+
+        let payload = ExternIO::encode(HashMap::from([("title", "Hello")])).unwrap();
+        let payload = transform
+            .transform_payload(&context("app"), payload)
+            .await
+            .unwrap();
+        assert_eq!(
+            payload.decode::<serde_json::Value>().unwrap(),
+            serde_json::json!({ "title": "Hello", "tags": [] })
+        );
+
+        let response = ExternIO::encode(HashMap::from([("title", "Hello")])).unwrap();
+        let response = transform
+            .transform_response(&context("app"), response)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.decode::<HashMap<String, String>>().unwrap(),
+            HashMap::from([("name".to_string(), "Hello".to_string())])
+        );
+    }
+
+    #[tokio::test]
+    async fn other_apps_are_not_transformed() {
+        let transform = ScriptTransform::new("app", SCRIPT).unwrap();
+
+        let payload = ExternIO::encode(HashMap::from([("title", "Hello")])).unwrap();
+        let transformed = transform
+            .transform_payload(&context("other"), payload.clone())
+            .await
+            .unwrap();
+        assert_eq!(transformed, payload);
+    }
+
+    #[tokio::test]
+    async fn failing_script_is_a_transform_error() {
+        let transform = ScriptTransform::new(
+            "app",
+            "fn transform_payload(zome_name, fn_name, payload) { throw \"no\"; }",
+        )
+        .unwrap();
+
+        let payload = ExternIO::encode(()).unwrap();
+        let result = transform.transform_payload(&context("app"), payload).await;
+        assert2::assert!(let Err(HcHttpGatewayError::TransformError(_)) = result);
+
+        ScriptTransform::new("app", "fn transform_payload(").unwrap_err();
+    }
+}
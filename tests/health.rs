@@ -1,7 +1,11 @@
+use axum::body::Body;
+use axum::http::Request;
 use holochain::sweettest::SweetConductor;
 use holochain_http_gateway::test::test_tracing::initialize_testing_tracing_subscriber;
+use holochain_http_gateway::{Conductors, HcHttpGatewayRouter};
 use reqwest::StatusCode;
-use setup::TestGateway;
+use std::time::Duration;
+use tower::ServiceExt;
 
 mod setup;
 
@@ -11,11 +15,11 @@ async fn health_check_works() {
 
     let sweet_conductor = SweetConductor::standard().await;
 
-    let gateway = TestGateway::spawn(sweet_conductor.clone()).await;
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
 
     let response = gateway
         .client
-        .get(format!("http://{}/health", gateway.address))
+        .get(gateway.url("/health"))
         .send()
         .await
         .expect("Failed to execute request");
@@ -25,3 +29,86 @@ async fn health_check_works() {
     let body = response.text().await.expect("Failed to read response body");
     assert_eq!(body, "Ok");
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_stops_the_gateway() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    let mut gateway = setup::spawn_gateway(&sweet_conductor).await;
+
+    let response = gateway
+        .client
+        .get(gateway.url("/health"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert!(gateway.handle.status().await.is_healthy());
+
+    tokio::time::timeout(Duration::from_secs(5), gateway.handle.shutdown())
+        .await
+        .expect("Gateway did not shut down")
+        .unwrap();
+
+    gateway
+        .client
+        .get(gateway.url("/health"))
+        .send()
+        .await
+        .expect_err("Gateway still accepts requests");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn embedded_router_is_served_under_a_path_of_another_app() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    let configuration = setup::fixture_config(&sweet_conductor);
+    let conductors = Conductors::from_configuration(&configuration);
+    let (router, tasks) = HcHttpGatewayRouter::new(configuration, conductors).build();
+    let app = axum::Router::new()
+        .route("/", axum::routing::get(|| async { "Home" }))
+        .nest("/gateway", router);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/gateway/health")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    tokio::time::timeout(Duration::from_secs(5), tasks.stop())
+        .await
+        .expect("Gateway tasks did not stop");
+}
+
+#[cfg(feature = "h2c")]
+#[tokio::test(flavor = "multi_thread")]
+async fn health_check_is_served_over_h2c() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
+
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let response = client
+        .get(gateway.url("/health"))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+}
@@ -4,7 +4,8 @@ use holochain::conductor::Conductor;
 use holochain::conductor::api::error::ConductorApiResult;
 use holochain::conductor::error::ConductorResult;
 use holochain::prelude::InitCallbackResult;
-use holochain_types::app::{AppBundleSource, InstallAppPayload, InstalledApp, InstalledAppId};
+use holochain_http_gateway::test::gateway::TestApp;
+use holochain_types::app::{InstalledApp, InstalledAppId};
 use std::path::PathBuf;
 use std::sync::Arc;
 // TODO `SerializedBytes` has an unclean macro reference to `holochain_serial!`
@@ -71,20 +72,7 @@ async fn install_app_from_path(
     happ_path: PathBuf,
     installed_app_id: Option<InstalledAppId>,
 ) -> ConductorResult<InstalledApp> {
-    let app = conductor
-        .clone()
-        .install_app_bundle(InstallAppPayload {
-            source: AppBundleSource::Path(happ_path),
-            agent_key: None,
-            installed_app_id,
-            network_seed: None,
-            roles_settings: None,
-            ignore_genesis_failure: false,
-            restore_from_dht: false,
-        })
-        .await?;
-
-    conductor.enable_app(app.installed_app_id.clone()).await?;
-
-    Ok(app)
+    Ok(TestApp::install(&conductor, happ_path, installed_app_id)
+        .await
+        .app)
 }
@@ -4,13 +4,15 @@ use holochain_client::{AdminWebsocket, CellInfo, ConductorApiError, ExternIO, Zo
 use holochain_conductor_api::{AdminInterfaceConfig, InterfaceDriver};
 use holochain_http_gateway::test::test_tracing::initialize_testing_tracing_subscriber;
 use holochain_http_gateway::{
-    AdminCall, AdminConn, AllowedFns, AppConnPool, Configuration, HTTP_GW_ORIGIN,
-    HcHttpGatewayError, ZomeFn,
+    AdminCall, AdminConn, AllowedFns, AppCall, AppConnPool, Configuration, HTTP_GW_ORIGIN,
+    HcHttpGatewayError, ZomeCallRequest, ZomeFn,
 };
 use holochain_types::app::DisabledAppReason;
 use holochain_types::websocket::AllowedOrigins;
+use std::collections::HashSet;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 
 mod sweet;
 
@@ -30,6 +32,46 @@ async fn connect_admin_websocket() {
     assert!(app_list.is_empty());
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn admin_websocket_introspection() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+    install_fixture1(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    let conn = AdminConn::new(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), admin_port));
+
+    let storage_info = conn.storage_info().await.unwrap();
+    assert!(!storage_info.blobs.is_empty());
+
+    conn.agent_info(None).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connect_admin_websocket_to_resolved_url() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    // The address that was resolved at startup is no longer valid
+    let conn = AdminConn::new(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1))
+        .with_admin_ws_url(format!("ws://127.0.0.1:{admin_port}"));
+
+    let app_list = conn.list_apps(None).await.unwrap();
+    assert!(app_list.is_empty());
+    assert_eq!(conn.socket_addr().port(), admin_port);
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn reconnect_admin_websocket() {
     initialize_testing_tracing_subscriber();
@@ -69,6 +111,36 @@ async fn reconnect_admin_websocket() {
 
     let app_list = conn.list_apps(None).await.unwrap();
     assert!(app_list.is_empty());
+
+    // The lost connection was evicted, and the connection failed until the conductor was back
+    let counters = conn.connection_counters();
+    assert_eq!(counters.open, 1);
+    assert_eq!(counters.evictions, 1);
+    assert!(counters.failed_connects >= 1);
+    assert_eq!(counters.connect_attempts, counters.failed_connects + 2,);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pooled_admin_websockets_serve_concurrent_calls() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    let conn = AdminConn::with_pool_size(
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), admin_port),
+        3,
+    );
+    assert_eq!(conn.pool_size(), 3);
+
+    // More calls than connections, so that every slot is used and some are reused.
+    let results = futures::future::join_all((0..5).map(|_| conn.list_apps(None))).await;
+    for result in results {
+        assert!(result.unwrap().is_empty());
+    }
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -168,9 +240,9 @@ async fn reuse_connection() {
         app_client_1.cached_app_info().installed_app_id
     );
 
-    // Take out a read lock so that the pool cannot create a new connection
+    // Hold on to the pooled connection, which doesn't stop the pool from handing it out again
     let inner_pool = pool.get_inner_pool();
-    let _read_lock = inner_pool.read().await;
+    let _entry = inner_pool.get("fixture1").unwrap();
 
     let app_client_1_handle = tokio::time::timeout(std::time::Duration::from_millis(100), {
         let pool = pool.clone();
@@ -191,16 +263,16 @@ async fn reuse_connection() {
             .installed_app_id
     );
 
-    // Demonstrate that the pool was prevented from writing by the read lock held above.
-    assert!(inner_pool.try_write().is_err());
+    // Demonstrate that the pool could not have replaced the connection while it was held above.
+    assert!(inner_pool.try_get_mut("fixture1").is_locked());
 }
 
 /// When making calls using the app connection pool, we need to reconnect websockets that are
 /// closed or otherwise in a problem state. However, we don't want to reconnect for other errors.
-/// In this test, we connect an app websocket and then disable the target app. We then prevent the
-/// pool from opening new connections and try to make a call. The call should fail with an error
-/// immediately, without trying to reconnect.
-/// If the code did try to reconnect, this test will fail with a timeout instead.
+/// In this test, we connect an app websocket and then disable the target app. We then try to make
+/// a call. The call should fail with the error from the conductor, without trying to reconnect.
+/// If the code did try to reconnect, connecting to the disabled app would fail and the call would
+/// return [`HcHttpGatewayError::UpstreamUnavailable`] instead.
 #[tokio::test(flavor = "multi_thread")]
 async fn does_not_reconnect_on_non_websocket_error() {
     initialize_testing_tracing_subscriber();
@@ -240,10 +312,6 @@ async fn does_not_reconnect_on_non_websocket_error() {
         .await
         .unwrap();
 
-    // Take out a write lock so that the pool cannot create a new connection
-    let inner_pool = pool.get_inner_pool();
-    let _read_lock = inner_pool.read().await;
-
     let cells = app_client
         .cached_app_info()
         .cell_info
@@ -289,6 +357,66 @@ async fn does_not_reconnect_on_non_websocket_error() {
     ))
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn authorize_signing_credentials_on_first_call() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    let app = install_fixture1(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+    init_zome(sweet_conductor.clone(), &app, "coordinator1".to_string())
+        .await
+        .unwrap();
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    let mut configuration = create_test_configuration(admin_port);
+    configuration.eager_signing_authorization = false;
+
+    let admin_call = Arc::new(AdminConn::new(configuration.admin_socket_addr));
+    let pool = AppConnPool::new(configuration, admin_call.clone());
+    let inner_pool = pool.get_inner_pool();
+
+    let app_client = pool
+        .get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+
+    // No credentials are authorized until a cell is called
+    let client = inner_pool.get("fixture1").unwrap().value().clone();
+    assert!(client.authorized_cells().await.is_empty());
+
+    let cell_id = app_client
+        .cached_app_info()
+        .cell_info
+        .values()
+        .flatten()
+        .find_map(|cell_info| match cell_info {
+            CellInfo::Provisioned(provisioned) => Some(provisioned.cell_id.clone()),
+            _ => None,
+        })
+        .unwrap();
+
+    let response = pool
+        .handle_zome_call(ZomeCallRequest::new(
+            "fixture1",
+            cell_id.clone(),
+            "coordinator1",
+            "get_all_1",
+            ExternIO::encode(()).unwrap(),
+        ))
+        .await
+        .unwrap();
+    assert!(response.decode::<Vec<TestType>>().unwrap().is_empty());
+
+    let client = inner_pool.get("fixture1").unwrap().value().clone();
+    assert_eq!(client.authorized_cells().await, HashSet::from([cell_id]));
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn reconnect_on_failed_websocket() {
     initialize_testing_tracing_subscriber();
@@ -321,6 +449,9 @@ async fn reconnect_on_failed_websocket() {
         "fixture1".to_string(),
         app_client.cached_app_info().installed_app_id
     );
+    let first_token = pool
+        .get_cached_app_auth_token(&"fixture1".to_string())
+        .unwrap();
 
     // Stop the conductor
     sweet_conductor.shutdown().await;
@@ -361,9 +492,8 @@ async fn reconnect_on_failed_websocket() {
 
     let cell_id = cells[0].cell_id.clone();
 
-    // Now try to make a call, which should reconnect and succeed
-    let response = pool
-        .call::<ExternIO>("fixture1".to_string(), |app_ws| {
+    let call_zome = || {
+        pool.call::<ExternIO>("fixture1".to_string(), |app_ws| {
             Box::pin({
                 let cell_id = cell_id.clone();
                 async move {
@@ -380,10 +510,326 @@ async fn reconnect_on_failed_websocket() {
                 }
             })
         })
+    };
+
+    // Now try to make a call, which finds the connection lost. The call may have been sent, so it
+    // is not retried.
+    let err = call_zome().await.unwrap_err();
+    assert!(
+        matches!(err, HcHttpGatewayError::UpstreamLost(_)),
+        "Expected upstream lost, got {err:?}"
+    );
+
+    // The lost connection was removed, so the next call should reconnect and succeed
+    let response = call_zome().await.unwrap();
+
+    assert!(response.decode::<Vec<TestType>>().unwrap().is_empty());
+
+    // The restarted conductor rejected the cached token, so a new one was issued
+    assert_ne!(
+        pool.get_cached_app_auth_token(&"fixture1".to_string())
+            .unwrap(),
+        first_token
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reuse_app_auth_token_on_reconnect() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    install_fixture1(sweet_conductor.clone(), None)
         .await
         .unwrap();
 
-    assert!(response.decode::<Vec<TestType>>().unwrap().is_empty());
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    let admin_call = Arc::new(AdminConn::new(SocketAddr::new(
+        Ipv4Addr::LOCALHOST.into(),
+        admin_port,
+    )));
+    let pool = AppConnPool::new(create_test_configuration(admin_port), admin_call.clone());
+
+    pool.get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    let token = pool
+        .get_cached_app_auth_token(&"fixture1".to_string())
+        .unwrap();
+
+    // Close the connection and connect again
+    pool.remove_app_client(&"fixture1".to_string()).await;
+    let app_client = pool
+        .get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    app_client.app_info().await.unwrap();
+
+    // The same token was used for the new connection
+    assert_eq!(
+        pool.get_cached_app_auth_token(&"fixture1".to_string())
+            .unwrap(),
+        token
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn evict_dead_app_websockets() {
+    initialize_testing_tracing_subscriber();
+
+    let mut sweet_conductor = SweetConductor::standard().await;
+
+    install_fixture1(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+    install_fixture2(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    let admin_call = Arc::new(AdminConn::new(SocketAddr::new(
+        Ipv4Addr::LOCALHOST.into(),
+        admin_port,
+    )));
+    let pool = AppConnPool::new(create_test_configuration(admin_port), admin_call.clone());
+
+    pool.get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    pool.get_or_connect_app_client("fixture2".to_string())
+        .await
+        .unwrap();
+
+    let inner_pool = pool.get_inner_pool();
+
+    // Live connections are kept
+    pool.evict_dead_app_clients().await;
+    assert_eq!(inner_pool.len(), 2);
+
+    // Stop the conductor, which closes the app websockets
+    sweet_conductor.shutdown().await;
+
+    pool.evict_dead_app_clients().await;
+    assert!(inner_pool.is_empty());
+
+    let counters = pool.connection_counters();
+    assert_eq!(counters.open, 0);
+    assert_eq!(counters.connect_attempts, 2);
+    assert_eq!(counters.evictions, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn evict_idle_app_websockets() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    install_fixture1(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+    install_fixture2(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    let mut configuration = create_test_configuration(admin_port);
+    configuration.app_idle_timeout = Some(Duration::from_millis(500));
+
+    let admin_call = Arc::new(AdminConn::new(configuration.admin_socket_addr));
+    let pool = AppConnPool::new(configuration, admin_call.clone());
+
+    pool.get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    pool.get_or_connect_app_client("fixture2".to_string())
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // Keep using one of the connections
+    pool.get_or_connect_app_client("fixture2".to_string())
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    pool.evict_idle_app_clients().await;
+
+    let inner_pool = pool.get_inner_pool();
+    let ws_for_apps = inner_pool
+        .iter()
+        .map(|state| state.app_ws.cached_app_info().installed_app_id.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(ws_for_apps, vec!["fixture2"]);
+    assert_eq!(pool.connection_counters().evictions, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recycle_app_websocket_after_max_age() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    install_fixture1(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    let mut configuration = create_test_configuration(admin_port);
+    configuration.app_max_age = Some(Duration::from_millis(500));
+
+    let admin_call = Arc::new(AdminConn::new(configuration.admin_socket_addr));
+    let pool = AppConnPool::new(configuration, admin_call.clone());
+    let inner_pool = pool.get_inner_pool();
+
+    pool.get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    let first_opened_at = inner_pool.get("fixture1").unwrap().opened_at;
+
+    // Still within the maximum age, so the connection is reused
+    pool.get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    assert_eq!(
+        inner_pool.get("fixture1").unwrap().opened_at,
+        first_opened_at
+    );
+
+    tokio::time::sleep(Duration::from_millis(600)).await;
+
+    // Past the maximum age, so a new connection is opened and is usable
+    let app_client = pool
+        .get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    assert_ne!(
+        inner_pool.get("fixture1").unwrap().opened_at,
+        first_opened_at
+    );
+    app_client.app_info().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recycle_app_websocket_after_max_requests() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    install_fixture1(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    let mut configuration = create_test_configuration(admin_port);
+    configuration.app_max_requests = Some(2);
+
+    let admin_call = Arc::new(AdminConn::new(configuration.admin_socket_addr));
+    let pool = AppConnPool::new(configuration, admin_call.clone());
+    let inner_pool = pool.get_inner_pool();
+
+    pool.get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    let first_opened_at = inner_pool.get("fixture1").unwrap().opened_at;
+
+    pool.get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    assert_eq!(
+        inner_pool.get("fixture1").unwrap().opened_at,
+        first_opened_at
+    );
+    assert_eq!(inner_pool.get("fixture1").unwrap().request_count(), 2);
+
+    // The connection has served its maximum number of requests, so a new one is opened
+    pool.get_or_connect_app_client("fixture1".to_string())
+        .await
+        .unwrap();
+    assert_ne!(
+        inner_pool.get("fixture1").unwrap().opened_at,
+        first_opened_at
+    );
+    assert_eq!(inner_pool.get("fixture1").unwrap().request_count(), 1);
+}
+
+/// Calls that are in flight at the same time share the app websocket, which carries them
+/// concurrently. With a limit on the calls in flight, a call that finds the websocket busy is made
+/// over a second websocket for the app instead.
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_calls_spill_over_to_a_second_app_websocket() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    install_fixture1(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    // Each call waits for the others to be in flight, so the calls only complete if they are
+    // carried concurrently.
+    async fn concurrent_calls(pool: &AppConnPool, calls: usize) {
+        let barrier = Arc::new(tokio::sync::Barrier::new(calls));
+        let results = tokio::time::timeout(
+            Duration::from_secs(30),
+            futures::future::join_all((0..calls).map(|_| {
+                pool.call("fixture1".to_string(), |app_ws| {
+                    let barrier = barrier.clone();
+                    Box::pin(async move {
+                        let app_info = app_ws.app_info().await?;
+                        barrier.wait().await;
+                        Ok(app_info)
+                    })
+                })
+            })),
+        )
+        .await
+        .expect("Timeout waiting for concurrent calls");
+        for result in results {
+            assert!(result.unwrap().is_some());
+        }
+    }
+
+    let configuration = create_test_configuration(admin_port);
+    let admin_call = Arc::new(AdminConn::new(configuration.admin_socket_addr));
+    let pool = AppConnPool::new(configuration, admin_call.clone());
+
+    concurrent_calls(&pool, 3).await;
+    let stats = pool.app_connection_stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].request_count, 3);
+    assert_eq!(stats[0].in_flight, 0);
+
+    let mut configuration = create_test_configuration(admin_port);
+    configuration.app_max_in_flight = Some(1);
+    let pool = AppConnPool::new(configuration, admin_call);
+
+    concurrent_calls(&pool, 2).await;
+    let stats = pool.app_connection_stats();
+    assert_eq!(stats.len(), 2);
+    assert!(!stats[0].overflow);
+    assert!(stats[1].overflow);
+    assert!(stats.iter().all(|stats| stats.in_flight == 0));
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -437,9 +883,8 @@ async fn reconnect_gives_up() {
 
     let cell_id = cells[0].cell_id.clone();
 
-    // Now try to make a call, which won't be able to reconnect
-    let err = pool
-        .call::<ExternIO>("fixture1".to_string(), |app_ws| {
+    let call_zome = || {
+        pool.call::<ExternIO>("fixture1".to_string(), |app_ws| {
             Box::pin({
                 let cell_id = cell_id.clone();
                 async move {
@@ -456,11 +901,19 @@ async fn reconnect_gives_up() {
                 }
             })
         })
-        .await
-        .unwrap_err();
+    };
 
+    // Now try to make a call, which finds the connection lost
+    let err = call_zome().await.unwrap_err();
     assert!(
-        matches!(err, HcHttpGatewayError::UpstreamUnavailable),
+        matches!(err, HcHttpGatewayError::UpstreamLost(_)),
+        "Expected upstream lost, got {err:?}"
+    );
+
+    // The next call won't be able to reconnect
+    let err = call_zome().await.unwrap_err();
+    assert!(
+        matches!(err, HcHttpGatewayError::UpstreamUnavailable(_)),
         "Expected upstream unavailable, got {err:?}"
     );
 }
@@ -488,7 +941,90 @@ async fn close_old_connections_on_limit() {
         .get_arbitrary_admin_websocket_port()
         .unwrap();
 
-    let configuration = Configuration::try_new(
+    let configuration = create_three_app_configuration(admin_port);
+
+    let admin_call = Arc::new(AdminConn::new(configuration.admin_socket_addr));
+    let pool = AppConnPool::new(configuration, admin_call.clone());
+
+    // Take out connections to all 3 apps
+    let _app_client_2 = pool
+        .get_or_connect_app_client("app_2".to_string())
+        .await
+        .unwrap();
+
+    let _app_client_1 = pool
+        .get_or_connect_app_client("app_1".to_string())
+        .await
+        .unwrap();
+
+    let _app_client_3 = pool
+        .get_or_connect_app_client("app_3".to_string())
+        .await
+        .unwrap();
+
+    let inner_pool = pool.get_inner_pool();
+
+    let mut ws_for_apps = inner_pool
+        .iter()
+        .map(|state| state.app_ws.cached_app_info().installed_app_id.clone())
+        .collect::<Vec<_>>();
+    ws_for_apps.sort();
+
+    // We should have open websockets for app_1 and app_3, the connection for app_2 should have
+    // been removed from state because we're only allowing 2 connections at once.
+    assert_eq!(ws_for_apps, vec!["app_1", "app_3"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn close_least_recently_used_connection_on_limit() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    for app_id in ["app_1", "app_2", "app_3"] {
+        install_fixture1(sweet_conductor.clone(), Some(app_id.to_string()))
+            .await
+            .unwrap();
+    }
+
+    let admin_port = sweet_conductor
+        .get_arbitrary_admin_websocket_port()
+        .unwrap();
+
+    let configuration = create_three_app_configuration(admin_port);
+    let admin_call = Arc::new(AdminConn::new(configuration.admin_socket_addr));
+    let pool = AppConnPool::new(configuration, admin_call.clone());
+
+    pool.get_or_connect_app_client("app_2".to_string())
+        .await
+        .unwrap();
+    pool.get_or_connect_app_client("app_1".to_string())
+        .await
+        .unwrap();
+
+    // Use the older connection again, so that app_1 is now the least recently used
+    pool.get_or_connect_app_client("app_2".to_string())
+        .await
+        .unwrap();
+
+    pool.get_or_connect_app_client("app_3".to_string())
+        .await
+        .unwrap();
+
+    let inner_pool = pool.get_inner_pool();
+
+    let mut ws_for_apps = inner_pool
+        .iter()
+        .map(|state| state.app_ws.cached_app_info().installed_app_id.clone())
+        .collect::<Vec<_>>();
+    ws_for_apps.sort();
+
+    // The connection for app_2 was opened first but has been used since app_1 was last used.
+    assert_eq!(ws_for_apps, vec!["app_2", "app_3"]);
+}
+
+fn create_three_app_configuration(admin_port: u16) -> Configuration {
+    Configuration::try_new(
         SocketAddr::new(Ipv4Addr::LOCALHOST.into(), admin_port),
         "",
         "app_1,app_2,app_3",
@@ -532,44 +1068,13 @@ async fn close_old_connections_on_limit() {
         "2",
         "",
     )
-    .unwrap();
-
-    let admin_call = Arc::new(AdminConn::new(configuration.admin_socket_addr));
-    let pool = AppConnPool::new(configuration, admin_call.clone());
-
-    // Take out connections to all 3 apps
-    let _app_client_2 = pool
-        .get_or_connect_app_client("app_2".to_string())
-        .await
-        .unwrap();
-
-    let _app_client_1 = pool
-        .get_or_connect_app_client("app_1".to_string())
-        .await
-        .unwrap();
-
-    let _app_client_3 = pool
-        .get_or_connect_app_client("app_3".to_string())
-        .await
-        .unwrap();
-
-    let inner_pool = pool.get_inner_pool();
-
-    let mut ws_for_apps = inner_pool
-        .read()
-        .await
-        .values()
-        .map(|state| state.app_ws.cached_app_info().installed_app_id.clone())
-        .collect::<Vec<_>>();
-    ws_for_apps.sort();
-
-    // We should have open websockets for app_1 and app_3, the connection for app_2 should have
-    // been removed from state because we're only allowing 2 connections at once.
-    assert_eq!(ws_for_apps, vec!["app_1", "app_3"]);
+    .unwrap()
 }
 
+/// Tests make zome calls directly over the pooled app websockets, which requires the signing
+/// credentials for every cell to be authorized when connecting.
 fn create_test_configuration(admin_port: u16) -> Configuration {
-    Configuration::try_new(
+    let mut configuration = Configuration::try_new(
         SocketAddr::new(Ipv4Addr::LOCALHOST.into(), admin_port),
         "",
         "fixture1,fixture2",
@@ -602,5 +1107,7 @@ fn create_test_configuration(admin_port: u16) -> Configuration {
         "",
         "",
     )
-    .unwrap()
+    .unwrap();
+    configuration.eager_signing_authorization = true;
+    configuration
 }
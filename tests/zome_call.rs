@@ -6,10 +6,11 @@ use holochain::prelude::{CellId, DnaHash};
 use holochain::sweettest::SweetConductor;
 use holochain_conductor_api::CellInfo;
 use holochain_http_gateway::ErrorResponse;
+use holochain_http_gateway::test::gateway::{TestApp, TestGateway, assert_status};
 use holochain_http_gateway::test::test_tracing::initialize_testing_tracing_subscriber;
-use holochain_types::app::InstalledApp;
+use holochain_types::app::{DisabledAppReason, InstalledApp};
 use reqwest::StatusCode;
-use setup::TestGateway;
+use std::time::Duration;
 
 mod setup;
 mod sweet;
@@ -29,7 +30,7 @@ async fn simple_zome_call() {
 
     let cell_id = get_first_cell_from_app(&sweet_conductor, &app).await;
 
-    let gateway = TestGateway::spawn(sweet_conductor.clone()).await;
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
 
     let response = gateway
         .call_zome(
@@ -40,7 +41,38 @@ async fn simple_zome_call() {
             None,
         )
         .await;
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_status(response, StatusCode::OK).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn zome_call_through_the_test_harness() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    let app = TestApp::install(
+        &sweet_conductor,
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixture/package/happ1/fixture1.happ"
+        ),
+        None,
+    )
+    .await;
+    init_zome(
+        sweet_conductor.clone(),
+        &app.app,
+        "coordinator1".to_string(),
+    )
+    .await
+    .unwrap();
+
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
+
+    let response = app
+        .call_zome(&gateway, "coordinator1", "get_all_1", None)
+        .await;
+    assert_eq!(assert_status(response, StatusCode::OK).await, "[]");
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -70,7 +102,7 @@ async fn respond_with_data() {
             .unwrap();
     }
 
-    let gateway = TestGateway::spawn(sweet_conductor.clone()).await;
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
 
     let response = gateway
         .call_zome(
@@ -113,7 +145,7 @@ async fn get_data_with_agent_key_payload() {
             .unwrap();
     }
 
-    let gateway = TestGateway::spawn(sweet_conductor.clone()).await;
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
 
     let response = gateway
         .call_zome(
@@ -156,7 +188,7 @@ async fn get_data_with_object_payload() {
             .unwrap();
     }
 
-    let gateway = TestGateway::spawn(sweet_conductor.clone()).await;
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
 
     let response = gateway
         .call_zome(
@@ -219,7 +251,7 @@ async fn get_data_from_multiple_apps() {
             .unwrap();
     }
 
-    let gateway = TestGateway::spawn(sweet_conductor.clone()).await;
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
 
     let response = gateway
         .call_zome(
@@ -263,7 +295,7 @@ async fn call_function_that_is_not_exposed() {
 
     let cell_id = get_first_cell_from_app(&sweet_conductor, &app).await;
 
-    let gateway = TestGateway::spawn(sweet_conductor.clone()).await;
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
 
     let response = gateway
         .call_zome(
@@ -288,7 +320,7 @@ async fn call_missing_app() {
 
     let sweet_conductor = SweetConductor::standard().await;
 
-    let gateway = TestGateway::spawn(sweet_conductor.clone()).await;
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
 
     let response = gateway
         .call_zome(
@@ -302,6 +334,55 @@ async fn call_missing_app() {
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn call_app_after_it_is_disabled_and_enabled() {
+    initialize_testing_tracing_subscriber();
+
+    let sweet_conductor = SweetConductor::standard().await;
+
+    let app = install_fixture1(sweet_conductor.clone(), None)
+        .await
+        .unwrap();
+    init_zome(sweet_conductor.clone(), &app, "coordinator1".to_string())
+        .await
+        .unwrap();
+
+    let cell_id = get_first_cell_from_app(&sweet_conductor, &app).await;
+
+    let mut config = setup::fixture_config(&sweet_conductor);
+    config.app_watch_interval = Duration::from_millis(100);
+    let gateway = TestGateway::spawn_with_config(config).await;
+
+    let call = || {
+        gateway.call_zome(
+            cell_id.dna_hash(),
+            "fixture1",
+            "coordinator1",
+            "get_all_1",
+            None,
+        )
+    };
+
+    assert_eq!(call().await.status(), StatusCode::OK);
+
+    sweet_conductor
+        .disable_app("fixture1".to_string(), DisabledAppReason::User)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // The cached app info has been invalidated, so the disabled app is not found
+    assert_eq!(call().await.status(), StatusCode::NOT_FOUND);
+
+    sweet_conductor
+        .enable_app("fixture1".to_string())
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(call().await.status(), StatusCode::OK);
+}
+
 async fn get_first_cell_from_app(sweet_conductor: &SweetConductor, app: &InstalledApp) -> CellId {
     let app_info = sweet_conductor
         .list_apps(None)
@@ -351,7 +432,7 @@ async fn zome_call_load_test() {
             .unwrap();
     }
 
-    let gateway = TestGateway::spawn(sweet_conductor.clone()).await;
+    let gateway = setup::spawn_gateway(&sweet_conductor).await;
     let address = gateway.address.clone();
 
     //  test parameters